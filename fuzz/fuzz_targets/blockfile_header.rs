@@ -0,0 +1,26 @@
+#![no_main]
+
+use std::collections::HashSet;
+
+use fusedav_rs::blockfile::BlockFile;
+use libfuzzer_sys::fuzz_target;
+
+// Note : cache files are read back from disk, so the header must never be trusted.
+fuzz_target!(|data: &[u8]| {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("blockfile");
+    std::fs::write(&path, data).unwrap();
+    let path = path.to_str().unwrap();
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        if let Ok(mut file) = BlockFile::open(path, false).await {
+            let mut buf = vec![0; 4096];
+            let _ = file.is_data_ready(0, buf.len() as u64).await;
+            let _ = file.read(&mut buf, 0).await;
+            let _ = file.verify(0, buf.len() as u64, &mut HashSet::new()).await;
+        }
+    });
+});
@@ -0,0 +1,142 @@
+use std::{
+    fmt::Display,
+    fs::OpenOptions,
+    io::{self, BufRead, BufReader, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use zeroize::Zeroizing;
+
+use crate::webdav::Secret;
+
+const TTY_PATH: &str = "/dev/tty";
+/// Askpass program of the user, checked before `SSH_ASKPASS`.
+const ASKPASS_ENV: &str = "FUSEDAV_ASKPASS";
+const SSH_ASKPASS_ENV: &str = "SSH_ASKPASS";
+
+#[derive(Debug)]
+pub enum AskpassError {
+    /// The askpass program exited with a failure, e.g. because the dialog was cancelled.
+    Cancelled(PathBuf),
+    IO(io::Error),
+}
+
+/// Asks the user for a password, showing `prompt`. The askpass program is run with the prompt as
+/// its only argument and must print the password on stdout, like `ssh-askpass`.
+///
+/// The program is `askpass`, else `$FUSEDAV_ASKPASS`. Without one the password is read from the
+/// terminal with echo off, and without a terminal `$SSH_ASKPASS` is run. Returns None when there
+/// is no way to ask.
+pub fn read_password(prompt: &str, askpass: Option<&Path>) -> Result<Option<Secret>, AskpassError> {
+    let askpass = askpass
+        .map(Path::to_path_buf)
+        .or_else(|| std::env::var_os(ASKPASS_ENV).map(PathBuf::from));
+    if let Some(askpass) = askpass {
+        return run_askpass(&askpass, prompt).map(Some);
+    }
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } == 1 {
+        return read_from_tty(prompt)
+            .map(Some)
+            .map_err(|err| AskpassError::IO(err));
+    }
+    match std::env::var_os(SSH_ASKPASS_ENV) {
+        Some(askpass) => run_askpass(Path::new(&askpass), prompt).map(Some),
+        None => Ok(None),
+    }
+}
+
+fn run_askpass(askpass: &Path, prompt: &str) -> Result<Secret, AskpassError> {
+    let output = Command::new(askpass)
+        .arg(prompt)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|err| AskpassError::IO(err))?;
+    let stdout = Zeroizing::new(output.stdout);
+    if !output.status.success() {
+        return Err(AskpassError::Cancelled(askpass.to_path_buf()));
+    }
+    let password = String::from_utf8(stdout.to_vec())
+        .map_err(|err| AskpassError::IO(io::Error::new(io::ErrorKind::InvalidData, err)))?;
+    Ok(Secret::new(trim_line_end(password)))
+}
+
+/// Reads a line from the controlling terminal with echo turned off, restoring it afterwards even
+/// when reading fails.
+fn read_from_tty(prompt: &str) -> io::Result<Secret> {
+    let mut tty = OpenOptions::new().read(true).write(true).open(TTY_PATH)?;
+    let fd = tty.as_raw_fd();
+    let mut termios = unsafe { std::mem::zeroed::<libc::termios>() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let echo_off = libc::termios {
+        c_lflag: (termios.c_lflag & !libc::ECHO) | libc::ECHONL,
+        ..termios
+    };
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &echo_off) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut line = Zeroizing::new(String::new());
+    let result = tty
+        .write_all(prompt.as_bytes())
+        .and_then(|_| tty.flush())
+        .and_then(|_| BufReader::new(&tty).read_line(&mut line));
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) };
+    result?;
+    Ok(Secret::new(trim_line_end(std::mem::take(&mut *line))))
+}
+
+fn trim_line_end(mut line: String) -> String {
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    line
+}
+
+impl Display for AskpassError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AskpassError::Cancelled(askpass) => {
+                write!(f, "{} did not return a password", askpass.display())
+            }
+            AskpassError::IO(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for AskpassError {}
+
+#[cfg(test)]
+mod askpass_test {
+    use std::path::Path;
+
+    use super::{read_password, trim_line_end, AskpassError};
+
+    #[test]
+    fn trim_line_end_test() {
+        assert_eq!(trim_line_end("secret\n".to_string()), "secret");
+        assert_eq!(trim_line_end("secret\r\n".to_string()), "secret");
+        assert_eq!(trim_line_end(" secret ".to_string()), " secret ");
+    }
+
+    #[test]
+    fn askpass_program_test() {
+        // Note : echo prints the prompt back, which stands in for what the user typed.
+        let password = read_password("Password: ", Some(Path::new("echo")))
+            .unwrap()
+            .unwrap();
+        assert_eq!(password.expose(), "Password: ");
+
+        assert!(matches!(
+            read_password("Password: ", Some(Path::new("false"))),
+            Err(AskpassError::Cancelled(_))
+        ));
+    }
+}
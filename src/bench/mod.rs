@@ -0,0 +1,294 @@
+use std::{
+    fmt::Display,
+    io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+use tokio::task::JoinSet;
+
+use crate::{
+    blockfile::BlockFile,
+    webdav::{self, encode_path, StatOptions, WebDAVClient, WebDAVList},
+};
+
+/// Sizes of a benchmark run.
+#[derive(Debug, Clone)]
+pub struct BenchOptions {
+    /// Bytes written to and read from the scratch cache file, and read from the remote file at
+    /// most.
+    pub size: u64,
+    pub block_size: u32,
+    /// Bytes of every read, like a read of a program through the mount.
+    pub read_size: u32,
+    pub random_reads: usize,
+    /// Downloads running at the same time in the parallel remote run.
+    pub concurrency: usize,
+}
+
+/// The bytes a run moved and the time it took.
+#[derive(Debug)]
+pub struct Measurement {
+    pub name: String,
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+#[derive(Debug)]
+pub enum BenchError {
+    IO(io::Error),
+    WebDAV(webdav::Error),
+    NotAFile(String),
+}
+
+impl Measurement {
+    fn since(name: String, bytes: u64, started_at: Instant) -> Measurement {
+        Measurement {
+            name,
+            bytes,
+            elapsed: started_at.elapsed(),
+        }
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes as f64 / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+impl Display for Measurement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} bytes in {} ms, {:.1} MiB/s",
+            self.name,
+            self.bytes,
+            self.elapsed.as_millis(),
+            self.bytes_per_sec() / (1024.0 * 1024.0)
+        )
+    }
+}
+
+/// Measures writing, then reading sequentially and at random offsets, a cache file in `dir`.
+/// The file is removed afterwards.
+pub async fn bench_cache(
+    dir: &Path,
+    options: &BenchOptions,
+) -> Result<Vec<Measurement>, BenchError> {
+    let path = scratch_path(dir, "cache");
+    let result = run_cache(&path, options).await;
+    let _ = BlockFile::remove(&path).await;
+    result.map_err(|err| BenchError::IO(err))
+}
+
+async fn run_cache(path: &str, options: &BenchOptions) -> io::Result<Vec<Measurement>> {
+    let size = options.size;
+    let read_size = options.read_size as u64;
+    let mut buf: Vec<u8> = (0..read_size).map(|x| (x % 251) as u8).collect();
+    let mut measurements = Vec::new();
+
+    let started_at = Instant::now();
+    let mut file = BlockFile::create(path, size, options.block_size).await?;
+    for offset in (0..size).step_by(read_size as usize) {
+        let len = read_size.min(size - offset) as usize;
+        file.write(&buf[..len], offset).await?;
+    }
+    file.sync().await?;
+    drop(file);
+    measurements.push(Measurement::since(
+        "cache write".to_string(),
+        size,
+        started_at,
+    ));
+
+    let started_at = Instant::now();
+    let mut file = BlockFile::open(path, false).await?;
+    let mut bytes = 0;
+    for offset in (0..size).step_by(read_size as usize) {
+        bytes += file.read(&mut buf, offset).await? as u64;
+    }
+    measurements.push(Measurement::since(
+        "cache sequential read".to_string(),
+        bytes,
+        started_at,
+    ));
+
+    let started_at = Instant::now();
+    let mut bytes = 0;
+    for offset in random_offsets(size, read_size, options.random_reads) {
+        bytes += file.read(&mut buf, offset).await? as u64;
+    }
+    measurements.push(Measurement::since(
+        "cache random read".to_string(),
+        bytes,
+        started_at,
+    ));
+    Ok(measurements)
+}
+
+/// Measures downloading the remote file at `path` block by block, with several downloads at the
+/// same time, and in reads at random offsets. The downloads go to scratch cache files in `dir`,
+/// which are removed afterwards.
+pub async fn bench_remote(
+    client: &WebDAVClient,
+    path: &str,
+    dir: &Path,
+    options: &BenchOptions,
+) -> Result<Vec<Measurement>, BenchError> {
+    let encoded_path = encode_path(path);
+    let (item, _) = client
+        .stat(&encoded_path, StatOptions::default())
+        .await
+        .map_err(|e| BenchError::WebDAV(e))?;
+    let size = match item {
+        WebDAVList::File(file) if file.content_length > 0 => file.content_length.min(options.size),
+        _ => return Err(BenchError::NotAFile(path.to_string())),
+    };
+    let block_size = options.block_size as u64;
+    let blocks: Vec<u64> = (0..size).step_by(block_size as usize).collect();
+    let mut measurements = Vec::new();
+
+    let scratch = scratch_path(dir, "remote");
+    let started_at = Instant::now();
+    let result = download_blocks(client, &encoded_path, &scratch, size, options, &blocks).await;
+    let _ = BlockFile::remove(&scratch).await;
+    result?;
+    measurements.push(Measurement::since(
+        "remote sequential read".to_string(),
+        size,
+        started_at,
+    ));
+
+    // Note : every download writes its own scratch file, the mount too keeps one file per
+    // remote file.
+    let concurrency = options.concurrency.max(1);
+    let started_at = Instant::now();
+    let mut downloads = JoinSet::new();
+    for worker in 0..concurrency {
+        let client = client.clone();
+        let encoded_path = encoded_path.clone();
+        let options = options.clone();
+        let blocks: Vec<u64> = blocks
+            .iter()
+            .copied()
+            .skip(worker)
+            .step_by(concurrency)
+            .collect();
+        let scratch = scratch_path(dir, &format!("remote-{}", worker));
+        downloads.spawn(async move {
+            let result =
+                download_blocks(&client, &encoded_path, &scratch, size, &options, &blocks).await;
+            let _ = BlockFile::remove(&scratch).await;
+            result
+        });
+    }
+    while let Some(result) = downloads.join_next().await {
+        result.map_err(|e| BenchError::IO(io::Error::other(e)))??;
+    }
+    measurements.push(Measurement::since(
+        format!("remote parallel read ({} downloads)", concurrency),
+        size,
+        started_at,
+    ));
+
+    let read_size = options.read_size as u64;
+    let started_at = Instant::now();
+    let result = async {
+        let mut file = BlockFile::create(&scratch, size, options.block_size)
+            .await
+            .map_err(|err| BenchError::IO(err))?;
+        let mut bytes = 0;
+        for offset in random_offsets(size, read_size, options.random_reads) {
+            let len = read_size.min(size - offset);
+            client
+                .download(&encoded_path, &mut file, offset, len)
+                .await
+                .map_err(|e| BenchError::WebDAV(e))?;
+            bytes += len;
+        }
+        Ok(bytes)
+    }
+    .await;
+    let _ = BlockFile::remove(&scratch).await;
+    measurements.push(Measurement::since(
+        "remote random read".to_string(),
+        result?,
+        started_at,
+    ));
+    Ok(measurements)
+}
+
+async fn download_blocks(
+    client: &WebDAVClient,
+    encoded_path: &str,
+    scratch: &str,
+    size: u64,
+    options: &BenchOptions,
+    blocks: &[u64],
+) -> Result<(), BenchError> {
+    let mut file = BlockFile::create(scratch, size, options.block_size)
+        .await
+        .map_err(|err| BenchError::IO(err))?;
+    for offset in blocks {
+        let len = (options.block_size as u64).min(size - offset);
+        client
+            .download(encoded_path, &mut file, *offset, len)
+            .await
+            .map_err(|e| BenchError::WebDAV(e))?;
+    }
+    Ok(())
+}
+
+/// Offsets of `count` reads of `read_size` bytes spread at random over `size` bytes.
+fn random_offsets(size: u64, read_size: u64, count: usize) -> Vec<u64> {
+    let last = size.saturating_sub(read_size);
+    let mut rng = rand::thread_rng();
+    (0..count).map(|_| rng.gen_range(0..=last)).collect()
+}
+
+fn scratch_path(dir: &Path, name: &str) -> String {
+    dir.join(format!(".fusedav-bench-{}-{}", std::process::id(), name))
+        .to_string_lossy()
+        .to_string()
+}
+
+impl Display for BenchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BenchError::IO(err) => write!(f, "{}", err),
+            BenchError::WebDAV(err) => write!(f, "{}", err),
+            BenchError::NotAFile(path) => write!(f, "{} is no file or is empty", path),
+        }
+    }
+}
+
+impl std::error::Error for BenchError {}
+
+#[cfg(test)]
+mod bench_test {
+    use super::{bench_cache, random_offsets, BenchOptions};
+
+    #[test]
+    fn random_offsets_test() {
+        let offsets = random_offsets(100, 40, 50);
+        assert_eq!(offsets.len(), 50);
+        assert!(offsets.iter().all(|x| *x <= 60));
+        assert!(random_offsets(10, 40, 3).iter().all(|x| *x == 0));
+    }
+
+    #[tokio::test]
+    async fn bench_cache_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = BenchOptions {
+            size: 100,
+            block_size: 16,
+            read_size: 24,
+            random_reads: 3,
+            concurrency: 1,
+        };
+        let measurements = bench_cache(dir.path(), &options).await.unwrap();
+        let bytes: Vec<u64> = measurements.iter().map(|x| x.bytes).collect();
+        assert_eq!(bytes, vec![100, 100, 72]);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+}
@@ -1,54 +1,98 @@
-use std::io::SeekFrom;
+mod positional;
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    fs::OpenOptions,
+    io::SeekFrom,
+    os::unix::fs::{FileExt, MetadataExt},
+};
 
 use tokio::{
     fs::File,
-    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    io::{AsyncSeekExt, AsyncWriteExt},
 };
 
-const FILE_FORMAT_SIGNATURE: &[u8] = b"FDrs";
+use positional::{blocking, PositionalFile};
+
+/// Version 5 records the origin of the data after the block infos, version 4 keeps the data in a
+/// separate file, version 3 added a CRC-32 per block. Caches of older versions fail validation
+/// or lack a `.meta` file, and are recreated.
+const FILE_FORMAT_SIGNATURE: &[u8] = b"FDr5";
+/// Largest block size of a cache file.
+pub const MAX_BLOCK_SIZE: u32 = 1024 * 1024 * 1024;
+/// Data is moved between files in chunks of this size, since blocks may be up to 1 GiB.
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+/// Block infos of incomplete blocks are written to the header once this many bytes were written
+/// since the last time, instead of after every write.
+const BLOCK_INFO_FLUSH_BYTES: usize = 1024 * 1024;
+/// Longest origin a header holds, far more than a remote path and its version take.
+const MAX_ORIGIN_LEN: u32 = 64 * 1024;
+
+/// Returns the bytes of disk space allocated to the file at `path`, which is less than its length
+/// when it has holes.
+async fn file_disk_usage(path: &str) -> std::io::Result<u64> {
+    Ok(tokio::fs::metadata(path).await?.blocks() * 512)
+}
+
+fn meta_path(path: &str) -> String {
+    format!("{}.meta", path)
+}
+
+fn data_path(path: &str) -> String {
+    format!("{}.data", path)
+}
+
+/// Cache files are read back from disk, so every value in the header is checked before use and
+/// reported as `InvalidData`, which callers treat as "corrupt cache, recreate it".
+fn corrupted(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Positions are computed with checked u64 arithmetic, so offsets in huge files fail instead of
+/// wrapping around to another block.
+fn out_of_range(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
 
 struct BlockInfo {
-    block_info_index: u32,
+    block_info_index: u64,
 
     pub used: bool,
-    pub block_index: u32,
+    /// Bytes written contiguously from the start of the block, at most the block size.
     pub usage: u32,
+    /// CRC-32 of the first `usage` bytes of the block.
+    pub checksum: u32,
 }
 
 impl BlockInfo {
-    async fn from(file: &mut File, index: u32) -> std::io::Result<BlockInfo> {
-        file.seek(SeekFrom::Start(
-            BlockFileHeader::first_block_info_start_pos() + BlockInfo::size() * index as u64,
-        ))
-        .await?;
-
-        let used = file.read_u8().await? == 1;
-        let block_index = file.read_u32().await?;
-        let usage = file.read_u32().await?;
-        Ok(BlockInfo {
+    /// Decodes the block info at `index` from its `BlockInfo::size()` bytes.
+    fn decode(index: u64, bytes: &[u8]) -> BlockInfo {
+        let be_u32 = |x: &[u8]| u32::from_be_bytes(x.try_into().unwrap());
+        BlockInfo {
             block_info_index: index,
-            used,
-            block_index,
-            usage,
-        })
+            used: bytes[0] == 1,
+            usage: be_u32(&bytes[1..5]),
+            checksum: be_u32(&bytes[5..9]),
+        }
     }
 
-    async fn write(&self, file: &mut File) -> std::io::Result<()> {
-        file.seek(SeekFrom::Start(
-            BlockFileHeader::first_block_info_start_pos()
-                + BlockInfo::size() * self.block_info_index as u64,
-        ))
-        .await?;
+    fn encode(&self, bytes: &mut Vec<u8>) {
+        bytes.push(self.used as u8);
+        bytes.extend_from_slice(&self.usage.to_be_bytes());
+        bytes.extend_from_slice(&self.checksum.to_be_bytes());
+    }
 
-        file.write_u8(self.used as u8).await?;
-        file.write_u32(self.block_index).await?;
-        file.write_u32(self.usage).await?;
-        Ok(())
+    fn pos(index: u64) -> u64 {
+        BlockFileHeader::first_block_info_start_pos() + BlockInfo::size() * index
     }
 
-    async fn reload(&mut self, file: &mut File) -> std::io::Result<()> {
-        *self = BlockInfo::from(file, self.block_info_index).await?;
-        Ok(())
+    // Note : every field is written with a single write, so the header costs one syscall per
+    // update instead of one per field.
+    async fn write(&self, file: &PositionalFile) -> std::io::Result<()> {
+        let mut bytes = Vec::with_capacity(BlockInfo::size() as usize);
+        self.encode(&mut bytes);
+        file.write_all_at(bytes, BlockInfo::pos(self.block_info_index))
+            .await
     }
 
     fn size() -> u64 {
@@ -61,114 +105,171 @@ struct BlockFileHeader {
 
     file_size: u64,
     block_size: u32,
-    next_block_index: u32,
+    /// What the data was copied from, opaque to the cache, see `BlockFile::set_origin`. Stored
+    /// after the block infos, prefixed with its length.
+    origin: Vec<u8>,
 }
 
 impl BlockFileHeader {
-    fn new(file_size: u64, block_size: u32) -> Self {
-        let block_count = (file_size as f64 / block_size as f64).ceil() as usize;
+    fn new(file_size: u64, block_size: u32) -> std::io::Result<Self> {
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+            return Err(out_of_range(format!("Invalid block size {}", block_size)));
+        }
+        let block_count = file_size.div_ceil(block_size as u64);
+        BlockInfo::size()
+            .checked_mul(block_count)
+            .and_then(|x| x.checked_add(BlockFileHeader::first_block_info_start_pos()))
+            .ok_or_else(|| out_of_range(format!("Too many blocks: {}", block_count)))?;
+
         let empty_blocks = (0..block_count)
             .map(|index| BlockInfo {
-                block_info_index: index as u32,
+                block_info_index: index,
                 used: false,
-                block_index: 0,
                 usage: 0,
+                checksum: 0,
             })
             .collect();
 
-        BlockFileHeader {
+        Ok(BlockFileHeader {
             block_info_list: empty_blocks,
             file_size,
             block_size,
-            next_block_index: 0,
-        }
+            origin: Vec::new(),
+        })
     }
 
-    async fn from(file: &mut File) -> std::io::Result<BlockFileHeader> {
-        BlockFileHeader::validate_file_header(file).await?;
-        let file_size = BlockFileHeader::read_file_size_from(file).await?;
-        let block_size = BlockFileHeader::read_block_size_from(file).await?;
-        let block_info_list = BlockFileHeader::read_block_info_list_from(file).await?;
-        let next_block_index = BlockFileHeader::find_last_block_index(&block_info_list)
-            .map_or(0, |last_block_index| last_block_index + 1);
+    async fn from(file: &PositionalFile) -> std::io::Result<BlockFileHeader> {
+        // Note : the fixed fields are read at once; a file too short for them is no cache.
+        let fields = file
+            .read_exact_at(BlockFileHeader::first_block_info_start_pos() as usize, 0)
+            .await
+            .map_err(|_| corrupted("Invalid file format".to_string()))?;
+        let field = |pos: u64, len: usize| &fields[pos as usize..pos as usize + len];
+        if field(BlockFileHeader::signatire_pose(), 4) != FILE_FORMAT_SIGNATURE {
+            return Err(corrupted("Invalid file format".to_string()));
+        }
+        let file_size = u64::from_be_bytes(
+            field(BlockFileHeader::file_size_pos(), 8)
+                .try_into()
+                .unwrap(),
+        );
+        let block_size = u32::from_be_bytes(
+            field(BlockFileHeader::block_size_pose(), 4)
+                .try_into()
+                .unwrap(),
+        );
+        let block_info_list_len = u64::from_be_bytes(
+            field(BlockFileHeader::block_info_list_len_start_pos(), 8)
+                .try_into()
+                .unwrap(),
+        );
+        if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+            return Err(corrupted(format!("Invalid block size {}", block_size)));
+        }
+
+        let block_count = file_size.div_ceil(block_size as u64);
+        let block_info_list =
+            BlockFileHeader::read_block_info_list_from(file, block_info_list_len, block_count)
+                .await?;
+        let origin = BlockFileHeader::read_origin_from(file, block_count).await?;
 
         Ok(BlockFileHeader {
             block_info_list,
             file_size,
             block_size,
-            next_block_index,
+            origin,
         })
     }
 
-    async fn write_file_header(&self, file: &mut File) -> std::io::Result<()> {
-        file.write_all(&FILE_FORMAT_SIGNATURE).await?;
-        file.write_u64(self.file_size).await?;
-        file.write_u32(self.block_size).await?;
-        BlockFileHeader::write_all_block_info(self, file).await?;
-        file.flush().await?;
-        Ok(())
-    }
-
-    async fn validate_file_header(file: &mut File) -> std::io::Result<()> {
-        file.seek(SeekFrom::Start(BlockFileHeader::signatire_pose()))
-            .await?;
-
-        let mut header = [0u8; 4];
-        file.read(&mut header).await?;
-
-        if header != FILE_FORMAT_SIGNATURE {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid file format",
-            ))
-        } else {
-            Ok(())
+    async fn write_file_header(&self, file: &PositionalFile) -> std::io::Result<()> {
+        let mut bytes = Vec::with_capacity(
+            BlockInfo::pos(0) as usize
+                + self.block_info_list.len() * BlockInfo::size() as usize
+                + 4
+                + self.origin.len(),
+        );
+        bytes.extend_from_slice(FILE_FORMAT_SIGNATURE);
+        bytes.extend_from_slice(&self.file_size.to_be_bytes());
+        bytes.extend_from_slice(&self.block_size.to_be_bytes());
+        bytes.extend_from_slice(&(self.block_info_list.len() as u64).to_be_bytes());
+        for block_info in self.block_info_list.iter() {
+            block_info.encode(&mut bytes);
         }
+        self.encode_origin(&mut bytes);
+        file.write_all_at(bytes, BlockFileHeader::signatire_pose())
+            .await
     }
 
-    async fn read_file_size_from(file: &mut File) -> std::io::Result<u64> {
-        file.seek(SeekFrom::Start(BlockFileHeader::file_size_pos()))
-            .await?;
-        file.read_u64().await
+    fn encode_origin(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&(self.origin.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.origin);
     }
 
-    async fn read_block_size_from(file: &mut File) -> std::io::Result<u32> {
-        file.seek(SeekFrom::Start(BlockFileHeader::block_size_pose()))
-            .await?;
-        file.read_u32().await
+    fn origin_pos(&self) -> u64 {
+        BlockInfo::pos(self.block_info_list.len() as u64)
     }
 
-    async fn read_block_info_list_from(file: &mut File) -> std::io::Result<Vec<BlockInfo>> {
-        file.seek(SeekFrom::Start(
-            BlockFileHeader::block_info_list_len_start_pos(),
-        ))
-        .await?;
-
-        let block_info_list_len = file.read_u32().await? as usize;
-        let mut block_info_list = Vec::with_capacity(block_info_list_len);
-        for i in 0..block_info_list_len {
-            block_info_list.push(BlockInfo::from(file, i as u32).await?);
+    async fn read_origin_from(file: &PositionalFile, block_count: u64) -> std::io::Result<Vec<u8>> {
+        let pos = BlockInfo::pos(block_count);
+        let len = file
+            .read_exact_at(4, pos)
+            .await
+            .map_err(|_| corrupted("Truncated header: origin is missing".to_string()))?;
+        let len = u32::from_be_bytes(len.try_into().unwrap());
+        if len > MAX_ORIGIN_LEN {
+            return Err(corrupted(format!("Invalid origin length {}", len)));
         }
-        Ok(block_info_list)
+        file.read_exact_at(len as usize, pos + 4)
+            .await
+            .map_err(|_| corrupted("Truncated header: origin is cut off".to_string()))
     }
 
-    async fn write_all_block_info(&self, file: &mut File) -> std::io::Result<()> {
-        file.seek(SeekFrom::Start(
-            BlockFileHeader::block_info_list_len_start_pos(),
-        ))
-        .await?;
+    async fn read_block_info_list_from(
+        file: &PositionalFile,
+        block_info_list_len: u64,
+        block_count: u64,
+    ) -> std::io::Result<Vec<BlockInfo>> {
+        if block_info_list_len != block_count {
+            return Err(corrupted(format!(
+                "Block count mismatch: header {}, expected {}",
+                block_info_list_len, block_count
+            )));
+        }
 
-        file.write_u32(self.block_info_list.len() as u32).await?;
-        for index in 0..self.block_info_list.len() {
-            let block_info = &self.block_info_list[index];
-            block_info.write(file).await?;
+        // Note : the whole list must fit in the file, which also bounds the allocation below.
+        let disk_size = file.file_len().await?;
+        let header_size = BlockInfo::size()
+            .checked_mul(block_info_list_len)
+            .and_then(|x| x.checked_add(BlockFileHeader::first_block_info_start_pos()))
+            .ok_or_else(|| corrupted(format!("Invalid block count {}", block_info_list_len)))?;
+        if header_size > disk_size {
+            return Err(corrupted(format!(
+                "Truncated header: {} bytes expected, file has {}",
+                header_size, disk_size
+            )));
         }
-        Ok(())
+
+        // Note : the list is read at once, which matters for small blocks on big files.
+        let bytes = file
+            .read_exact_at(
+                (header_size - BlockInfo::pos(0)) as usize,
+                BlockInfo::pos(0),
+            )
+            .await?;
+
+        Ok(bytes
+            .chunks_exact(BlockInfo::size() as usize)
+            .enumerate()
+            .map(|(i, chunk)| BlockInfo::decode(i as u64, chunk))
+            .collect())
     }
 
-    fn get_header_size(&self) -> u64 {
-        BlockFileHeader::first_block_info_start_pos()
-            + self.block_info_list.len() as u64 * BlockInfo::size()
+    /// Length of the data of a block, which is shorter than the block size for the last block.
+    fn block_len(&self, block_info_index: u64) -> u32 {
+        let block_begin = block_info_index.saturating_mul(self.block_size as u64);
+        self.file_size
+            .saturating_sub(block_begin)
+            .min(self.block_size as u64) as u32
     }
 
     fn get_mut_block_info(&mut self, pos: u64) -> std::io::Result<&mut BlockInfo> {
@@ -188,9 +289,8 @@ impl BlockFileHeader {
 
         if !block_info.used {
             block_info.used = true;
-            block_info.block_index = self.next_block_index;
             block_info.usage = 0;
-            self.next_block_index += 1;
+            block_info.checksum = 0;
         }
         Ok(block_info)
     }
@@ -200,23 +300,10 @@ impl BlockFileHeader {
         block_size: u64,
         pos: u64,
     ) -> std::io::Result<&mut BlockInfo> {
-        let block_info_index = (pos / block_size) as usize;
-        let block_info = block_info_list.get_mut(block_info_index).map_or(
-            Err(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid position",
-            )),
-            |block_info| Ok(block_info),
-        )?;
-        Ok(block_info)
-    }
-
-    fn find_last_block_index(block_info_list: &[BlockInfo]) -> Option<u32> {
+        let block_info_index = usize::try_from(pos / block_size).unwrap_or(usize::MAX);
         block_info_list
-            .iter()
-            .filter(|block_info| block_info.used)
-            .max_by_key(|block_info| block_info.block_index)
-            .map_or(None, |block_info| Some(block_info.block_index))
+            .get_mut(block_info_index)
+            .ok_or_else(|| out_of_range(format!("Invalid position {}", pos)))
     }
 
     fn signatire_pose() -> u64 {
@@ -236,48 +323,430 @@ impl BlockFileHeader {
     }
 
     fn first_block_info_start_pos() -> u64 {
-        20
+        24
+    }
+
+    /// Reads the block infos `begin..=end` again, since other handles may have written them.
+    async fn reload_block_infos(
+        &mut self,
+        file: &PositionalFile,
+        begin: u64,
+        end: u64,
+    ) -> std::io::Result<()> {
+        let bytes = file
+            .read_exact_at(
+                ((end - begin + 1) * BlockInfo::size()) as usize,
+                BlockInfo::pos(begin),
+            )
+            .await?;
+        for (i, chunk) in bytes.chunks_exact(BlockInfo::size() as usize).enumerate() {
+            let index = begin + i as u64;
+            self.block_info_list[index as usize] = BlockInfo::decode(index, chunk);
+        }
+        Ok(())
     }
 }
 
+/// A partial copy of a remote file, stored as two files. `<path>.data` is a sparse file holding
+/// the data at its offset in the remote file, so it can be inspected with ordinary tools, and
+/// `<path>.meta` holds the header with the state of every block. Header updates never touch the
+/// data file.
 pub struct BlockFile {
     header: BlockFileHeader,
-    file: File,
+    meta: PositionalFile,
+    data: PositionalFile,
+    /// Blocks whose info changed in memory but not yet in the header, see `flush_block_infos`.
+    dirty_blocks: BTreeSet<u64>,
+    unflushed_bytes: usize,
 }
 
 impl BlockFile {
     pub async fn create(path: &str, file_size: u64, block_size: u32) -> std::io::Result<BlockFile> {
-        let mut file = File::create(path).await?;
-        let header = BlockFileHeader::new(file_size, block_size);
-        header.write_file_header(&mut file).await?;
-        Ok(BlockFile { header, file })
+        let header = BlockFileHeader::new(file_size, block_size)?;
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(true);
+        let data = PositionalFile::open(data_path(path), options.clone()).await?;
+        data.set_len(file_size).await?;
+        let meta = PositionalFile::open(meta_path(path), options).await?;
+        header.write_file_header(&meta).await?;
+        Ok(BlockFile {
+            header,
+            meta,
+            data,
+            dirty_blocks: BTreeSet::new(),
+            unflushed_bytes: 0,
+        })
     }
 
     pub async fn open(path: &str, write: bool) -> std::io::Result<BlockFile> {
-        let mut file = File::options().write(write).read(true).open(path).await?;
-        let header = BlockFileHeader::from(&mut file).await?;
-        Ok(BlockFile { header, file })
+        let mut options = OpenOptions::new();
+        options.write(write).read(true);
+        let meta = PositionalFile::open(meta_path(path), options.clone()).await?;
+        let header = BlockFileHeader::from(&meta).await?;
+        let data = PositionalFile::open(data_path(path), options).await?;
+        Ok(BlockFile {
+            header,
+            meta,
+            data,
+            dirty_blocks: BTreeSet::new(),
+            unflushed_bytes: 0,
+        })
     }
 
-    pub async fn is_data_ready(&mut self, begin: u64, size: u64) -> std::io::Result<bool> {
-        let size = if self.header.file_size < begin + size {
-            self.header.file_size - begin
-        } else {
-            size
+    /// Removes the files of the cache at `path`, including a single file left by a version
+    /// before the data had a file of its own.
+    pub async fn remove(path: &str) -> std::io::Result<()> {
+        for path in [meta_path(path), data_path(path), path.to_string()] {
+            match tokio::fs::remove_file(&path).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Creates the cache at `path` from a complete local copy of the file at `source`, with
+    /// every block present. The copy is cheap on filesystems which share extents between files.
+    pub async fn import(path: &str, source: &str, block_size: u32) -> std::io::Result<BlockFile> {
+        let file_size = tokio::fs::metadata(source).await?.len();
+        let header = BlockFileHeader::new(file_size, block_size)?;
+        tokio::fs::copy(source, data_path(path)).await?;
+        let mut options = OpenOptions::new();
+        options.read(true).write(true);
+        let data = PositionalFile::open(data_path(path), options.clone()).await?;
+        options.create(true).truncate(true);
+        let meta = PositionalFile::open(meta_path(path), options).await?;
+        let mut file = BlockFile {
+            header,
+            meta,
+            data,
+            dirty_blocks: BTreeSet::new(),
+            unflushed_bytes: 0,
         };
 
+        for index in 0..file.header.block_info_list.len() as u64 {
+            let block_len = file.header.block_len(index);
+            let checksum = file.block_checksum(index, block_len).await?;
+            let block_info = &mut file.header.block_info_list[index as usize];
+            block_info.used = true;
+            block_info.usage = block_len;
+            block_info.checksum = checksum;
+        }
+        // Note : the data must reach the disk before the header claims the blocks complete.
+        file.data.sync_all().await?;
+        file.header.write_file_header(&file.meta).await?;
+        file.meta.sync_all().await?;
+        Ok(file)
+    }
+
+    /// Moves the cache at `from` to `to`, replacing the cache there.
+    pub async fn rename(from: &str, to: &str) -> std::io::Result<()> {
+        // Note : the data goes first, so the old header never claims blocks the data lacks.
+        tokio::fs::rename(data_path(from), data_path(to)).await?;
+        tokio::fs::rename(meta_path(from), meta_path(to)).await
+    }
+
+    /// Returns the bytes of disk space allocated to the files of the cache at `path`.
+    pub async fn disk_usage(path: &str) -> std::io::Result<u64> {
+        Ok(file_disk_usage(&meta_path(path)).await? + file_disk_usage(&data_path(path)).await?)
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.header.file_size
+    }
+
+    /// Returns what the data was copied from, as set by `set_origin`, empty when it was not.
+    pub fn origin(&self) -> &[u8] {
+        &self.header.origin
+    }
+
+    /// Records what the data is copied from, e.g. the remote file and its version, so a process
+    /// opening the cache later can tell whether the data is still the one it looks for.
+    pub async fn set_origin(&mut self, origin: &[u8]) -> std::io::Result<()> {
+        if origin.len() > MAX_ORIGIN_LEN as usize {
+            return Err(out_of_range(format!("Origin too long: {}", origin.len())));
+        }
+        self.header.origin = origin.to_vec();
+        let mut bytes = Vec::with_capacity(4 + origin.len());
+        self.header.encode_origin(&mut bytes);
+        self.meta
+            .write_all_at(bytes, self.header.origin_pos())
+            .await
+    }
+
+    /// Extends the file to `file_size` bytes, keeping the data written so far. The last block
+    /// keeps the bytes it holds and becomes incomplete if it got longer, so its next download
+    /// resumes after them, and the added blocks are empty.
+    pub async fn grow(&mut self, file_size: u64) -> std::io::Result<()> {
+        if file_size < self.header.file_size {
+            return Err(out_of_range(format!(
+                "Can not shrink from {} to {}",
+                self.header.file_size, file_size
+            )));
+        }
+        let mut header = BlockFileHeader::new(file_size, self.header.block_size)?;
+        self.flush_block_infos().await?;
+        header.origin = self.header.origin.clone();
+        for (index, block_info) in self.header.block_info_list.drain(..).enumerate() {
+            header.block_info_list[index] = block_info;
+        }
+        // Note : the data is extended first, so the header never claims more than it holds.
+        self.data.set_len(file_size).await?;
+        header.write_file_header(&self.meta).await?;
+        self.header = header;
+        Ok(())
+    }
+
+    /// Rewrites the cache at `path` with only its complete blocks. Incomplete blocks are
+    /// dropped, since they are downloaded again from their start anyway. Returns the bytes of
+    /// disk space reclaimed, or None when there is nothing to drop.
+    ///
+    /// Note : the new files replace the old ones by a rename, so handles opened before keep
+    /// reading the old data. Writers must be kept out by the caller.
+    pub async fn compact(path: &str) -> std::io::Result<Option<u64>> {
+        let mut source = BlockFile::open(path, false).await?;
+        if !source.has_incomplete_blocks() {
+            return Ok(None);
+        }
+        let disk_usage_before = BlockFile::disk_usage(path).await?;
+
+        let temp_path = format!("{}.compact", path);
+        let mut target =
+            BlockFile::create(&temp_path, source.file_size(), source.header.block_size).await?;
+        let copied = async {
+            target.set_origin(source.origin()).await?;
+            source.copy_blocks_to(&mut target).await
+        };
+        if let Err(err) = copied.await {
+            drop(target);
+            let _ = BlockFile::remove(&temp_path).await;
+            return Err(err);
+        }
+        drop(target);
+
+        BlockFile::rename(&temp_path, path).await?;
+        Ok(Some(
+            disk_usage_before.saturating_sub(BlockFile::disk_usage(path).await?),
+        ))
+    }
+
+    /// Copies the data of every complete block to the same offsets of `target`.
+    async fn copy_blocks_to(&mut self, target: &mut BlockFile) -> std::io::Result<()> {
+        let block_size = self.header.block_size as u64;
+        let mut buf = vec![0; COPY_BUFFER_SIZE.min(block_size as usize)];
+        for index in 0..self.header.block_info_list.len() {
+            let block_info = &self.header.block_info_list[index];
+            let block_len = self.header.block_len(index as u64);
+            if !block_info.used || block_info.usage < block_len {
+                continue;
+            }
+            let block_begin = index as u64 * block_size;
+            let block_len = block_len as u64;
+            let mut copied = 0;
+            while copied < block_len {
+                let len = buf.len().min((block_len - copied) as usize);
+                let offset = block_begin + copied;
+                let read_size = self.read(&mut buf[..len], offset).await?;
+                target.write(&buf[..read_size], offset).await?;
+                copied += read_size as u64;
+            }
+        }
+        target.sync().await
+    }
+
+    /// Returns the offset and the length of every block which is not complete.
+    pub fn missing_blocks(&self) -> Vec<(u64, u64)> {
+        let block_size = self.header.block_size as u64;
+        (0..self.header.block_info_list.len() as u64)
+            .filter_map(|index| {
+                let block_info = &self.header.block_info_list[index as usize];
+                let block_len = self.header.block_len(index);
+                (!block_info.used || block_info.usage < block_len)
+                    .then_some((index * block_size, block_len as u64))
+            })
+            .collect()
+    }
+
+    /// Writes the data of the complete blocks to `output` at their offsets, which should be a
+    /// file of `file_size()` zeros. Returns the offset and the length of the blocks which are
+    /// missing or do not match their checksum; they are left as zeros.
+    pub async fn export_to(&mut self, output: &mut File) -> std::io::Result<Vec<(u64, u64)>> {
+        let mut missing_blocks = self.missing_blocks();
+        let block_size = self.header.block_size as u64;
+        let mut buf = vec![0; COPY_BUFFER_SIZE.min(block_size as usize)];
+        for index in 0..self.header.block_info_list.len() as u64 {
+            let block_info = &self.header.block_info_list[index as usize];
+            let block_len = self.header.block_len(index);
+            if !block_info.used || block_info.usage < block_len {
+                continue;
+            }
+
+            let block_begin = index * block_size;
+            let block_len = block_len as u64;
+            output.seek(SeekFrom::Start(block_begin)).await?;
+            let mut hasher = crc32fast::Hasher::new();
+            let mut copied = 0;
+            while copied < block_len {
+                let len = COPY_BUFFER_SIZE.min((block_len - copied) as usize);
+                let chunk = self.data.read_exact_at(len, block_begin + copied).await?;
+                output.write_all(&chunk).await?;
+                hasher.update(&chunk);
+                copied += len as u64;
+            }
+
+            if hasher.finalize() != self.header.block_info_list[index as usize].checksum {
+                output.seek(SeekFrom::Start(block_begin)).await?;
+                buf.fill(0);
+                let mut zeroed = 0;
+                while zeroed < block_len {
+                    let len = buf.len().min((block_len - zeroed) as usize);
+                    output.write_all(&buf[..len]).await?;
+                    zeroed += len as u64;
+                }
+                missing_blocks.push((block_begin, block_len));
+            }
+        }
+        missing_blocks.sort();
+        Ok(missing_blocks)
+    }
+
+    /// Returns whether blocks were allocated but not written completely.
+    fn has_incomplete_blocks(&self) -> bool {
+        self.header
+            .block_info_list
+            .iter()
+            .enumerate()
+            .any(|(index, x)| x.used && x.usage < self.header.block_len(index as u64))
+    }
+
+    /// Returns the number of bytes of the file whose blocks are complete.
+    pub fn cached_bytes(&self) -> u64 {
+        self.header
+            .block_info_list
+            .iter()
+            .enumerate()
+            .filter(|(index, x)| x.used && x.usage >= self.header.block_len(*index as u64))
+            .map(|(index, _)| self.header.block_len(index as u64) as u64)
+            .sum()
+    }
+
+    /// Returns the offset of the last block holding data and the number of bytes it holds from
+    /// its start, or None when no block does.
+    pub fn last_written_block(&self) -> Option<(u64, u64)> {
+        let block_size = self.header.block_size as u64;
+        self.header
+            .block_info_list
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, x)| x.used && x.usage > 0)
+            .map(|(index, x)| (index as u64 * block_size, x.usage as u64))
+    }
+
+    pub async fn is_data_ready(&mut self, begin: u64, size: u64) -> std::io::Result<bool> {
+        if begin >= self.header.file_size {
+            return Ok(true);
+        }
+        let size = size.min(self.header.file_size - begin);
+
         let (begin1, end) = self.find_block_info_range(begin, size);
+        self.reload_block_infos(begin1, end).await?;
 
-        let block_info_list = &mut self.header.block_info_list[begin1 as usize..(end + 1) as usize];
-        for block_info in block_info_list.iter_mut() {
-            block_info.reload(&mut self.file).await?;
-            if !block_info.used {
+        for index in begin1..end + 1 {
+            let block_len = self.header.block_len(index);
+            let block_info = &self.header.block_info_list[index as usize];
+            if !block_info.used || block_info.usage < block_len {
                 return Ok(false);
             }
         }
         Ok(true)
     }
 
+    /// Returns the first byte of `begin..end` which is not in the file yet, or the end of the
+    /// range capped to the file size when all of them are. A block only holds the bytes written
+    /// contiguously from its start, so a download broken off in the middle of a block resumes
+    /// right after the last byte it wrote.
+    pub async fn first_missing_byte(&mut self, begin: u64, end: u64) -> std::io::Result<u64> {
+        let end = end.min(self.header.file_size);
+        if begin >= end {
+            return Ok(end);
+        }
+
+        let (begin1, end1) = self.find_block_info_range(begin, end - begin);
+        self.reload_block_infos(begin1, end1).await?;
+        let block_size = self.header.block_size as u64;
+        let mut cursor = begin;
+        for index in begin1..end1 + 1 {
+            let block_info = &self.header.block_info_list[index as usize];
+            let usage = if block_info.used { block_info.usage } else { 0 };
+            let written_end = index * block_size + usage as u64;
+            if written_end <= cursor {
+                return Ok(cursor);
+            }
+            cursor = written_end;
+            if usage < self.header.block_len(index) {
+                break;
+            }
+        }
+        Ok(cursor.min(end))
+    }
+
+    /// Checks the data of the complete blocks overlapping `begin..begin + size` against their
+    /// checksums, skipping the blocks in `verified` and adding the ones which match. A block
+    /// which does not match, e.g. after a bad sector or a torn write, is emptied so it is
+    /// downloaded again. Returns false when such a block was found.
+    pub async fn verify(
+        &mut self,
+        begin: u64,
+        size: u64,
+        verified: &mut HashSet<u64>,
+    ) -> std::io::Result<bool> {
+        if begin >= self.header.file_size {
+            return Ok(true);
+        }
+        let size = size.min(self.header.file_size - begin);
+
+        let (begin1, end) = self.find_block_info_range(begin, size);
+        self.reload_block_infos(begin1, end).await?;
+        let mut all_valid = true;
+        for index in begin1..end + 1 {
+            let block_len = self.header.block_len(index);
+            let block_info = &self.header.block_info_list[index as usize];
+            if !block_info.used || block_info.usage < block_len || verified.contains(&index) {
+                continue;
+            }
+
+            let expected = block_info.checksum;
+            if self.block_checksum(index, block_len).await? == expected {
+                verified.insert(index);
+                continue;
+            }
+            all_valid = false;
+            let block_info = &mut self.header.block_info_list[index as usize];
+            block_info.usage = 0;
+            block_info.checksum = 0;
+            block_info.write(&self.meta).await?;
+        }
+        Ok(all_valid)
+    }
+
+    /// Returns the CRC-32 of the first `len` bytes of a block, as stored on disk.
+    async fn block_checksum(&mut self, index: u64, len: u32) -> std::io::Result<u32> {
+        let block_begin = index * self.header.block_size as u64;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut checked = 0;
+        while checked < len as usize {
+            let chunk_len = COPY_BUFFER_SIZE.min(len as usize - checked);
+            let chunk = self
+                .data
+                .read_exact_at(chunk_len, block_begin + checked as u64)
+                .await?;
+            hasher.update(&chunk);
+            checked += chunk_len;
+        }
+        Ok(hasher.finalize())
+    }
+
     pub async fn read(&mut self, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
         if self.header.file_size < offset {
             return Err(std::io::Error::new(
@@ -286,78 +755,120 @@ impl BlockFile {
             ));
         }
 
-        let header_size = self.header.get_header_size();
-
-        let end = buf.len().min((self.header.file_size - offset) as usize);
-        let mut total_read_size: usize = 0;
-        while total_read_size < end {
-            let offset = offset + total_read_size as u64;
-            let block_size = self.header.block_size as u64;
-            let block_cursor = offset % block_size;
-            let block_info = self.header.get_mut_block_info(offset)?;
-
-            block_info.reload(&mut self.file).await?;
-            BlockFile::try_move_cursor(
-                &mut self.file,
-                header_size,
-                block_size,
-                block_info,
-                block_cursor,
-            )
-            .await?;
-
-            let end_index = buf
-                .len()
-                .min(total_read_size + self.header.block_size as usize - block_cursor as usize);
-            total_read_size += self.file.read(&mut buf[total_read_size..end_index]).await?;
+        let remaining = usize::try_from(self.header.file_size - offset).unwrap_or(usize::MAX);
+        let end = buf.len().min(remaining);
+        if end > 0 {
+            let (begin1, end1) = self.find_block_info_range(offset, end as u64);
+            self.reload_block_infos(begin1, end1).await?;
         }
-        Ok(total_read_size)
+        let block_size = self.header.block_size as u64;
+        let mut block_offset = offset;
+        while block_offset < offset + end as u64 {
+            let block_info = self.header.get_mut_block_info(block_offset)?;
+            BlockFile::block_pos(block_size, block_info, block_offset % block_size)?;
+            block_offset = (block_offset / block_size + 1).saturating_mul(block_size);
+        }
+        let data = self.data.clone();
+        let read = blocking(move || {
+            let mut read = vec![0; end];
+            read_data(data.std(), &mut read, offset)?;
+            Ok(read)
+        })
+        .await?;
+        buf[..end].copy_from_slice(&read);
+        Ok(end)
     }
 
     pub async fn write(&mut self, buf: &[u8], offset: u64) -> std::io::Result<usize> {
-        let header_size = self.header.get_header_size();
-
+        if offset.checked_add(buf.len() as u64).is_none() {
+            return Err(out_of_range(format!("Invalid position {}", offset)));
+        }
         let mut total_wrote_size = 0;
         while total_wrote_size < buf.len() {
             let offset = offset + total_wrote_size as u64;
             let block_size = self.header.block_size as u64;
             let block_cursor = offset % block_size;
+            let block_len = self.header.block_len(offset / block_size);
             let block_info = self.header.get_mut_or_allocate_block(offset)?;
-
-            BlockFile::try_move_cursor(
-                &mut self.file,
-                header_size,
-                block_size,
-                block_info,
-                block_cursor,
-            )
-            .await?;
+            let pos = BlockFile::block_pos(block_size, block_info, block_cursor)?;
 
             let end_index = buf
                 .len()
-                .min((total_wrote_size + block_size as usize - block_cursor as usize) as usize);
-            let wrote_size = self
-                .file
-                .write(&buf[total_wrote_size..end_index as usize])
+                .min(total_wrote_size + block_size as usize - block_cursor as usize);
+            let wrote_size = end_index - total_wrote_size;
+            self.data
+                .write_all_at(buf[total_wrote_size..end_index].to_vec(), pos)
                 .await?;
 
-            block_info.usage += wrote_size as u32;
+            // Note : usage counts the bytes written contiguously from the start of the block, so a
+            // block interrupted in the middle of a download is never taken as complete. Bytes
+            // below usage are rewritten with the same data, so only new bytes extend the checksum.
+            let was_complete = block_info.usage >= block_len;
+            let write_end = block_cursor + wrote_size as u64;
+            if block_cursor <= block_info.usage as u64 && write_end > block_info.usage as u64 {
+                let new_bytes_begin =
+                    total_wrote_size + (block_info.usage as u64 - block_cursor) as usize;
+                let mut hasher = crc32fast::Hasher::new_with_initial(block_info.checksum);
+                hasher.update(&buf[new_bytes_begin..total_wrote_size + wrote_size]);
+                block_info.checksum = hasher.finalize();
+                block_info.usage = write_end as u32;
+            }
             total_wrote_size += wrote_size;
-            block_info.write(&mut self.file).await?;
+            let index = offset / block_size;
+            if !was_complete && block_info.usage >= block_len {
+                // Note : the data must reach the disk before the header claims the block complete.
+                self.data.sync_data().await?;
+                block_info.write(&self.meta).await?;
+                self.dirty_blocks.remove(&index);
+            } else if !was_complete {
+                self.dirty_blocks.insert(index);
+                self.unflushed_bytes += wrote_size;
+            }
+        }
+        if self.unflushed_bytes >= BLOCK_INFO_FLUSH_BYTES {
+            self.flush_block_infos().await?;
         }
         Ok(total_wrote_size)
     }
 
+    /// Writes the infos of the incomplete blocks written since the last flush to the header.
+    /// Writes only keep them in memory, so a download calls this when it ends; a handle dropped
+    /// without it merely downloads the unflushed bytes again.
+    pub async fn flush_block_infos(&mut self) -> std::io::Result<()> {
+        for index in self.dirty_blocks.iter() {
+            self.header.block_info_list[*index as usize]
+                .write(&self.meta)
+                .await?;
+        }
+        self.dirty_blocks.clear();
+        self.unflushed_bytes = 0;
+        Ok(())
+    }
+
+    pub async fn sync(&mut self) -> std::io::Result<()> {
+        self.flush_block_infos().await?;
+        self.data.sync_all().await?;
+        self.meta.sync_all().await
+    }
+
+    /// Reads the block infos `begin..=end` from the header again, since other handles may have
+    /// written them, after writing the ones of this handle.
+    async fn reload_block_infos(&mut self, begin: u64, end: u64) -> std::io::Result<()> {
+        self.flush_block_infos().await?;
+        self.header.reload_block_infos(&self.meta, begin, end).await
+    }
+
     pub fn calc_block_range_from(&self, offset: u64, size: u64) -> (u64, u64) {
         let (begin, end) = self.find_block_info_range(offset, size);
+        let block_size = self.header.block_size as u64;
         (
-            begin * self.header.block_size as u64,
-            end * self.header.block_size as u64 + self.header.block_size as u64,
+            begin * block_size,
+            end.saturating_add(1).saturating_mul(block_size),
         )
     }
 
     fn find_block_info_range(&self, offset: u64, size: u64) -> (u64, u64) {
-        let end = if size == 0 { offset } else { offset + size - 1 };
+        let end = offset.saturating_add(size.saturating_sub(1));
 
         let begin_block_info_index = offset / self.header.block_size as u64;
         let end_block_info_index = end / self.header.block_size as u64;
@@ -365,36 +876,124 @@ impl BlockFile {
         (begin_block_info_index, end_block_info_index)
     }
 
-    async fn try_move_cursor(
-        file: &mut File,
-        header_size: u64,
+    /// Returns the position in the data file of `block_cursor` in a block, which must have been
+    /// written to.
+    fn block_pos(
         block_size: u64,
         block_info: &BlockInfo,
         block_cursor: u64,
-    ) -> std::io::Result<()> {
+    ) -> std::io::Result<u64> {
         if !block_info.used {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidInput,
                 format!(
                     "access not exists block {} {} {} {}",
-                    block_info.used, block_info.block_index, block_info.usage, block_cursor
+                    block_info.used, block_info.block_info_index, block_info.usage, block_cursor
                 ),
             ));
         }
 
-        let block_pos = block_info.block_index as u64 * block_size;
         let block_cursor_pos = block_cursor % block_size;
-        let pos = header_size + block_pos + block_cursor_pos;
-        file.seek(SeekFrom::Start(pos)).await?;
-        Ok(())
+        block_info
+            .block_info_index
+            .checked_mul(block_size)
+            .and_then(|x| x.checked_add(block_cursor_pos))
+            .ok_or_else(|| out_of_range(format!("Invalid block {}", block_info.block_info_index)))
+    }
+
+    /// Returns a reader sharing the files of this handle.
+    pub fn reader(&self) -> BlockReader {
+        BlockReader {
+            meta: self.meta.clone(),
+            data: self.data.clone(),
+            file_size: self.header.file_size,
+            block_size: self.header.block_size,
+        }
+    }
+}
+
+/// Fills `buf` with the data at `offset`, which lies in blocks written to.
+fn read_data(data: &std::fs::File, buf: &mut [u8], offset: u64) -> std::io::Result<()> {
+    data.read_exact_at(buf, offset)
+        .map_err(|err| match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("Block data is missing at {}", offset),
+            ),
+            _ => err,
+        })
+}
+
+/// A read-only handle on a cache which many reads share. Reads are positional, so they share no
+/// file cursor and run in parallel instead of waiting for each other, and the header is only
+/// parsed once, when the reader is opened.
+#[derive(Clone)]
+pub struct BlockReader {
+    meta: PositionalFile,
+    data: PositionalFile,
+    file_size: u64,
+    block_size: u32,
+}
+
+impl BlockReader {
+    pub async fn open(path: &str) -> std::io::Result<BlockReader> {
+        Ok(BlockFile::open(path, false).await?.reader())
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Reads up to `size` bytes at `offset`, fewer at the end of the file. As with
+    /// `BlockFile::read`, every block of the range must have been written to.
+    pub async fn read(&self, offset: u64, size: usize) -> std::io::Result<Vec<u8>> {
+        let reader = self.clone();
+        blocking(move || reader.read_blocking(offset, size)).await
+    }
+
+    fn read_blocking(&self, offset: u64, size: usize) -> std::io::Result<Vec<u8>> {
+        if self.file_size < offset {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid position",
+            ));
+        }
+        let remaining = usize::try_from(self.file_size - offset).unwrap_or(usize::MAX);
+        let mut buf = vec![0; size.min(remaining)];
+        if buf.is_empty() {
+            return Ok(buf);
+        }
+
+        // Note : the block infos are read on every call, since writers update them meanwhile.
+        let block_size = self.block_size as u64;
+        let begin = offset / block_size;
+        let end = (offset + buf.len() as u64 - 1) / block_size;
+        let mut bytes = vec![0; ((end - begin + 1) * BlockInfo::size()) as usize];
+        self.meta
+            .std()
+            .read_exact_at(&mut bytes, BlockInfo::pos(begin))?;
+        for (i, chunk) in bytes.chunks_exact(BlockInfo::size() as usize).enumerate() {
+            let block_info = BlockInfo::decode(begin + i as u64, chunk);
+            if !block_info.used {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("access not exists block {}", block_info.block_info_index),
+                ));
+            }
+        }
+        read_data(self.data.std(), &mut buf, offset)?;
+        Ok(buf)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use crate::blockfile::BlockFile;
+    use crate::blockfile::{BlockFile, BlockReader};
+    use proptest::prelude::{any, prop, Just, ProptestConfig, Strategy};
+    use proptest::proptest;
     use rand::prelude::*;
     use rand::seq::SliceRandom;
+    use std::collections::HashSet;
 
     #[tokio::test]
     async fn block_file_test() {
@@ -429,6 +1028,7 @@ mod test {
                         break;
                     }
                 }
+                file.flush_block_infos().await.unwrap();
                 drop(file);
 
                 let mut file = BlockFile::open("./test", false).await.unwrap();
@@ -458,4 +1058,358 @@ mod test {
             }
         }
     }
+
+    #[derive(Debug, Clone)]
+    struct WriteOp {
+        offset: u64,
+        data: Vec<u8>,
+    }
+
+    #[tokio::test]
+    async fn interrupted_block_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        let path = path.to_str().unwrap();
+
+        let mut file = BlockFile::create(path, 40, 16).await.unwrap();
+        file.write(&[1; 16], 0).await.unwrap();
+        file.write(&[2; 10], 16).await.unwrap();
+        file.write(&[3; 8], 32).await.unwrap();
+        drop(file);
+
+        // Note : a restarted process only trusts the blocks which were written completely.
+        let mut file = BlockFile::open(path, true).await.unwrap();
+        assert!(file.is_data_ready(0, 16).await.unwrap());
+        assert!(!file.is_data_ready(16, 16).await.unwrap());
+        assert!(file.is_data_ready(32, 8).await.unwrap());
+        assert_eq!(file.cached_bytes(), 24);
+
+        file.write(&[2; 16], 16).await.unwrap();
+        assert!(file.is_data_ready(0, 40).await.unwrap());
+        assert_eq!(file.cached_bytes(), 40);
+    }
+
+    #[tokio::test]
+    async fn first_missing_byte_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        let path = path.to_str().unwrap();
+
+        let mut file = BlockFile::create(path, 40, 16).await.unwrap();
+        assert_eq!(file.first_missing_byte(0, 40).await.unwrap(), 0);
+        file.write(&[1; 16], 0).await.unwrap();
+        file.write(&[2; 10], 16).await.unwrap();
+        assert_eq!(file.first_missing_byte(0, 40).await.unwrap(), 26);
+        assert_eq!(file.first_missing_byte(4, 12).await.unwrap(), 12);
+        assert_eq!(file.first_missing_byte(20, 40).await.unwrap(), 26);
+        assert_eq!(file.first_missing_byte(30, 40).await.unwrap(), 30);
+        assert_eq!(file.first_missing_byte(32, 40).await.unwrap(), 32);
+
+        file.write(&[2; 6], 26).await.unwrap();
+        file.write(&[3; 8], 32).await.unwrap();
+        assert_eq!(file.first_missing_byte(0, 48).await.unwrap(), 40);
+    }
+
+    #[tokio::test]
+    async fn grow_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        let path = path.to_str().unwrap();
+
+        let mut file = BlockFile::create(path, 24, 16).await.unwrap();
+        file.write(&[1; 16], 0).await.unwrap();
+        file.write(&[2; 8], 16).await.unwrap();
+        assert_eq!(file.cached_bytes(), 24);
+        assert_eq!(file.last_written_block(), Some((16, 8)));
+
+        file.grow(40).await.unwrap();
+        drop(file);
+        let mut file = BlockFile::open(path, true).await.unwrap();
+        assert_eq!(file.file_size(), 40);
+        assert!(file.is_data_ready(0, 16).await.unwrap());
+        assert!(!file.is_data_ready(16, 8).await.unwrap());
+        assert_eq!(file.first_missing_byte(0, 40).await.unwrap(), 24);
+
+        file.write(&[3; 8], 24).await.unwrap();
+        file.write(&[4; 8], 32).await.unwrap();
+        assert!(file.is_data_ready(0, 40).await.unwrap());
+        let mut buf = vec![0; 16];
+        file.read(&mut buf, 16).await.unwrap();
+        assert_eq!(buf, [[2; 8], [3; 8]].concat());
+        assert!(file.grow(32).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn origin_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        let path = path.to_str().unwrap();
+
+        let mut file = BlockFile::create(path, 24, 16).await.unwrap();
+        assert_eq!(file.origin(), b"");
+        file.set_origin(b"/talk.mkv v1").await.unwrap();
+        file.write(&[1; 16], 0).await.unwrap();
+        file.flush_block_infos().await.unwrap();
+        drop(file);
+
+        let mut file = BlockFile::open(path, true).await.unwrap();
+        assert_eq!(file.origin(), b"/talk.mkv v1");
+        file.set_origin(b"/talk.mkv v2").await.unwrap();
+        file.grow(40).await.unwrap();
+        file.write(&[2; 4], 16).await.unwrap();
+        file.flush_block_infos().await.unwrap();
+        drop(file);
+
+        // Note : the origin moves behind the added block infos, and survives a compaction.
+        assert!(BlockFile::compact(path).await.unwrap().is_some());
+        let file = BlockFile::open(path, false).await.unwrap();
+        assert_eq!(file.origin(), b"/talk.mkv v2");
+        assert!(BlockFile::create(path, 24, 16)
+            .await
+            .unwrap()
+            .set_origin(&vec![0; 64 * 1024 + 1])
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn block_reader_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        let path = path.to_str().unwrap();
+
+        let mut file = BlockFile::create(path, 40, 16).await.unwrap();
+        let text: Vec<u8> = (0..32).collect();
+        file.write(&text, 0).await.unwrap();
+        file.flush_block_infos().await.unwrap();
+
+        let reader = BlockReader::open(path).await.unwrap();
+        assert_eq!(reader.file_size(), 40);
+        let reads = (0..16).map(|offset| {
+            let reader = reader.clone();
+            tokio::spawn(async move { reader.read(offset, 8).await.unwrap() })
+        });
+        for (offset, read) in reads.enumerate() {
+            assert_eq!(read.await.unwrap(), text[offset..offset + 8]);
+        }
+        assert!(reader.read(30, 4).await.is_err());
+
+        // Note : blocks written after the reader was opened are seen by it.
+        file.write(&[7; 8], 32).await.unwrap();
+        file.flush_block_infos().await.unwrap();
+        assert_eq!(reader.read(36, 8).await.unwrap(), vec![7; 4]);
+        assert_eq!(reader.read(40, 8).await.unwrap(), Vec::<u8>::new());
+        assert!(reader.read(41, 8).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn checksum_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        let path = path.to_str().unwrap();
+
+        let mut file = BlockFile::create(path, 40, 16).await.unwrap();
+        file.write(&[1; 10], 0).await.unwrap();
+        file.write(&[1; 6], 10).await.unwrap();
+        file.write(&[2; 16], 16).await.unwrap();
+        drop(file);
+
+        let mut file = BlockFile::open(path, false).await.unwrap();
+        let mut verified = HashSet::new();
+        assert!(file.verify(0, 40, &mut verified).await.unwrap());
+        assert_eq!(verified, HashSet::from([0, 1]));
+        drop(file);
+
+        // Note : flip a byte of the second block on disk, as a bad sector would.
+        let data_path = format!("{}.data", path);
+        let mut bytes = std::fs::read(&data_path).unwrap();
+        bytes[16 + 3] ^= 0xFF;
+        std::fs::write(&data_path, bytes).unwrap();
+
+        let mut file = BlockFile::open(path, true).await.unwrap();
+        let mut verified = HashSet::new();
+        assert!(!file.verify(0, 40, &mut verified).await.unwrap());
+        assert_eq!(verified, HashSet::from([0]));
+        assert!(file.is_data_ready(0, 16).await.unwrap());
+        assert!(!file.is_data_ready(16, 16).await.unwrap());
+
+        file.write(&[2; 16], 16).await.unwrap();
+        assert!(file.verify(0, 40, &mut verified).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn import_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let content: Vec<u8> = (0..40).collect();
+        std::fs::write(&source, &content).unwrap();
+        let path = dir.path().join("cache");
+        let path = path.to_str().unwrap();
+
+        drop(
+            BlockFile::import(path, source.to_str().unwrap(), 16)
+                .await
+                .unwrap(),
+        );
+
+        let mut file = BlockFile::open(path, false).await.unwrap();
+        assert_eq!(file.file_size(), 40);
+        assert_eq!(file.cached_bytes(), 40);
+        assert!(file.is_data_ready(0, 40).await.unwrap());
+        assert!(file.verify(0, 40, &mut HashSet::new()).await.unwrap());
+        let mut buf = vec![0; 40];
+        file.read(&mut buf, 0).await.unwrap();
+        assert_eq!(buf, content);
+    }
+
+    #[tokio::test]
+    async fn compact_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        let path = path.to_str().unwrap();
+
+        let mut file = BlockFile::create(path, 40, 16).await.unwrap();
+        file.write(&[3; 8], 32).await.unwrap();
+        file.write(&[2; 10], 16).await.unwrap();
+        file.write(&[1; 16], 0).await.unwrap();
+        file.flush_block_infos().await.unwrap();
+        drop(file);
+
+        assert!(BlockFile::compact(path).await.unwrap().is_some());
+        assert!(BlockFile::compact(path).await.unwrap().is_none());
+
+        let mut file = BlockFile::open(path, false).await.unwrap();
+        assert!(!file.has_incomplete_blocks());
+        assert!(file.is_data_ready(0, 16).await.unwrap());
+        assert!(!file.is_data_ready(16, 16).await.unwrap());
+        let mut buf = [0; 8];
+        file.read(&mut buf, 32).await.unwrap();
+        assert_eq!(buf, [3; 8]);
+        file.read(&mut buf, 8).await.unwrap();
+        assert_eq!(buf, [1; 8]);
+
+        // Note : the data file holds the data at its offset, with zeros where nothing is cached.
+        let data = std::fs::read(format!("{}.data", path)).unwrap();
+        assert_eq!(
+            data,
+            [[1; 16], [0; 16]]
+                .concat()
+                .into_iter()
+                .chain([3; 8])
+                .collect::<Vec<u8>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn large_offset_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        let path = path.to_str().unwrap();
+
+        // Note : the data file is sparse, only the header and two short writes take space.
+        let file_size = 8 << 40;
+        let block_size = 1 << 30;
+        let mut file = BlockFile::create(path, file_size, block_size)
+            .await
+            .unwrap();
+        file.write(b"middle", 5 << 30).await.unwrap();
+        file.write(b"end", file_size - 3).await.unwrap();
+        file.flush_block_infos().await.unwrap();
+        drop(file);
+
+        let mut file = BlockFile::open(path, false).await.unwrap();
+        let mut buf = [0; 6];
+        file.read(&mut buf, 5 << 30).await.unwrap();
+        assert_eq!(&buf, b"middle");
+        let mut buf = [0; 3];
+        file.read(&mut buf, file_size - 3).await.unwrap();
+        assert_eq!(&buf, b"end");
+        assert_eq!(
+            file.calc_block_range_from(file_size - 1, 1),
+            (file_size - block_size as u64, file_size)
+        );
+    }
+
+    fn write_ops(file_size: u64) -> impl Strategy<Value = Vec<WriteOp>> {
+        let write_op = (0..file_size).prop_flat_map(move |offset| {
+            let max_len = (file_size - offset).min(100) as usize;
+            prop::collection::vec(any::<u8>(), 1..=max_len)
+                .prop_map(move |data| WriteOp { offset, data })
+        });
+        prop::collection::vec(write_op, 1..32)
+    }
+
+    fn block_file_params() -> impl Strategy<Value = (u64, u32, Vec<WriteOp>)> {
+        (1u64..2048, 1u32..128).prop_flat_map(|(file_size, block_size)| {
+            (Just(file_size), Just(block_size), write_ops(file_size))
+        })
+    }
+
+    /// Returns the ranges of consecutive bytes which have been written.
+    fn written_ranges(model: &[Option<u8>]) -> Vec<(usize, usize)> {
+        let mut ranges = Vec::new();
+        let mut begin = None;
+        for (i, byte) in model.iter().chain([None].iter()).enumerate() {
+            match (byte, begin) {
+                (Some(_), None) => begin = Some(i),
+                (None, Some(b)) => {
+                    ranges.push((b, i));
+                    begin = None;
+                }
+                _ => {}
+            }
+        }
+        ranges
+    }
+
+    async fn verify_written_ranges(file: &mut BlockFile, model: &[Option<u8>]) {
+        for (begin, end) in written_ranges(model) {
+            let mut buf = vec![0; end - begin];
+            let read_size = file.read(&mut buf, begin as u64).await.unwrap();
+            assert_eq!(read_size, end - begin);
+            let expected: Vec<u8> = model[begin..end].iter().map(|x| x.unwrap()).collect();
+            assert_eq!(buf, expected);
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn block_file_round_trip_test((file_size, block_size, ops) in block_file_params()) {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("blockfile");
+            let path = path.to_str().unwrap();
+
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            runtime.block_on(async {
+                drop(BlockFile::create(path, file_size, block_size).await.unwrap());
+
+                let mut model: Vec<Option<u8>> = vec![None; file_size as usize];
+                let mut file = BlockFile::open(path, true).await.unwrap();
+                for op in ops {
+                    let wrote_size = file.write(&op.data, op.offset).await.unwrap();
+                    assert_eq!(wrote_size, op.data.len());
+                    for (i, byte) in op.data.iter().enumerate() {
+                        model[op.offset as usize + i] = Some(*byte);
+                    }
+
+                    let mut buf = vec![0; op.data.len()];
+                    file.read(&mut buf, op.offset).await.unwrap();
+                    assert_eq!(buf, op.data);
+                }
+                drop(file);
+
+                let mut file = BlockFile::open(path, false).await.unwrap();
+                verify_written_ranges(&mut file, &model).await;
+                for (index, block) in model.chunks(block_size as usize).enumerate() {
+                    let offset = index as u64 * block_size as u64;
+                    let complete = block.iter().all(|x| x.is_some());
+                    if file.is_data_ready(offset, 1).await.unwrap() {
+                        assert!(complete);
+                    }
+                }
+            });
+        }
+    }
 }
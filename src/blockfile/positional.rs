@@ -0,0 +1,75 @@
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    os::unix::fs::FileExt,
+    sync::Arc,
+};
+
+/// A file only accessed at explicit offsets, with `pread` and `pwrite`. Having no cursor, it can
+/// be shared by concurrent operations without one moving the position of another, and every
+/// access is one syscall instead of a seek and a read or a write. The calls run on the blocking
+/// pool, as the ones of `tokio::fs` do.
+#[derive(Clone)]
+pub(super) struct PositionalFile {
+    file: Arc<File>,
+}
+
+impl PositionalFile {
+    pub async fn open(path: String, options: OpenOptions) -> io::Result<PositionalFile> {
+        let file = blocking(move || options.open(path)).await?;
+        Ok(PositionalFile {
+            file: Arc::new(file),
+        })
+    }
+
+    /// The file itself, for calls made on a blocking thread already.
+    pub fn std(&self) -> &File {
+        &self.file
+    }
+
+    pub async fn read_exact_at(&self, len: usize, pos: u64) -> io::Result<Vec<u8>> {
+        let file = self.file.clone();
+        blocking(move || {
+            let mut buf = vec![0; len];
+            file.read_exact_at(&mut buf, pos)?;
+            Ok(buf)
+        })
+        .await
+    }
+
+    pub async fn write_all_at(&self, buf: Vec<u8>, pos: u64) -> io::Result<()> {
+        let file = self.file.clone();
+        blocking(move || file.write_all_at(&buf, pos)).await
+    }
+
+    pub async fn set_len(&self, len: u64) -> io::Result<()> {
+        let file = self.file.clone();
+        blocking(move || file.set_len(len)).await
+    }
+
+    pub async fn file_len(&self) -> io::Result<u64> {
+        let file = self.file.clone();
+        blocking(move || Ok(file.metadata()?.len())).await
+    }
+
+    pub async fn sync_all(&self) -> io::Result<()> {
+        let file = self.file.clone();
+        blocking(move || file.sync_all()).await
+    }
+
+    pub async fn sync_data(&self) -> io::Result<()> {
+        let file = self.file.clone();
+        blocking(move || file.sync_data()).await
+    }
+}
+
+/// Runs `f` on the blocking pool.
+pub(super) async fn blocking<T, F>(f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?
+}
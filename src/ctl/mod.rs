@@ -0,0 +1,269 @@
+use std::{
+    fmt::Display,
+    io,
+    os::unix::{ffi::OsStrExt, fs::DirBuilderExt},
+    path::{Path, PathBuf},
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{UnixListener, UnixStream},
+};
+
+use crate::{
+    fs::{self, MountHandle},
+    hash::fnv1a,
+};
+
+/// A request sent to a running mount through its ctl socket, one line per connection. The
+/// response is `ok` or `error` followed by a message, which may span lines until EOF.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Request {
+    Invalidate(String),
+    Stats,
+    Prefetch(String),
+    Pin(String),
+    PinPause,
+    PinResume,
+    PinStatus,
+    /// Lists the operations in flight, to tell what a hanging mount waits for.
+    Ops,
+    ExportManifest,
+    /// Absolute path of a manifest file, read by the mount.
+    ImportManifest(String),
+    CompactCache,
+    /// Remote path and absolute path of a local copy of the file, separated by a tab on the
+    /// wire since both may contain spaces.
+    ImportFile(String, String),
+    /// Writes the cache to disk, then unmounts. With force, the mount is unmounted even when
+    /// the cache can not be written.
+    Unmount {
+        force: bool,
+    },
+}
+
+#[derive(Debug)]
+pub enum CtlError {
+    IO(io::Error),
+    InvalidRequest(String),
+    Failed(String),
+}
+
+impl Request {
+    fn parse(line: &str) -> Result<Request, CtlError> {
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        match command {
+            "invalidate" if !argument.is_empty() => Ok(Request::Invalidate(argument.to_string())),
+            "stats" => Ok(Request::Stats),
+            "prefetch" if !argument.is_empty() => Ok(Request::Prefetch(argument.to_string())),
+            "pin" if !argument.is_empty() => Ok(Request::Pin(argument.to_string())),
+            "pin-pause" => Ok(Request::PinPause),
+            "pin-resume" => Ok(Request::PinResume),
+            "pin-status" => Ok(Request::PinStatus),
+            "ops" => Ok(Request::Ops),
+            "export-manifest" => Ok(Request::ExportManifest),
+            "import-manifest" if argument.starts_with('/') => {
+                Ok(Request::ImportManifest(argument.to_string()))
+            }
+            "compact-cache" => Ok(Request::CompactCache),
+            "import-file" => match argument.split_once('\t') {
+                Some((path, local_path))
+                    if path.starts_with('/') && local_path.starts_with('/') =>
+                {
+                    Ok(Request::ImportFile(
+                        path.to_string(),
+                        local_path.to_string(),
+                    ))
+                }
+                _ => Err(CtlError::InvalidRequest(line.to_string())),
+            },
+            "unmount" if argument.is_empty() => Ok(Request::Unmount { force: false }),
+            "unmount" if argument == "force" => Ok(Request::Unmount { force: true }),
+            _ => Err(CtlError::InvalidRequest(line.to_string())),
+        }
+    }
+
+    fn to_line(&self) -> String {
+        match self {
+            Request::Invalidate(path) => format!("invalidate {}\n", path),
+            Request::Stats => "stats\n".to_string(),
+            Request::Prefetch(path) => format!("prefetch {}\n", path),
+            Request::Pin(path) => format!("pin {}\n", path),
+            Request::PinPause => "pin-pause\n".to_string(),
+            Request::PinResume => "pin-resume\n".to_string(),
+            Request::PinStatus => "pin-status\n".to_string(),
+            Request::Ops => "ops\n".to_string(),
+            Request::ExportManifest => "export-manifest\n".to_string(),
+            Request::ImportManifest(path) => format!("import-manifest {}\n", path),
+            Request::CompactCache => "compact-cache\n".to_string(),
+            Request::ImportFile(path, local_path) => {
+                format!("import-file {}\t{}\n", path, local_path)
+            }
+            Request::Unmount { force: false } => "unmount\n".to_string(),
+            Request::Unmount { force: true } => "unmount force\n".to_string(),
+        }
+    }
+}
+
+/// Returns the ctl socket path of the mount at `mount_path`. The mount path is hashed because
+/// unix socket paths are limited to about 100 bytes.
+pub fn socket_path(mount_path: &Path) -> io::Result<PathBuf> {
+    let mount_path = std::fs::canonicalize(mount_path)?;
+    let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime_dir) => PathBuf::from(runtime_dir).join("fusedav-rs"),
+        None => std::env::temp_dir().join(format!("fusedav-rs-{}", unsafe { libc::getuid() })),
+    };
+    Ok(dir.join(format!(
+        "{:016x}.sock",
+        fnv1a(mount_path.as_os_str().as_bytes())
+    )))
+}
+
+/// Binds the ctl socket, replacing a socket left behind by a previous process.
+pub fn bind(socket_path: &Path) -> io::Result<UnixListener> {
+    if let Some(dir) = socket_path.parent() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+    if socket_path.exists() {
+        std::fs::remove_file(socket_path)?;
+    }
+    UnixListener::bind(socket_path)
+}
+
+/// Serves requests until the listener fails.
+pub async fn serve(listener: UnixListener, mount_handle: MountHandle) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let mount_handle = mount_handle.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle_connection(stream, mount_handle).await {
+                        eprintln!("Ctl connection error: {}", err);
+                    }
+                });
+            }
+            Err(err) => {
+                eprintln!("Ctl socket error: {}", err);
+                return;
+            }
+        }
+    }
+}
+
+async fn handle_connection(stream: UnixStream, mount_handle: MountHandle) -> io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(reader).read_line(&mut line).await?;
+
+    let response = match Request::parse(line.trim_end_matches('\n')) {
+        Ok(request) => execute(request, &mount_handle).await,
+        Err(err) => Err(err.to_string()),
+    };
+    let response = match response {
+        Ok(message) => format!("ok {}\n", message),
+        Err(message) => format!("error {}\n", message),
+    };
+    writer.write_all(response.as_bytes()).await
+}
+
+async fn execute(request: Request, mount_handle: &MountHandle) -> Result<String, String> {
+    match request {
+        Request::Invalidate(path) => mount_handle
+            .invalidate(&path)
+            .await
+            .map(|_| format!("invalidated {}", path))
+            .map_err(|e| format!("{:?}", e)),
+        Request::Stats => Ok(mount_handle.stats().await.to_prometheus()),
+        Request::Prefetch(path) => mount_handle
+            .prefetch(&path)
+            .await
+            .map(|(dirs, entries)| format!("listed {} directories, {} entries", dirs, entries))
+            .map_err(|e| format!("{:?}", e)),
+        Request::Pin(path) => mount_handle
+            .pin(&path)
+            .await
+            .map(|_| format!("pinned {}", path))
+            .map_err(|e| format!("{:?}", e)),
+        Request::PinPause => {
+            mount_handle.pause_pins();
+            Ok(mount_handle.pin_status().to_string())
+        }
+        Request::PinResume => {
+            mount_handle.resume_pins();
+            Ok(mount_handle.pin_status().to_string())
+        }
+        Request::PinStatus => Ok(mount_handle.pin_status().to_string()),
+        Request::Ops => Ok(mount_handle.dump_ops()),
+        Request::ExportManifest => Ok(mount_handle
+            .export_manifest()
+            .await
+            .iter()
+            .map(|entry| format!("{}\n", entry.to_line()))
+            .collect()),
+        Request::ImportManifest(path) => {
+            let text = tokio::fs::read_to_string(&path)
+                .await
+                .map_err(|e| format!("{}: {}", path, e))?;
+            let entries = fs::parse_manifest(&text)
+                .map_err(|line| format!("{}:{}: malformed manifest line", path, line))?;
+            Ok(mount_handle.import_manifest(entries).await.to_string())
+        }
+        Request::CompactCache => mount_handle
+            .compact_cache()
+            .await
+            .map(|(count, bytes)| {
+                format!("compacted {} cache files, reclaimed {} bytes", count, bytes)
+            })
+            .map_err(|e| format!("{:?}", e)),
+        Request::ImportFile(path, local_path) => mount_handle
+            .adopt(&path, &local_path)
+            .await
+            .map(|_| format!("imported {} from {}", path, local_path))
+            .map_err(|e| format!("{:?}", e)),
+        Request::Unmount { force } => mount_handle
+            .request_unmount(force)
+            .await
+            .map(|_| "cache written, unmounting".to_string())
+            .map_err(|e| format!("{:?}", e)),
+    }
+}
+
+/// Sends a request to the mount at `mount_path` and returns the message of the response.
+pub async fn send(mount_path: &Path, request: &Request) -> Result<String, CtlError> {
+    let socket_path = socket_path(mount_path).map_err(|e| CtlError::IO(e))?;
+    let stream = UnixStream::connect(&socket_path)
+        .await
+        .map_err(|e| CtlError::IO(e))?;
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(request.to_line().as_bytes())
+        .await
+        .map_err(|e| CtlError::IO(e))?;
+
+    let mut response = String::new();
+    BufReader::new(reader)
+        .read_to_string(&mut response)
+        .await
+        .map_err(|e| CtlError::IO(e))?;
+    let response = response.trim_end_matches('\n');
+    match response.split_once(' ') {
+        Some(("ok", message)) => Ok(message.to_string()),
+        Some(("error", message)) => Err(CtlError::Failed(message.to_string())),
+        _ => Err(CtlError::InvalidRequest(response.to_string())),
+    }
+}
+
+impl Display for CtlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CtlError::IO(e) => write!(f, "IOError: {}", e),
+            CtlError::InvalidRequest(line) => write!(f, "InvalidRequest: {}", line),
+            CtlError::Failed(message) => write!(f, "Failed: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for CtlError {}
@@ -0,0 +1,114 @@
+use std::{
+    fmt::Display,
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+use fuser::FileAttr;
+
+/// With `relatime`, an atime newer than the mtime is still updated once it is this old.
+const RELATIME_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// When reads update the access time of files, as the mount options of the same names.
+///
+/// The access times are only kept in memory; they start at the mtime of the file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AtimeMode {
+    /// Reads never update the access time.
+    NoAtime,
+    /// Reads update the access time when it is not newer than the mtime, or older than a day,
+    /// so tools can still tell whether a file was read since it changed.
+    #[default]
+    RelAtime,
+    /// Every read updates the access time.
+    Strict,
+}
+
+impl FromStr for AtimeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "noatime" => Ok(AtimeMode::NoAtime),
+            "relatime" => Ok(AtimeMode::RelAtime),
+            "strict" => Ok(AtimeMode::Strict),
+            _ => Err(format!(
+                "invalid atime mode {:?}, expected noatime, relatime or strict",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for AtimeMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AtimeMode::NoAtime => write!(f, "noatime"),
+            AtimeMode::RelAtime => write!(f, "relatime"),
+            AtimeMode::Strict => write!(f, "strict"),
+        }
+    }
+}
+
+impl AtimeMode {
+    /// Returns the access time of `attr` after a read at `now`, or `None` if it stays as is.
+    pub(super) fn read_atime(&self, attr: &FileAttr, now: SystemTime) -> Option<SystemTime> {
+        let update = match self {
+            AtimeMode::NoAtime => false,
+            AtimeMode::RelAtime => {
+                attr.atime <= attr.mtime
+                    || attr.atime <= attr.ctime
+                    || now
+                        .duration_since(attr.atime)
+                        .map_or(false, |x| x >= RELATIME_INTERVAL)
+            }
+            AtimeMode::Strict => true,
+        };
+        Some(now).filter(|x| update && *x > attr.atime)
+    }
+}
+
+#[cfg(test)]
+mod atime_mode_test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use fuser::{FileAttr, FileType};
+
+    use super::AtimeMode;
+
+    #[test]
+    fn read_atime_test() {
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut attr = FileAttr {
+            ino: 2,
+            size: 0,
+            blocks: 0,
+            atime: mtime,
+            mtime,
+            ctime: mtime,
+            crtime: mtime,
+            kind: FileType::RegularFile,
+            perm: 0o664,
+            nlink: 2,
+            uid: 1000,
+            gid: 100,
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        };
+        let now = mtime + Duration::from_secs(60);
+        assert_eq!(AtimeMode::NoAtime.read_atime(&attr, now), None);
+        assert_eq!(AtimeMode::RelAtime.read_atime(&attr, now), Some(now));
+        assert_eq!(AtimeMode::Strict.read_atime(&attr, now), Some(now));
+
+        attr.atime = now;
+        let later = now + Duration::from_secs(60);
+        assert_eq!(AtimeMode::RelAtime.read_atime(&attr, later), None);
+        assert_eq!(AtimeMode::Strict.read_atime(&attr, later), Some(later));
+        let next_day = now + Duration::from_secs(24 * 60 * 60);
+        assert_eq!(
+            AtimeMode::RelAtime.read_atime(&attr, next_day),
+            Some(next_day)
+        );
+    }
+}
@@ -0,0 +1,39 @@
+use std::{ffi::OsString, fs::File, io::Write, path::Path};
+
+/// Replaces the file at `path` with `content`, so a crash at any moment leaves either the old
+/// or the new content. The content goes to `<path>.tmp` first, which is synced to disk and
+/// renamed over `path`, and the rename is synced with the directory.
+pub(super) fn write_atomic(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let mut temp_name = path
+        .file_name()
+        .map_or(OsString::new(), |x| x.to_os_string());
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    let mut file = File::create(&temp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    drop(file);
+    std::fs::rename(&temp_path, path)?;
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(test)]
+mod atomic_file_test {
+    use super::write_atomic;
+
+    #[test]
+    fn write_atomic_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pins.state");
+        write_atomic(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+};
+
+use tokio::fs::File;
+
+use super::{
+    cache_namespace::CacheNamespace,
+    errors::FSError,
+    webdav_fs_file_downloader::{cache_file_name, origin_path},
+};
+use crate::{
+    blockfile::BlockFile,
+    webdav::{encode_path, FileSink, StatOptions, WebDAVClient, WebDAVList},
+};
+
+/// Result of `cache export`.
+#[derive(Debug, Default)]
+pub struct CacheExport {
+    pub size: u64,
+    pub downloaded_bytes: u64,
+    /// Bytes which were neither cached nor downloaded, left as zeros in the output.
+    pub missing_bytes: u64,
+}
+
+impl Display for CacheExport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bytes exported, {} downloaded, {} missing",
+            self.size, self.downloaded_bytes, self.missing_bytes
+        )
+    }
+}
+
+/// Writes the cached data of the remote file at `path` into the plain file `output`, without a
+/// running mount. `cache_dir` is the directory given to the mount with `--tmp-path`, or one of
+/// its namespaces.
+///
+/// With a client, the blocks which are not cached are downloaded into `output`, leaving the
+/// cache as is, and the namespace of the client is locked, so this fails while a mount uses it.
+/// Otherwise the export fails when blocks are missing, unless `allow_missing` is set.
+pub async fn export_cached_file(
+    cache_dir: &Path,
+    path: &str,
+    output: &Path,
+    client: Option<&WebDAVClient>,
+    allow_missing: bool,
+) -> Result<CacheExport, FSError> {
+    let (cache_path, _namespace) = match client {
+        Some(client) => {
+            let namespace = CacheNamespace::open(cache_dir, &client.identity())
+                .map_err(|err| FSError::IO(err))?;
+            let cache_path = namespace.path().join(cache_file_name(path));
+            (cache_path, Some(namespace))
+        }
+        None => (find_cache(cache_dir, path)?, None),
+    };
+    let cache_path = cache_path.to_string_lossy().to_string();
+
+    let mut file = BlockFile::open(&cache_path, false)
+        .await
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => FSError::FileNotFoundInInode(path.to_string()),
+            _ => FSError::IO(err),
+        })?;
+    // Note : the cache file of another path with the same name hash is not the one looked for.
+    if origin_path(&file).is_some_and(|x| x != path) {
+        return Err(FSError::FileNotFoundInInode(path.to_string()));
+    }
+    let mut export = CacheExport {
+        size: file.file_size(),
+        ..CacheExport::default()
+    };
+    if let Some(client) = client {
+        check_remote_size(client, path, &file).await?;
+    }
+
+    let mut output_file = File::create(output).await.map_err(|err| FSError::IO(err))?;
+    let result = write_output(&mut file, &mut output_file).await;
+    drop(output_file);
+    let mut missing_blocks = match result {
+        Ok(missing_blocks) => missing_blocks,
+        Err(err) => {
+            let _ = tokio::fs::remove_file(output).await;
+            return Err(FSError::IO(err));
+        }
+    };
+    if let Some(client) = client {
+        if let Err(err) = download_missing_blocks(client, path, output, &missing_blocks).await {
+            let _ = tokio::fs::remove_file(output).await;
+            return Err(err);
+        }
+        export.downloaded_bytes = missing_blocks.iter().map(|(_, len)| len).sum();
+        missing_blocks.clear();
+    }
+
+    export.missing_bytes = missing_blocks.iter().map(|(_, len)| len).sum();
+    if export.missing_bytes > 0 && !allow_missing {
+        let _ = tokio::fs::remove_file(output).await;
+        return Err(FSError::InvalidOperation(format!(
+            "{} blocks ({} bytes) of {} are not cached",
+            missing_blocks.len(),
+            export.missing_bytes,
+            path
+        )));
+    }
+    Ok(export)
+}
+
+/// Finds the cache of `path` in `cache_dir` or in one of its namespaces.
+fn find_cache(cache_dir: &Path, path: &str) -> Result<PathBuf, FSError> {
+    let name = cache_file_name(path);
+    if cache_dir.join(format!("{}.meta", name)).exists() {
+        return Ok(cache_dir.join(name));
+    }
+
+    let mut found = Vec::new();
+    for entry in std::fs::read_dir(cache_dir).map_err(|err| FSError::IO(err))? {
+        let entry = entry.map_err(|err| FSError::IO(err))?;
+        if entry.path().join(format!("{}.meta", name)).exists() {
+            found.push(entry.path());
+        }
+    }
+    match found.len() {
+        0 => Err(FSError::FileNotFoundInInode(path.to_string())),
+        1 => Ok(found[0].join(name)),
+        _ => Err(FSError::InvalidOperation(format!(
+            "{} is cached in several namespaces, pass one of {:?}",
+            path, found
+        ))),
+    }
+}
+
+/// Fails when the remote file at `path` is gone or no longer has the size of its cache, since
+/// blocks downloaded from it now would not fit the cached ones.
+async fn check_remote_size(
+    client: &WebDAVClient,
+    path: &str,
+    file: &BlockFile,
+) -> Result<(), FSError> {
+    let (item, _) = client
+        .stat(&encode_path(path), StatOptions::default())
+        .await
+        .map_err(|e| FSError::WebDAV(e))?;
+    match item {
+        WebDAVList::File(remote) if remote.content_length == file.file_size() => {}
+        WebDAVList::File(_) => {
+            return Err(FSError::InvalidOperation(format!(
+                "{} changed on the server since it was cached",
+                path
+            )))
+        }
+        _ => return Err(FSError::FileNotFoundInInode(path.to_string())),
+    }
+    Ok(())
+}
+
+/// Downloads `blocks`, the offset and the length of the blocks which were not cached or did
+/// not match their checksum, into the exported file at `output`.
+async fn download_missing_blocks(
+    client: &WebDAVClient,
+    path: &str,
+    output: &Path,
+    blocks: &[(u64, u64)],
+) -> Result<(), FSError> {
+    let encoded_path = encode_path(path);
+    let output_file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(output)
+        .map_err(|err| FSError::IO(err))?;
+    let mut sink = FileSink::new(output_file);
+    for &(offset, len) in blocks {
+        client
+            .download(&encoded_path, &mut sink, offset, len)
+            .await
+            .map_err(|e| FSError::WebDAV(e))?;
+    }
+    Ok(())
+}
+
+async fn write_output(file: &mut BlockFile, output: &mut File) -> io::Result<Vec<(u64, u64)>> {
+    output.set_len(file.file_size()).await?;
+    let missing_blocks = file.export_to(output).await?;
+    output.sync_all().await?;
+    Ok(missing_blocks)
+}
+
+#[cfg(test)]
+mod cache_export_test {
+    use super::{cache_file_name, export_cached_file};
+    use crate::blockfile::BlockFile;
+
+    #[tokio::test]
+    async fn export_cached_file_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let namespace = dir.path().join("0123456789abcdef");
+        std::fs::create_dir(&namespace).unwrap();
+        let cache_path = namespace.join(cache_file_name("/talk.mkv"));
+        let mut file = BlockFile::create(cache_path.to_str().unwrap(), 40, 16)
+            .await
+            .unwrap();
+        file.write(&[1; 16], 0).await.unwrap();
+        file.write(&[3; 8], 32).await.unwrap();
+        drop(file);
+
+        let output = dir.path().join("talk.mkv");
+        assert!(
+            export_cached_file(dir.path(), "/talk.mkv", &output, None, false)
+                .await
+                .is_err()
+        );
+        assert!(!output.exists());
+
+        let export = export_cached_file(dir.path(), "/talk.mkv", &output, None, true)
+            .await
+            .unwrap();
+        assert_eq!((export.size, export.missing_bytes), (40, 16));
+        let expected: Vec<u8> = [[1; 16], [0; 16]]
+            .concat()
+            .into_iter()
+            .chain([3; 8])
+            .collect();
+        assert_eq!(std::fs::read(&output).unwrap(), expected);
+
+        assert!(
+            export_cached_file(dir.path(), "/other.mkv", &output, None, true)
+                .await
+                .is_err()
+        );
+    }
+}
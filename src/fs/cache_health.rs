@@ -0,0 +1,116 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use super::errors::FSError;
+use crate::webdav;
+
+/// Cache errors in a row after which reads stop using the cache.
+const FAILURE_THRESHOLD: u32 = 3;
+/// How often the cache directory is tried again while reads do not use it.
+const PROBE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Returns the error of the cache files behind `err` of a cached read, if any. Errors of the
+/// server come as other `webdav::Error`s.
+fn cache_io_error(err: &FSError) -> Option<&io::Error> {
+    match err {
+        FSError::IO(e) | FSError::WebDAV(webdav::Error::IO(e)) => Some(e),
+        _ => None,
+    }
+}
+
+/// Whether the cache directory still works, which it stops doing when its filesystem goes away,
+/// turns read-only or fills up, e.g. when a USB disk is pulled. After `FAILURE_THRESHOLD` cache
+/// errors in a row, reads are answered straight from the server without caching, and the cache
+/// directory is tried again every `PROBE_INTERVAL`. Both switches are logged once.
+#[derive(Clone, Default)]
+pub(super) struct CacheHealth {
+    failures: Arc<AtomicU32>,
+    bypassed: Arc<AtomicBool>,
+    last_probe: Arc<Mutex<Option<Instant>>>,
+}
+
+impl CacheHealth {
+    /// Counts `err` if the cache caused it. Returns whether it did, so the read can be answered
+    /// from the server instead.
+    pub fn record_failure(&self, err: &FSError, temp_path: &str) -> bool {
+        let Some(e) = cache_io_error(err) else {
+            return false;
+        };
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= FAILURE_THRESHOLD && !self.bypassed.swap(true, Ordering::Relaxed) {
+            *self.last_probe.lock().unwrap() = Some(Instant::now());
+            eprintln!(
+                "Cache {:?} keeps failing ({}), reading without caching until it works again",
+                temp_path, e
+            );
+        }
+        true
+    }
+
+    pub fn record_success(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+    }
+
+    /// Returns whether reads are answered without the cache.
+    pub fn is_bypassed(&self) -> bool {
+        self.bypassed.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the cache directory should be tried again now, at most once per
+    /// `PROBE_INTERVAL`.
+    pub fn probe_due(&self) -> bool {
+        let mut last_probe = self.last_probe.lock().unwrap();
+        if last_probe.is_some_and(|x| x.elapsed() < PROBE_INTERVAL) {
+            return false;
+        }
+        *last_probe = Some(Instant::now());
+        true
+    }
+
+    pub fn recovered(&self, temp_path: &str) {
+        self.failures.store(0, Ordering::Relaxed);
+        if self.bypassed.swap(false, Ordering::Relaxed) {
+            eprintln!("Cache directory {:?} works again, caching reads", temp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod cache_health_test {
+    use std::io;
+
+    use super::CacheHealth;
+    use crate::{fs::errors::FSError, webdav};
+
+    #[test]
+    fn record_failure_test() {
+        let health = CacheHealth::default();
+        let eio = || FSError::IO(io::Error::from_raw_os_error(libc::EIO));
+        assert!(!health.record_failure(&FSError::INodeNotExists, "/tmp/cache"));
+        let not_found = webdav::Error::NotFound("/a.txt".to_string());
+        assert!(!health.record_failure(&FSError::WebDAV(not_found), "/tmp/cache"));
+
+        assert!(health.record_failure(&eio(), "/tmp/cache"));
+        assert!(health.record_failure(&eio(), "/tmp/cache"));
+        health.record_success();
+        assert!(health.record_failure(&eio(), "/tmp/cache"));
+        assert!(!health.is_bypassed());
+
+        let full = FSError::WebDAV(webdav::Error::IO(io::Error::from_raw_os_error(
+            libc::ENOSPC,
+        )));
+        assert!(health.record_failure(&full, "/tmp/cache"));
+        assert!(health.record_failure(&full, "/tmp/cache"));
+        assert!(health.is_bypassed());
+        assert!(!health.probe_due());
+
+        health.recovered("/tmp/cache");
+        assert!(!health.is_bypassed());
+    }
+}
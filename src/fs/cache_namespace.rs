@@ -0,0 +1,76 @@
+use std::{
+    fs::{DirBuilder, File},
+    io,
+    os::unix::{fs::DirBuilderExt, io::AsRawFd},
+    path::{Path, PathBuf},
+};
+
+use super::atomic_file::write_atomic;
+use crate::hash::fnv1a;
+
+const LOCK_FILE_NAME: &str = "lock";
+const REMOTE_FILE_NAME: &str = "remote";
+
+/// The part of the cache directory owned by one remote identity (server URL and user).
+///
+/// Every identity gets its own sub directory, so mounts of different servers or accounts never
+/// see, evict or resume each other's cache files and pin queue. The directory is locked for as
+/// long as the namespace is alive, so a second mount of the same identity fails instead of
+/// writing into the same files.
+pub struct CacheNamespace {
+    path: PathBuf,
+    // Note : the lock is released when the file is closed.
+    _lock: File,
+}
+
+impl CacheNamespace {
+    pub fn open(cache_path: &Path, identity: &str) -> io::Result<CacheNamespace> {
+        let path = cache_path.join(format!("{:016x}", fnv1a(identity.as_bytes())));
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&path)?;
+
+        let lock = File::create(path.join(LOCK_FILE_NAME))?;
+        if unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::WouldBlock {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("cache {:?} is used by another mount of {}", path, identity),
+                ));
+            }
+            return Err(err);
+        }
+
+        // Note : only to tell the directories apart when looking at the cache by hand.
+        write_atomic(
+            &path.join(REMOTE_FILE_NAME),
+            format!("{}\n", identity).as_bytes(),
+        )?;
+        Ok(CacheNamespace { path, _lock: lock })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod cache_namespace_test {
+    use super::CacheNamespace;
+
+    #[test]
+    fn open_test() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let alice = CacheNamespace::open(dir.path(), "https://dav.example.com alice").unwrap();
+        let bob = CacheNamespace::open(dir.path(), "https://dav.example.com bob").unwrap();
+        assert_ne!(alice.path(), bob.path());
+        assert!(alice.path().starts_with(dir.path()));
+
+        assert!(CacheNamespace::open(dir.path(), "https://dav.example.com alice").is_err());
+        drop(alice);
+        assert!(CacheNamespace::open(dir.path(), "https://dav.example.com alice").is_ok());
+    }
+}
@@ -0,0 +1,39 @@
+use std::time::Duration;
+
+use crate::webdav::CacheControl;
+
+/// Decides how long directory listings and downloaded file data stay fresh.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CachePolicy {
+    /// Lifetime of cached entries the server says nothing about. `None` keeps them until they
+    /// are invalidated.
+    pub default_ttl: Option<Duration>,
+    /// Ignore `Cache-Control` and `Expires` from the server and always use `default_ttl`.
+    pub ignore_cache_control: bool,
+    /// List the directory again on every readdir, so sizes and mtimes of known children follow
+    /// the server even while the listing is fresh.
+    pub refresh_on_readdir: bool,
+    /// Attributes fetched longer ago than this are confirmed with the server on getattr, so the
+    /// sizes of files growing on the server stay accurate. `None` never confirms them.
+    pub attr_ttl: Option<Duration>,
+    /// Serve cached file data which expired at once and confirm it with the server in the
+    /// background, dropping it when the file changed, instead of confirming it first.
+    pub stale_while_revalidate: bool,
+}
+
+impl CachePolicy {
+    /// Returns how long a response stays fresh, `None` meaning until it is invalidated. An
+    /// expired entry is confirmed with the server by its etag or mtime, and only fetched again
+    /// when it changed.
+    pub fn ttl(&self, cache_control: &CacheControl) -> Option<Duration> {
+        if self.ignore_cache_control {
+            return self.default_ttl;
+        }
+        // Note : a file system has to keep what it serves, so no-store can not be honored as
+        // is. Like no-cache, it makes entries expire at once, which confirms them on every use.
+        if cache_control.no_cache || cache_control.no_store {
+            return Some(Duration::ZERO);
+        }
+        cache_control.max_age.or(self.default_ttl)
+    }
+}
@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+/// What a rule does with the files of its media types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Action {
+    /// Download the whole file in the background when it is opened, if it has at most this many
+    /// bytes.
+    Prefetch(Option<u64>),
+    /// Drop the cached data once the file is closed, so e.g. a watched video does not fill the
+    /// cache.
+    EvictOnClose,
+}
+
+/// One line of a content rules file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    /// Lowercase media type, or its type followed by `/*`.
+    pattern: String,
+    action: Action,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let mut fields = line.split_whitespace();
+        let pattern = fields.next()?.to_ascii_lowercase();
+        let (kind, subtype) = pattern.split_once('/')?;
+        if kind.is_empty() || subtype.is_empty() {
+            return None;
+        }
+        let action = match (fields.next()?, fields.next()) {
+            ("prefetch", None) => Action::Prefetch(None),
+            ("prefetch", Some(max_size)) => Action::Prefetch(Some(parse_size(max_size)?)),
+            ("evict-on-close", None) => Action::EvictOnClose,
+            _ => return None,
+        };
+        if fields.next().is_some() {
+            return None;
+        }
+        Some(Rule { pattern, action })
+    }
+
+    fn matches(&self, content_type: &str) -> bool {
+        match self.pattern.strip_suffix("/*") {
+            Some(kind) => content_type.split_once('/').is_some_and(|(x, _)| x == kind),
+            None => content_type == self.pattern,
+        }
+    }
+}
+
+/// Behaviors chosen by the media type the server reports for a file, one rule per line:
+///
+/// ```text
+/// # <media type> <action> [<max size>]
+/// video/* evict-on-close
+/// image/* prefetch 5M
+/// ```
+///
+/// `prefetch` downloads the whole file in the background when it is opened, only files of at
+/// most the given size (bytes, or with a K, M or G suffix) if one is given. `evict-on-close`
+/// drops the cached data of the file once every handle of it is closed. A type followed by `/*`
+/// matches all of its subtypes.
+#[derive(Debug, Clone, Default)]
+pub struct ContentRules {
+    rules: Arc<Vec<Rule>>,
+}
+
+impl ContentRules {
+    /// Parses a rules file, skipping blank lines and `#` comments. Returns the number of the
+    /// first malformed line as the error.
+    pub fn parse(text: &str) -> Result<ContentRules, usize> {
+        let rules = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(index, line)| Rule::parse(line).ok_or(index + 1))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(ContentRules {
+            rules: Arc::new(rules),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns whether a file of `size` bytes should be downloaded whole when it is opened.
+    pub fn prefetches(&self, content_type: Option<&str>, size: u64) -> bool {
+        self.actions(content_type).any(|action| match action {
+            Action::Prefetch(max_size) => max_size.map_or(true, |x| size <= x),
+            _ => false,
+        })
+    }
+
+    /// Returns whether the cached data of a file should be dropped once it is closed.
+    pub fn evicts_on_close(&self, content_type: Option<&str>) -> bool {
+        self.actions(content_type)
+            .any(|action| action == Action::EvictOnClose)
+    }
+
+    fn actions(&self, content_type: Option<&str>) -> impl Iterator<Item = Action> + '_ {
+        // Note : parameters like `; charset=utf-8` are not part of the media type.
+        let content_type = content_type
+            .and_then(|x| x.split(';').next())
+            .map(|x| x.trim().to_ascii_lowercase());
+        self.rules
+            .iter()
+            .filter(move |rule| content_type.as_deref().is_some_and(|x| rule.matches(x)))
+            .map(|rule| rule.action)
+    }
+}
+
+/// Parses a byte count like `512`, `64K`, `5M` or `1G`.
+fn parse_size(text: &str) -> Option<u64> {
+    let (digits, unit) = match text.find(|x: char| !x.is_ascii_digit()) {
+        Some(index) => text.split_at(index),
+        None => (text, ""),
+    };
+    let multiplier = match unit.to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+#[cfg(test)]
+mod content_rules_test {
+    use super::{parse_size, ContentRules};
+
+    #[test]
+    fn parse_size_test() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("64k"), Some(64 * 1024));
+        assert_eq!(parse_size("5M"), Some(5 * 1024 * 1024));
+        assert_eq!(parse_size("M"), None);
+        assert_eq!(parse_size("5T"), None);
+    }
+
+    #[test]
+    fn rules_test() {
+        let rules = ContentRules::parse(
+            "# media\nvideo/* evict-on-close\n\nimage/* prefetch 5M\ntext/plain prefetch\n",
+        )
+        .unwrap();
+        assert!(rules.evicts_on_close(Some("video/mp4")));
+        assert!(rules.evicts_on_close(Some("Video/MP4")));
+        assert!(!rules.evicts_on_close(Some("image/png")));
+        assert!(!rules.evicts_on_close(None));
+
+        assert!(rules.prefetches(Some("image/png"), 5 * 1024 * 1024));
+        assert!(!rules.prefetches(Some("image/png"), 5 * 1024 * 1024 + 1));
+        assert!(rules.prefetches(Some("text/plain; charset=utf-8"), u64::MAX));
+        assert!(!rules.prefetches(Some("text/html"), 1));
+
+        assert_eq!(
+            ContentRules::parse("video/* evict-on-close\nvideo\n").err(),
+            Some(2)
+        );
+        assert_eq!(ContentRules::parse("image/* prefetch 5X").err(), Some(1));
+        assert_eq!(ContentRules::parse("image/* discard").err(), Some(1));
+    }
+}
@@ -1,5 +1,8 @@
 use std::io;
 
+use libc::{c_int, EACCES, EBUSY, ENAMETOOLONG, ENOENT, ENOSPC};
+use reqwest::StatusCode;
+
 use crate::webdav::{self};
 
 #[derive(Debug)]
@@ -9,4 +12,33 @@ pub enum FSError {
     INodeNotExists,
     FileNotFoundInInode(String),
     InvalidOperation(String),
+    NameTooLong(String),
+}
+
+impl FSError {
+    pub fn errno(&self) -> c_int {
+        match self {
+            FSError::NameTooLong(_) => ENAMETOOLONG,
+            FSError::WebDAV(webdav::Error::UriTooLong(_)) => ENAMETOOLONG,
+            FSError::WebDAV(webdav::Error::Locked(_)) => EBUSY,
+            FSError::WebDAV(webdav::Error::InsufficientStorage(_)) => ENOSPC,
+            FSError::WebDAV(e)
+                if matches!(
+                    e.status(),
+                    Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+                ) =>
+            {
+                EACCES
+            }
+            _ => ENOENT,
+        }
+    }
+
+    /// The HTTP status the server answered the failed request with, if any.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            FSError::WebDAV(e) => e.status(),
+            _ => None,
+        }
+    }
 }
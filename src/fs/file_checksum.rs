@@ -0,0 +1,95 @@
+use std::{fs::File, io::Read};
+
+use adler::Adler32;
+use sha1::{Digest, Sha1};
+
+/// A checksum of a remote file, as a Nextcloud client reported it when it uploaded the file.
+#[derive(Debug, PartialEq)]
+pub(super) enum FileChecksum {
+    /// Lowercase hex digits.
+    Sha1(String),
+    Adler32(u32),
+}
+
+impl FileChecksum {
+    /// Picks the strongest checksum which can be computed here from an `oc:checksums` value,
+    /// e.g. `SHA1:2fd4e1c6... MD5:9e107d9d... ADLER32:11e60398`.
+    pub fn parse(checksums: &str) -> Option<FileChecksum> {
+        let mut found = None;
+        for checksum in checksums.split_whitespace() {
+            let Some((kind, value)) = checksum.split_once(':') else {
+                continue;
+            };
+            if kind.eq_ignore_ascii_case("SHA1") {
+                return Some(FileChecksum::Sha1(value.to_ascii_lowercase()));
+            }
+            if kind.eq_ignore_ascii_case("ADLER32") {
+                // Note : clients write it without leading zeros.
+                found = u32::from_str_radix(value, 16)
+                    .ok()
+                    .map(FileChecksum::Adler32);
+            }
+        }
+        found
+    }
+
+    /// Hashes the whole local file at `path` and tells whether it has this checksum. It reads
+    /// the file with blocking calls, so it runs in `spawn_blocking`.
+    pub fn matches_file(&self, path: &str) -> std::io::Result<bool> {
+        let mut file = File::open(path)?;
+        let mut buf = vec![0; 1024 * 1024];
+        let mut sha1 = Sha1::new();
+        let mut adler = Adler32::new();
+        loop {
+            let len = file.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            match self {
+                FileChecksum::Sha1(_) => sha1.update(&buf[..len]),
+                FileChecksum::Adler32(_) => adler.write_slice(&buf[..len]),
+            }
+        }
+        Ok(match self {
+            FileChecksum::Sha1(expected) => format!("{:x}", sha1.finalize()) == *expected,
+            FileChecksum::Adler32(expected) => adler.checksum() == *expected,
+        })
+    }
+}
+
+#[cfg(test)]
+mod file_checksum_test {
+    use super::FileChecksum;
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(
+            FileChecksum::parse("MD5:90015098 SHA1:A9993E36"),
+            Some(FileChecksum::Sha1("a9993e36".to_string()))
+        );
+        assert_eq!(
+            FileChecksum::parse("ADLER32:24d0127"),
+            Some(FileChecksum::Adler32(0x024d0127))
+        );
+        assert_eq!(FileChecksum::parse("MD5:90015098"), None);
+        assert_eq!(FileChecksum::parse(""), None);
+    }
+
+    #[test]
+    fn matches_file_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("copy");
+        std::fs::write(&path, b"abc").unwrap();
+        let path = path.to_str().unwrap();
+
+        let sha1 = FileChecksum::parse("SHA1:a9993e364706816aba3e25717850c26c9cd0d89d").unwrap();
+        assert!(sha1.matches_file(path).unwrap());
+        assert!(FileChecksum::parse("ADLER32:24d0127")
+            .unwrap()
+            .matches_file(path)
+            .unwrap());
+        assert!(!FileChecksum::Sha1("0".repeat(40))
+            .matches_file(path)
+            .unwrap());
+    }
+}
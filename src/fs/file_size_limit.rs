@@ -0,0 +1,114 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::webdav::WebDAVList;
+
+/// What the mount does with remote files larger than the maximum file size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OversizedFiles {
+    /// The files are listed, but opening them fails with EFBIG.
+    #[default]
+    Error,
+    /// The files are left out of listings, as if they were not on the server.
+    Hide,
+}
+
+impl FromStr for OversizedFiles {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(OversizedFiles::Error),
+            "hide" => Ok(OversizedFiles::Hide),
+            _ => Err(format!(
+                "invalid oversized file handling {:?}, expected error or hide",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for OversizedFiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OversizedFiles::Error => write!(f, "error"),
+            OversizedFiles::Hide => write!(f, "hide"),
+        }
+    }
+}
+
+/// The largest remote file the mount exposes, so an application can not pull a huge archive
+/// onto a small device by opening it. Oversized files are never pinned either.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileSizeLimit {
+    max_size: Option<u64>,
+    oversized: OversizedFiles,
+}
+
+impl FileSizeLimit {
+    pub fn new(max_size: u64, oversized: OversizedFiles) -> FileSizeLimit {
+        FileSizeLimit {
+            max_size: Some(max_size),
+            oversized,
+        }
+    }
+
+    /// Returns whether a file of `size` bytes is above the limit.
+    pub(super) fn exceeds(&self, size: u64) -> bool {
+        self.max_size.is_some_and(|x| size > x)
+    }
+
+    /// Returns whether a listed item is left out of the mount.
+    pub(super) fn hides(&self, item: &WebDAVList) -> bool {
+        match item {
+            WebDAVList::File(file) => {
+                self.oversized == OversizedFiles::Hide && self.exceeds(file.content_length)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns whether opening a file of `size` bytes fails.
+    pub(super) fn refuses(&self, size: u64) -> bool {
+        self.oversized == OversizedFiles::Error && self.exceeds(size)
+    }
+}
+
+#[cfg(test)]
+mod file_size_limit_test {
+    use chrono::Utc;
+
+    use super::{FileSizeLimit, OversizedFiles};
+    use crate::webdav::{WebDAVFile, WebDAVList};
+
+    fn file(size: u64) -> WebDAVList {
+        WebDAVList::File(WebDAVFile {
+            href: "/archive.tar".to_string(),
+            path: "/archive.tar".to_string(),
+            encoded_path: "/archive.tar".to_string(),
+            name: "archive.tar".into(),
+            display_name: None,
+            last_modified: Utc::now(),
+            content_length: size,
+            content_type: String::new(),
+            etag: None,
+            read_only: false,
+        })
+    }
+
+    #[test]
+    fn file_size_limit_test() {
+        let hide = FileSizeLimit::new(1000, OversizedFiles::Hide);
+        assert!(!hide.hides(&file(1000)));
+        assert!(hide.hides(&file(1001)));
+        assert!(!hide.refuses(1001));
+
+        let error = FileSizeLimit::new(1000, OversizedFiles::Error);
+        assert!(!error.hides(&file(1001)));
+        assert!(!error.refuses(1000));
+        assert!(error.refuses(1001));
+        assert!(error.exceeds(1001) && hide.exceeds(1001));
+
+        let none = FileSizeLimit::default();
+        assert!(!none.exceeds(u64::MAX) && !none.refuses(u64::MAX));
+    }
+}
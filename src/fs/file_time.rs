@@ -0,0 +1,87 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Utc};
+
+/// Converts a time sent by the server to the time of an inode, keeping the fraction of a second
+/// when the server gives one. Every place comparing remote times with inode times goes through
+/// this, so the same remote time always gives the same `SystemTime`.
+pub(super) fn from_remote(time: &DateTime<Utc>) -> SystemTime {
+    let nanos = time.timestamp_subsec_nanos().min(999_999_999);
+    let secs = time.timestamp();
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::new(secs as u64, nanos)
+    } else {
+        // Note : the fraction counts forward from the second, even before the epoch.
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + Duration::from_nanos(nanos as u64)
+    }
+}
+
+/// Nanoseconds since the epoch, 0 for times before it.
+pub(super) fn to_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64
+}
+
+pub(super) fn from_nanos(nanos: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos)
+}
+
+/// Formats a time as seconds since the epoch, with the fraction only when there is one, e.g.
+/// `1700000000` or `1700000000.250000000`.
+pub(super) fn to_secs_string(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    match since_epoch.subsec_nanos() {
+        0 => since_epoch.as_secs().to_string(),
+        nanos => format!("{}.{:09}", since_epoch.as_secs(), nanos),
+    }
+}
+
+/// Parses the output of `to_secs_string`, also accepting fractions of less than 9 digits.
+pub(super) fn parse_secs_string(value: &str) -> Option<SystemTime> {
+    let (secs, fraction) = value.split_once('.').unwrap_or((value, ""));
+    let secs = secs.parse().ok()?;
+    if fraction.len() > 9 || !fraction.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    let nanos = match fraction {
+        "" => 0,
+        fraction => fraction.parse::<u32>().ok()? * 10u32.pow(9 - fraction.len() as u32),
+    };
+    Some(UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+#[cfg(test)]
+mod file_time_test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{from_remote, parse_secs_string, to_secs_string};
+
+    #[test]
+    fn from_remote_test() {
+        assert_eq!(
+            from_remote(&Utc.timestamp_opt(1_700_000_000, 250_000_000).unwrap()),
+            UNIX_EPOCH + Duration::new(1_700_000_000, 250_000_000)
+        );
+        assert_eq!(
+            from_remote(&Utc.timestamp_opt(-2, 500_000_000).unwrap()),
+            UNIX_EPOCH - Duration::from_millis(1_500)
+        );
+    }
+
+    #[test]
+    fn secs_string_test() {
+        let time = UNIX_EPOCH + Duration::new(1_700_000_000, 250_000_000);
+        assert_eq!(to_secs_string(time), "1700000000.250000000");
+        assert_eq!(parse_secs_string("1700000000.250000000"), Some(time));
+        assert_eq!(parse_secs_string("1700000000.25"), Some(time));
+        assert_eq!(
+            to_secs_string(UNIX_EPOCH + Duration::from_secs(1_700_000_000)),
+            "1700000000"
+        );
+        assert_eq!(parse_secs_string("17.-5"), None);
+        assert_eq!(parse_secs_string("17.1234567890"), None);
+    }
+}
@@ -0,0 +1,48 @@
+use super::sync_rules::SyncRules;
+
+/// Remote files which are appended to while they are read, such as logs written on the server,
+/// given in the syntax of the sync rules. A pattern of a directory covers every file below it.
+///
+/// The size of such a file is checked with the server on every getattr and open, and on every
+/// read which reaches the end of the file, instead of once per attribute TTL. When it grew, the
+/// cached data is kept and only the appended bytes are downloaded, so `tail -f` follows the file.
+#[derive(Debug, Clone, Default)]
+pub struct GrowingFiles {
+    rules: SyncRules,
+}
+
+impl GrowingFiles {
+    /// Parses a file of patterns, skipping blank lines and `#` comments. Returns the number of
+    /// the first malformed line as the error.
+    pub fn parse(text: &str) -> Result<GrowingFiles, usize> {
+        Ok(GrowingFiles {
+            rules: SyncRules::parse(text)?,
+        })
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns whether the file at the decoded remote `path` grows.
+    pub(super) fn matches(&self, path: &str) -> bool {
+        // Note : the patterns match what a sync rule with them would exclude.
+        self.rules.is_excluded(path, false)
+    }
+}
+
+#[cfg(test)]
+mod growing_files_test {
+    use super::GrowingFiles;
+
+    #[test]
+    fn matches_test() {
+        let growing_files = GrowingFiles::parse("*.log\n!debug.log\n/var/log/\n").unwrap();
+        assert!(growing_files.matches("/srv/app/server.log"));
+        assert!(!growing_files.matches("/srv/app/debug.log"));
+        assert!(growing_files.matches("/var/log/syslog"));
+        assert!(!growing_files.matches("/var/syslog"));
+        assert!(!GrowingFiles::default().matches("/server.log"));
+        assert!(GrowingFiles::default().is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::{atomic_file::write_atomic, mount_guard::MountHandle};
+
+/// Operations answered with an error since the mount started, by operation.
+#[derive(Clone, Default)]
+pub(super) struct ErrorCounts {
+    counts: Arc<Mutex<BTreeMap<&'static str, u64>>>,
+}
+
+impl ErrorCounts {
+    pub fn record(&self, op: &'static str) {
+        *self.counts.lock().unwrap().entry(op).or_default() += 1;
+    }
+
+    pub fn snapshot(&self) -> BTreeMap<&'static str, u64> {
+        self.counts.lock().unwrap().clone()
+    }
+}
+
+/// The state of a mount a monitoring agent needs to tell whether it is healthy. It holds no
+/// paths, server URL or user name, so the report can be collected without revealing what is
+/// mounted.
+#[derive(Debug, Clone)]
+pub struct HealthReport {
+    pub written_at: SystemTime,
+    pub uptime: Duration,
+    pub errors: BTreeMap<&'static str, u64>,
+    pub inodes: usize,
+    pub cached_directories: usize,
+    pub cached_files: usize,
+    pub cached_bytes: u64,
+    /// Reads are answered without the cache, since its directory keeps failing.
+    pub cache_bypassed: bool,
+    /// When the server last answered a request, `None` before it did.
+    pub last_server_contact: Option<SystemTime>,
+    /// Seconds the clock of the server is ahead of the local one, negative when it is behind.
+    pub server_clock_offset_secs: i64,
+}
+
+impl HealthReport {
+    /// Formats the report as one JSON object, with times in seconds since the epoch, e.g.
+    /// `{"written_at":1700000060,"uptime_secs":60,"errors_total":1,"errors":{"read":1},...}`.
+    pub fn to_json(&self) -> String {
+        let secs = |time: SystemTime| {
+            time.duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        };
+        let mut out = format!(
+            "{{\"written_at\":{},\"uptime_secs\":{},\"errors_total\":{},\"errors\":{{",
+            secs(self.written_at),
+            self.uptime.as_secs(),
+            self.errors.values().sum::<u64>()
+        );
+        for (index, (op, count)) in self.errors.iter().enumerate() {
+            let separator = if index == 0 { "" } else { "," };
+            let _ = write!(out, "{}\"{}\":{}", separator, op, count);
+        }
+        let _ = write!(
+            out,
+            "}},\"inodes\":{},\"cached_directories\":{},\"cached_files\":{},\"cached_bytes\":{},\
+             \"cache_bypassed\":{}",
+            self.inodes,
+            self.cached_directories,
+            self.cached_files,
+            self.cached_bytes,
+            self.cache_bypassed
+        );
+        match self.last_server_contact {
+            Some(contact) => {
+                let since = self.written_at.duration_since(contact).unwrap_or_default();
+                let _ = write!(
+                    out,
+                    ",\"last_server_contact\":{},\"secs_since_server_contact\":{}",
+                    secs(contact),
+                    since.as_secs()
+                );
+            }
+            None => {
+                out.push_str(",\"last_server_contact\":null,\"secs_since_server_contact\":null")
+            }
+        }
+        let _ = write!(
+            out,
+            ",\"server_clock_offset_secs\":{}}}\n",
+            self.server_clock_offset_secs
+        );
+        out
+    }
+}
+
+/// Writes the health report of the mount to `path` every `interval`, replacing the previous
+/// one at once, so an agent such as Telegraf never reads half a report.
+pub async fn health_report(handle: MountHandle, path: PathBuf, interval: Duration) {
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        let report = handle.health().await;
+        if let Err(e) = write_atomic(&path, report.to_json().as_bytes()) {
+            eprintln!("Can not write health report {:?}: {}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod health_report_test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{ErrorCounts, HealthReport};
+
+    #[test]
+    fn to_json_test() {
+        let errors = ErrorCounts::default();
+        errors.record("read");
+        errors.record("read");
+        errors.record("getattr");
+        let written_at = UNIX_EPOCH + Duration::from_secs(1_700_000_060);
+        let mut report = HealthReport {
+            written_at,
+            uptime: Duration::from_millis(60_500),
+            errors: errors.snapshot(),
+            inodes: 12,
+            cached_directories: 3,
+            cached_files: 2,
+            cached_bytes: 4096,
+            cache_bypassed: false,
+            last_server_contact: Some(written_at - Duration::from_secs(5)),
+            server_clock_offset_secs: -300,
+        };
+        assert_eq!(
+            report.to_json(),
+            "{\"written_at\":1700000060,\"uptime_secs\":60,\"errors_total\":3,\
+             \"errors\":{\"getattr\":1,\"read\":2},\"inodes\":12,\"cached_directories\":3,\
+             \"cached_files\":2,\"cached_bytes\":4096,\"cache_bypassed\":false,\
+             \"last_server_contact\":1700000055,\
+             \"secs_since_server_contact\":5,\"server_clock_offset_secs\":-300}\n"
+        );
+
+        report.errors.clear();
+        report.last_server_contact = None;
+        report.cache_bypassed = true;
+        assert!(report.to_json().contains(
+            "\"errors_total\":0,\"errors\":{},\"inodes\":12,\"cached_directories\":3,\
+             \"cached_files\":2,\"cached_bytes\":4096,\"cache_bypassed\":true,\
+             \"last_server_contact\":null,"
+        ));
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    fmt::Display,
+    process::Stdio,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{io::AsyncWriteExt, process::Command};
+
+/// `on_error` hooks run at most once in this interval; the errors in between are counted in the
+/// payload of the next one, so a server which is down does not start a process per request.
+const ERROR_HOOK_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    /// The filesystem was mounted.
+    Mount,
+    /// The filesystem was unmounted.
+    Unmount,
+    /// An operation of the mount failed.
+    Error,
+}
+
+impl FromStr for HookEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "on_mount" => Ok(HookEvent::Mount),
+            "on_unmount" => Ok(HookEvent::Unmount),
+            "on_error" => Ok(HookEvent::Error),
+            _ => Err(format!("unknown hook event {}", s)),
+        }
+    }
+}
+
+impl Display for HookEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HookEvent::Mount => write!(f, "on_mount"),
+            HookEvent::Unmount => write!(f, "on_unmount"),
+            HookEvent::Error => write!(f, "on_error"),
+        }
+    }
+}
+
+/// Commands run on events of the mount, one per line:
+///
+/// ```text
+/// # <event> <command>
+/// on_mount notify-send "fusedav-rs mounted"
+/// on_error curl -s -d @- ntfy.sh/my-topic
+/// ```
+///
+/// The command runs through `sh -c` and gets a JSON object on its standard input with the
+/// `event`, the `time` in seconds since the epoch and the fields of the event, e.g.
+/// `{"event":"on_error","time":1700000000,"ino":"42","error":"...","suppressed":"0"}`.
+/// Every event may have several commands; they run in the order of the file.
+#[derive(Debug, Clone, Default)]
+pub struct Hooks {
+    commands: Arc<Vec<(HookEvent, String)>>,
+    /// When an `on_error` hook last ran, and the errors since which ran none.
+    error_throttle: Arc<Mutex<(Option<Instant>, u64)>>,
+}
+
+impl Hooks {
+    /// Parses a hooks file, skipping blank lines and `#` comments. Returns the number of the
+    /// first malformed line as the error.
+    pub fn parse(text: &str) -> Result<Hooks, usize> {
+        let commands = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(index, line)| parse_line(line).ok_or(index + 1))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Hooks {
+            commands: Arc::new(commands),
+            error_throttle: Arc::new(Mutex::new((None, 0))),
+        })
+    }
+
+    /// Runs the commands of `event` one after the other and waits for them.
+    pub async fn run(&self, event: HookEvent, fields: &[(&str, String)]) {
+        let commands: Vec<&String> = self
+            .commands
+            .iter()
+            .filter(|(x, _)| *x == event)
+            .map(|(_, command)| command)
+            .collect();
+        if commands.is_empty() {
+            return;
+        }
+        let payload = payload(event, fields);
+        for command in commands {
+            run_command(command, event, &payload).await;
+        }
+    }
+
+    /// Runs the `on_error` commands in the background, unless they ran less than
+    /// `ERROR_HOOK_INTERVAL` ago.
+    pub fn error(&self, fields: Vec<(&'static str, String)>) {
+        if !self.commands.iter().any(|(x, _)| *x == HookEvent::Error) {
+            return;
+        }
+        let Some(suppressed) = self.take_error_slot(Instant::now()) else {
+            return;
+        };
+        let hooks = self.clone();
+        tokio::spawn(async move {
+            let mut fields = fields;
+            fields.push(("suppressed", suppressed.to_string()));
+            hooks.run(HookEvent::Error, &fields).await;
+        });
+    }
+
+    /// Returns whether an `on_error` hook may run at `now`, with the number of errors which ran
+    /// none since the last one, and counts the error as suppressed otherwise.
+    fn take_error_slot(&self, now: Instant) -> Option<u64> {
+        let mut throttle = self.error_throttle.lock().unwrap();
+        let (last_run, suppressed) = &mut *throttle;
+        if last_run.is_some_and(|x| now.saturating_duration_since(x) < ERROR_HOOK_INTERVAL) {
+            *suppressed += 1;
+            return None;
+        }
+        *last_run = Some(now);
+        Some(std::mem::take(suppressed))
+    }
+}
+
+fn parse_line(line: &str) -> Option<(HookEvent, String)> {
+    let (event, command) = line.split_once(char::is_whitespace)?;
+    Some((event.parse().ok()?, command.trim().to_string()))
+}
+
+async fn run_command(command: &str, event: HookEvent, payload: &str) {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("Can not run {} hook: {}", event, e);
+            return;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        // Note : a command which does not read its input is not an error.
+        let _ = stdin.write_all(payload.as_bytes()).await;
+    }
+    match child.wait().await {
+        Ok(status) if !status.success() => eprintln!("{} hook failed: {}", event, status),
+        Ok(_) => {}
+        Err(e) => eprintln!("Can not run {} hook: {}", event, e),
+    }
+}
+
+fn payload(event: HookEvent, fields: &[(&str, String)]) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+    let mut payload = format!(
+        "{{\"event\":{},\"time\":{}",
+        json_string(&event.to_string()),
+        time
+    );
+    for (key, value) in fields {
+        payload.push_str(&format!(",{}:{}", json_string(key), json_string(value)));
+    }
+    payload.push_str("}\n");
+    payload
+}
+
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod hooks_test {
+    use std::time::{Duration, Instant};
+
+    use super::{json_string, HookEvent, Hooks, ERROR_HOOK_INTERVAL};
+
+    #[test]
+    fn parse_test() {
+        let hooks = Hooks::parse(
+            "# events\non_mount notify-send mounted\n\non_error  curl -d @- ntfy.sh/x\n",
+        )
+        .unwrap();
+        assert_eq!(
+            *hooks.commands,
+            vec![
+                (HookEvent::Mount, "notify-send mounted".to_string()),
+                (HookEvent::Error, "curl -d @- ntfy.sh/x".to_string()),
+            ]
+        );
+        assert_eq!(Hooks::parse("on_mount true\non_upload x").err(), Some(2));
+        assert_eq!(Hooks::parse("on_unmount").err(), Some(1));
+    }
+
+    #[test]
+    fn error_throttle_test() {
+        let hooks = Hooks::parse("on_error true").unwrap();
+        let start = Instant::now();
+        assert_eq!(hooks.take_error_slot(start), Some(0));
+        assert_eq!(hooks.take_error_slot(start + Duration::from_secs(1)), None);
+        assert_eq!(hooks.take_error_slot(start + Duration::from_secs(59)), None);
+
+        let next = start + ERROR_HOOK_INTERVAL;
+        assert_eq!(hooks.take_error_slot(next), Some(2));
+        assert_eq!(hooks.take_error_slot(next), None);
+        assert_eq!(hooks.take_error_slot(next + ERROR_HOOK_INTERVAL), Some(1));
+    }
+
+    #[test]
+    fn json_string_test() {
+        assert_eq!(json_string("a \"b\"\\c\n"), "\"a \\\"b\\\"\\\\c\\n\"");
+        assert_eq!(json_string("\u{1}é"), "\"\\u0001é\"");
+    }
+}
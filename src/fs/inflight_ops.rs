@@ -0,0 +1,146 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// An operation of the mount which has not finished yet.
+#[derive(Debug, Clone)]
+pub(super) struct InflightOp {
+    pub op: &'static str,
+    /// The inode the operation works on, the directory for a lookup.
+    pub ino: u64,
+    pub context: String,
+    pub started_at: Instant,
+}
+
+/// The operations running on the runtime, so a mount which hangs can be asked what it waits
+/// for. Each operation is registered by the name it is spawned with until its task ends.
+#[derive(Clone, Default)]
+pub(super) struct InflightOps {
+    next_id: Arc<AtomicU64>,
+    ops: Arc<Mutex<HashMap<u64, InflightOp>>>,
+}
+
+/// Removes its operation from the registry when dropped, which also happens when the task
+/// panics.
+pub(super) struct InflightGuard {
+    id: u64,
+    ops: Arc<Mutex<HashMap<u64, InflightOp>>>,
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        self.ops.lock().unwrap().remove(&self.id);
+    }
+}
+
+impl InflightOps {
+    pub fn start(&self, op: &'static str, ino: u64, context: String) -> InflightGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.ops.lock().unwrap().insert(
+            id,
+            InflightOp {
+                op,
+                ino,
+                context,
+                started_at: Instant::now(),
+            },
+        );
+        InflightGuard {
+            id,
+            ops: self.ops.clone(),
+        }
+    }
+
+    /// Returns the running operations, the longest running first.
+    pub fn snapshot(&self) -> Vec<InflightOp> {
+        let mut ops: Vec<(u64, InflightOp)> = self
+            .ops
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, op)| (*id, op.clone()))
+            .collect();
+        ops.sort_by_key(|(id, op)| (op.started_at, *id));
+        ops.into_iter().map(|(_, op)| op).collect()
+    }
+}
+
+/// Formats running operations as a summary per operation followed by one line per operation,
+/// e.g. `read: 3, oldest 120.0 s` and `120.0 s read /Videos/talk.mkv (ino 42 offset 0 size
+/// 131072)`. `path_of` gives the remote path of an inode, if it is known.
+pub(super) fn format_dump(
+    ops: &[InflightOp],
+    now: Instant,
+    path_of: impl Fn(u64) -> Option<String>,
+) -> String {
+    let mut dump = format!("{} operations in flight\n", ops.len());
+    let mut summary: BTreeMap<&str, (usize, Duration)> = BTreeMap::new();
+    for op in ops {
+        let (count, oldest) = summary.entry(op.op).or_default();
+        *count += 1;
+        *oldest = (*oldest).max(now.saturating_duration_since(op.started_at));
+    }
+    for (op, (count, oldest)) in summary {
+        dump.push_str(&format!(
+            "{}: {}, oldest {:.1} s\n",
+            op,
+            count,
+            oldest.as_secs_f64()
+        ));
+    }
+    for op in ops {
+        let path = path_of(op.ino).unwrap_or_else(|| "?".to_string());
+        dump.push_str(&format!(
+            "{:.1} s {} {} ({})\n",
+            now.saturating_duration_since(op.started_at).as_secs_f64(),
+            op.op,
+            path,
+            op.context
+        ));
+    }
+    dump
+}
+
+#[cfg(test)]
+mod inflight_ops_test {
+    use std::time::{Duration, Instant};
+
+    use super::{format_dump, InflightOps};
+
+    #[test]
+    fn format_dump_test() {
+        let ops = InflightOps::default();
+        let read = ops.start("read", 42, "ino 42 offset 0 size 4096".to_string());
+        let readdir = ops.start("readdir", 1, "ino 1 offset 0".to_string());
+        let second_read = ops.start("read", 43, "ino 43 offset 0 size 4096".to_string());
+        drop(readdir);
+
+        let snapshot = ops.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        let now = snapshot[0].started_at + Duration::from_secs(120);
+        let dump = format_dump(&snapshot, now, |ino| {
+            (ino == 42).then(|| "/Videos/talk.mkv".to_string())
+        });
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines[0], "2 operations in flight");
+        assert_eq!(lines[1], "read: 2, oldest 120.0 s");
+        assert_eq!(
+            lines[2],
+            "120.0 s read /Videos/talk.mkv (ino 42 offset 0 size 4096)"
+        );
+        assert!(lines[3].ends_with(" s read ? (ino 43 offset 0 size 4096)"));
+
+        drop(read);
+        drop(second_read);
+        assert!(ops.snapshot().is_empty());
+        assert_eq!(
+            format_dump(&[], Instant::now(), |_| None),
+            "0 operations in flight\n"
+        );
+    }
+}
@@ -1,41 +1,139 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    mem::size_of,
+    ops::RangeInclusive,
     path::Path,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime},
 };
 
 use fuser::{FileAttr, FileType};
 
-use crate::webdav::WebDAVList;
+use super::{
+    file_time,
+    listing_spill::ListingSpill,
+    name_source::{unique_name, NameSource},
+};
+use crate::webdav::{ServerClock, WebDAVList};
+
+/// Bytes an entry takes in the maps and listings of `InodeInfoMap` besides its `InodeInfo`,
+/// roughly a hash map slot with its key and a listing slot.
+const ENTRY_OVERHEAD: usize = 64;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub(super) struct InodeInfo {
     pub file_attr: FileAttr,
     pub path: String,
+    /// Name in the mount, the last segment of the href as the server sent it unless picked
+    /// otherwise by the `NameSource`.
+    pub name: OsString,
+    /// The path as the server encoded it, which requests are sent to.
+    pub encoded_path: String,
+    /// Entity tag the server reported, if any.
+    pub etag: Option<String>,
+    /// Media type the server reported for files, e.g. `video/mp4`, if any.
+    pub content_type: Option<String>,
+    /// When the attributes should be confirmed with the server again, `None` for never.
+    pub expires_at: Option<Instant>,
+    /// When the attributes were fetched from the server.
+    pub fetched_at: Instant,
 }
 
 impl InodeInfo {
-    pub fn new(file_attr: FileAttr, path: String) -> InodeInfo {
-        InodeInfo { file_attr, path }
-    }
-
-    pub fn file_name(&self) -> &str {
-        if self.path == "/" {
-            "/"
+    pub fn new(file_attr: FileAttr, path: String, encoded_path: String) -> InodeInfo {
+        let name = if path == "/" {
+            OsString::from("/")
         } else {
-            Path::new(&self.path).file_name().unwrap().to_str().unwrap()
+            Path::new(&path).file_name().unwrap().to_os_string()
+        };
+        InodeInfo {
+            file_attr,
+            path,
+            name,
+            encoded_path,
+            etag: None,
+            content_type: None,
+            expires_at: None,
+            fetched_at: Instant::now(),
         }
     }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .map_or(false, |expires_at| expires_at <= Instant::now())
+    }
+
+    pub fn file_name(&self) -> &OsStr {
+        &self.name
+    }
+
+    /// Approximate bytes of memory the entry takes in an `InodeInfoMap`.
+    fn memory_size(&self) -> usize {
+        size_of::<InodeInfo>()
+            + ENTRY_OVERHEAD
+            + self.path.capacity()
+            + self.name.capacity()
+            + self.encoded_path.capacity()
+            + self.etag.as_ref().map_or(0, |x| x.capacity())
+            + self.content_type.as_ref().map_or(0, |x| x.capacity())
+    }
+}
+
+/// An entry which was added, modified or removed on the server since the previous listing.
+pub(super) struct ChangedEntry {
+    pub ino: u64,
+    pub name: OsString,
+    pub path: String,
+}
+
+/// A listing encoded for the spill, see `InodeInfoMap::snapshot_listing`.
+pub(super) struct ListingSnapshot {
+    pub dir: u64,
+    pub content: String,
+    /// The entries as they were encoded, to tell whether the listing changed meanwhile.
+    entries: Vec<InodeInfo>,
+}
+
+impl ListingSnapshot {
+    pub fn entry_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Bytes of memory the entries take in an `InodeInfoMap`.
+    pub fn memory_bytes(&self) -> usize {
+        self.entries.iter().map(|x| x.memory_size()).sum()
+    }
+}
+
+/// Where to find the entries of a spilled listing. Only the range of their inode numbers is kept,
+/// so the index takes the same memory whatever the size of the listing.
+struct SpilledListing {
+    /// The directory whose listing holds the spilled directory.
+    parent: u64,
+    entries: RangeInclusive<u64>,
 }
 
 pub(super) struct InodeInfoMap {
     ino_info_map: HashMap<u64, InodeInfo>,
     ino_item_list_map: HashMap<u64, Vec<u64>>,
     ino_parent_map: HashMap<u64, u64>,
+    stale_dirs: HashSet<u64>,
+    dir_expiry: HashMap<u64, Instant>,
+    dir_listed_at: HashMap<u64, Instant>,
+    /// Etags the directories had when they were listed, see `listing_etag`.
+    listing_etags: HashMap<u64, String>,
+    /// Where cold listings go to bound the memory, see `snapshot_listing`.
+    spill: Option<ListingSpill>,
+    /// Directories whose listing is spilled.
+    spilled_dirs: HashMap<u64, SpilledListing>,
+    /// Approximate bytes of the entries in memory, see `InodeInfo::memory_size`.
+    memory_bytes: usize,
 
     next_ino_id: u64,
     user_id: u32,
     group_id: u32,
+    read_only_perms: bool,
+    server_clock: ServerClock,
 }
 
 impl InodeInfoMap {
@@ -43,19 +141,178 @@ impl InodeInfoMap {
         let root = InodeInfo::new(
             InodeInfoMap::root_directory_attr(user_id, group_id),
             "/".to_string(),
+            "/".to_string(),
         );
         InodeInfoMap {
+            memory_bytes: root.memory_size(),
             ino_info_map: HashMap::from([(1, root)]),
             ino_item_list_map: HashMap::new(),
             ino_parent_map: HashMap::from([(1, 1)]),
+            stale_dirs: HashSet::new(),
+            dir_expiry: HashMap::new(),
+            dir_listed_at: HashMap::new(),
+            listing_etags: HashMap::new(),
+            spill: None,
+            spilled_dirs: HashMap::new(),
 
             next_ino_id: 2,
             user_id: user_id,
             group_id: group_id,
+            read_only_perms: false,
+            server_clock: ServerClock::default(),
+        }
+    }
+
+    pub fn set_spill(&mut self, spill: ListingSpill) {
+        self.spill = Some(spill);
+    }
+
+    /// Gives the entries the server does not let the user write no write permission bits.
+    pub fn set_read_only_perms(&mut self, read_only_perms: bool) {
+        self.read_only_perms = read_only_perms;
+    }
+
+    /// Sets the clock the times the mount gives directories are taken from, so they compare
+    /// with the mtimes from the server.
+    pub fn set_server_clock(&mut self, server_clock: ServerClock) {
+        self.server_clock = server_clock;
+    }
+
+    /// Whether `ino` is a directory whose listing is spilled or may be an entry of such a
+    /// listing, which must be restored before use.
+    pub fn is_spilled(&self, ino: u64) -> bool {
+        self.spilled_dirs.contains_key(&ino)
+            || (!self.ino_info_map.contains_key(&ino)
+                && self.spilled_dirs.values().any(|x| x.entries.contains(&ino)))
+    }
+
+    /// Restores the spilled listings which `ino` needs, the one holding its entry and its own.
+    pub fn restore(&mut self, ino: u64) -> std::io::Result<()> {
+        if !self.ino_info_map.contains_key(&ino) {
+            // Note : the ranges of listings updated over time overlap, so more than one listing
+            // may have to come back to find the entry.
+            let dirs: Vec<u64> = self
+                .spilled_dirs
+                .iter()
+                .filter(|(_, x)| x.entries.contains(&ino))
+                .map(|(dir, _)| *dir)
+                .collect();
+            for dir in dirs {
+                if self.ino_info_map.contains_key(&ino) {
+                    break;
+                }
+                self.restore_listing(dir)?;
+            }
+        }
+        if self.spilled_dirs.contains_key(&ino) {
+            self.restore_listing(ino)?;
+        }
+        Ok(())
+    }
+
+    fn restore_listing(&mut self, dir: u64) -> std::io::Result<()> {
+        let Some(parent) = self.spilled_dirs.get(&dir).map(|x| x.parent) else {
+            return Ok(());
+        };
+        // Note : the directory itself is an entry of its parent's listing, which may be spilled.
+        if !self.ino_info_map.contains_key(&dir) && self.spilled_dirs.contains_key(&parent) {
+            self.restore_listing(parent)?;
+        }
+        let Some(spill) = self.spill.as_ref() else {
+            return Ok(());
+        };
+        let entries = match spill.read(dir, self.user_id, self.group_id) {
+            Ok(entries) => entries,
+            Err(err) => {
+                // Note : the entries are listed again from the server with new inode numbers.
+                self.spilled_dirs.remove(&dir);
+                return Err(err);
+            }
+        };
+        spill.remove(dir);
+        let mut ino_item_list = Vec::with_capacity(entries.len());
+        for entry in entries {
+            let ino = entry.file_attr.ino;
+            self.ino_parent_map.insert(ino, dir);
+            self.insert_info(ino, entry);
+            ino_item_list.push(ino);
+        }
+        self.ino_item_list_map.insert(dir, ino_item_list);
+        self.spilled_dirs.remove(&dir);
+        Ok(())
+    }
+
+    /// Returns the spill listings are written to, if any.
+    pub fn spill(&self) -> Option<ListingSpill> {
+        self.spill.clone()
+    }
+
+    /// Encodes the listing of `dir` to be written to the spill, see `drop_spilled`. Only listings
+    /// without cached sub directory listings can be spilled; returns `None` for others.
+    pub fn snapshot_listing(&self, dir: u64) -> Option<ListingSnapshot> {
+        let ino_item_list = self.ino_item_list_map.get(&dir)?;
+        if ino_item_list
+            .iter()
+            .any(|ino| self.ino_item_list_map.contains_key(ino))
+        {
+            return None;
+        }
+        let entries: Vec<&InodeInfo> = ino_item_list
+            .iter()
+            .filter_map(|ino| self.ino_info_map.get(ino))
+            .collect();
+        Some(ListingSnapshot {
+            dir,
+            content: ListingSpill::encode(&entries),
+            entries: entries.into_iter().cloned().collect(),
+        })
+    }
+
+    /// Moves the listing of `dir` out of memory once its snapshot is written to the spill. Its
+    /// entries keep their inode numbers, since the kernel may still refer to them, and come back
+    /// with `restore`. Returns false, keeping the listing, when it changed since the snapshot.
+    pub fn drop_spilled(&mut self, snapshot: &ListingSnapshot) -> bool {
+        let dir = snapshot.dir;
+        let unchanged = self
+            .ino_item_list_map
+            .get(&dir)
+            .is_some_and(|ino_item_list| {
+                ino_item_list.len() == snapshot.entries.len()
+                    && ino_item_list
+                        .iter()
+                        .zip(snapshot.entries.iter())
+                        .all(|(ino, entry)| self.ino_info_map.get(ino) == Some(entry))
+            });
+        // Note : a sub directory listed meanwhile would be lost with the entries.
+        if !unchanged
+            || snapshot
+                .entries
+                .iter()
+                .any(|x| self.ino_item_list_map.contains_key(&x.file_attr.ino))
+        {
+            return false;
+        }
+        let Some(parent) = self.ino_parent_map.get(&dir).copied() else {
+            return false;
+        };
+        let ino_item_list = self.ino_item_list_map.remove(&dir).unwrap_or_default();
+        let first = ino_item_list.iter().min().copied().unwrap_or(0);
+        let last = ino_item_list.iter().max().copied().unwrap_or(0);
+        for ino in ino_item_list {
+            self.remove_info(ino);
+            self.ino_parent_map.remove(&ino);
         }
+        self.spilled_dirs.insert(
+            dir,
+            SpilledListing {
+                parent,
+                entries: first..=last,
+            },
+        );
+        true
     }
 
-    pub fn find_by_path(&self, parent: u64, target: &str) -> Option<&InodeInfo> {
+    pub fn find_by_path(&self, parent: u64, target: &OsStr) -> Option<&InodeInfo> {
         let empty_vec = Vec::new();
         let ino_item_list = self
             .ino_item_list_map
@@ -80,6 +337,18 @@ impl InodeInfoMap {
         self.ino_info_map.get(&ino)
     }
 
+    /// Finds a known inode by its remote path, with or without the trailing slash of directories.
+    pub fn find_by_remote_path(&self, path: &str) -> Option<&InodeInfo> {
+        let target = path.trim_end_matches('/');
+        self.ino_info_map
+            .values()
+            .find(|inode_info| inode_info.path.trim_end_matches('/') == target)
+    }
+
+    pub fn parent_ino(&self, ino: u64) -> Option<u64> {
+        self.ino_parent_map.get(&ino).copied()
+    }
+
     pub fn parent(&self, ino: u64) -> Option<&InodeInfo> {
         self.ino_parent_map
             .get(&ino)
@@ -88,6 +357,53 @@ impl InodeInfoMap {
 
     pub fn is_cached_dir(&self, ino: u64) -> bool {
         self.ino_item_list_map.contains_key(&ino)
+            && !self.stale_dirs.contains(&ino)
+            && self
+                .dir_expiry
+                .get(&ino)
+                .map_or(true, |expiry| Instant::now() < *expiry)
+    }
+
+    /// Whether the directory was listed less than `duration` ago.
+    pub fn listed_within(&self, ino: u64, duration: Duration) -> bool {
+        self.dir_listed_at
+            .get(&ino)
+            .map_or(false, |listed_at| listed_at.elapsed() < duration)
+    }
+
+    /// Marks the listing of a directory to be fetched again on the next access. The current
+    /// listing is kept, so children keep their inode numbers when they are listed again.
+    pub fn mark_stale(&mut self, ino: u64) {
+        if self.ino_item_list_map.contains_key(&ino) {
+            self.stale_dirs.insert(ino);
+            self.dir_listed_at.remove(&ino);
+        }
+    }
+
+    /// Returns every directory with a cached listing.
+    pub fn cached_dirs(&self) -> Vec<InodeInfo> {
+        self.ino_item_list_map
+            .keys()
+            .filter_map(|ino| self.ino_info_map.get(ino).cloned())
+            .collect()
+    }
+
+    pub fn inode_count(&self) -> usize {
+        self.ino_info_map.len()
+    }
+
+    /// Returns the approximate bytes of memory taken by the entries in memory.
+    pub fn memory_bytes(&self) -> usize {
+        self.memory_bytes
+    }
+
+    pub fn cached_dir_count(&self) -> usize {
+        self.ino_item_list_map.len()
+    }
+
+    /// Returns the directories with a listing in memory.
+    pub fn cached_dir_inos(&self) -> Vec<u64> {
+        self.ino_item_list_map.keys().copied().collect()
     }
 
     pub fn childs(&self, ino: u64) -> Option<Vec<&InodeInfo>> {
@@ -104,7 +420,20 @@ impl InodeInfoMap {
         }
     }
 
-    pub fn update_cache(&mut self, current_ino: u64, list: Vec<WebDAVList>) {
+    /// Replaces the listing of a directory, which is fetched again after `ttl` if given.
+    /// Returns the entries which changed since the previous listing, if there was one.
+    pub fn update_cache(
+        &mut self,
+        current_ino: u64,
+        list: Vec<WebDAVList>,
+        ttl: Option<Duration>,
+        name_source: NameSource,
+    ) -> Vec<ChangedEntry> {
+        if self.spilled_dirs.contains_key(&current_ino) {
+            if let Err(err) = self.restore(current_ino) {
+                eprintln!("Can not restore spilled listing {}: {}", current_ino, err);
+            }
+        }
         let mut list = list
             .iter()
             .filter(|x| match x {
@@ -114,44 +443,231 @@ impl InodeInfoMap {
             .collect::<Vec<&WebDAVList>>();
         list.sort_by(Self::sort_webdav_list);
 
-        for item in list {
-            if let Some(inode_info) = self.convert_web_dav_list_to_file_attr(item) {
-                let ino_item_list: &mut Vec<u64> = match self.ino_item_list_map.entry(current_ino) {
-                    Entry::Occupied(entry) => entry.into_mut(),
-                    Entry::Vacant(entry) => entry.insert(Vec::new()),
-                };
-
-                ino_item_list.push(inode_info.file_attr.ino);
-                self.ino_parent_map
-                    .insert(inode_info.file_attr.ino, current_ino);
+        let had_listing = self.ino_item_list_map.contains_key(&current_ino);
+        let mut previous_items: HashMap<String, (u64, FileType)> = self
+            .ino_item_list_map
+            .remove(&current_ino)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|ino| {
                 self.ino_info_map
-                    .insert(inode_info.file_attr.ino, inode_info);
+                    .get(&ino)
+                    .map(|x| (x.path.clone(), (ino, x.file_attr.kind)))
+            })
+            .collect();
+
+        let mut changed_entries = Vec::new();
+        let mut ino_item_list = Vec::with_capacity(list.len());
+        let mut used_names = HashSet::new();
+        for item in list {
+            let (ino, is_new) = match previous_items.remove(Self::webdav_list_path(item)) {
+                Some((ino, kind)) if kind == Self::webdav_list_kind(item) => (ino, false),
+                previous_item => {
+                    if let Some((ino, _)) = previous_item {
+                        self.remove_subtree(ino);
+                    }
+                    let ino = self.next_ino_id;
+                    self.next_ino_id += 1;
+                    (ino, true)
+                }
+            };
+
+            if let Some(mut inode_info) = self.convert_web_dav_list_to_file_attr(ino, item) {
+                inode_info.expires_at = ttl.map(|ttl| Instant::now() + ttl);
+                let name = name_source.name_of(item, &inode_info.name);
+                inode_info.name = unique_name(&mut used_names, name);
+                let previous = self.ino_info_map.get(&ino);
+                if let Some(previous) = previous {
+                    Self::keep_touched_times(previous, &mut inode_info);
+                }
+                let modified = previous.map_or(false, |x| {
+                    x.file_attr.size != inode_info.file_attr.size
+                        || x.file_attr.mtime != inode_info.file_attr.mtime
+                        || x.name != inode_info.name
+                });
+                if had_listing && (is_new || modified) {
+                    // Note : a renamed entry is known to the kernel by its previous name.
+                    changed_entries.push(ChangedEntry {
+                        ino,
+                        name: previous.map_or(inode_info.name.clone(), |x| x.name.clone()),
+                        path: inode_info.path.clone(),
+                    });
+                }
+                ino_item_list.push(ino);
+                self.ino_parent_map.insert(ino, current_ino);
+                self.insert_info(ino, inode_info);
+            }
+        }
+        self.ino_item_list_map.insert(current_ino, ino_item_list);
+        self.stale_dirs.remove(&current_ino);
+        self.listing_etags.remove(&current_ino);
+        self.set_listing_expiry(current_ino, ttl);
+
+        for (path, (ino, _)) in previous_items {
+            if let Some(inode_info) = self.ino_info_map.get(&ino) {
+                changed_entries.push(ChangedEntry {
+                    ino,
+                    name: inode_info.name.clone(),
+                    path,
+                });
+            }
+            self.remove_subtree(ino);
+        }
+        if !changed_entries.is_empty() {
+            self.touch_dir(current_ino);
+        }
+        changed_entries
+    }
+
+    fn set_listing_expiry(&mut self, ino: u64, ttl: Option<Duration>) {
+        self.dir_listed_at.insert(ino, Instant::now());
+        match ttl {
+            Some(ttl) => self.dir_expiry.insert(ino, Instant::now() + ttl),
+            None => self.dir_expiry.remove(&ino),
+        };
+    }
+
+    /// Records the etag the server reported for a directory along with its listing.
+    pub fn set_listing_etag(&mut self, ino: u64, etag: Option<&str>) {
+        match etag {
+            Some(etag) if self.ino_item_list_map.contains_key(&ino) => {
+                self.listing_etags.insert(ino, etag.to_string());
+            }
+            _ => {
+                self.listing_etags.remove(&ino);
             }
         }
     }
 
-    fn convert_web_dav_list_to_file_attr(&mut self, item: &WebDAVList) -> Option<InodeInfo> {
-        if let WebDAVList::Err = item {
+    /// Returns the etag a directory had when it was listed, while its listing merely expired and
+    /// was not marked stale, so the server can confirm the listing without sending it again.
+    pub fn listing_etag(&self, ino: u64) -> Option<&str> {
+        if !self.ino_item_list_map.contains_key(&ino) || self.stale_dirs.contains(&ino) {
             return None;
         }
+        self.listing_etags.get(&ino).map(|x| x.as_str())
+    }
 
-        let next_inode_id = self.next_ino_id;
-        self.next_ino_id += 1;
-        match item {
+    /// Keeps the listing of a directory which the server confirmed unchanged, for `ttl` more.
+    pub fn extend_listing(&mut self, ino: u64, ttl: Option<Duration>) {
+        if self.ino_item_list_map.contains_key(&ino) {
+            self.set_listing_expiry(ino, ttl);
+        }
+    }
+
+    /// Sets the mtime and ctime of a directory whose entries changed to now, so tools which
+    /// poll directory mtimes, like make or file watchers, notice the change.
+    ///
+    /// Note : now is taken from the clock of the server, since the time is compared with the
+    /// mtimes the server sends later, see `keep_touched_times`.
+    fn touch_dir(&mut self, ino: u64) {
+        if let Some(inode_info) = self.ino_info_map.get_mut(&ino) {
+            let now = self.server_clock.now().max(inode_info.file_attr.mtime);
+            inode_info.file_attr.mtime = now;
+            inode_info.file_attr.ctime = now;
+        }
+    }
+
+    /// Sets the access time of a known inode.
+    pub fn set_atime(&mut self, ino: u64, atime: SystemTime) {
+        if let Some(inode_info) = self.ino_info_map.get_mut(&ino) {
+            inode_info.file_attr.atime = atime;
+        }
+    }
+
+    /// Replaces the attributes of a known inode with ones fetched on their own.
+    pub fn update_entry(
+        &mut self,
+        ino: u64,
+        item: &WebDAVList,
+        ttl: Option<Duration>,
+    ) -> Option<&InodeInfo> {
+        let kind = self.ino_info_map.get(&ino)?.file_attr.kind;
+        if kind != Self::webdav_list_kind(item) {
+            return None;
+        }
+        let mut inode_info = self.convert_web_dav_list_to_file_attr(ino, item)?;
+        inode_info.expires_at = ttl.map(|ttl| Instant::now() + ttl);
+        let previous = self.ino_info_map.get(&ino)?;
+        // Note : names are picked among the siblings, so they only change with a listing.
+        inode_info.name = previous.name.clone();
+        Self::keep_touched_times(previous, &mut inode_info);
+        self.insert_info(ino, inode_info);
+        self.ino_info_map.get(&ino)
+    }
+
+    /// Keeps the attributes of a known inode, which could not be confirmed with the server, for
+    /// `ttl` more, so they are not asked for again with every access while the server fails.
+    pub fn extend_entry(&mut self, ino: u64, ttl: Duration) -> Option<&InodeInfo> {
+        let inode_info = self.ino_info_map.get_mut(&ino)?;
+        let now = Instant::now();
+        inode_info.fetched_at = now;
+        inode_info.expires_at = Some(now + ttl);
+        Some(inode_info)
+    }
+
+    /// Servers seldom change the mtime of a collection when its children change, so the times
+    /// of a directory set by `touch_dir` are not taken back by older ones from the server.
+    /// Access times are only known to the mount, so they are kept as well.
+    fn keep_touched_times(previous: &InodeInfo, inode_info: &mut InodeInfo) {
+        let attr = &mut inode_info.file_attr;
+        attr.atime = attr.atime.max(previous.file_attr.atime);
+        if previous.file_attr.kind == FileType::Directory {
+            let attr = &mut inode_info.file_attr;
+            attr.mtime = attr.mtime.max(previous.file_attr.mtime);
+            attr.ctime = attr.ctime.max(previous.file_attr.ctime);
+        }
+    }
+
+    fn remove_subtree(&mut self, ino: u64) {
+        // Note : restored only to find what to remove below it.
+        if self.spilled_dirs.contains_key(&ino) {
+            let _ = self.restore_listing(ino);
+        }
+        self.spilled_dirs.remove(&ino);
+        if let Some(ino_item_list) = self.ino_item_list_map.remove(&ino) {
+            for item_ino in ino_item_list {
+                self.remove_subtree(item_ino);
+            }
+        }
+        self.stale_dirs.remove(&ino);
+        self.dir_expiry.remove(&ino);
+        self.dir_listed_at.remove(&ino);
+        self.listing_etags.remove(&ino);
+        self.ino_parent_map.remove(&ino);
+        self.remove_info(ino);
+    }
+
+    fn insert_info(&mut self, ino: u64, inode_info: InodeInfo) {
+        self.memory_bytes += inode_info.memory_size();
+        if let Some(previous) = self.ino_info_map.insert(ino, inode_info) {
+            self.memory_bytes -= previous.memory_size();
+        }
+    }
+
+    fn remove_info(&mut self, ino: u64) {
+        if let Some(inode_info) = self.ino_info_map.remove(&ino) {
+            self.memory_bytes -= inode_info.memory_size();
+        }
+    }
+
+    fn convert_web_dav_list_to_file_attr(&self, ino: u64, item: &WebDAVList) -> Option<InodeInfo> {
+        let mut inode_info = match item {
             WebDAVList::File(f) => Some(InodeInfo::new(
                 FileAttr {
-                    ino: next_inode_id,
+                    ino,
                     size: f.content_length,
                     blocks: 0,
-                    atime: SystemTime::now(),
-                    mtime: UNIX_EPOCH
-                        + std::time::Duration::from_secs(f.last_modified.timestamp() as u64),
-                    ctime: UNIX_EPOCH
-                        + std::time::Duration::from_secs(f.last_modified.timestamp() as u64),
-                    crtime: UNIX_EPOCH
-                        + std::time::Duration::from_secs(f.last_modified.timestamp() as u64),
+                    atime: file_time::from_remote(&f.last_modified),
+                    mtime: file_time::from_remote(&f.last_modified),
+                    ctime: file_time::from_remote(&f.last_modified),
+                    crtime: file_time::from_remote(&f.last_modified),
                     kind: FileType::RegularFile,
-                    perm: 0o664,
+                    perm: if self.read_only_perms && f.read_only {
+                        0o444
+                    } else {
+                        0o664
+                    },
                     nlink: 2,
                     uid: self.user_id,
                     gid: self.group_id,
@@ -160,21 +676,23 @@ impl InodeInfoMap {
                     blksize: 512,
                 },
                 f.path.clone(),
+                f.encoded_path.clone(),
             )),
             WebDAVList::Folder(d) => Some(InodeInfo::new(
                 FileAttr {
-                    ino: next_inode_id,
+                    ino,
                     size: d.quota_used_bytes.map_or(4096, |x| x as u64),
                     blocks: 0,
-                    atime: SystemTime::now(),
-                    mtime: UNIX_EPOCH
-                        + std::time::Duration::from_secs(d.last_modified.timestamp() as u64),
-                    ctime: UNIX_EPOCH
-                        + std::time::Duration::from_secs(d.last_modified.timestamp() as u64),
-                    crtime: UNIX_EPOCH
-                        + std::time::Duration::from_secs(d.last_modified.timestamp() as u64),
+                    atime: file_time::from_remote(&d.last_modified),
+                    mtime: file_time::from_remote(&d.last_modified),
+                    ctime: file_time::from_remote(&d.last_modified),
+                    crtime: file_time::from_remote(&d.last_modified),
                     kind: FileType::Directory,
-                    perm: 0o755,
+                    perm: if self.read_only_perms && d.read_only {
+                        0o555
+                    } else {
+                        0o755
+                    },
                     nlink: 2,
                     uid: self.user_id,
                     gid: self.group_id,
@@ -183,8 +701,37 @@ impl InodeInfoMap {
                     blksize: 512,
                 },
                 d.path.clone(),
+                d.encoded_path.clone(),
             )),
             _ => None,
+        }?;
+        match item {
+            WebDAVList::File(f) => {
+                inode_info.name = f.name.clone();
+                inode_info.etag = f.etag.clone();
+                inode_info.content_type = Some(f.content_type.clone()).filter(|x| !x.is_empty());
+            }
+            WebDAVList::Folder(d) => {
+                inode_info.name = d.name.clone();
+                inode_info.etag = d.etag.clone();
+            }
+            WebDAVList::Err => {}
+        }
+        Some(inode_info)
+    }
+
+    fn webdav_list_path(item: &WebDAVList) -> &str {
+        match item {
+            WebDAVList::File(f) => &f.path,
+            WebDAVList::Folder(d) => &d.path,
+            WebDAVList::Err => "",
+        }
+    }
+
+    fn webdav_list_kind(item: &WebDAVList) -> FileType {
+        match item {
+            WebDAVList::Folder(_) => FileType::Directory,
+            _ => FileType::RegularFile,
         }
     }
 
@@ -222,3 +769,199 @@ impl InodeInfoMap {
         };
     }
 }
+
+#[cfg(test)]
+mod inode_info_map_test {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::Path, time::Duration};
+
+    use chrono::{TimeZone, Utc};
+
+    use super::InodeInfoMap;
+    use crate::{
+        fs::{listing_spill::ListingSpill, name_source::NameSource},
+        webdav::{WebDAVDirectory, WebDAVFile, WebDAVList},
+    };
+
+    fn folder(path: &str) -> WebDAVList {
+        WebDAVList::Folder(WebDAVDirectory {
+            href: path.to_string(),
+            path: path.to_string(),
+            encoded_path: path.to_string(),
+            name: Path::new(path).file_name().unwrap().to_os_string(),
+            display_name: None,
+            last_modified: Utc.timestamp_opt(1_000_000, 0).unwrap(),
+            quota_used_bytes: None,
+            quota_available_bytes: None,
+            etag: None,
+            read_only: false,
+        })
+    }
+
+    fn file(path: &str, size: u64) -> WebDAVList {
+        WebDAVList::File(WebDAVFile {
+            href: path.to_string(),
+            path: path.to_string(),
+            encoded_path: path.to_string(),
+            name: Path::new(path).file_name().unwrap().to_os_string(),
+            display_name: None,
+            last_modified: Utc.timestamp_opt(1_000_000, 0).unwrap(),
+            content_length: size,
+            content_type: String::new(),
+            etag: None,
+            read_only: false,
+        })
+    }
+
+    fn spill_listing(map: &mut InodeInfoMap, dir: u64) -> bool {
+        let Some(snapshot) = map.snapshot_listing(dir) else {
+            return false;
+        };
+        map.spill().unwrap().write(dir, &snapshot.content).unwrap();
+        map.drop_spilled(&snapshot)
+    }
+
+    #[test]
+    fn touch_changed_dir_test() {
+        let mut map = InodeInfoMap::new(0, 0);
+        map.update_cache(1, vec![folder("/src/")], None, NameSource::Href);
+        let src = map
+            .find_by_path(1, OsStr::new("src"))
+            .unwrap()
+            .file_attr
+            .ino;
+        map.update_cache(src, vec![file("/src/a.c", 1)], None, NameSource::Href);
+        let listed_mtime = map.find_by_ino(src).unwrap().file_attr.mtime;
+
+        map.update_cache(src, vec![file("/src/a.c", 1)], None, NameSource::Href);
+        assert_eq!(map.find_by_ino(src).unwrap().file_attr.mtime, listed_mtime);
+
+        map.update_cache(src, vec![file("/src/a.c", 2)], None, NameSource::Href);
+        let touched_mtime = map.find_by_ino(src).unwrap().file_attr.mtime;
+        assert!(touched_mtime > listed_mtime);
+
+        // Note : the server still sends the old mtime of the directory.
+        let changed = map.update_cache(1, vec![folder("/src/")], None, NameSource::Href);
+        assert!(changed.is_empty());
+        assert_eq!(map.find_by_ino(src).unwrap().file_attr.mtime, touched_mtime);
+    }
+
+    #[test]
+    fn find_by_raw_name_test() {
+        let mut map = InodeInfoMap::new(0, 0);
+        let WebDAVList::File(mut latin1) = file("/caf%E9.txt", 1) else {
+            unreachable!()
+        };
+        latin1.encoded_path = "/caf%E9.txt".to_string();
+        latin1.name = OsStr::from_bytes(b"caf\xe9.txt").to_os_string();
+        map.update_cache(
+            1,
+            vec![WebDAVList::File(latin1), file("/café.txt", 2)],
+            None,
+            NameSource::Href,
+        );
+
+        let found = map
+            .find_by_path(1, OsStr::from_bytes(b"caf\xe9.txt"))
+            .unwrap();
+        assert_eq!(found.file_attr.size, 1);
+        assert_eq!(found.encoded_path, "/caf%E9.txt");
+        let found = map.find_by_path(1, OsStr::new("café.txt")).unwrap();
+        assert_eq!(found.file_attr.size, 2);
+        assert!(map.find_by_path(1, OsStr::new("caf%E9.txt")).is_none());
+    }
+
+    #[test]
+    fn extend_entry_test() {
+        let mut map = InodeInfoMap::new(0, 0);
+        map.update_cache(
+            1,
+            vec![file("/a", 1)],
+            Some(Duration::ZERO),
+            NameSource::Href,
+        );
+        let a = map.find_by_path(1, OsStr::new("a")).unwrap().clone();
+        assert!(a.is_expired());
+
+        let extended = map
+            .extend_entry(a.file_attr.ino, Duration::from_secs(60))
+            .unwrap();
+        assert!(!extended.is_expired());
+        assert!(extended.fetched_at > a.fetched_at);
+        assert!(map
+            .extend_entry(a.file_attr.ino + 1, Duration::ZERO)
+            .is_none());
+    }
+
+    #[test]
+    fn spill_listing_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut map = InodeInfoMap::new(0, 0);
+        map.set_spill(ListingSpill::open(dir.path().join("listings")).unwrap());
+        map.update_cache(
+            1,
+            vec![folder("/a/"), folder("/b/")],
+            None,
+            NameSource::Href,
+        );
+        let a = map.find_by_path(1, OsStr::new("a")).unwrap().file_attr.ino;
+        map.update_cache(
+            a,
+            vec![file("/a/x", 1), file("/a/y", 2)],
+            None,
+            NameSource::Href,
+        );
+        let x = map.find_by_path(a, OsStr::new("x")).unwrap().file_attr.ino;
+        assert_eq!(map.inode_count(), 5);
+
+        // Note : the root listing holds the cached listing of /a, so /a goes first.
+        assert!(!spill_listing(&mut map, 1));
+        assert!(spill_listing(&mut map, a));
+        assert!(spill_listing(&mut map, 1));
+        assert_eq!(map.inode_count(), 1);
+        assert!(map.is_spilled(x) && map.is_spilled(a) && map.is_spilled(1));
+        assert!(map.find_by_ino(x).is_none());
+
+        map.restore(x).unwrap();
+        assert_eq!(map.inode_count(), 5);
+        assert!(!map.is_spilled(x) && !map.is_spilled(a) && !map.is_spilled(1));
+        assert_eq!(map.find_by_ino(x).unwrap().path, "/a/x");
+        assert_eq!(
+            map.find_by_path(a, OsStr::new("y")).unwrap().file_attr.size,
+            2
+        );
+        assert_eq!(map.parent_ino(x), Some(a));
+
+        // Note : a listing which changed while its snapshot was written stays in memory.
+        let snapshot = map.snapshot_listing(a).unwrap();
+        map.update_cache(
+            a,
+            vec![file("/a/x", 3), file("/a/y", 2)],
+            None,
+            NameSource::Href,
+        );
+        assert!(!map.drop_spilled(&snapshot));
+        assert_eq!(map.find_by_ino(x).unwrap().file_attr.size, 3);
+    }
+
+    #[test]
+    fn memory_bytes_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut map = InodeInfoMap::new(0, 0);
+        map.set_spill(ListingSpill::open(dir.path().join("listings")).unwrap());
+        let root_bytes = map.memory_bytes();
+        map.update_cache(1, vec![folder("/a/")], None, NameSource::Href);
+        let a = map.find_by_path(1, OsStr::new("a")).unwrap().file_attr.ino;
+        let listed_bytes = map.memory_bytes();
+        assert!(listed_bytes > root_bytes);
+
+        map.update_cache(a, vec![file("/a/x", 1)], None, NameSource::Href);
+        assert!(map.memory_bytes() > listed_bytes);
+        map.update_cache(a, vec![file("/a/x", 2)], None, NameSource::Href);
+        map.update_cache(a, vec![], None, NameSource::Href);
+        assert_eq!(map.memory_bytes(), listed_bytes);
+
+        assert!(spill_listing(&mut map, a));
+        assert!(spill_listing(&mut map, 1));
+        assert_eq!(map.memory_bytes(), root_bytes);
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+/// Inodes whose error is kept at most, the oldest errors are forgotten beyond that, so a scan of
+/// a tree the server refuses does not grow the map without bound.
+const MAX_LAST_ERRORS: usize = 4096;
+
+/// The error of the last failed operation per inode, until an operation on it succeeds again.
+#[derive(Clone, Default)]
+pub(super) struct LastErrors {
+    errors: Arc<Mutex<LastErrorsInner>>,
+}
+
+#[derive(Default)]
+struct LastErrorsInner {
+    messages: HashMap<u64, String>,
+    /// The inodes of `messages`, the one recorded first at the front.
+    order: VecDeque<u64>,
+}
+
+impl LastErrors {
+    pub fn record(&self, ino: u64, message: String) {
+        let mut errors = self.errors.lock().unwrap();
+        if errors.messages.insert(ino, message).is_some() {
+            errors.order.retain(|x| *x != ino);
+        }
+        errors.order.push_back(ino);
+        while errors.order.len() > MAX_LAST_ERRORS {
+            if let Some(oldest) = errors.order.pop_front() {
+                errors.messages.remove(&oldest);
+            }
+        }
+    }
+
+    /// Forgets the error of `ino`, called when an operation on it succeeded.
+    pub fn clear(&self, ino: u64) {
+        let mut errors = self.errors.lock().unwrap();
+        if errors.messages.remove(&ino).is_some() {
+            errors.order.retain(|x| *x != ino);
+        }
+    }
+
+    pub fn get(&self, ino: u64) -> Option<String> {
+        self.errors.lock().unwrap().messages.get(&ino).cloned()
+    }
+
+    pub fn contains(&self, ino: u64) -> bool {
+        self.errors.lock().unwrap().messages.contains_key(&ino)
+    }
+}
+
+#[cfg(test)]
+mod last_errors_test {
+    use super::{LastErrors, MAX_LAST_ERRORS};
+
+    #[test]
+    fn record_and_clear_test() {
+        let last_errors = LastErrors::default();
+        last_errors.record(2, "403 Forbidden".to_string());
+        last_errors.record(2, "404 Not Found".to_string());
+        assert_eq!(last_errors.get(2).as_deref(), Some("404 Not Found"));
+        assert!(last_errors.contains(2));
+
+        last_errors.clear(2);
+        assert_eq!(last_errors.get(2), None);
+        assert!(!last_errors.contains(2));
+    }
+
+    #[test]
+    fn capacity_test() {
+        let last_errors = LastErrors::default();
+        for ino in 0..MAX_LAST_ERRORS as u64 {
+            last_errors.record(ino, "error".to_string());
+        }
+        // Note : recording again makes an error the newest.
+        last_errors.record(0, "again".to_string());
+        last_errors.record(MAX_LAST_ERRORS as u64, "error".to_string());
+
+        assert!(last_errors.contains(0));
+        assert!(!last_errors.contains(1));
+        assert!(last_errors.contains(MAX_LAST_ERRORS as u64));
+        assert_eq!(
+            last_errors.errors.lock().unwrap().order.len(),
+            MAX_LAST_ERRORS
+        );
+    }
+}
@@ -0,0 +1,278 @@
+use std::{
+    ffi::{OsStr, OsString},
+    fs::DirBuilder,
+    io,
+    os::unix::{
+        ffi::{OsStrExt, OsStringExt},
+        fs::DirBuilderExt,
+    },
+    path::PathBuf,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use fuser::{FileAttr, FileType};
+use urlencoding::{decode, decode_binary};
+
+use super::{file_time, inode_info_map::InodeInfo};
+
+/// Directory listings moved out of memory, one file per directory named after its inode.
+///
+/// Each line is one entry, `<ino> <kind> <perm> <size> <atime> <mtime> <ctime> <crtime>
+/// <fetched at> <expires at or -> <etag or -> <content type or -> <name> <path> <encoded path>`
+/// separated by tabs, with times in nanoseconds since the epoch. Inode numbers only mean
+/// something to the process which assigned them, so the files of a previous mount are removed
+/// when the spill is opened.
+#[derive(Clone)]
+pub(super) struct ListingSpill {
+    dir: PathBuf,
+}
+
+impl ListingSpill {
+    pub fn open(dir: PathBuf) -> io::Result<ListingSpill> {
+        match std::fs::remove_dir_all(&dir) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+        DirBuilder::new().recursive(true).mode(0o700).create(&dir)?;
+        Ok(ListingSpill { dir })
+    }
+
+    /// Encodes the entries of a listing as the content of its file.
+    pub fn encode(entries: &[&InodeInfo]) -> String {
+        entries
+            .iter()
+            .map(|entry| format!("{}\n", encode_entry(entry)))
+            .collect()
+    }
+
+    /// Writes a listing encoded with `encode`. It blocks, so it runs in `spawn_blocking`.
+    pub fn write(&self, dir_ino: u64, content: &str) -> io::Result<()> {
+        std::fs::write(self.path(dir_ino), content)
+    }
+
+    /// Reads the entries of a spilled listing back. `uid` and `gid` are the owner of every
+    /// inode of the mount.
+    pub fn read(&self, dir_ino: u64, uid: u32, gid: u32) -> io::Result<Vec<InodeInfo>> {
+        std::fs::read_to_string(self.path(dir_ino))?
+            .lines()
+            .map(|line| {
+                decode_entry(line, uid, gid).ok_or(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed spilled entry of directory {}", dir_ino),
+                ))
+            })
+            .collect()
+    }
+
+    pub fn remove(&self, dir_ino: u64) {
+        let _ = std::fs::remove_file(self.path(dir_ino));
+    }
+
+    fn path(&self, dir_ino: u64) -> PathBuf {
+        self.dir.join(dir_ino.to_string())
+    }
+}
+
+fn encode_entry(entry: &InodeInfo) -> String {
+    let attr = &entry.file_attr;
+    let fields = [
+        attr.ino.to_string(),
+        match attr.kind {
+            FileType::Directory => "d".to_string(),
+            _ => "f".to_string(),
+        },
+        attr.perm.to_string(),
+        attr.size.to_string(),
+        file_time::to_nanos(attr.atime).to_string(),
+        file_time::to_nanos(attr.mtime).to_string(),
+        file_time::to_nanos(attr.ctime).to_string(),
+        file_time::to_nanos(attr.crtime).to_string(),
+        instant_to_nanos(entry.fetched_at).to_string(),
+        entry
+            .expires_at
+            .map_or("-".to_string(), |x| instant_to_nanos(x).to_string()),
+        entry.etag.as_deref().map_or("-".to_string(), escape),
+        entry
+            .content_type
+            .as_deref()
+            .map_or("-".to_string(), escape),
+        escape_name(&entry.name),
+        escape(&entry.path),
+        escape(&entry.encoded_path),
+    ];
+    fields.join("\t")
+}
+
+fn decode_entry(line: &str, uid: u32, gid: u32) -> Option<InodeInfo> {
+    let mut fields = line.split('\t');
+    let ino = fields.next()?;
+    let kind = fields.next()?;
+    let perm = fields.next()?;
+    let size = fields.next()?;
+    let atime = fields.next()?;
+    let mtime = fields.next()?;
+    let ctime = fields.next()?;
+    let crtime = fields.next()?;
+    let fetched_at = fields.next()?;
+    let expires_at = fields.next()?;
+    let etag = fields.next()?;
+    let content_type = fields.next()?;
+    let name = fields.next()?;
+    let path = fields.next()?;
+    let encoded_path = fields.next()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    let file_attr = FileAttr {
+        ino: ino.parse().ok()?,
+        size: size.parse().ok()?,
+        blocks: 0,
+        atime: file_time::from_nanos(atime.parse().ok()?),
+        mtime: file_time::from_nanos(mtime.parse().ok()?),
+        ctime: file_time::from_nanos(ctime.parse().ok()?),
+        crtime: file_time::from_nanos(crtime.parse().ok()?),
+        kind: match kind {
+            "d" => FileType::Directory,
+            "f" => FileType::RegularFile,
+            _ => return None,
+        },
+        perm: perm.parse().ok()?,
+        nlink: 2,
+        uid,
+        gid,
+        rdev: 0,
+        flags: 0,
+        blksize: 512,
+    };
+    let mut entry = InodeInfo::new(file_attr, unescape(path)?, unescape(encoded_path)?);
+    entry.name = unescape_name(name);
+    entry.etag = match etag {
+        "-" => None,
+        etag => Some(unescape(etag)?),
+    };
+    entry.content_type = match content_type {
+        "-" => None,
+        content_type => Some(unescape(content_type)?),
+    };
+    entry.fetched_at = nanos_to_instant(fetched_at.parse().ok()?);
+    entry.expires_at = match expires_at {
+        "-" => None,
+        expires_at => Some(nanos_to_instant(expires_at.parse().ok()?)),
+    };
+    Some(entry)
+}
+
+/// Escapes the characters which separate fields and lines. `%` is escaped too, so percent
+/// sequences already in a name survive the round trip.
+fn escape(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\t', "%09")
+        .replace('\n', "%0A")
+        .replace('\r', "%0D")
+}
+
+fn unescape(value: &str) -> Option<String> {
+    decode(value).ok().map(|x| x.into_owned())
+}
+
+/// Like `escape`, for names, which need not be UTF-8. Bytes past ASCII are percent-encoded.
+fn escape_name(name: &OsStr) -> String {
+    name.as_bytes()
+        .iter()
+        .map(|&byte| match byte {
+            b'%' | b'\t' | b'\n' | b'\r' | 0x80.. => format!("%{:02X}", byte),
+            _ => (byte as char).to_string(),
+        })
+        .collect()
+}
+
+fn unescape_name(value: &str) -> OsString {
+    OsString::from_vec(decode_binary(value.as_bytes()).into_owned())
+}
+
+// Note : an `Instant` has no meaning outside of the process, so it is stored as the wall clock
+// time it corresponds to now.
+fn instant_to_nanos(instant: Instant) -> u64 {
+    let now = SystemTime::now();
+    let time = match instant.checked_duration_since(Instant::now()) {
+        Some(ahead) => now + ahead,
+        None => now
+            .checked_sub(Instant::now().duration_since(instant))
+            .unwrap_or(UNIX_EPOCH),
+    };
+    file_time::to_nanos(time)
+}
+
+fn nanos_to_instant(nanos: u64) -> Instant {
+    let time = file_time::from_nanos(nanos);
+    let now = Instant::now();
+    match time.duration_since(SystemTime::now()) {
+        Ok(ahead) => now + ahead,
+        Err(err) => now.checked_sub(err.duration()).unwrap_or(now),
+    }
+}
+
+#[cfg(test)]
+mod listing_spill_test {
+    use std::{
+        ffi::OsStr,
+        os::unix::ffi::OsStrExt,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    };
+
+    use fuser::{FileAttr, FileType};
+
+    use super::{decode_entry, encode_entry};
+    use crate::fs::inode_info_map::InodeInfo;
+
+    #[test]
+    fn entry_round_trip_test() {
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let file_attr = FileAttr {
+            ino: 42,
+            size: 1234,
+            blocks: 0,
+            atime: SystemTime::now(),
+            mtime,
+            ctime: mtime + Duration::from_nanos(5),
+            crtime: mtime,
+            kind: FileType::RegularFile,
+            perm: 0o664,
+            nlink: 2,
+            uid: 1000,
+            gid: 100,
+            rdev: 0,
+            flags: 0,
+            blksize: 512,
+        };
+        let mut entry = InodeInfo::new(
+            file_attr,
+            "/a\tb/50%.txt".to_string(),
+            "/a%09b/50%25.txt".to_string(),
+        );
+        entry.name = OsStr::from_bytes(b"50%\xe9.txt\n(2)").to_os_string();
+        entry.etag = Some("\"abc\"".to_string());
+        entry.content_type = Some("text/plain".to_string());
+        entry.expires_at = Some(Instant::now() + Duration::from_secs(60));
+
+        let line = encode_entry(&entry);
+        assert_eq!(line.lines().count(), 1);
+        let decoded = decode_entry(&line, 1000, 100).unwrap();
+        assert_eq!(decoded.file_attr.ino, 42);
+        assert_eq!(decoded.file_attr.size, 1234);
+        assert_eq!(decoded.file_attr.kind, FileType::RegularFile);
+        assert_eq!(decoded.file_attr.atime, entry.file_attr.atime);
+        assert_eq!(decoded.file_attr.mtime, entry.file_attr.mtime);
+        assert_eq!(decoded.file_attr.ctime, entry.file_attr.ctime);
+        assert_eq!(decoded.path, entry.path);
+        assert_eq!(decoded.encoded_path, entry.encoded_path);
+        assert_eq!(decoded.name, entry.name);
+        assert_eq!(decoded.etag, entry.etag);
+        assert_eq!(decoded.content_type, entry.content_type);
+        let expires_in = decoded.expires_at.unwrap() - Instant::now();
+        assert!(expires_in > Duration::from_secs(58) && expires_in <= Duration::from_secs(60));
+
+        assert!(decode_entry("42\tf", 1000, 100).is_none());
+    }
+}
@@ -0,0 +1,95 @@
+use std::time::SystemTime;
+
+use super::file_time;
+
+/// A remote file and the version of it, one line of a cache manifest.
+///
+/// Manifests list what a cache holds so another cache directory can be filled with the same
+/// files, e.g. to prepare a laptop before it leaves the office network. Each line is
+/// `<size> <mtime seconds[.nanoseconds]> <etag or -> <path>`, the path last since it may contain
+/// spaces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub path: String,
+    pub size: u64,
+    pub mtime: SystemTime,
+    pub etag: Option<String>,
+}
+
+impl ManifestEntry {
+    pub fn to_line(&self) -> String {
+        format!(
+            "{} {} {} {}",
+            self.size,
+            file_time::to_secs_string(self.mtime),
+            self.etag.as_deref().unwrap_or("-"),
+            self.path
+        )
+    }
+
+    pub fn parse(line: &str) -> Option<ManifestEntry> {
+        let mut fields = line.splitn(4, ' ');
+        let size = fields.next()?.parse().ok()?;
+        let mtime = file_time::parse_secs_string(fields.next()?)?;
+        let etag = match fields.next()? {
+            "-" => None,
+            etag => Some(etag.to_string()),
+        };
+        let path = fields.next()?.to_string();
+        if !path.starts_with('/') {
+            return None;
+        }
+        Some(ManifestEntry {
+            path,
+            size,
+            mtime,
+            etag,
+        })
+    }
+}
+
+/// Parses a manifest, skipping blank lines and `#` comments. Returns the number of the first
+/// malformed line as the error.
+pub fn parse_manifest(text: &str) -> Result<Vec<ManifestEntry>, usize> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|(index, line)| ManifestEntry::parse(line).ok_or(index + 1))
+        .collect()
+}
+
+#[cfg(test)]
+mod manifest_test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{parse_manifest, ManifestEntry};
+
+    #[test]
+    fn parse_manifest_test() {
+        let entries = vec![
+            ManifestEntry {
+                path: "/maps/region north.mbtiles".to_string(),
+                size: 734_003_200,
+                mtime: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+                etag: Some("\"5f3a-1b2c\"".to_string()),
+            },
+            ManifestEntry {
+                path: "/forms/inspection.pdf".to_string(),
+                size: 0,
+                mtime: UNIX_EPOCH,
+                etag: None,
+            },
+        ];
+        let text: String = entries
+            .iter()
+            .map(|entry| format!("{}\n", entry.to_line()))
+            .collect();
+        assert_eq!(
+            parse_manifest(&format!("# fusedav-rs\n\n{}", text)),
+            Ok(entries)
+        );
+
+        assert_eq!(parse_manifest("1 2 - /ok\n1 2 - relative/path\n"), Err(2));
+        assert_eq!(parse_manifest("size 2 - /file\n"), Err(1));
+    }
+}
@@ -0,0 +1,29 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use super::mount_guard::MountHandle;
+
+/// Keeps remote subtrees hydrated in the cache, a one-way sync while the mount stays live.
+///
+/// Every `interval`, starting at mount time, each of `paths` is pinned again. The pin queue
+/// walks the subtree anew, so files added on the server are downloaded, files changed on the
+/// server replace their cached data, and the blocks of unchanged files are not downloaded
+/// again. A round is skipped while the pin queue is still busy, e.g. with the previous round.
+pub async fn mirror(handle: MountHandle, paths: Vec<String>, interval: Duration) {
+    let mut round_start = Instant::now();
+    loop {
+        tokio::time::sleep_until(round_start).await;
+        let status = handle.pin_status();
+        if status.pending > 0 || status.active > 0 {
+            eprintln!("Mirror round skipped, pinning is still busy: {}", status);
+        } else {
+            for path in paths.iter() {
+                if let Err(e) = handle.pin(path).await {
+                    eprintln!("Mirror Error: {} {:?}", path, e);
+                }
+            }
+        }
+        round_start = (round_start + interval).max(Instant::now());
+    }
+}
@@ -1,8 +1,57 @@
 pub mod errors;
 
+mod atime_mode;
+mod atomic_file;
+mod cache_export;
+mod cache_health;
+mod cache_namespace;
+mod cache_policy;
+mod content_rules;
+mod file_checksum;
+mod file_size_limit;
+mod file_time;
+mod growing_files;
+mod health_report;
+mod hooks;
+mod inflight_ops;
 mod inode_info_map;
+mod last_errors;
+mod listing_spill;
+mod manifest;
+mod mirror;
+mod mount_guard;
+mod mount_stats;
+mod name_source;
+mod path_stats;
+mod pin_queue;
+mod profile;
+mod prometheus;
+mod single_flight;
+mod slow_ops;
+mod sync_rules;
+mod versions;
+mod watcher;
 mod webdav_fs;
-mod webdav_fs_file_downloader;
 mod webdav_fs_explorer;
+mod webdav_fs_file_downloader;
 
+pub use atime_mode::AtimeMode;
+pub use cache_export::{export_cached_file, CacheExport};
+pub use cache_namespace::CacheNamespace;
+pub use cache_policy::CachePolicy;
+pub use content_rules::ContentRules;
+pub use file_size_limit::{FileSizeLimit, OversizedFiles};
+pub use growing_files::GrowingFiles;
+pub use health_report::{health_report, HealthReport};
+pub use hooks::{HookEvent, Hooks};
+pub use manifest::{parse_manifest, ManifestEntry};
+pub use mirror::mirror;
+pub use mount_guard::*;
+pub use mount_stats::*;
+pub use name_source::NameSource;
+pub use path_stats::PathStat;
+pub use pin_queue::{ManifestImport, PinStatus};
+pub use profile::{Profile, Tuning};
+pub use sync_rules::SyncRules;
+pub use watcher::watch;
 pub use webdav_fs::*;
@@ -0,0 +1,279 @@
+use std::{
+    io,
+    path::Path,
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
+
+use fuser::{BackgroundSession, MountOption, Notifier};
+use tokio::sync::{watch, Notify};
+
+use super::{
+    errors::FSError,
+    health_report::{ErrorCounts, HealthReport},
+    inflight_ops::{self, InflightOps},
+    inode_info_map::InodeInfo,
+    manifest::ManifestEntry,
+    mount_stats::MountStats,
+    path_stats::PathStats,
+    pin_queue::{ManifestImport, PinQueue, PinStatus},
+    webdav_fs::WebDAVFS,
+    webdav_fs_explorer::{InvalidatedEntry, WebDAVFSExplorer},
+    webdav_fs_file_downloader::WebDAVFSFileDownloader,
+};
+
+const TOP_PATHS_COUNT: usize = 20;
+
+/// A running mount. The filesystem is unmounted when the guard is dropped.
+pub struct MountGuard {
+    session: Option<BackgroundSession>,
+    handle: MountHandle,
+    terminated: watch::Receiver<bool>,
+}
+
+/// A cloneable handle to operate on a running mount, e.g. from the ctl socket.
+#[derive(Clone)]
+pub struct MountHandle {
+    explorer: WebDAVFSExplorer,
+    downloader: WebDAVFSFileDownloader,
+    path_stats: PathStats,
+    inflight_ops: InflightOps,
+    error_counts: ErrorCounts,
+    mounted_at: Instant,
+    pins: PinQueue,
+    notifier: Notifier,
+    unmount_request: Arc<Notify>,
+}
+
+/// Mounts the filesystem on background threads and returns immediately.
+pub fn mount(
+    webdavfs: WebDAVFS,
+    mount_path: &Path,
+    options: &[MountOption],
+) -> io::Result<MountGuard> {
+    let explorer = webdavfs.explorer().clone();
+    let downloader = webdavfs.downloader().clone();
+    let path_stats = webdavfs.path_stats().clone();
+    let inflight_ops = webdavfs.inflight_ops().clone();
+    let error_counts = webdavfs.error_counts().clone();
+    let terminated = webdavfs.subscribe_terminated();
+    let pins = webdavfs.start_pin_queue();
+    let session = fuser::spawn_mount2(webdavfs, mount_path, options)?;
+    let notifier = session.notifier();
+    Ok(MountGuard {
+        session: Some(session),
+        handle: MountHandle {
+            explorer,
+            downloader,
+            path_stats,
+            inflight_ops,
+            error_counts,
+            mounted_at: Instant::now(),
+            pins,
+            notifier,
+            unmount_request: Arc::new(Notify::new()),
+        },
+        terminated,
+    })
+}
+
+impl MountGuard {
+    /// Unmounts the filesystem and waits for the session threads to finish.
+    pub async fn unmount(mut self) -> io::Result<()> {
+        if let Some(session) = self.session.take() {
+            tokio::task::spawn_blocking(move || session.join())
+                .await
+                .map_err(io::Error::other)?;
+        }
+        Ok(())
+    }
+
+    pub fn handle(&self) -> MountHandle {
+        self.handle.clone()
+    }
+
+    pub async fn flush(&self) -> Result<(), FSError> {
+        self.handle.flush().await
+    }
+
+    pub async fn stats(&self) -> MountStats {
+        self.handle.stats().await
+    }
+
+    /// Waits until the session ends, e.g. when unmounted externally with `fusermount -u`.
+    pub async fn terminated(&mut self) {
+        let _ = self.terminated.wait_for(|terminated| *terminated).await;
+    }
+}
+
+impl MountHandle {
+    pub async fn flush(&self) -> Result<(), FSError> {
+        self.downloader.flush().await
+    }
+
+    pub async fn stats(&self) -> MountStats {
+        let (inodes, cached_directories) = self.explorer.cache_counts().await;
+        let (cached_files, cached_bytes) = self.downloader.cache_usage().await;
+        let (file_handle_memory_bytes, inflight_bytes) = self.downloader.memory_usage().await;
+        MountStats {
+            inodes,
+            cached_directories,
+            cached_files,
+            cached_bytes,
+            inode_memory_bytes: self.explorer.memory_bytes().await,
+            file_handle_memory_bytes,
+            inflight_bytes,
+            download_concurrency: self.downloader.client().download_concurrency(),
+            top_paths: self.path_stats.top(TOP_PATHS_COUNT),
+        }
+    }
+
+    /// Returns the anonymous health report of the mount, see `health_report`.
+    pub async fn health(&self) -> HealthReport {
+        let (inodes, cached_directories) = self.explorer.cache_counts().await;
+        let (cached_files, cached_bytes) = self.downloader.cache_usage().await;
+        HealthReport {
+            written_at: SystemTime::now(),
+            uptime: self.mounted_at.elapsed(),
+            errors: self.error_counts.snapshot(),
+            inodes,
+            cached_directories,
+            cached_files,
+            cached_bytes,
+            cache_bypassed: self.downloader.cache_health().is_bypassed(),
+            last_server_contact: self.downloader.client().last_contact(),
+            server_clock_offset_secs: self.downloader.client().clock().offset_millis() / 1000,
+        }
+    }
+
+    /// Describes the operations in flight with the path they work on and how long they have
+    /// been running, the longest running first.
+    pub fn dump_ops(&self) -> String {
+        inflight_ops::format_dump(&self.inflight_ops.snapshot(), Instant::now(), |ino| {
+            self.explorer.try_path_of(ino)
+        })
+    }
+
+    /// Drops the cached listing and cache files of a remote path and tells the kernel to forget
+    /// what it cached, so the next access fetches it from the server again.
+    pub async fn invalidate(&self, path: &str) -> Result<(), FSError> {
+        let entry = self.explorer.invalidate(path).await?;
+        self.downloader.evict(path).await;
+        self.notify_kernel(vec![entry]).await
+    }
+
+    /// Rewrites the cache files without their partially downloaded blocks, which give back their
+    /// space. Returns the number of files which were rewritten and the bytes of disk space
+    /// reclaimed.
+    pub async fn compact_cache(&self) -> Result<(usize, u64), FSError> {
+        self.downloader.compact().await
+    }
+
+    /// Lists every cached directory again and drops the cache of entries which changed on the
+    /// server. Returns the remote paths of the changed entries.
+    pub async fn poll_changes(&self) -> Result<Vec<String>, FSError> {
+        let mut paths = Vec::new();
+        for dir in self.dirs_to_refresh().await {
+            match self.refresh_dir(&dir).await {
+                Ok(changed_paths) => paths.extend(changed_paths),
+                Err(e) => eprintln!("Refresh Error: {} {:?}", dir.path, e),
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Returns the cached directories in the order they should be refreshed.
+    pub(super) async fn dirs_to_refresh(&self) -> Vec<InodeInfo> {
+        self.explorer.cached_dirs_by_access().await
+    }
+
+    /// Lists a cached directory again and drops the cache of entries which changed on the
+    /// server. Returns the remote paths of the changed entries.
+    pub(super) async fn refresh_dir(&self, dir: &InodeInfo) -> Result<Vec<String>, FSError> {
+        let entries = self.explorer.refresh_dir(dir).await?;
+        for entry in entries.iter() {
+            self.downloader.evict(&entry.path).await;
+        }
+        let paths = entries.iter().map(|entry| entry.path.clone()).collect();
+        self.notify_kernel(entries).await?;
+        Ok(paths)
+    }
+
+    /// Lists a remote directory and every directory below it into the mount. Returns the number
+    /// of directories and entries.
+    pub async fn prefetch(&self, path: &str) -> Result<(usize, usize), FSError> {
+        self.explorer.clone().prefetch(path).await
+    }
+
+    /// Queues a remote file or directory to be downloaded into the cache in the background.
+    pub async fn pin(&self, path: &str) -> Result<(), FSError> {
+        self.pins.pin(path).await
+    }
+
+    /// Takes a complete local copy of the remote file at `path`, e.g. one made with rsync, as
+    /// its cache, so it is not downloaded again.
+    pub async fn adopt(&self, path: &str, local_path: &str) -> Result<(), FSError> {
+        self.pins.adopt(path, local_path).await
+    }
+
+    /// Lists the cached files with the remote version they belong to.
+    pub async fn export_manifest(&self) -> Vec<ManifestEntry> {
+        self.downloader.manifest().await
+    }
+
+    /// Queues the files of a manifest exported from another cache to be downloaded.
+    pub async fn import_manifest(&self, entries: Vec<ManifestEntry>) -> ManifestImport {
+        self.pins.pin_manifest(entries).await
+    }
+
+    /// Writes the cache files to disk and asks the process owning the mount to unmount it.
+    /// Unless `force` is set, the mount stays when the cache can not be written.
+    pub async fn request_unmount(&self, force: bool) -> Result<(), FSError> {
+        if let Err(err) = self.flush().await {
+            if !force {
+                return Err(err);
+            }
+            eprintln!("Flush Error before unmount: {:?}", err);
+        }
+        self.unmount_request.notify_one();
+        Ok(())
+    }
+
+    /// Waits until an unmount is requested through `request_unmount`.
+    pub async fn unmount_requested(&self) {
+        self.unmount_request.notified().await
+    }
+
+    pub fn pause_pins(&self) {
+        self.pins.pause();
+    }
+
+    pub fn resume_pins(&self) {
+        self.pins.resume();
+    }
+
+    pub fn pin_status(&self) -> PinStatus {
+        self.pins.status()
+    }
+
+    async fn notify_kernel(&self, entries: Vec<InvalidatedEntry>) -> Result<(), FSError> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        // Note : notifications write to /dev/fuse and may wait for in-flight requests.
+        let notifier = self.notifier.clone();
+        tokio::task::spawn_blocking(move || {
+            for entry in entries {
+                // Note : errors only mean that the kernel has nothing cached for the inode.
+                let _ = notifier.inval_inode(entry.ino, 0, 0);
+                if entry.ino != entry.parent {
+                    let _ = notifier.inval_entry(entry.parent, &entry.name);
+                    let _ = notifier.inval_inode(entry.parent, 0, 0);
+                }
+            }
+        })
+        .await
+        .map_err(|e| FSError::IO(io::Error::other(e)))
+    }
+}
@@ -0,0 +1,19 @@
+use super::path_stats::PathStat;
+
+#[derive(Debug, Clone, Default)]
+pub struct MountStats {
+    pub inodes: usize,
+    pub cached_directories: usize,
+    pub cached_files: usize,
+    pub cached_bytes: u64,
+    /// Approximate bytes of memory taken by the known inodes.
+    pub inode_memory_bytes: usize,
+    /// Approximate bytes of memory taken by the handles of the cache files.
+    pub file_handle_memory_bytes: usize,
+    /// Response bytes held in memory by downloads in flight.
+    pub inflight_bytes: usize,
+    /// Downloads currently allowed to run at once by the adaptive limit.
+    pub download_concurrency: usize,
+    /// Remote paths with the most traffic, heaviest first.
+    pub top_paths: Vec<(String, PathStat)>,
+}
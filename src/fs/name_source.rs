@@ -0,0 +1,104 @@
+use std::{
+    collections::HashSet,
+    ffi::{OsStr, OsString},
+    fmt::Display,
+    path::Path,
+    str::FromStr,
+};
+
+use crate::webdav::WebDAVList;
+
+/// Where the names of files in the mount come from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NameSource {
+    /// The last segment of the href, which is what most servers show as the name.
+    #[default]
+    Href,
+    /// The `displayname` property, for gateways whose hrefs are ids. Entries without one keep
+    /// the href name.
+    DisplayName,
+}
+
+impl FromStr for NameSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "href" => Ok(NameSource::Href),
+            "displayname" => Ok(NameSource::DisplayName),
+            _ => Err(format!(
+                "invalid name source {:?}, expected href or displayname",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for NameSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NameSource::Href => write!(f, "href"),
+            NameSource::DisplayName => write!(f, "displayname"),
+        }
+    }
+}
+
+impl NameSource {
+    /// Picks the file name of a listed item, `href_name` being the last segment of its path.
+    pub(super) fn name_of(&self, item: &WebDAVList, href_name: &OsStr) -> OsString {
+        let display_name = match self {
+            NameSource::Href => None,
+            NameSource::DisplayName => item.display_name(),
+        };
+        // Note : a display name is free text, it may contain slashes or be no valid name at all.
+        match display_name.map(|x| x.trim().replace(['/', '\0'], "_")) {
+            Some(name) if !name.is_empty() && name != "." && name != ".." => OsString::from(name),
+            _ => href_name.to_os_string(),
+        }
+    }
+}
+
+/// Returns `name`, or `name (2)`, `name (3)`, ... before the extension if a sibling already
+/// has it, and records the returned name in `used`.
+pub(super) fn unique_name(used: &mut HashSet<OsString>, name: OsString) -> OsString {
+    if used.insert(name.clone()) {
+        return name;
+    }
+
+    let path = Path::new(&name);
+    let stem = path.file_stem().unwrap_or(&name);
+    let extension = path.extension();
+    (2..)
+        .map(|n| {
+            let mut candidate = stem.to_os_string();
+            candidate.push(format!(" ({})", n));
+            if let Some(extension) = extension {
+                candidate.push(".");
+                candidate.push(extension);
+            }
+            candidate
+        })
+        .find(|candidate| used.insert(candidate.clone()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod name_source_test {
+    use std::collections::HashSet;
+
+    use super::unique_name;
+
+    #[test]
+    fn unique_name_test() {
+        let mut used = HashSet::new();
+        assert_eq!(unique_name(&mut used, "Plan.docx".into()), "Plan.docx");
+        assert_eq!(unique_name(&mut used, "Plan.docx".into()), "Plan (2).docx");
+        assert_eq!(
+            unique_name(&mut used, "Plan (2).docx".into()),
+            "Plan (2) (2).docx"
+        );
+        assert_eq!(unique_name(&mut used, "Plan.docx".into()), "Plan (3).docx");
+        assert_eq!(unique_name(&mut used, "Notes".into()), "Notes");
+        assert_eq!(unique_name(&mut used, "Notes".into()), "Notes (2)");
+    }
+}
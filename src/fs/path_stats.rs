@@ -0,0 +1,79 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// Number of remote paths tracked at once. When full, the least active path is replaced and the
+/// new path inherits its counters, which over-estimates new paths but never drops a heavy one
+/// (the space-saving algorithm).
+const PATH_STATS_CAPACITY: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PathStat {
+    pub read_bytes: u64,
+    pub read_requests: u64,
+    pub remote_requests: u64,
+}
+
+impl PathStat {
+    fn weight(&self) -> u64 {
+        self.read_bytes + self.remote_requests
+    }
+}
+
+#[derive(Clone)]
+pub(super) struct PathStats {
+    entries: Arc<Mutex<HashMap<String, PathStat>>>,
+}
+
+impl PathStats {
+    pub fn new() -> PathStats {
+        PathStats {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn record_read(&self, path: &str, bytes: u64) {
+        self.update(path, |stat| {
+            stat.read_bytes += bytes;
+            stat.read_requests += 1;
+        });
+    }
+
+    pub fn record_remote_request(&self, path: &str) {
+        self.update(path, |stat| stat.remote_requests += 1);
+    }
+
+    /// Returns the `n` paths with the most traffic, heaviest first.
+    pub fn top(&self, n: usize) -> Vec<(String, PathStat)> {
+        let entries = self.entries.lock().unwrap();
+        let mut result: Vec<(String, PathStat)> = entries
+            .iter()
+            .map(|(path, stat)| (path.clone(), *stat))
+            .collect();
+        result.sort_by(|l, r| r.1.weight().cmp(&l.1.weight()).then(l.0.cmp(&r.0)));
+        result.truncate(n);
+        result
+    }
+
+    fn update<F: FnOnce(&mut PathStat)>(&self, path: &str, update: F) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(stat) = entries.get_mut(path) {
+            update(stat);
+            return;
+        }
+
+        let mut stat = PathStat::default();
+        if entries.len() >= PATH_STATS_CAPACITY {
+            let min_path = entries
+                .iter()
+                .min_by_key(|(_, stat)| stat.weight())
+                .map(|(path, _)| path.clone());
+            if let Some(min_path) = min_path {
+                stat = entries.remove(&min_path).unwrap();
+            }
+        }
+        update(&mut stat);
+        entries.insert(path.to_string(), stat);
+    }
+}
@@ -0,0 +1,480 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{runtime::Handle, sync::Notify};
+
+use super::{
+    atomic_file::write_atomic,
+    errors::FSError,
+    file_size_limit::FileSizeLimit,
+    file_time,
+    manifest::ManifestEntry,
+    sync_rules::SyncRules,
+    webdav_fs_file_downloader::{RemoteFile, WebDAVFSFileDownloader},
+};
+use crate::webdav::{encode_path, Error as WebDAVError, StatOptions, WebDAVClient, WebDAVList};
+
+pub const DEFAULT_PIN_WORKERS: usize = 4;
+const PIN_STATE_FILE_NAME: &str = "pins.state";
+/// How often the workers look again whether the cache works, while reads do not use it.
+const CACHE_BYPASS_WAIT: Duration = Duration::from_secs(5);
+
+/// A pinned item with the path the server sent for it, which requests are sent to. Encoding the
+/// decoded path again would not give back the bytes of names which are no valid UTF-8.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PinItem {
+    Dir {
+        path: String,
+        encoded_path: String,
+    },
+    File {
+        file: ManifestEntry,
+        encoded_path: String,
+    },
+}
+
+impl PinItem {
+    fn from(item: WebDAVList) -> Option<PinItem> {
+        match item {
+            WebDAVList::File(f) => Some(PinItem::File {
+                file: ManifestEntry {
+                    path: f.path,
+                    size: f.content_length,
+                    // Note : same precision as the inode attributes, so the downloader sees one
+                    // mtime.
+                    mtime: file_time::from_remote(&f.last_modified),
+                    etag: f.etag,
+                },
+                encoded_path: f.encoded_path,
+            }),
+            WebDAVList::Folder(d) => Some(PinItem::Dir {
+                path: d.path,
+                encoded_path: d.encoded_path,
+            }),
+            WebDAVList::Err => None,
+        }
+    }
+
+    fn path(&self) -> &str {
+        match self {
+            PinItem::Dir { path, .. } => path,
+            PinItem::File { file, .. } => &file.path,
+        }
+    }
+
+    /// `dir <encoded path> <path>` or `file <encoded path> <manifest line>`. The encoded path
+    /// goes first since it has no spaces.
+    fn to_line(&self) -> String {
+        match self {
+            PinItem::Dir { path, encoded_path } => format!("dir {} {}", encoded_path, path),
+            PinItem::File { file, encoded_path } => {
+                format!("file {} {}", encoded_path, file.to_line())
+            }
+        }
+    }
+
+    fn parse(line: &str) -> Option<PinItem> {
+        let (kind, rest) = line.split_once(' ')?;
+        let (encoded_path, rest) = rest.split_once(' ')?;
+        if !encoded_path.starts_with('/') {
+            return None;
+        }
+        let encoded_path = encoded_path.to_string();
+        match kind {
+            "dir" if rest.starts_with('/') => Some(PinItem::Dir {
+                path: rest.to_string(),
+                encoded_path,
+            }),
+            "file" => ManifestEntry::parse(rest).map(|file| PinItem::File { file, encoded_path }),
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of queueing the files of a manifest.
+#[derive(Debug, Clone, Default)]
+pub struct ManifestImport {
+    pub queued: usize,
+    /// Files queued in a newer version than the one listed in the manifest.
+    pub changed: usize,
+    pub missing: usize,
+}
+
+impl Display for ManifestImport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} files queued ({} changed on the server), {} missing",
+            self.queued, self.changed, self.missing
+        )
+    }
+}
+
+/// Progress of the pin queue.
+#[derive(Debug, Clone, Default)]
+pub struct PinStatus {
+    pub pending: usize,
+    pub active: usize,
+    pub hydrated_files: u64,
+    pub hydrated_bytes: u64,
+    pub failed: u64,
+    pub paused: bool,
+}
+
+impl Display for PinStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}, {} pending, {} active, {} files ({} bytes) hydrated, {} failed",
+            if self.paused { "paused" } else { "running" },
+            self.pending,
+            self.active,
+            self.hydrated_files,
+            self.hydrated_bytes,
+            self.failed
+        )
+    }
+}
+
+#[derive(Default)]
+struct PinState {
+    pending: VecDeque<PinItem>,
+    active: Vec<PinItem>,
+    hydrated_files: u64,
+    hydrated_bytes: u64,
+    failed: u64,
+    paused: bool,
+}
+
+impl PinState {
+    fn load(path: &Path) -> io::Result<PinState> {
+        let mut state = PinState::default();
+        for line in std::fs::read_to_string(path)?.lines() {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "paused" => state.paused = value == "1",
+                "hydrated_files" => state.hydrated_files = value.parse().unwrap_or(0),
+                "hydrated_bytes" => state.hydrated_bytes = value.parse().unwrap_or(0),
+                "failed" => state.failed = value.parse().unwrap_or(0),
+                "pending" => {
+                    if let Some(item) = PinItem::parse(value) {
+                        state.pending.push_back(item);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(state)
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        let mut content = format!(
+            "paused {}\nhydrated_files {}\nhydrated_bytes {}\nfailed {}\n",
+            self.paused as u8, self.hydrated_files, self.hydrated_bytes, self.failed
+        );
+        // Note : items being worked on are saved as pending, so they are redone after a crash.
+        for item in self.active.iter().chain(self.pending.iter()) {
+            content.push_str(&format!("pending {}\n", item.to_line()));
+        }
+
+        write_atomic(path, content.as_bytes())
+    }
+}
+
+/// Hydrates pinned files and directories in the background with a fixed number of workers.
+///
+/// Directories are expanded when a worker takes them, and their entries go to the front of the
+/// queue, so the queue only holds the entries of the directories being walked. The queue is
+/// saved in the cache directory after every item, so pinning continues after a restart.
+#[derive(Clone)]
+pub(super) struct PinQueue {
+    client: WebDAVClient,
+    downloader: WebDAVFSFileDownloader,
+    sync_rules: SyncRules,
+    file_size_limit: FileSizeLimit,
+    state_path: PathBuf,
+    state: Arc<Mutex<PinState>>,
+    wakeup: Arc<Notify>,
+}
+
+impl PinQueue {
+    pub fn new(
+        downloader: WebDAVFSFileDownloader,
+        sync_rules: SyncRules,
+        file_size_limit: FileSizeLimit,
+    ) -> PinQueue {
+        let client = downloader.client().clone();
+        let state_path = Path::new(downloader.temp_path()).join(PIN_STATE_FILE_NAME);
+        let state = match PinState::load(&state_path) {
+            Ok(state) => state,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => PinState::default(),
+            Err(err) => {
+                eprintln!("Can not load pin state {:?}: {}", state_path, err);
+                PinState::default()
+            }
+        };
+        PinQueue {
+            client,
+            downloader,
+            sync_rules,
+            file_size_limit,
+            state_path,
+            state: Arc::new(Mutex::new(state)),
+            wakeup: Arc::new(Notify::new()),
+        }
+    }
+
+    pub fn start(&self, tokio_handle: &Handle, workers: usize) {
+        for _ in 0..workers.max(1) {
+            tokio_handle.spawn(self.clone().work());
+        }
+    }
+
+    /// Queues a remote file or directory to be hydrated.
+    pub async fn pin(&self, path: &str) -> Result<(), FSError> {
+        let (item, _) = self
+            .client
+            .stat(&encode_path(path), StatOptions::default())
+            .await
+            .map_err(|e| FSError::WebDAV(e))?;
+        // Note : excluded paths are not in the mount, so they are treated as missing.
+        if self.sync_rules.is_item_excluded(&item) {
+            return Err(FSError::FileNotFoundInInode(path.to_string()));
+        }
+        let item = PinItem::from(item).ok_or(FSError::FileNotFoundInInode(path.to_string()))?;
+        if let PinItem::File { file, .. } = &item {
+            if self.file_size_limit.exceeds(file.size) {
+                return Err(FSError::InvalidOperation(format!(
+                    "{} has {} bytes, more than the maximum file size",
+                    path, file.size
+                )));
+            }
+        }
+
+        self.update(|state| state.pending.push_back(item));
+        self.wakeup.notify_waiters();
+        Ok(())
+    }
+
+    /// Takes a complete local copy of the remote file at `path` as its cache.
+    pub async fn adopt(&self, path: &str, local_path: &str) -> Result<(), FSError> {
+        let (item, _) = self
+            .client
+            .stat(&encode_path(path), StatOptions::default())
+            .await
+            .map_err(|e| FSError::WebDAV(e))?;
+        let (file, encoded_path) = match PinItem::from(item) {
+            Some(PinItem::File { file, encoded_path })
+                if !self.sync_rules.is_excluded(&file.path, false) =>
+            {
+                (file, encoded_path)
+            }
+            _ => return Err(FSError::FileNotFoundInInode(path.to_string())),
+        };
+        let remote_file = RemoteFile {
+            path: &file.path,
+            encoded_path: &encoded_path,
+            size: file.size,
+            mtime: file.mtime,
+            etag: file.etag.as_deref(),
+        };
+        self.downloader.adopt(&remote_file, local_path).await
+    }
+
+    /// Queues the files of a manifest in their current version on the server.
+    pub async fn pin_manifest(&self, entries: Vec<ManifestEntry>) -> ManifestImport {
+        let mut import = ManifestImport::default();
+        let mut items = Vec::new();
+        for entry in entries {
+            if self.sync_rules.is_excluded(&entry.path, false) {
+                import.missing += 1;
+                continue;
+            }
+            let item = match self
+                .client
+                .stat(&encode_path(&entry.path), StatOptions::default())
+                .await
+            {
+                Ok((item, _)) => PinItem::from(item),
+                Err(WebDAVError::NotFound(_)) => None,
+                Err(e) => {
+                    eprintln!("Stat Error: {} {:?}", entry.path, e);
+                    None
+                }
+            };
+            match item {
+                Some(PinItem::File { file, encoded_path })
+                    if !self.file_size_limit.exceeds(file.size) =>
+                {
+                    let changed = match (&file.etag, &entry.etag) {
+                        (Some(etag), Some(expected)) => etag != expected,
+                        _ => file.size != entry.size || file.mtime != entry.mtime,
+                    };
+                    if changed {
+                        import.changed += 1;
+                    }
+                    import.queued += 1;
+                    items.push(PinItem::File { file, encoded_path });
+                }
+                _ => import.missing += 1,
+            }
+        }
+
+        self.update(|state| state.pending.extend(items));
+        self.wakeup.notify_waiters();
+        import
+    }
+
+    pub fn pause(&self) {
+        self.update(|state| state.paused = true);
+    }
+
+    pub fn resume(&self) {
+        self.update(|state| state.paused = false);
+        self.wakeup.notify_waiters();
+    }
+
+    pub fn status(&self) -> PinStatus {
+        let state = self.state.lock().unwrap();
+        PinStatus {
+            pending: state.pending.len(),
+            active: state.active.len(),
+            hydrated_files: state.hydrated_files,
+            hydrated_bytes: state.hydrated_bytes,
+            failed: state.failed,
+            paused: state.paused,
+        }
+    }
+
+    async fn work(self) {
+        loop {
+            // Note : hydrating only fills the cache, so the queue waits while reads bypass it
+            // instead of failing every item.
+            if !self.downloader.uses_cache().await {
+                tokio::time::sleep(CACHE_BYPASS_WAIT).await;
+                continue;
+            }
+            // Note : registered before looking at the queue, so a pin in between is not missed.
+            let wakeup = self.wakeup.notified();
+            tokio::pin!(wakeup);
+            wakeup.as_mut().enable();
+
+            let Some(item) = self.take() else {
+                wakeup.await;
+                continue;
+            };
+            let result = self.process(&item).await;
+            self.update(|state| {
+                if let Some(index) = state.active.iter().position(|x| *x == item) {
+                    state.active.remove(index);
+                }
+                match &result {
+                    Ok(children) => {
+                        for child in children.iter().rev() {
+                            state.pending.push_front(child.clone());
+                        }
+                        if let PinItem::File { file, .. } = &item {
+                            state.hydrated_files += 1;
+                            state.hydrated_bytes += file.size;
+                        }
+                    }
+                    Err(_) => state.failed += 1,
+                }
+            });
+            match result {
+                Ok(children) if !children.is_empty() => self.wakeup.notify_waiters(),
+                Ok(_) => {}
+                Err(e) => eprintln!("Pin Error: {} {:?}", item.path(), e),
+            }
+        }
+    }
+
+    fn take(&self) -> Option<PinItem> {
+        let mut state = self.state.lock().unwrap();
+        if state.paused {
+            return None;
+        }
+        let item = state.pending.pop_front()?;
+        state.active.push(item.clone());
+        Some(item)
+    }
+
+    /// Hydrates a file, or lists a directory and returns its entries.
+    async fn process(&self, item: &PinItem) -> Result<Vec<PinItem>, FSError> {
+        match item {
+            PinItem::Dir { encoded_path, .. } => {
+                let (mut list, _) = self
+                    .client
+                    .list_children(encoded_path)
+                    .await
+                    .map_err(|e| FSError::WebDAV(e))?;
+                list.retain(|x| !self.sync_rules.is_item_excluded(x));
+                // Note : files above the size limit are skipped, hidden from the mount or not.
+                Ok(list
+                    .into_iter()
+                    .filter_map(PinItem::from)
+                    .filter(|x| match x {
+                        PinItem::File { file, .. } => !self.file_size_limit.exceeds(file.size),
+                        PinItem::Dir { .. } => true,
+                    })
+                    .collect())
+            }
+            PinItem::File { file, encoded_path } => {
+                let remote_file = RemoteFile {
+                    path: &file.path,
+                    encoded_path,
+                    size: file.size,
+                    mtime: file.mtime,
+                    etag: file.etag.as_deref(),
+                };
+                self.downloader.hydrate(&remote_file).await?;
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut PinState)) {
+        let mut state = self.state.lock().unwrap();
+        f(&mut state);
+        if let Err(err) = state.save(&self.state_path) {
+            eprintln!("Can not save pin state {:?}: {}", self.state_path, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod pin_item_test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::PinItem;
+    use crate::fs::manifest::ManifestEntry;
+
+    #[test]
+    fn line_round_trip_test() {
+        let items = [
+            PinItem::Dir {
+                path: "/photos/2024 trip/".to_string(),
+                encoded_path: "/photos/2024%20trip/".to_string(),
+            },
+            PinItem::File {
+                file: ManifestEntry {
+                    path: "/photos/2024 trip/caf%E9 1.jpg".to_string(),
+                    size: 4_000_000,
+                    mtime: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+                    etag: Some("\"a1b2\"".to_string()),
+                },
+                encoded_path: "/photos/2024%20trip/caf%E9%201.jpg".to_string(),
+            },
+        ];
+        for item in items {
+            assert_eq!(PinItem::parse(&item.to_line()), Some(item));
+        }
+        assert_eq!(PinItem::parse("dir /photos/2024 trip/"), None);
+    }
+}
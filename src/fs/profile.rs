@@ -0,0 +1,87 @@
+use std::{fmt::Display, str::FromStr};
+
+const MIB: usize = 1024 * 1024;
+
+/// Named tunings of the mount for a kind of workload, selected with `--profile`. Flags given
+/// explicitly override the values of the profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Profile {
+    /// Large files read sequentially, e.g. videos streamed by a player.
+    Media,
+    /// Small files edited by other clients, which should show up quickly.
+    Docs,
+    /// Large archives read once from start to end, which rarely change.
+    Backup,
+}
+
+/// The settings a profile sets. None leaves the setting at its default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tuning {
+    /// Bytes of the blocks new cache files are split into.
+    pub block_size: Option<u32>,
+    /// Bytes downloaded past the end of a read which misses the cache.
+    pub readahead: Option<u32>,
+    pub cache_ttl: Option<u64>,
+    pub attr_ttl: Option<u64>,
+    pub pin_workers: Option<usize>,
+    pub max_inflight_bytes: Option<usize>,
+}
+
+impl Profile {
+    pub fn tuning(&self) -> Tuning {
+        match self {
+            // Note : a player reads a few hundred KiB at a time, reading ahead keeps it fed.
+            Profile::Media => Tuning {
+                block_size: Some(16 * MIB as u32),
+                readahead: Some(32 * MIB as u32),
+                cache_ttl: Some(3600),
+                attr_ttl: None,
+                pin_workers: Some(2),
+                max_inflight_bytes: Some(256 * MIB),
+            },
+            // Note : small blocks, so opening a document does not download its neighbours.
+            Profile::Docs => Tuning {
+                block_size: Some(MIB as u32),
+                readahead: Some(0),
+                cache_ttl: Some(30),
+                attr_ttl: Some(10),
+                pin_workers: Some(8),
+                max_inflight_bytes: Some(64 * MIB),
+            },
+            Profile::Backup => Tuning {
+                block_size: Some(64 * MIB as u32),
+                readahead: Some(128 * MIB as u32),
+                cache_ttl: Some(86400),
+                attr_ttl: None,
+                pin_workers: Some(2),
+                max_inflight_bytes: Some(512 * MIB),
+            },
+        }
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "media" => Ok(Profile::Media),
+            "docs" => Ok(Profile::Docs),
+            "backup" => Ok(Profile::Backup),
+            _ => Err(format!(
+                "invalid profile {:?}, expected media, docs or backup",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Profile::Media => write!(f, "media"),
+            Profile::Docs => write!(f, "docs"),
+            Profile::Backup => write!(f, "backup"),
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt::Write;
+
+use super::{mount_stats::MountStats, path_stats::PathStat};
+
+/// Metric name, help text and the value of a per-path counter.
+type PathCounter = (&'static str, &'static str, fn(&PathStat) -> u64);
+
+impl MountStats {
+    /// Formats the stats in the Prometheus text exposition format.
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let gauges = [
+            ("fusedav_inodes", "Known inodes.", self.inodes as u64),
+            (
+                "fusedav_cached_directories",
+                "Directories with a cached listing.",
+                self.cached_directories as u64,
+            ),
+            (
+                "fusedav_cached_files",
+                "Files in the cache directory.",
+                self.cached_files as u64,
+            ),
+            (
+                "fusedav_cached_bytes",
+                "Bytes used by the cache directory.",
+                self.cached_bytes,
+            ),
+            (
+                "fusedav_download_concurrency",
+                "Downloads currently allowed to run at once.",
+                self.download_concurrency as u64,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} gauge", name);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+
+        let _ = writeln!(
+            out,
+            "# HELP fusedav_memory_bytes Approximate bytes of memory by what holds them."
+        );
+        let _ = writeln!(out, "# TYPE fusedav_memory_bytes gauge");
+        let memory = [
+            ("inodes", self.inode_memory_bytes),
+            ("file_handles", self.file_handle_memory_bytes),
+            ("inflight_downloads", self.inflight_bytes),
+        ];
+        for (part, value) in memory {
+            let _ = writeln!(out, "fusedav_memory_bytes{{part=\"{}\"}} {}", part, value);
+        }
+
+        let path_counters: [PathCounter; 3] = [
+            (
+                "fusedav_path_read_bytes_total",
+                "Bytes read through the mount per remote path (top paths only).",
+                |stat| stat.read_bytes,
+            ),
+            (
+                "fusedav_path_read_requests_total",
+                "Read requests per remote path (top paths only).",
+                |stat| stat.read_requests,
+            ),
+            (
+                "fusedav_path_remote_requests_total",
+                "Requests sent to the server per remote path (top paths only).",
+                |stat| stat.remote_requests,
+            ),
+        ];
+        for (name, help, value) in path_counters {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for (path, stat) in self.top_paths.iter() {
+                let _ = writeln!(
+                    out,
+                    "{}{{path=\"{}\"}} {}",
+                    name,
+                    escape_label_value(path),
+                    value(stat)
+                );
+            }
+        }
+        out
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
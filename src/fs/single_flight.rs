@@ -0,0 +1,80 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::OnceCell;
+
+type Entry<V> = (Instant, Arc<OnceCell<V>>);
+
+/// Shares one resolution between identical requests issued within a short window.
+pub(super) struct SingleFlight<K, V> {
+    window: Duration,
+    entries: Mutex<HashMap<K, Entry<V>>>,
+}
+
+impl<K: Eq + Hash, V: Clone> SingleFlight<K, V> {
+    pub fn new(window: Duration) -> Self {
+        SingleFlight {
+            window,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the result of a finished resolution if it is still within the window.
+    pub fn get_ready(&self, key: &K) -> Option<V> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|(created, _)| created.elapsed() < self.window)
+            .and_then(|(_, cell)| cell.get().cloned())
+    }
+
+    /// Drops the result of `key`, so the next request resolves it again.
+    pub fn forget(&self, key: &K) {
+        self.entries.lock().unwrap().remove(key);
+    }
+
+    pub async fn run<F, Fut>(&self, key: K, resolve: F) -> V
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let cell = {
+            let mut entries = self.entries.lock().unwrap();
+            let now = Instant::now();
+            entries.retain(|_, (created, _)| now.duration_since(*created) < self.window);
+            entries
+                .entry(key)
+                .or_insert_with(|| (now, Arc::new(OnceCell::new())))
+                .1
+                .clone()
+        };
+        cell.get_or_init(resolve).await.clone()
+    }
+}
+
+#[cfg(test)]
+mod single_flight_test {
+    use std::time::Duration;
+
+    use super::SingleFlight;
+
+    #[tokio::test]
+    async fn forget_test() {
+        let flight = SingleFlight::new(Duration::from_secs(60));
+        assert_eq!(flight.run((1, true), || async { 1 }).await, 1);
+        assert_eq!(flight.run((1, true), || async { 2 }).await, 1);
+        // Note : the other key is resolved on its own.
+        assert_eq!(flight.run((1, false), || async { 3 }).await, 3);
+        assert_eq!(flight.get_ready(&(1, true)), Some(1));
+
+        flight.forget(&(1, true));
+        assert_eq!(flight.get_ready(&(1, true)), None);
+        assert_eq!(flight.run((1, true), || async { 4 }).await, 4);
+        assert_eq!(flight.get_ready(&(1, false)), Some(3));
+    }
+}
@@ -0,0 +1,108 @@
+use std::time::{Duration, Instant};
+
+/// Times a FUSE operation phase by phase and logs it when it takes longer than a threshold, e.g.
+/// `Slow read took 1523 ms (attributes 0 ms, cache check 2 ms, network fetch 1519 ms, disk read
+/// 2 ms): /Videos/talk.mkv offset 1048576 size 131072`.
+///
+/// The operation ends when the timer is dropped, so one which returns early, e.g. on an error, is
+/// logged as well, with the context it was started with.
+pub(super) struct OpTimer {
+    op: &'static str,
+    threshold: Option<Duration>,
+    started_at: Instant,
+    phase_started_at: Instant,
+    phases: Vec<(&'static str, Duration)>,
+    context: String,
+}
+
+impl OpTimer {
+    /// Starts timing `op`, which works on `context`, e.g. `ino 42`. Without a threshold nothing
+    /// is recorded.
+    pub fn start(op: &'static str, threshold: Option<Duration>, context: String) -> OpTimer {
+        let now = Instant::now();
+        OpTimer {
+            op,
+            threshold,
+            started_at: now,
+            phase_started_at: now,
+            phases: Vec::new(),
+            context,
+        }
+    }
+
+    /// A timer which records nothing, for work done outside of a FUSE operation.
+    pub fn disabled() -> OpTimer {
+        OpTimer::start("", None, String::new())
+    }
+
+    /// Ends the current phase, which is called `name`, and starts the next one.
+    pub fn phase(&mut self, name: &'static str) {
+        if self.threshold.is_none() {
+            return;
+        }
+        let now = Instant::now();
+        self.phases.push((name, now - self.phase_started_at));
+        self.phase_started_at = now;
+    }
+
+    /// Ends the operation and logs it if it was slow. `detail` tells which file or directory it
+    /// worked on better than the context, and is only built for slow operations.
+    pub fn finish(mut self, detail: impl FnOnce() -> String) {
+        self.log(detail);
+        self.threshold = None;
+    }
+
+    fn log(&self, detail: impl FnOnce() -> String) {
+        let Some(threshold) = self.threshold else {
+            return;
+        };
+        let elapsed = self.started_at.elapsed();
+        if elapsed < threshold {
+            return;
+        }
+        eprintln!(
+            "Slow {} took {} ms{}: {}",
+            self.op,
+            elapsed.as_millis(),
+            format_phases(&self.phases),
+            detail()
+        );
+    }
+}
+
+impl Drop for OpTimer {
+    fn drop(&mut self) {
+        let context = std::mem::take(&mut self.context);
+        self.log(|| context);
+    }
+}
+
+fn format_phases(phases: &[(&'static str, Duration)]) -> String {
+    if phases.is_empty() {
+        return String::new();
+    }
+    let phases: Vec<String> = phases
+        .iter()
+        .map(|(name, duration)| format!("{} {} ms", name, duration.as_millis()))
+        .collect();
+    format!(" ({})", phases.join(", "))
+}
+
+#[cfg(test)]
+mod slow_ops_test {
+    use std::time::Duration;
+
+    use super::format_phases;
+
+    #[test]
+    fn format_phases_test() {
+        assert_eq!(format_phases(&[]), "");
+        assert_eq!(
+            format_phases(&[
+                ("cache check", Duration::from_micros(2500)),
+                ("network fetch", Duration::from_millis(1519)),
+            ]),
+            " (cache check 2 ms, network fetch 1519 ms)"
+        );
+    }
+}
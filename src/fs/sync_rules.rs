@@ -0,0 +1,182 @@
+use std::sync::Arc;
+
+use crate::webdav::WebDAVList;
+
+/// One line of a rules file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    pattern: Vec<char>,
+    /// `!pattern`, which brings back paths an earlier rule excluded.
+    include: bool,
+    /// `pattern/`, which only matches directories.
+    dir_only: bool,
+    /// Patterns with a slash match the whole path below the root, others only the name.
+    anchored: bool,
+}
+
+impl Rule {
+    fn parse(line: &str) -> Option<Rule> {
+        let (include, pattern) = match line.strip_prefix('!') {
+            Some(pattern) => (true, pattern),
+            None => (false, line),
+        };
+        let dir_only = pattern.ends_with('/');
+        let pattern = pattern.trim_end_matches('/');
+        let anchored = pattern.contains('/');
+        let pattern = pattern.trim_start_matches('/');
+        if pattern.is_empty() {
+            return None;
+        }
+        Some(Rule {
+            pattern: pattern.chars().collect(),
+            include,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, path: &[char], is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            return glob(&self.pattern, path);
+        }
+        let name_begin = path.iter().rposition(|x| *x == '/').map_or(0, |x| x + 1);
+        glob(&self.pattern, &path[name_begin..])
+    }
+}
+
+/// Remote paths hidden from the mount, in the syntax of `.gitignore`.
+///
+/// `*` and `?` match within a path segment, `**` across segments, `\` escapes the next character.
+/// Patterns containing a slash are relative to the root of the mount, others match the name at
+/// any depth. A trailing slash only matches directories and a leading `!` includes a path again.
+/// The last matching rule decides, and everything below an excluded directory stays excluded.
+#[derive(Debug, Clone, Default)]
+pub struct SyncRules {
+    rules: Arc<Vec<Rule>>,
+}
+
+impl SyncRules {
+    /// Parses a rules file, skipping blank lines and `#` comments. Returns the number of the
+    /// first malformed line as the error.
+    pub fn parse(text: &str) -> Result<SyncRules, usize> {
+        let rules = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index, line.trim_end()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(index, line)| Rule::parse(line).ok_or(index + 1))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SyncRules {
+            rules: Arc::new(rules),
+        })
+    }
+
+    /// Returns whether the decoded remote `path` is hidden from the mount.
+    pub fn is_excluded(&self, path: &str, is_dir: bool) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let path: Vec<char> = path.trim_matches('/').chars().collect();
+        let mut ancestors = path
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| **x == '/')
+            .map(|(index, _)| index);
+        // Note : like git, a file can not be included again once its directory is excluded.
+        ancestors.any(|end| self.excludes(&path[..end], true)) || self.excludes(&path, is_dir)
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub(super) fn is_item_excluded(&self, item: &WebDAVList) -> bool {
+        match item {
+            WebDAVList::File(file) => self.is_excluded(&file.path, false),
+            WebDAVList::Folder(dir) => self.is_excluded(&dir.path, true),
+            WebDAVList::Err => false,
+        }
+    }
+
+    fn excludes(&self, path: &[char], is_dir: bool) -> bool {
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| rule.matches(path, is_dir))
+            .is_some_and(|rule| !rule.include)
+    }
+}
+
+fn glob(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // Note : `a/**/b` also matches `a/b`.
+            if let ['/', after_slash @ ..] = rest {
+                if glob(after_slash, text) {
+                    return true;
+                }
+            }
+            (0..=text.len()).any(|begin| glob(rest, &text[begin..]))
+        }
+        ['*', rest @ ..] => {
+            for begin in 0..=text.len() {
+                if glob(rest, &text[begin..]) {
+                    return true;
+                }
+                if text.get(begin) == Some(&'/') {
+                    break;
+                }
+            }
+            false
+        }
+        ['?', rest @ ..] => match text {
+            [c, text @ ..] if *c != '/' => glob(rest, text),
+            _ => false,
+        },
+        ['\\', c, rest @ ..] | [c, rest @ ..] => match text {
+            [t, text @ ..] if t == c => glob(rest, text),
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod sync_rules_test {
+    use super::SyncRules;
+
+    #[test]
+    fn is_excluded_test() {
+        let rules = SyncRules::parse(
+            "# shared account\n\
+             /Users/*/\n\
+             !/Users/alice/\n\
+             *.tmp\n\
+             build/\n\
+             /Projects/**/node_modules\n\
+             \\#notes\n",
+        )
+        .unwrap();
+
+        assert!(rules.is_excluded("/Users/bob", true));
+        assert!(rules.is_excluded("/Users/bob/Videos/a.mp4", false));
+        assert!(!rules.is_excluded("/Users/alice/Videos/a.mp4", false));
+        assert!(!rules.is_excluded("/Users/readme.txt", false));
+        assert!(rules.is_excluded("/Users/alice/draft.tmp", false));
+        assert!(rules.is_excluded("/src/build", true));
+        assert!(!rules.is_excluded("/src/build", false));
+        assert!(rules.is_excluded("/Projects/node_modules/x.js", false));
+        assert!(rules.is_excluded("/Projects/web/app/node_modules", true));
+        assert!(!rules.is_excluded("/node_modules", true));
+        assert!(rules.is_excluded("/#notes", false));
+        assert!(!SyncRules::default().is_excluded("/a.tmp", false));
+    }
+
+    #[test]
+    fn parse_error_test() {
+        assert_eq!(SyncRules::parse("*.tmp\n\n!/\n").unwrap_err(), 3);
+    }
+}
@@ -0,0 +1,134 @@
+use std::{ffi::OsString, path::Path};
+
+use chrono::Utc;
+
+use super::sync_rules::SyncRules;
+use crate::webdav::{
+    Error as WebDAVError, StatOptions, WebDAVClient, WebDAVDirectory, WebDAVFile, WebDAVList,
+};
+
+/// Directory at the root of the mount which holds the previous revisions of files.
+pub(super) const VERSIONS_PATH: &str = "/.versions";
+
+/// Returns whether `path` is `/.versions` or below it.
+pub(super) fn is_versions_path(path: &str) -> bool {
+    path.strip_prefix(VERSIONS_PATH)
+        .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// A read-only view of the previous revisions of files, from the versions collection of a
+/// Nextcloud or ownCloud server.
+///
+/// `/.versions` mirrors the directories of the mount, and every file in it is a directory which
+/// holds one file per revision, named by the time the revision was made, e.g.
+/// `/.versions/Docs/Plan.docx/2024-05-01T10:22:03Z.docx`. The revisions of a file are listed
+/// from `<versions root>/<fileid>`.
+#[derive(Clone)]
+pub(super) struct VersionsView {
+    client: WebDAVClient,
+    versions_client: WebDAVClient,
+}
+
+impl VersionsView {
+    pub fn new(client: WebDAVClient, versions_client: WebDAVClient) -> VersionsView {
+        VersionsView {
+            client,
+            versions_client,
+        }
+    }
+
+    /// The entry of `/.versions` in the root directory.
+    pub fn root_entry(&self) -> WebDAVList {
+        WebDAVList::Folder(WebDAVDirectory {
+            href: String::new(),
+            path: VERSIONS_PATH.to_string(),
+            encoded_path: "/".to_string(),
+            name: OsString::from(&VERSIONS_PATH[1..]),
+            display_name: None,
+            last_modified: Utc::now(),
+            quota_used_bytes: None,
+            quota_available_bytes: None,
+            etag: None,
+            read_only: true,
+        })
+    }
+
+    /// Lists the directory at `path` below `/.versions`, which mirrors the remote item at
+    /// `encoded_path`. Like a listing of the server, the first entry is the directory itself.
+    pub async fn list(
+        &self,
+        path: &str,
+        encoded_path: &str,
+        sync_rules: &SyncRules,
+    ) -> Result<Vec<WebDAVList>, WebDAVError> {
+        let (item, _) = self
+            .client
+            .stat(encoded_path, StatOptions::default())
+            .await?;
+        match item {
+            WebDAVList::Folder(_) => {
+                let mut list = self.client.list(encoded_path).await?;
+                list.retain(|x| !sync_rules.is_item_excluded(x));
+                Ok(list.iter().filter_map(mirror).collect())
+            }
+            WebDAVList::File(file) => {
+                let file_id = self.client.file_id(encoded_path).await?;
+                let (revisions, _) = self
+                    .versions_client
+                    .list_children(&format!("/{}", file_id))
+                    .await?;
+
+                let mut list = Vec::with_capacity(revisions.len() + 1);
+                list.extend(mirror(&WebDAVList::File(file.clone())));
+                list.extend(revisions.into_iter().filter_map(|x| match x {
+                    WebDAVList::File(revision) => Some(revision_entry(path, &file, revision)),
+                    _ => None,
+                }));
+                Ok(list)
+            }
+            WebDAVList::Err => Err(WebDAVError::NotFound(encoded_path.to_string())),
+        }
+    }
+}
+
+/// The directory below `/.versions` standing for a remote file or directory.
+fn mirror(item: &WebDAVList) -> Option<WebDAVList> {
+    let (path, encoded_path, name, last_modified) = match item {
+        WebDAVList::File(f) => (&f.path, &f.encoded_path, &f.name, f.last_modified),
+        WebDAVList::Folder(d) => (&d.path, &d.encoded_path, &d.name, d.last_modified),
+        WebDAVList::Err => return None,
+    };
+    Some(WebDAVList::Folder(WebDAVDirectory {
+        href: String::new(),
+        path: format!("{}{}", VERSIONS_PATH, path),
+        encoded_path: encoded_path.clone(),
+        name: name.clone(),
+        display_name: None,
+        last_modified,
+        quota_used_bytes: None,
+        quota_available_bytes: None,
+        etag: None,
+        read_only: true,
+    }))
+}
+
+/// A revision in the directory `path` of `file`. It keeps the extension of the file, so it opens
+/// with the same application.
+fn revision_entry(path: &str, file: &WebDAVFile, revision: WebDAVFile) -> WebDAVList {
+    let extension = Path::new(&file.path)
+        .extension()
+        .and_then(|x| x.to_str())
+        .map_or(String::new(), |x| format!(".{}", x));
+    let name = format!(
+        "{}{}",
+        revision.last_modified.format("%Y-%m-%dT%H:%M:%SZ"),
+        extension
+    );
+    WebDAVList::File(WebDAVFile {
+        path: format!("{}/{}", path.trim_end_matches('/'), name),
+        name: OsString::from(name),
+        display_name: None,
+        read_only: true,
+        ..revision
+    })
+}
@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use rand::Rng;
+use tokio::{process::Command, time::Instant};
+
+use super::{errors::FSError, mount_guard::MountHandle};
+use crate::webdav::Error as WebDAVError;
+
+/// Pause added after the first failed refresh, doubled with every further failure.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Polls the server for changes in the cached directories. Every `interval`, each cached
+/// directory is listed once, the most recently accessed first. The requests are spread over the
+/// interval with some jitter, so a mount with thousands of cached directories keeps the load on
+/// the server smooth, and slow down while the server fails. When directories changed,
+/// `on_change` runs once at the end of the round through `sh -c`, with all the changed remote
+/// paths as its arguments.
+pub async fn watch(handle: MountHandle, interval: Duration, on_change: Option<String>) {
+    let mut backoff = Duration::ZERO;
+    // Note : nothing is cached at mount time, so the first round starts after an interval.
+    let mut round_start = Instant::now() + interval;
+    loop {
+        tokio::time::sleep_until(round_start).await;
+        let dirs = handle.dirs_to_refresh().await;
+        let spacing = interval.div_f64(dirs.len().max(1) as f64);
+        let mut round_changes = Vec::new();
+        for dir in dirs {
+            match handle.refresh_dir(&dir).await {
+                Ok(changed_paths) => {
+                    backoff = Duration::ZERO;
+                    round_changes.extend(changed_paths);
+                }
+                // Note : a directory removed on the server disappears with its parent's refresh.
+                Err(FSError::WebDAV(WebDAVError::NotFound(_))) => {}
+                Err(e) => {
+                    eprintln!("Watch Error: {} {:?}", dir.path, e);
+                    backoff = (backoff * 2).clamp(MIN_BACKOFF, interval);
+                }
+            }
+            let jitter = rand::thread_rng().gen_range(0.5..1.5);
+            tokio::time::sleep(spacing.mul_f64(jitter) + backoff).await;
+        }
+        match &on_change {
+            Some(command) if !round_changes.is_empty() => {
+                round_changes.sort();
+                round_changes.dedup();
+                run_on_change(command, &round_changes).await
+            }
+            _ => {}
+        }
+        round_start = (round_start + interval).max(Instant::now());
+    }
+}
+
+async fn run_on_change(command: &str, changed_paths: &[String]) {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .arg("fusedav-rs")
+        .args(changed_paths)
+        .status()
+        .await;
+    match status {
+        Ok(status) if !status.success() => {
+            eprintln!("On-change command failed: {}", status)
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("Can not run on-change command: {}", e),
+    }
+}
@@ -1,22 +1,78 @@
 use core::time;
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    future::Future,
+    io,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use fuser::Filesystem;
-use libc::ENOENT;
-use tokio::runtime::Handle;
+use fuser::{consts::FOPEN_DIRECT_IO, FileType, Filesystem, KernelConfig};
+use libc::{c_int, EFBIG, ENODATA, ERANGE, O_DIRECT};
+use tokio::{runtime::Handle, sync::watch};
 
 use super::{
-    webdav_fs_file_downloader::WebDAVFSFileDownloader,
-    webdav_fs_explorer::WebDAVFSExplorer,
+    atime_mode::AtimeMode,
+    cache_policy::CachePolicy,
+    content_rules::ContentRules,
+    errors::FSError,
+    file_size_limit::FileSizeLimit,
+    growing_files::GrowingFiles,
+    health_report::ErrorCounts,
+    hooks::Hooks,
+    inflight_ops::InflightOps,
+    inode_info_map::InodeInfo,
+    last_errors::LastErrors,
+    listing_spill::ListingSpill,
+    name_source::NameSource,
+    path_stats::PathStats,
+    pin_queue::{PinQueue, DEFAULT_PIN_WORKERS},
+    slow_ops::OpTimer,
+    sync_rules::SyncRules,
+    versions::VersionsView,
+    webdav_fs_explorer::{ListItemInfo, WebDAVFSExplorer},
+    webdav_fs_file_downloader::{RemoteFile, WebDAVFSFileDownloader},
 };
-use crate::webdav::WebDAVClient;
+use crate::{telemetry, webdav::WebDAVClient};
+
+const XATTR_CACHED_BYTES: &str = "user.fusedav.cached_bytes";
+const XATTR_TOTAL_BYTES: &str = "user.fusedav.total_bytes";
+const XATTR_LAST_ERROR: &str = "user.fusedav.last_error";
+
+/// Bytes the kernel reads ahead of sequential reads, if it allows that much.
+const MAX_READAHEAD: u32 = 1024 * 1024;
+/// Requests the kernel keeps in flight in the background, e.g. readahead of several files.
+const MAX_BACKGROUND: u16 = 64;
 
 pub struct WebDAVFS {
     tokio_handle: Handle,
     explorer: WebDAVFSExplorer,
     downloader: WebDAVFSFileDownloader,
+    path_stats: PathStats,
+    pin_workers: usize,
+    sync_rules: SyncRules,
+    slow_op_threshold: Option<Duration>,
+    direct_io: bool,
+    content_rules: ContentRules,
+    file_size_limit: FileSizeLimit,
+    growing_files: GrowingFiles,
+    /// Open handles per inode, so data is only evicted on close once the last one is closed.
+    open_counts: Arc<Mutex<HashMap<u64, usize>>>,
+    last_errors: LastErrors,
+    hooks: Hooks,
+    inflight_ops: InflightOps,
+    error_counts: ErrorCounts,
+    terminated: watch::Sender<bool>,
 }
 
 impl WebDAVFS {
+    /// Every instance keeps its own inodes, cache handles, statistics and queues, so any number
+    /// can be mounted in one process. They only share the runtime and what clones of `client`
+    /// share, such as its download budget. Each needs its own `temp_path`: use a
+    /// [`CacheNamespace`](super::CacheNamespace) per remote identity so two mounts never write
+    /// into the same cache files.
     pub fn new(
         tokio_handle: Handle,
         client: WebDAVClient,
@@ -24,17 +80,270 @@ impl WebDAVFS {
         user_id: u32,
         group_id: u32,
     ) -> WebDAVFS {
-        let explorer = WebDAVFSExplorer::new(client.clone(), user_id, group_id);
-        let downloader = WebDAVFSFileDownloader::new(client, temp_path);
+        let path_stats = PathStats::new();
+        let explorer = WebDAVFSExplorer::new(client.clone(), user_id, group_id, path_stats.clone());
+        let downloader = WebDAVFSFileDownloader::new(client, temp_path, path_stats.clone());
+        let (terminated, _) = watch::channel(false);
         WebDAVFS {
             tokio_handle,
             explorer,
             downloader,
+            path_stats,
+            pin_workers: DEFAULT_PIN_WORKERS,
+            sync_rules: SyncRules::default(),
+            slow_op_threshold: None,
+            direct_io: false,
+            content_rules: ContentRules::default(),
+            file_size_limit: FileSizeLimit::default(),
+            growing_files: GrowingFiles::default(),
+            open_counts: Arc::new(Mutex::new(HashMap::new())),
+            last_errors: LastErrors::default(),
+            hooks: Hooks::default(),
+            inflight_ops: InflightOps::default(),
+            error_counts: ErrorCounts::default(),
+            terminated,
         }
     }
+
+    /// Sets how long listings and file data stay fresh. Must be called before mounting.
+    pub fn set_cache_policy(&mut self, cache_policy: CachePolicy) {
+        self.explorer.set_cache_policy(cache_policy);
+        self.downloader.set_cache_policy(cache_policy);
+    }
+
+    /// Sets the size of the blocks new cache files are split into. Must be called before
+    /// mounting.
+    pub fn set_block_size(&mut self, block_size: u32) {
+        self.downloader.set_block_size(block_size);
+    }
+
+    /// Sets how many bytes past the end of a read are downloaded along with it when the read
+    /// misses the cache. Must be called before mounting.
+    pub fn set_readahead(&mut self, readahead: u32) {
+        self.downloader.set_readahead(readahead);
+    }
+
+    /// Sets where file names come from. Must be called before mounting.
+    pub fn set_name_source(&mut self, name_source: NameSource) {
+        self.explorer.set_name_source(name_source);
+    }
+
+    /// Sets when reads update the access time of files. Must be called before mounting.
+    pub fn set_atime_mode(&mut self, atime_mode: AtimeMode) {
+        self.explorer.set_atime_mode(atime_mode);
+    }
+
+    /// Sets the remote paths hidden from the mount and skipped by pinning. Must be called before
+    /// mounting.
+    pub fn set_sync_rules(&mut self, sync_rules: SyncRules) {
+        self.explorer.set_sync_rules(sync_rules.clone());
+        self.sync_rules = sync_rules;
+    }
+
+    /// Shows the previous revisions of files below `/.versions`, listed from the versions
+    /// collection `versions_client` points to. Must be called before mounting.
+    pub fn set_versions_client(&mut self, versions_client: WebDAVClient) {
+        let client = self.downloader.client().clone();
+        self.explorer
+            .set_versions(VersionsView::new(client, versions_client.clone()));
+        self.downloader.set_versions_client(versions_client);
+    }
+
+    /// Logs the operations which take longer than `threshold`, with the time spent in each
+    /// phase. Must be called before mounting.
+    pub fn set_slow_op_threshold(&mut self, threshold: Duration) {
+        self.slow_op_threshold = Some(threshold);
+    }
+
+    /// Keeps about `max_entries` directory entries, and about `max_memory` bytes of them, in
+    /// memory. Beyond that, the listings of the least recently used directories are moved to
+    /// files in `spill_dir` until they are used again. Must be called before mounting.
+    pub fn set_listing_limits(
+        &mut self,
+        max_entries: Option<usize>,
+        max_memory: Option<usize>,
+        spill_dir: PathBuf,
+    ) -> io::Result<()> {
+        self.explorer
+            .set_listing_limits(max_entries, max_memory, ListingSpill::open(spill_dir)?);
+        Ok(())
+    }
+
+    /// Shows the files the server does not let the user write as 0444 and such directories as
+    /// 0555, so applications know before saving. Must be called before mounting.
+    pub fn set_read_only_perms(&mut self, read_only_perms: bool) {
+        self.explorer.set_read_only_perms(read_only_perms);
+    }
+
+    /// Lists the sub directories of a directory in the background once it is read, at most
+    /// `max_concurrent` at a time, so entering them needs no request. Must be called before
+    /// mounting.
+    pub fn set_subdir_prefetch(&mut self, max_concurrent: usize) {
+        self.explorer.set_subdir_prefetch(max_concurrent);
+    }
+
+    /// Makes every read bypass the kernel page cache, so it always reaches the cache of the
+    /// mount. Files can then not be mapped with mmap. Must be called before mounting.
+    pub fn set_direct_io(&mut self, direct_io: bool) {
+        self.direct_io = direct_io;
+    }
+
+    /// Sets what is done with files by the media type the server reports for them. Must be
+    /// called before mounting.
+    pub fn set_content_rules(&mut self, content_rules: ContentRules) {
+        self.content_rules = content_rules;
+    }
+
+    /// Sets the largest remote file the mount exposes. Must be called before mounting.
+    pub fn set_file_size_limit(&mut self, file_size_limit: FileSizeLimit) {
+        self.explorer.set_file_size_limit(file_size_limit);
+        self.file_size_limit = file_size_limit;
+    }
+
+    /// Sets the files which are appended to on the server, whose size is checked on every open
+    /// and on reads reaching their end. They are read without the page cache, so reads past the
+    /// size the kernel knows reach the mount. Must be called before mounting.
+    pub fn set_growing_files(&mut self, growing_files: GrowingFiles) {
+        self.explorer.set_growing_files(growing_files.clone());
+        self.downloader.set_growing_files(growing_files.clone());
+        self.growing_files = growing_files;
+    }
+
+    /// Sets the commands run when an operation fails. Must be called before mounting.
+    pub fn set_hooks(&mut self, hooks: Hooks) {
+        self.hooks = hooks;
+    }
+
+    /// Sets how many pinned files are downloaded at the same time.
+    pub fn set_pin_workers(&mut self, pin_workers: usize) {
+        self.pin_workers = pin_workers;
+    }
+
+    /// Creates the pin queue, loading the progress saved by a previous mount, and starts its
+    /// workers.
+    pub(super) fn start_pin_queue(&self) -> PinQueue {
+        let pins = PinQueue::new(
+            self.downloader.clone(),
+            self.sync_rules.clone(),
+            self.file_size_limit,
+        );
+        pins.start(&self.tokio_handle, self.pin_workers);
+        pins
+    }
+
+    /// Runs the task of an operation on the runtime, registered as in flight on `ino` until it
+    /// ends. A panic in the task is logged with the
+    /// operation and `context`. The reply the task owns is dropped while unwinding, and fuser
+    /// answers a dropped reply with EIO, so the calling process gets an error instead of
+    /// waiting forever.
+    fn spawn_op<F>(&self, op: &'static str, ino: u64, context: String, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let guard = self.inflight_ops.start(op, ino, context.clone());
+        let task = self.tokio_handle.spawn(async move {
+            let _guard = guard;
+            task.await
+        });
+        self.tokio_handle.spawn(async move {
+            let Err(err) = task.await else {
+                return;
+            };
+            if !err.is_panic() {
+                return;
+            }
+            let panic = err.into_panic();
+            let message = match panic.downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => panic.downcast_ref::<String>().cloned().unwrap_or_default(),
+            };
+            eprintln!("Panic in {} {}: {}", op, context, message);
+        });
+    }
+
+    /// Opens a file once its attributes were confirmed. Fails with EFBIG when the file is above
+    /// the file size limit, and opens growing files with direct I/O.
+    fn open_checking_attr(&self, ino: u64, open_flags: u32, reply: fuser::ReplyOpen) {
+        let downloader = self.downloader.clone();
+        let mut explorer = self.explorer.clone();
+        let content_rules = self.content_rules.clone();
+        let open_counts = self.open_counts.clone();
+        let file_size_limit = self.file_size_limit;
+        let last_errors = self.last_errors.clone();
+        let error_counts = self.error_counts.clone();
+        let hooks = self.hooks.clone();
+        self.spawn_op("open", ino, format!("ino {}", ino), async move {
+            let attr = match explorer.getattr(ino).await {
+                Ok(attr) => attr,
+                Err(e) => {
+                    eprintln!("Open Error: {:?}", e);
+                    record_error(&last_errors, &hooks, &error_counts, "open", ino, &e);
+                    reply.error(e.errno());
+                    return;
+                }
+            };
+            if file_size_limit.refuses(attr.file_attr.size) {
+                reply.error(EFBIG);
+                return;
+            }
+            last_errors.clear(ino);
+            let open_flags = match explorer.is_growing(&attr) {
+                true => open_flags | FOPEN_DIRECT_IO,
+                false => open_flags,
+            };
+            // Note : counted before the reply, so the release of the handle finds the count.
+            if !content_rules.is_empty() {
+                *open_counts.lock().unwrap().entry(ino).or_default() += 1;
+            }
+            reply.opened(0, open_flags);
+            prefetch_on_open(&downloader, &content_rules, &attr).await;
+        });
+    }
+
+    pub(super) fn explorer(&self) -> &WebDAVFSExplorer {
+        &self.explorer
+    }
+
+    pub(super) fn downloader(&self) -> &WebDAVFSFileDownloader {
+        &self.downloader
+    }
+
+    pub(super) fn path_stats(&self) -> &PathStats {
+        &self.path_stats
+    }
+
+    pub(super) fn inflight_ops(&self) -> &InflightOps {
+        &self.inflight_ops
+    }
+
+    pub(super) fn error_counts(&self) -> &ErrorCounts {
+        &self.error_counts
+    }
+
+    pub(super) fn subscribe_terminated(&self) -> watch::Receiver<bool> {
+        self.terminated.subscribe()
+    }
 }
 
 impl Filesystem for WebDAVFS {
+    /// Asks for larger reads than the kernel defaults to. The kernel only lowers its readahead,
+    /// so the most it offers is taken when that is less. A single read request covers up to
+    /// `max_pages`, which fuser negotiates from its maximum write size, up to 1 MiB on current
+    /// kernels instead of 128 KiB.
+    fn init(&mut self, _req: &fuser::Request<'_>, config: &mut KernelConfig) -> Result<(), c_int> {
+        if let Err(max_readahead) = config.set_max_readahead(MAX_READAHEAD) {
+            let _ = config.set_max_readahead(max_readahead);
+        }
+        if let Err(max_background) = config.set_max_background(MAX_BACKGROUND) {
+            let _ = config.set_max_background(max_background);
+        }
+        Ok(())
+    }
+
+    fn destroy(&mut self) {
+        self.terminated.send_replace(true);
+    }
+
     fn lookup(
         &mut self,
         _req: &fuser::Request<'_>,
@@ -42,36 +351,73 @@ impl Filesystem for WebDAVFS {
         name: &std::ffi::OsStr,
         reply: fuser::ReplyEntry,
     ) {
-        let mut explorer = self.explorer.clone();
         let name = name.to_os_string();
-        self.tokio_handle.spawn(async move {
-            match explorer.lookup(parent, name.to_str().unwrap()).await {
-                Ok(info) => {
-                    let ttl = time::Duration::from_secs(1);
-                    reply.entry(&ttl, &info.file_attr, 0);
-                }
-                Err(e) => {
-                    eprintln!("Lookup Error: {:?}", e);
-                    reply.error(ENOENT);
+        let mut explorer = self.explorer.clone();
+        let last_errors = self.last_errors.clone();
+        let error_counts = self.error_counts.clone();
+        let hooks = self.hooks.clone();
+        let attributes = vec![("parent", parent.to_string())];
+        let context = format!("parent {} name {:?}", parent, name);
+        let timer = OpTimer::start("lookup", self.slow_op_threshold, context.clone());
+        self.spawn_op(
+            "lookup",
+            parent,
+            context,
+            telemetry::in_span("fuse.lookup", attributes, async move {
+                let _timer = timer;
+                match explorer.lookup(parent, &name).await {
+                    Ok(info) => {
+                        let ttl = time::Duration::from_secs(1);
+                        reply.entry(&ttl, &info.file_attr, 0);
+                        last_errors.clear(parent);
+                    }
+                    // Note : a name which is not in the directory is the answer to most
+                    // lookups, e.g. of shells searching their PATH, so it is no error.
+                    Err(e @ FSError::FileNotFoundInInode(_)) => reply.error(e.errno()),
+                    Err(e) => {
+                        eprintln!("Lookup Error: {:?}", e);
+                        record_error(&last_errors, &hooks, &error_counts, "lookup", parent, &e);
+                        reply.error(e.errno());
+                    }
                 }
-            }
-        });
+            }),
+        );
     }
 
     fn getattr(&mut self, _req: &fuser::Request<'_>, ino: u64, reply: fuser::ReplyAttr) {
+        if let Some(info) = self.explorer.try_getattr(ino) {
+            let ttl = time::Duration::from_secs(1);
+            reply.attr(&ttl, &info.file_attr);
+            return;
+        }
+
         let mut explorer = self.explorer.clone();
-        self.tokio_handle.spawn(async move {
-            match explorer.getattr(ino).await {
-                Ok(info) => {
-                    let ttl = time::Duration::from_secs(1);
-                    reply.attr(&ttl, &info.file_attr);
-                }
-                Err(e) => {
-                    eprintln!("Getattr Error: {:?}", e);
-                    reply.error(ENOENT);
+        let last_errors = self.last_errors.clone();
+        let error_counts = self.error_counts.clone();
+        let hooks = self.hooks.clone();
+        let attributes = vec![("ino", ino.to_string())];
+        let context = format!("ino {}", ino);
+        let timer = OpTimer::start("getattr", self.slow_op_threshold, context.clone());
+        self.spawn_op(
+            "getattr",
+            ino,
+            context,
+            telemetry::in_span("fuse.getattr", attributes, async move {
+                let _timer = timer;
+                match explorer.getattr(ino).await {
+                    Ok(info) => {
+                        let ttl = time::Duration::from_secs(1);
+                        reply.attr(&ttl, &info.file_attr);
+                        last_errors.clear(ino);
+                    }
+                    Err(e) => {
+                        eprintln!("Getattr Error: {:?}", e);
+                        record_error(&last_errors, &hooks, &error_counts, "getattr", ino, &e);
+                        reply.error(e.errno());
+                    }
                 }
-            }
-        });
+            }),
+        );
     }
 
     fn read(
@@ -87,44 +433,146 @@ impl Filesystem for WebDAVFS {
     ) {
         let downloader = self.downloader.clone();
         let mut explorer = self.explorer.clone();
-        self.tokio_handle.spawn(async move {
-            let attr_result = explorer.getattr(ino).await;
-            if attr_result.is_err() {
-                eprintln!("Get attr error: {:?}", attr_result.unwrap_err());
-                reply.error(ENOENT);
-                return;
-            }
+        let path_stats = self.path_stats.clone();
+        let last_errors = self.last_errors.clone();
+        let error_counts = self.error_counts.clone();
+        let hooks = self.hooks.clone();
+        let attributes = vec![
+            ("ino", ino.to_string()),
+            ("offset", offset.to_string()),
+            ("size", size.to_string()),
+        ];
+        let context = format!("ino {} offset {} size {}", ino, offset, size);
+        let mut timer = OpTimer::start("read", self.slow_op_threshold, context.clone());
+        self.spawn_op(
+            "read",
+            ino,
+            context,
+            telemetry::in_span("fuse.read", attributes, async move {
+                let end = (offset as u64).saturating_add(size as u64);
+                let attr_result = explorer.getattr_for_read(ino, end).await;
+                timer.phase("attributes");
+                if let Err(e) = &attr_result {
+                    eprintln!("Get attr error: {:?}", e);
+                    record_error(&last_errors, &hooks, &error_counts, "read", ino, e);
+                    reply.error(e.errno());
+                    return;
+                }
 
-            let attr = attr_result.unwrap();
-            let file_handle_result = downloader
-                .download(&attr.path, attr.file_attr.size, offset as u64, size)
-                .await;
-            if file_handle_result.is_err() {
-                eprintln!(
-                    "Get file handle error: {:?}",
-                    file_handle_result.err().unwrap()
-                );
-                reply.error(ENOENT);
-                return;
-            }
+                let attr = attr_result.unwrap();
+                let remote_file = RemoteFile {
+                    path: &attr.path,
+                    encoded_path: &attr.encoded_path,
+                    size: attr.file_attr.size,
+                    mtime: attr.file_attr.mtime,
+                    etag: attr.etag.as_deref(),
+                };
+                let read_result = if downloader.uses_cache().await {
+                    match read_cached(&downloader, &remote_file, offset as u64, size, &mut timer)
+                        .await
+                    {
+                        Ok(buf) => {
+                            downloader.cache_health().record_success();
+                            Ok(buf)
+                        }
+                        // Note : the cache failed, not the file, so the server still has it.
+                        Err(e)
+                            if downloader
+                                .cache_health()
+                                .record_failure(&e, downloader.temp_path()) =>
+                        {
+                            eprintln!("Cache error, reading {} without it: {:?}", attr.path, e);
+                            downloader.stream(&remote_file, offset as u64, size).await
+                        }
+                        Err(e) => Err(e),
+                    }
+                } else {
+                    downloader.stream(&remote_file, offset as u64, size).await
+                };
+                let buf = match read_result {
+                    Ok(buf) => {
+                        path_stats.record_read(&attr.path, buf.len() as u64);
+                        buf
+                    }
+                    Err(e) => {
+                        eprintln!("Read error: {:?}", e);
+                        record_error(&last_errors, &hooks, &error_counts, "read", ino, &e);
+                        reply.error(e.errno());
+                        return;
+                    }
+                };
+                reply.data(&buf);
+                last_errors.clear(ino);
+                explorer.record_read(ino).await;
+                timer.phase("disk read");
+                timer.finish(|| format!("{} offset {} size {}", attr.path, offset, size));
+            }),
+        );
+    }
 
-            let file_handle = file_handle_result.unwrap();
-            let file = file_handle.get_file().await;
-            if file.is_err() {
-                eprintln!("Can not file open : {:?}", file.err().unwrap());
-                reply.error(ENOENT);
-                return;
+    /// Reads go through the kernel page cache, which mmap needs, unless direct I/O is enabled for
+    /// the mount or the file is opened with O_DIRECT. Without FOPEN_KEEP_CACHE the kernel drops
+    /// the cached pages of a file when it is opened, so an open sees data changed on the server.
+    fn open(&mut self, _req: &fuser::Request<'_>, ino: u64, flags: i32, reply: fuser::ReplyOpen) {
+        let open_flags = if self.direct_io || flags & O_DIRECT != 0 {
+            FOPEN_DIRECT_IO
+        } else {
+            0
+        };
+        if self.file_size_limit.refuses(u64::MAX) || !self.growing_files.is_empty() {
+            self.open_checking_attr(ino, open_flags, reply);
+            return;
+        }
+        reply.opened(0, open_flags);
+        if self.content_rules.is_empty() {
+            return;
+        }
+
+        *self.open_counts.lock().unwrap().entry(ino).or_default() += 1;
+        let downloader = self.downloader.clone();
+        let mut explorer = self.explorer.clone();
+        let content_rules = self.content_rules.clone();
+        self.spawn_op("open", ino, format!("ino {}", ino), async move {
+            if let Ok(attr) = explorer.getattr(ino).await {
+                prefetch_on_open(&downloader, &content_rules, &attr).await;
             }
+        });
+    }
 
-            let mut file = file.unwrap();
-            let mut buf = vec![0; size as usize];
-            let result = file.read(&mut buf, offset as u64).await;
-            if result.is_err() {
-                eprintln!("Read error: {:?}", result.unwrap_err());
-                reply.error(ENOENT);
-                return;
+    /// Drops the cached data of files whose content rules evict them on close, once their last
+    /// handle is closed.
+    fn release(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        _fh: u64,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        _flush: bool,
+        reply: fuser::ReplyEmpty,
+    ) {
+        reply.ok();
+        let mut open_counts = self.open_counts.lock().unwrap();
+        let Some(count) = open_counts.get_mut(&ino) else {
+            return;
+        };
+        *count -= 1;
+        if *count > 0 {
+            return;
+        }
+        open_counts.remove(&ino);
+        drop(open_counts);
+
+        let downloader = self.downloader.clone();
+        let mut explorer = self.explorer.clone();
+        let content_rules = self.content_rules.clone();
+        self.spawn_op("release", ino, format!("ino {}", ino), async move {
+            match explorer.getattr(ino).await {
+                Ok(attr) if content_rules.evicts_on_close(attr.content_type.as_deref()) => {
+                    downloader.evict(&attr.path).await
+                }
+                _ => {}
             }
-            reply.data(&buf);
         });
     }
 
@@ -147,23 +595,328 @@ impl Filesystem for WebDAVFS {
         mut reply: fuser::ReplyDirectory,
     ) {
         let mut explorer = self.explorer.clone();
-        self.tokio_handle.spawn(async move {
-            let list = explorer.list(ino).await;
-            match list {
-                Ok(list) => {
-                    for (i, info) in list.into_iter().enumerate().skip(offset as usize) {
-                        if reply.add(info.attr.ino, (i + 1) as i64, info.attr.kind, info.name) {
-                            break;
-                        };
+        let last_errors = self.last_errors.clone();
+        let error_counts = self.error_counts.clone();
+        let hooks = self.hooks.clone();
+        let attributes = vec![("ino", ino.to_string())];
+        let context = format!("ino {} offset {}", ino, offset);
+        let timer = OpTimer::start("readdir", self.slow_op_threshold, context.clone());
+        self.spawn_op(
+            "readdir",
+            ino,
+            context,
+            telemetry::in_span("fuse.readdir", attributes, async move {
+                let _timer = timer;
+                let list = explorer.list(ino, offset == 0).await;
+                match list {
+                    Ok(list) => {
+                        add_dir_entries(list, offset, |info| {
+                            reply.add(info.attr.ino, info.offset, info.attr.kind, info.name)
+                        });
+                        reply.ok();
+                        last_errors.clear(ino);
+                    }
+                    Err(e) => {
+                        eprintln!("Readdir Error: {:?}", e);
+                        record_error(&last_errors, &hooks, &error_counts, "readdir", ino, &e);
+                        reply.error(e.errno());
                     }
-                    reply.ok();
                 }
-                Err(e) => {
-                    eprintln!("Readdir Error: {:?}", e);
-                    reply.error(ENOENT);
+            }),
+        );
+    }
+
+    /// Files report how much of them is hydrated, as decimal byte counts, and entries whose last
+    /// operation failed report the error, with the HTTP status the server answered with.
+    fn getxattr(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        name: &OsStr,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        let name = name.to_string_lossy().to_string();
+        if name == XATTR_LAST_ERROR {
+            match self.last_errors.get(ino) {
+                Some(message) => reply_xattr(reply, size, message.as_bytes()),
+                None => reply.error(ENODATA),
+            }
+            return;
+        }
+        if name != XATTR_CACHED_BYTES && name != XATTR_TOTAL_BYTES {
+            reply.error(ENODATA);
+            return;
+        }
+
+        let downloader = self.downloader.clone();
+        let mut explorer = self.explorer.clone();
+        let attributes = vec![("ino", ino.to_string()), ("name", name.clone())];
+        let context = format!("ino {} name {}", ino, name);
+        self.spawn_op(
+            "getxattr",
+            ino,
+            context,
+            telemetry::in_span("fuse.getxattr", attributes, async move {
+                let attr = match explorer.getattr(ino).await {
+                    Ok(attr) => attr,
+                    Err(e) => {
+                        eprintln!("Getxattr Error: {:?}", e);
+                        reply.error(e.errno());
+                        return;
+                    }
+                };
+                if attr.file_attr.kind != FileType::RegularFile {
+                    reply.error(ENODATA);
                     return;
                 }
+
+                let value = if name == XATTR_TOTAL_BYTES {
+                    attr.file_attr.size
+                } else {
+                    let remote_file = RemoteFile {
+                        path: &attr.path,
+                        encoded_path: &attr.encoded_path,
+                        size: attr.file_attr.size,
+                        mtime: attr.file_attr.mtime,
+                        etag: attr.etag.as_deref(),
+                    };
+                    downloader.cached_bytes(&remote_file).await
+                };
+                reply_xattr(reply, size, value.to_string().as_bytes());
+            }),
+        );
+    }
+
+    fn listxattr(
+        &mut self,
+        _req: &fuser::Request<'_>,
+        ino: u64,
+        size: u32,
+        reply: fuser::ReplyXattr,
+    ) {
+        let mut explorer = self.explorer.clone();
+        let has_last_error = self.last_errors.contains(ino);
+        self.spawn_op("listxattr", ino, format!("ino {}", ino), async move {
+            let last_error = match has_last_error {
+                true => format!("{}\0", XATTR_LAST_ERROR),
+                false => String::new(),
+            };
+            match explorer.getattr(ino).await {
+                Ok(attr) if attr.file_attr.kind == FileType::RegularFile => {
+                    let names = format!(
+                        "{}\0{}\0{}",
+                        XATTR_CACHED_BYTES, XATTR_TOTAL_BYTES, last_error
+                    );
+                    reply_xattr(reply, size, names.as_bytes());
+                }
+                Ok(_) => reply_xattr(reply, size, last_error.as_bytes()),
+                Err(e) => reply.error(e.errno()),
+            }
+        });
+    }
+}
+
+/// Reads `offset..offset + size` of `remote_file` through the cache, downloading what is missing.
+async fn read_cached(
+    downloader: &WebDAVFSFileDownloader,
+    remote_file: &RemoteFile<'_>,
+    offset: u64,
+    size: u32,
+    timer: &mut OpTimer,
+) -> Result<Vec<u8>, FSError> {
+    let file_handle = downloader
+        .download_timed(remote_file, offset, size, timer)
+        .await?;
+    let reader = downloader.reader(&file_handle).await?;
+    // Note : with direct I/O the reply is passed to the reader as is, so it must end at the end
+    // of the file.
+    reader
+        .read(offset, size as usize)
+        .await
+        .map_err(|err| FSError::IO(err))
+}
+
+/// Keeps `e` as the last error of `ino`, leading with the HTTP status when the server sent one,
+/// e.g. `403 Forbidden: ...`, passes it to the `on_error` hooks and counts it for `op`.
+fn record_error(
+    last_errors: &LastErrors,
+    hooks: &Hooks,
+    error_counts: &ErrorCounts,
+    op: &'static str,
+    ino: u64,
+    e: &FSError,
+) {
+    error_counts.record(op);
+    let message = match e.status() {
+        Some(status) => format!("{}: {:?}", status, e),
+        None => format!("{:?}", e),
+    };
+    hooks.error(vec![("ino", ino.to_string()), ("error", message.clone())]);
+    last_errors.record(ino, message);
+}
+
+/// Downloads a file which was opened whole in the background, if its content rules say so.
+async fn prefetch_on_open(
+    downloader: &WebDAVFSFileDownloader,
+    content_rules: &ContentRules,
+    attr: &InodeInfo,
+) {
+    if !content_rules.prefetches(attr.content_type.as_deref(), attr.file_attr.size) {
+        return;
+    }
+    let remote_file = RemoteFile {
+        path: &attr.path,
+        encoded_path: &attr.encoded_path,
+        size: attr.file_attr.size,
+        mtime: attr.file_attr.mtime,
+        etag: attr.etag.as_deref(),
+    };
+    if let Err(e) = downloader.hydrate(&remote_file).await {
+        eprintln!("Prefetch Error: {} {:?}", attr.path, e);
+    }
+}
+
+/// Adds the entries of a listing after `offset`, the offset of the last entry the kernel took (see
+/// `ListItemInfo::offset`), until `add` reports that the reply is full.
+fn add_dir_entries(
+    list: Vec<ListItemInfo>,
+    offset: i64,
+    mut add: impl FnMut(ListItemInfo) -> bool,
+) {
+    for info in list.into_iter().filter(|x| x.offset > offset) {
+        if add(info) {
+            break;
+        };
+    }
+}
+
+/// Answers an xattr request, which asks for the length of the value when `size` is 0.
+fn reply_xattr(reply: fuser::ReplyXattr, size: u32, value: &[u8]) {
+    if size == 0 {
+        reply.size(value.len() as u32);
+    } else if value.len() > size as usize {
+        reply.error(ERANGE);
+    } else {
+        reply.data(value);
+    }
+}
+
+#[cfg(test)]
+mod webdav_fs_test {
+    use std::{ffi::OsString, path::Path};
+
+    use chrono::{TimeZone, Utc};
+    use tokio::runtime::Handle;
+
+    use super::{add_dir_entries, WebDAVFS};
+    use crate::{
+        fs::{
+            inode_info_map::InodeInfoMap, name_source::NameSource, webdav_fs_explorer::list_items,
+        },
+        webdav::{Secret, WebDAVClient, WebDAVFile, WebDAVList},
+    };
+
+    fn webdavfs(cache_dir: &tempfile::TempDir) -> WebDAVFS {
+        let client = WebDAVClient::new(
+            "http://127.0.0.1:1".to_string(),
+            String::new(),
+            Secret::new(String::new()),
+        )
+        .unwrap();
+        WebDAVFS::new(
+            Handle::current(),
+            client,
+            cache_dir.path().to_str().unwrap().to_string(),
+            0,
+            0,
+        )
+    }
+
+    #[tokio::test]
+    async fn instances_share_no_state_test() {
+        let first_dir = tempfile::tempdir().unwrap();
+        let second_dir = tempfile::tempdir().unwrap();
+        let first = webdavfs(&first_dir);
+        let second = webdavfs(&second_dir);
+
+        first.path_stats().record_read("/a.txt", 100);
+        first.error_counts().record("read");
+        let _op = first.inflight_ops().start("read", 2, "/a.txt".to_string());
+        first.last_errors.record(2, "404".to_string());
+        *first.open_counts.lock().unwrap().entry(2).or_default() += 1;
+
+        assert_eq!(first.path_stats().top(10).len(), 1);
+        assert_eq!(first.error_counts().snapshot().len(), 1);
+        assert_eq!(first.inflight_ops().snapshot().len(), 1);
+        assert!(second.path_stats().top(10).is_empty());
+        assert!(second.error_counts().snapshot().is_empty());
+        assert!(second.inflight_ops().snapshot().is_empty());
+        assert!(!second.last_errors.contains(2));
+        assert!(second.open_counts.lock().unwrap().is_empty());
+        assert_eq!(second.downloader().cache_usage().await, (0, 0));
+    }
+
+    fn file(path: &str) -> WebDAVList {
+        WebDAVList::File(WebDAVFile {
+            href: path.to_string(),
+            path: path.to_string(),
+            encoded_path: path.to_string(),
+            name: Path::new(path).file_name().unwrap().to_os_string(),
+            display_name: None,
+            last_modified: Utc.timestamp_opt(1_000_000, 0).unwrap(),
+            content_length: 1,
+            content_type: String::new(),
+            etag: None,
+            read_only: false,
+        })
+    }
+
+    /// Reads a page of the root the way the kernel does, into a reply with room for `capacity`
+    /// entries. Returns the names added and the offset to resume at.
+    fn readdir_page(map: &InodeInfoMap, offset: i64, capacity: usize) -> (Vec<OsString>, i64) {
+        let mut names = Vec::new();
+        let mut next_offset = offset;
+        add_dir_entries(list_items(map, 1).unwrap(), offset, |info| {
+            if names.len() == capacity {
+                return true;
             }
+            names.push(info.name);
+            next_offset = info.offset;
+            false
         });
+        (names, next_offset)
+    }
+
+    #[test]
+    fn readdir_resumes_where_reply_filled_test() {
+        let mut map = InodeInfoMap::new(0, 0);
+        let list = vec![file("/a"), file("/b"), file("/c"), file("/d")];
+        map.update_cache(1, list, None, NameSource::Href);
+
+        let (first, offset) = readdir_page(&map, 0, 3);
+        assert_eq!(first, [".", "..", "a"]);
+        let (second, offset) = readdir_page(&map, offset, 2);
+        assert_eq!(second, ["b", "c"]);
+        let (third, offset) = readdir_page(&map, offset, 2);
+        assert_eq!(third, ["d"]);
+        let (last, _) = readdir_page(&map, offset, 2);
+        assert!(last.is_empty());
+    }
+
+    #[test]
+    fn readdir_resumes_after_listing_changed_test() {
+        let mut map = InodeInfoMap::new(0, 0);
+        let list = vec![file("/a"), file("/b"), file("/c"), file("/d")];
+        map.update_cache(1, list, None, NameSource::Href);
+        let (first, offset) = readdir_page(&map, 0, 4);
+        assert_eq!(first, [".", "..", "a", "b"]);
+
+        // Note : `a`, which the kernel took, and `c`, which it did not yet, are removed. The new `0`
+        // sorts first by name but comes after the entries which stayed.
+        let list = vec![file("/0"), file("/b"), file("/d"), file("/e")];
+        map.update_cache(1, list, None, NameSource::Href);
+        let (rest, _) = readdir_page(&map, offset, 10);
+        assert_eq!(rest, ["d", "0", "e"]);
     }
 }
@@ -1,95 +1,678 @@
-use std::sync::Arc;
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::{OsStr, OsString},
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
 use fuser::{FileAttr, FileType};
-use tokio::sync::RwLock;
+use tokio::{
+    sync::{Mutex as AsyncMutex, RwLock, Semaphore},
+    task::JoinSet,
+};
 
-use crate::webdav::WebDAVClient;
+use crate::webdav::{
+    split_collection, Error as WebDAVError, StatOptions, WebDAVClient, WebDAVList,
+};
 
 use super::{
+    atime_mode::AtimeMode,
+    cache_policy::CachePolicy,
     errors::FSError,
+    file_size_limit::FileSizeLimit,
+    growing_files::GrowingFiles,
     inode_info_map::{InodeInfo, InodeInfoMap},
+    listing_spill::ListingSpill,
+    name_source::NameSource,
+    path_stats::PathStats,
+    single_flight::SingleFlight,
+    sync_rules::SyncRules,
+    versions::{is_versions_path, VersionsView},
 };
 
+const NAME_MAX: usize = 255;
+const GETATTR_COALESCE_WINDOW: Duration = Duration::from_millis(300);
+/// A directory listed this recently is not listed again for a lookup.
+const LOOKUP_REFRESH_DEBOUNCE: Duration = Duration::from_secs(1);
+/// Attributes which could not be confirmed with the server are used this long before it is
+/// asked again.
+const STAT_RETRY_DELAY: Duration = Duration::from_secs(5);
+/// Directories listed at the same time by `prefetch`.
+const PREFETCH_CONCURRENCY: usize = 8;
+
+/// Readdir offset of the `.` entry, which the kernel passes back to resume after it.
+const WORKING_DIR_OFFSET: i64 = 1;
+const PARENT_DIR_OFFSET: i64 = 2;
+
 pub(super) struct ListItemInfo {
     pub attr: FileAttr,
-    pub name: String,
+    pub name: OsString,
+    /// Readdir offset of the entry. A child has its inode number past the offsets of `.` and
+    /// `..`, so a listing resumed after the directory changed neither repeats nor skips the
+    /// entries which stayed, wherever the previous reply ended.
+    pub offset: i64,
 }
 
 impl ListItemInfo {
     fn new_parent_dir_from(inode_info: &InodeInfo) -> ListItemInfo {
         ListItemInfo {
             attr: inode_info.file_attr.clone(),
-            name: "..".to_string(),
+            name: OsString::from(".."),
+            offset: PARENT_DIR_OFFSET,
         }
     }
 
     fn new_working_dir_from(inode_info: &InodeInfo) -> ListItemInfo {
         ListItemInfo {
             attr: inode_info.file_attr.clone(),
-            name: ".".to_string(),
+            name: OsString::from("."),
+            offset: WORKING_DIR_OFFSET,
         }
     }
 
     fn new_child_from(inode_info: &InodeInfo) -> ListItemInfo {
         ListItemInfo {
             attr: inode_info.file_attr,
-            name: inode_info.file_name().to_string(),
+            name: inode_info.name.clone(),
+            offset: PARENT_DIR_OFFSET + inode_info.file_attr.ino as i64,
         }
     }
 }
 
+pub(super) struct InvalidatedEntry {
+    pub ino: u64,
+    pub parent: u64,
+    pub name: OsString,
+    pub path: String,
+}
+
 #[derive(Clone)]
 pub(super) struct WebDAVFSExplorer {
     client: WebDAVClient,
     inode_info_map: Arc<RwLock<InodeInfoMap>>,
+    /// Keyed by the inode and whether the size of growing files is confirmed.
+    getattr_flight: Arc<SingleFlight<(u64, bool), Option<InodeInfo>>>,
+    /// When each directory was last looked into, which orders the background refresh.
+    accessed_at: Arc<Mutex<HashMap<u64, Instant>>>,
+    path_stats: PathStats,
+    cache_policy: CachePolicy,
+    name_source: NameSource,
+    sync_rules: SyncRules,
+    file_size_limit: FileSizeLimit,
+    growing_files: GrowingFiles,
+    versions: Option<VersionsView>,
+    /// Known entries kept in memory before cold listings are spilled to disk.
+    max_entries: Option<usize>,
+    /// Approximate bytes of known entries kept in memory before cold listings are spilled.
+    max_memory: Option<usize>,
+    /// Held by the task spilling listings, see `spill_cold_listings`.
+    spilling: Arc<AsyncMutex<()>>,
+    /// Bounds the listings of sub directories fetched in the background after a readdir. None
+    /// fetches none.
+    subdir_prefetch: Option<Arc<Semaphore>>,
+    atime_mode: AtimeMode,
 }
 
 impl WebDAVFSExplorer {
-    pub fn new(client: WebDAVClient, user_id: u32, group_id: u32) -> WebDAVFSExplorer {
+    pub fn new(
+        client: WebDAVClient,
+        user_id: u32,
+        group_id: u32,
+        path_stats: PathStats,
+    ) -> WebDAVFSExplorer {
+        let mut inode_info_map = InodeInfoMap::new(user_id, group_id);
+        inode_info_map.set_server_clock(client.clock().clone());
         WebDAVFSExplorer {
             client,
-            inode_info_map: Arc::new(RwLock::new(InodeInfoMap::new(user_id, group_id))),
+            inode_info_map: Arc::new(RwLock::new(inode_info_map)),
+            getattr_flight: Arc::new(SingleFlight::new(GETATTR_COALESCE_WINDOW)),
+            accessed_at: Arc::new(Mutex::new(HashMap::new())),
+            path_stats,
+            cache_policy: CachePolicy::default(),
+            name_source: NameSource::default(),
+            sync_rules: SyncRules::default(),
+            file_size_limit: FileSizeLimit::default(),
+            growing_files: GrowingFiles::default(),
+            versions: None,
+            max_entries: None,
+            max_memory: None,
+            spilling: Arc::new(AsyncMutex::new(())),
+            subdir_prefetch: None,
+            atime_mode: AtimeMode::default(),
         }
     }
 
-    pub async fn lookup(&mut self, parent: u64, target: &str) -> Result<InodeInfo, FSError> {
+    pub fn set_cache_policy(&mut self, cache_policy: CachePolicy) {
+        self.cache_policy = cache_policy;
+    }
+
+    pub fn set_name_source(&mut self, name_source: NameSource) {
+        self.name_source = name_source;
+    }
+
+    pub fn set_atime_mode(&mut self, atime_mode: AtimeMode) {
+        self.atime_mode = atime_mode;
+    }
+
+    pub fn set_sync_rules(&mut self, sync_rules: SyncRules) {
+        self.sync_rules = sync_rules;
+    }
+
+    pub fn set_file_size_limit(&mut self, file_size_limit: FileSizeLimit) {
+        self.file_size_limit = file_size_limit;
+    }
+
+    pub fn set_growing_files(&mut self, growing_files: GrowingFiles) {
+        self.growing_files = growing_files;
+    }
+
+    pub fn set_versions(&mut self, versions: VersionsView) {
+        self.versions = Some(versions);
+    }
+
+    /// Keeps about `max_entries` entries, and about `max_memory` bytes of them, in memory and
+    /// moves the listings of the least recently used directories to `spill` beyond that. Must be
+    /// called before mounting.
+    pub fn set_listing_limits(
+        &mut self,
+        max_entries: Option<usize>,
+        max_memory: Option<usize>,
+        spill: ListingSpill,
+    ) {
+        // Note : nothing else holds the map before mounting.
+        self.inode_info_map
+            .try_write()
+            .expect("explorer is in use")
+            .set_spill(spill);
+        self.max_entries = max_entries;
+        self.max_memory = max_memory;
+    }
+
+    /// Gives the entries the server does not let the user write no write permission bits. Must
+    /// be called before mounting.
+    pub fn set_read_only_perms(&mut self, read_only_perms: bool) {
+        // Note : nothing else holds the map before mounting.
+        self.inode_info_map
+            .try_write()
+            .expect("explorer is in use")
+            .set_read_only_perms(read_only_perms);
+    }
+
+    /// Lists the sub directories of every directory read from its start in the background,
+    /// at most `max_concurrent` at a time across the mount.
+    pub fn set_subdir_prefetch(&mut self, max_concurrent: usize) {
+        self.subdir_prefetch = Some(Arc::new(Semaphore::new(max_concurrent.max(1))));
+    }
+
+    pub async fn lookup(&mut self, parent: u64, target: &OsStr) -> Result<InodeInfo, FSError> {
+        if target.len() > NAME_MAX {
+            return Err(FSError::NameTooLong(target.to_string_lossy().into_owned()));
+        }
+        self.record_access(parent);
+        self.restore_if_spilled(parent).await?;
+
+        // Note : a listing which went stale just now, e.g. with a very short TTL, still answers,
+        // so globbing in a directory does not list it again for every name.
+        let inode_info_map = self.inode_info_map.read().await;
+        if inode_info_map.listed_within(parent, LOOKUP_REFRESH_DEBOUNCE) {
+            let inode_info = inode_info_map.find_by_path(parent, target).ok_or_else(|| {
+                FSError::FileNotFoundInInode(target.to_string_lossy().into_owned())
+            })?;
+            return Ok(inode_info.clone());
+        }
+        drop(inode_info_map);
         self.update_dir_cache_if_not_exists(parent).await?;
 
         let inode_info_map = self.inode_info_map.read().await;
         let inode_info = inode_info_map
             .find_by_path(parent, target)
-            .ok_or(FSError::FileNotFoundInInode(target.to_string()))?;
+            .ok_or_else(|| FSError::FileNotFoundInInode(target.to_string_lossy().into_owned()))?;
         Ok(inode_info.clone())
     }
 
-    pub async fn list(&mut self, ino: u64) -> Result<Vec<ListItemInfo>, FSError> {
+    /// Lists a directory. `rewind` is set when a readdir starts from the first entry, which
+    /// lists the directory again if the cache policy asks to refresh on readdir.
+    /// Returns `.`, `..` and the children of a directory in the order of their readdir offsets.
+    /// Children are in the order of their inode numbers, so entries added to the directory come
+    /// last.
+    pub async fn list(&mut self, ino: u64, rewind: bool) -> Result<Vec<ListItemInfo>, FSError> {
+        self.record_access(ino);
+        self.restore_if_spilled(ino).await?;
+        if rewind && self.cache_policy.refresh_on_readdir {
+            self.inode_info_map.write().await.mark_stale(ino);
+        }
         self.update_dir_cache_if_not_exists(ino).await?;
 
         let inode_info_map = self.inode_info_map.read().await;
+        if rewind {
+            self.prefetch_subdirs(&inode_info_map, ino);
+        }
+        list_items(&inode_info_map, ino).ok_or(FSError::INodeNotExists)
+    }
 
-        let mut result = Vec::new();
+    /// Returns the number of known inodes and cached directories.
+    pub async fn cache_counts(&self) -> (usize, usize) {
+        let inode_info_map = self.inode_info_map.read().await;
+        (
+            inode_info_map.inode_count(),
+            inode_info_map.cached_dir_count(),
+        )
+    }
+
+    /// Returns the remote path of a known inode without waiting, None while the inodes are
+    /// being changed, e.g. by an operation which hangs.
+    pub fn try_path_of(&self, ino: u64) -> Option<String> {
+        let inode_info_map = self.inode_info_map.try_read().ok()?;
+        inode_info_map.find_by_ino(ino).map(|x| x.path.clone())
+    }
 
-        inode_info_map
+    /// Returns the approximate bytes of memory taken by the known inodes.
+    pub async fn memory_bytes(&self) -> usize {
+        self.inode_info_map.read().await.memory_bytes()
+    }
+
+    /// File managers issue bursts of identical getattr calls, so concurrent calls for the same
+    /// inode share a single lookup. Attributes older than the attribute TTL, and those of
+    /// growing files, are confirmed with the server first.
+    pub async fn getattr(&mut self, ino: u64) -> Result<InodeInfo, FSError> {
+        self.getattr_checking(ino, true).await
+    }
+
+    async fn getattr_checking(&self, ino: u64, check_growth: bool) -> Result<InodeInfo, FSError> {
+        let explorer = self.clone();
+        self.getattr_flight
+            .run((ino, check_growth), || async move {
+                explorer.restore_if_spilled(ino).await.ok()?;
+                let inode_info = explorer
+                    .inode_info_map
+                    .read()
+                    .await
+                    .find_by_ino(ino)
+                    .cloned()?;
+                let expired = explorer
+                    .cache_policy
+                    .attr_ttl
+                    .is_some_and(|attr_ttl| inode_info.fetched_at.elapsed() >= attr_ttl);
+                let growing = check_growth && explorer.is_growing(&inode_info);
+                if (expired || growing) && !explorer.is_versions_entry(&inode_info) {
+                    explorer.refresh_attr(inode_info).await.ok()
+                } else {
+                    Some(inode_info)
+                }
+            })
+            .await
+            .ok_or(FSError::INodeNotExists)
+    }
+
+    /// Returns the attributes of a file about to be read up to `end`. Expired attributes are
+    /// confirmed with a Depth-0 PROPFIND first, so the cache file is created with the current
+    /// size, and so are those of a growing file when the read reaches its end.
+    pub async fn getattr_for_read(&mut self, ino: u64, end: u64) -> Result<InodeInfo, FSError> {
+        let inode_info = self.getattr_checking(ino, false).await?;
+        let past_end = end > inode_info.file_attr.size && self.is_growing(&inode_info);
+        // Note : revisions are listed from the versions collection, the files client can not
+        // stat them. They never change, and their listing expires like any other.
+        if (!inode_info.is_expired() && !past_end) || self.is_versions_entry(&inode_info) {
+            return Ok(inode_info);
+        }
+        self.refresh_attr(inode_info).await
+    }
+
+    /// Returns whether an entry is a file which grows while it is read.
+    pub fn is_growing(&self, inode_info: &InodeInfo) -> bool {
+        inode_info.file_attr.kind == FileType::RegularFile
+            && !self.is_versions_entry(inode_info)
+            && self.growing_files.matches(&inode_info.path)
+    }
+
+    /// Updates the access time of a file which was read, as the atime mode says. The time is
+    /// taken from the clock of the server, since relatime compares it with the mtime.
+    pub async fn record_read(&self, ino: u64) {
+        if self.atime_mode == AtimeMode::NoAtime {
+            return;
+        }
+        let now = self.client.clock().now();
+        let atime = self
+            .inode_info_map
+            .read()
+            .await
             .find_by_ino(ino)
-            .map(|x| result.push(ListItemInfo::new_working_dir_from(x)));
-        inode_info_map
-            .parent(ino)
-            .map(|x| result.push(ListItemInfo::new_parent_dir_from(x)));
+            .and_then(|x| self.atime_mode.read_atime(&x.file_attr, now));
+        // Note : most reads of relatime change nothing, so those only take the read lock.
+        if let Some(atime) = atime {
+            self.inode_info_map.write().await.set_atime(ino, atime);
+        }
+    }
+
+    /// Fetches the attributes of a known entry again with a Depth-0 PROPFIND. When the server
+    /// can not be reached, the cached attributes are returned.
+    async fn refresh_attr(&self, inode_info: InodeInfo) -> Result<InodeInfo, FSError> {
+        let ino = inode_info.file_attr.ino;
+        self.path_stats.record_remote_request(&inode_info.path);
+        let options = StatOptions {
+            collection: inode_info.file_attr.kind == FileType::Directory,
+        };
+        let stat = self.client.stat(&inode_info.encoded_path, options).await;
+        let (item, cache_control) = match stat {
+            Ok(result) => result,
+            Err(e @ WebDAVError::NotFound(_)) => return Err(FSError::WebDAV(e)),
+            Err(e) => {
+                // Note : the server may be briefly unreachable, the cached attributes still work.
+                // They are kept a while, so the server is not asked again with every access.
+                eprintln!("Stat Error: {} {:?}", inode_info.path, e);
+                let mut inode_info_map = self.inode_info_map.write().await;
+                return Ok(inode_info_map
+                    .extend_entry(ino, STAT_RETRY_DELAY)
+                    .cloned()
+                    .unwrap_or(inode_info));
+            }
+        };
+        let ttl = self.cache_policy.ttl(&cache_control);
+        let mut inode_info_map = self.inode_info_map.write().await;
+        let inode_info = inode_info_map
+            .update_entry(ino, &item, ttl)
+            .cloned()
+            .unwrap_or(inode_info);
+        self.forget_getattr(ino);
+        Ok(inode_info)
+    }
+
+    /// Returns a recently resolved getattr result without waiting.
+    pub fn try_getattr(&self, ino: u64) -> Option<InodeInfo> {
+        self.getattr_flight.get_ready(&(ino, true)).flatten()
+    }
+
+    /// Drops the shared getattr results of an entry whose attributes changed.
+    fn forget_getattr(&self, ino: u64) {
+        self.getattr_flight.forget(&(ino, true));
+        self.getattr_flight.forget(&(ino, false));
+    }
+
+    /// Marks the directory listings which contain `path` as stale, so the next access lists them
+    /// again from the server.
+    pub async fn invalidate(&self, path: &str) -> Result<InvalidatedEntry, FSError> {
+        let mut inode_info_map = self.inode_info_map.write().await;
+        let inode_info = inode_info_map
+            .find_by_remote_path(path)
+            .ok_or(FSError::FileNotFoundInInode(path.to_string()))?;
+        let ino = inode_info.file_attr.ino;
+        let kind = inode_info.file_attr.kind;
+        let name = inode_info.name.clone();
+        let path = inode_info.path.clone();
+        let parent = inode_info_map.parent_ino(ino).unwrap_or(ino);
 
-        inode_info_map
+        if kind == FileType::Directory {
+            inode_info_map.mark_stale(ino);
+        }
+        inode_info_map.mark_stale(parent);
+        self.forget_getattr(ino);
+        Ok(InvalidatedEntry {
+            ino,
+            parent,
+            name,
+            path,
+        })
+    }
+
+    /// Returns the cached directories, the most recently accessed first.
+    pub async fn cached_dirs_by_access(&self) -> Vec<InodeInfo> {
+        let mut dirs = self.inode_info_map.read().await.cached_dirs();
+        let mut accessed_at = self.accessed_at.lock().unwrap();
+        let cached_inos: HashSet<u64> = dirs.iter().map(|x| x.file_attr.ino).collect();
+        accessed_at.retain(|ino, _| cached_inos.contains(ino));
+        dirs.sort_by_key(|x| Reverse(accessed_at.get(&x.file_attr.ino).copied()));
+        dirs
+    }
+
+    /// Lists a cached directory again and returns the entries which changed on the server.
+    pub async fn refresh_dir(&self, dir: &InodeInfo) -> Result<Vec<InvalidatedEntry>, FSError> {
+        let ino = dir.file_attr.ino;
+        // Note : the map is not locked during the request, so the mount stays responsive.
+        let (list, ttl, etag) = self
+            .list_entries(&dir.path, &dir.encoded_path)
+            .await
+            .map_err(|e| FSError::WebDAV(e))?;
+        self.path_stats.record_remote_request(&dir.path);
+
+        let mut inode_info_map = self.inode_info_map.write().await;
+        if inode_info_map.find_by_ino(ino).map(|x| &x.path) != Some(&dir.path) {
+            return Ok(Vec::new());
+        }
+        let changed_entries = inode_info_map.update_cache(ino, list, ttl, self.name_source);
+        inode_info_map.set_listing_etag(ino, etag.as_deref());
+        drop(inode_info_map);
+        self.spill_cold_listings(ino).await;
+        for entry in changed_entries.iter() {
+            self.forget_getattr(entry.ino);
+        }
+        Ok(changed_entries
+            .into_iter()
+            .map(|entry| InvalidatedEntry {
+                ino: entry.ino,
+                parent: ino,
+                name: entry.name,
+                path: entry.path,
+            })
+            .collect())
+    }
+
+    /// Whether the entry is a revision below the versions view, which can not be stat'ed.
+    fn is_versions_entry(&self, inode_info: &InodeInfo) -> bool {
+        self.versions.is_some() && is_versions_path(&inode_info.path)
+    }
+
+    /// Lists the directory at the remote `path` and every directory below it, breadth first and
+    /// `PREFETCH_CONCURRENCY` at a time, so browsing the tree afterwards sends no requests.
+    /// Fresh listings are not fetched again. Returns the number of directories and entries.
+    pub async fn prefetch(&mut self, path: &str) -> Result<(usize, usize), FSError> {
+        let root = self.resolve_remote_path(path).await?;
+        let mut pending = VecDeque::from([root]);
+        let mut running = JoinSet::new();
+        let (mut dirs, mut entries) = (0, 0);
+        loop {
+            while running.len() < PREFETCH_CONCURRENCY {
+                let Some(ino) = pending.pop_front() else {
+                    break;
+                };
+                let explorer = self.clone();
+                running.spawn(async move { explorer.list_for_prefetch(ino).await });
+            }
+            let Some(result) = running.join_next().await else {
+                break;
+            };
+            match result.map_err(|e| FSError::IO(io::Error::other(e)))? {
+                Ok((subdirs, count)) => {
+                    dirs += 1;
+                    entries += count;
+                    pending.extend(subdirs);
+                }
+                // Note : the rest of the tree is still worth listing.
+                Err(e) => eprintln!("Prefetch Error: {:?}", e),
+            }
+        }
+        Ok((dirs, entries))
+    }
+
+    /// Lists the sub directories of `ino` whose listing is not cached in the background, so
+    /// entering one of them next answers at once. Sub directories are not walked further.
+    fn prefetch_subdirs(&self, inode_info_map: &InodeInfoMap, ino: u64) {
+        let Some(semaphore) = self.subdir_prefetch.clone() else {
+            return;
+        };
+        let subdirs: Vec<u64> = inode_info_map
             .childs(ino)
-            .ok_or(FSError::INodeNotExists)?
+            .unwrap_or_default()
             .iter()
-            .for_each(|x| result.push(ListItemInfo::new_child_from(x)));
+            .filter(|x| {
+                x.file_attr.kind == FileType::Directory
+                    && !self.is_versions_entry(x)
+                    && !inode_info_map.is_cached_dir(x.file_attr.ino)
+            })
+            .map(|x| x.file_attr.ino)
+            .collect();
+        if subdirs.is_empty() {
+            return;
+        }
 
-        Ok(result)
+        let explorer = self.clone();
+        tokio::spawn(async move {
+            let mut running = JoinSet::new();
+            for subdir in subdirs {
+                // Note : the semaphore is never closed, so acquiring can not fail.
+                let permit = semaphore.clone().acquire_owned().await.unwrap();
+                let explorer = explorer.clone();
+                running.spawn(async move {
+                    let _permit = permit;
+                    explorer.list_for_prefetch(subdir).await
+                });
+            }
+            while let Some(result) = running.join_next().await {
+                if let Ok(Err(e)) = result {
+                    eprintln!("Prefetch Error: {:?}", e);
+                }
+            }
+        });
     }
 
-    pub async fn getattr(&mut self, ino: u64) -> Result<InodeInfo, FSError> {
+    /// Finds the inode of a remote path, listing the directories on the way as needed.
+    async fn resolve_remote_path(&mut self, path: &str) -> Result<u64, FSError> {
+        let mut ino = 1;
+        let mut prefix = String::new();
+        for segment in path.split('/').filter(|x| !x.is_empty()) {
+            prefix = format!("{}/{}", prefix, segment);
+            self.restore_if_spilled(ino).await?;
+            self.update_dir_cache_if_not_exists(ino).await?;
+            let inode_info_map = self.inode_info_map.read().await;
+            ino = inode_info_map
+                .childs(ino)
+                .unwrap_or_default()
+                .iter()
+                .find(|x| x.path.trim_end_matches('/') == prefix)
+                .ok_or(FSError::FileNotFoundInInode(path.to_string()))?
+                .file_attr
+                .ino;
+        }
+        Ok(ino)
+    }
+
+    /// Lists a directory unless its listing is fresh, and returns its sub directories and the
+    /// number of its entries. Unlike `update_dir_cache_if_not_exists`, the map is not locked
+    /// during the request, so other directories are listed meanwhile.
+    async fn list_for_prefetch(&self, ino: u64) -> Result<(Vec<u64>, usize), FSError> {
+        self.restore_if_spilled(ino).await?;
         let inode_info_map = self.inode_info_map.read().await;
-        inode_info_map
+        let dir = inode_info_map
             .find_by_ino(ino)
-            .map_or(Err(FSError::INodeNotExists), |x| Ok(x.clone()))
+            .cloned()
+            .ok_or(FSError::INodeNotExists)?;
+        let is_cached = inode_info_map.is_cached_dir(ino);
+        drop(inode_info_map);
+
+        if !is_cached {
+            self.path_stats.record_remote_request(&dir.path);
+            let (list, ttl, etag) = self
+                .list_entries(&dir.path, &dir.encoded_path)
+                .await
+                .map_err(|e| FSError::WebDAV(e))?;
+            let mut inode_info_map = self.inode_info_map.write().await;
+            if inode_info_map.find_by_ino(ino).map(|x| &x.path) != Some(&dir.path) {
+                return Ok((Vec::new(), 0));
+            }
+            inode_info_map.update_cache(ino, list, ttl, self.name_source);
+            inode_info_map.set_listing_etag(ino, etag.as_deref());
+            drop(inode_info_map);
+            self.spill_cold_listings(ino).await;
+        }
+
+        let inode_info_map = self.inode_info_map.read().await;
+        let childs = inode_info_map.childs(ino).unwrap_or_default();
+        // Note : revisions are listed per file, walking them would list every file again.
+        let subdirs = childs
+            .iter()
+            .filter(|x| x.file_attr.kind == FileType::Directory && !self.is_versions_entry(x))
+            .map(|x| x.file_attr.ino)
+            .collect();
+        Ok((subdirs, childs.len()))
+    }
+
+    /// Brings back the spilled listings `ino` needs before it is used.
+    async fn restore_if_spilled(&self, ino: u64) -> Result<(), FSError> {
+        if !self.inode_info_map.read().await.is_spilled(ino) {
+            return Ok(());
+        }
+        self.inode_info_map
+            .write()
+            .await
+            .restore(ino)
+            .map_err(|e| FSError::IO(e))
+    }
+
+    /// Spills the listings of the least recently used directories until the entries in memory
+    /// are back under the maximums. The listing of `keep`, which was just fetched, stays.
+    ///
+    /// Note : the listings are encoded under the map lock, but written to disk after it is
+    /// released, so lookups do not wait for the disk. One task spills at a time.
+    async fn spill_cold_listings(&self, keep: u64) {
+        let Ok(_spilling) = self.spilling.try_lock() else {
+            return;
+        };
+        let over_limits = |entries: usize, memory_bytes: usize| {
+            self.max_entries.is_some_and(|x| entries > x)
+                || self.max_memory.is_some_and(|x| memory_bytes > x)
+        };
+        let (spill, snapshots) = {
+            let inode_info_map = self.inode_info_map.read().await;
+            let mut entries = inode_info_map.inode_count();
+            let mut memory_bytes = inode_info_map.memory_bytes();
+            let Some(spill) = inode_info_map
+                .spill()
+                .filter(|_| over_limits(entries, memory_bytes))
+            else {
+                return;
+            };
+            let mut dirs = inode_info_map.cached_dir_inos();
+            dirs.retain(|ino| *ino != keep);
+            {
+                let accessed_at = self.accessed_at.lock().unwrap();
+                dirs.sort_by_key(|ino| accessed_at.get(ino).copied());
+            }
+            let mut snapshots = Vec::new();
+            for ino in dirs {
+                if !over_limits(entries, memory_bytes) {
+                    break;
+                }
+                if let Some(snapshot) = inode_info_map.snapshot_listing(ino) {
+                    entries -= snapshot.entry_count();
+                    memory_bytes -= snapshot.memory_bytes();
+                    snapshots.push(snapshot);
+                }
+            }
+            (spill, snapshots)
+        };
+
+        let written = tokio::task::spawn_blocking(move || {
+            let mut written = Vec::with_capacity(snapshots.len());
+            for snapshot in snapshots {
+                if let Err(e) = spill.write(snapshot.dir, &snapshot.content) {
+                    eprintln!("Spill Error: {} {:?}", snapshot.dir, e);
+                    break;
+                }
+                written.push(snapshot);
+            }
+            written
+        })
+        .await
+        .unwrap_or_default();
+        let mut inode_info_map = self.inode_info_map.write().await;
+        for snapshot in written {
+            inode_info_map.drop_spilled(&snapshot);
+        }
+    }
+
+    fn record_access(&self, ino: u64) {
+        self.accessed_at.lock().unwrap().insert(ino, Instant::now());
     }
 
     async fn update_dir_cache_if_not_exists(&mut self, ino: u64) -> Result<(), FSError> {
@@ -100,23 +683,96 @@ impl WebDAVFSExplorer {
         drop(inode_info_map);
 
         let mut inode_info_map = self.inode_info_map.write().await;
+        // Note : concurrent callers wait for the write lock, the first one lists for all of them.
+        if inode_info_map.is_cached_dir(ino) {
+            return Ok(());
+        }
         let info = &inode_info_map
             .find_by_ino(ino)
             .ok_or(FSError::INodeNotExists)?;
         match info.file_attr.kind {
             FileType::Directory => {
-                let mut list = self
-                    .client
-                    .list(&info.path)
+                self.path_stats.record_remote_request(&info.path);
+                if let Some(etag) = inode_info_map.listing_etag(ino) {
+                    if let Some(ttl) = self.confirm_listing(&info.encoded_path, etag).await {
+                        inode_info_map.extend_listing(ino, ttl);
+                        return Ok(());
+                    }
+                }
+                let (list, ttl, etag) = self
+                    .list_entries(&info.path, &info.encoded_path)
                     .await
                     .map_err(|e| FSError::WebDAV(e))?;
-
-                // Note : the first item in result of webdav is current path. so, remove it.
-                list.remove(0);
-                inode_info_map.update_cache(ino, list);
+                inode_info_map.update_cache(ino, list, ttl, self.name_source);
+                inode_info_map.set_listing_etag(ino, etag.as_deref());
+                drop(inode_info_map);
+                self.spill_cold_listings(ino).await;
                 Ok(())
             }
             _ => Err(FSError::InvalidOperation(info.path.clone())),
         }
     }
+
+    /// Asks the server whether the directory at `encoded_path` still has the etag it had when it
+    /// was listed, and returns how long its listing stays fresh when it does. A failed request
+    /// counts as changed, the directory is listed again then.
+    async fn confirm_listing(&self, encoded_path: &str, etag: &str) -> Option<Option<Duration>> {
+        let options = StatOptions { collection: true };
+        let (item, cache_control) = self.client.stat(encoded_path, options).await.ok()?;
+        match item {
+            WebDAVList::Folder(d) if d.etag.as_deref() == Some(etag) => {
+                Some(self.cache_policy.ttl(&cache_control))
+            }
+            _ => None,
+        }
+    }
+
+    /// Lists the entries of the directory at `path`, without the directory itself, and returns
+    /// how long they stay fresh along with the etag of the directory. Entries hidden by the sync
+    /// rules or the file size limit are left out.
+    async fn list_entries(
+        &self,
+        path: &str,
+        encoded_path: &str,
+    ) -> Result<(Vec<WebDAVList>, Option<Duration>, Option<String>), WebDAVError> {
+        if let Some(versions) = self.versions.as_ref().filter(|_| is_versions_path(path)) {
+            let mut list = versions.list(path, encoded_path, &self.sync_rules).await?;
+            list.remove(0);
+            list.retain(|x| !self.file_size_limit.hides(x));
+            // Note : new revisions show up once the listing expires, like a directory.
+            return Ok((list, self.cache_policy.default_ttl, None));
+        }
+
+        let (list, cache_control) = self.client.list_with_cache_control(encoded_path).await?;
+        let (collection, mut list) = split_collection(list);
+        let etag = collection.and_then(|x| x.etag().map(|x| x.to_string()));
+        list.retain(|x| !self.sync_rules.is_item_excluded(x) && !self.file_size_limit.hides(x));
+        if let Some(versions) = self.versions.as_ref().filter(|_| path == "/") {
+            // Note : a remote entry of the same name is hidden by the view.
+            list.retain(|x| !is_versions_path(x.path().trim_end_matches('/')));
+            list.push(versions.root_entry());
+        }
+        Ok((list, self.cache_policy.ttl(&cache_control), etag))
+    }
+}
+
+/// Returns `.`, `..` and the children of a directory in the order of their readdir offsets,
+/// `None` when it is not listed.
+pub(super) fn list_items(inode_info_map: &InodeInfoMap, ino: u64) -> Option<Vec<ListItemInfo>> {
+    let mut result = Vec::new();
+
+    inode_info_map
+        .find_by_ino(ino)
+        .map(|x| result.push(ListItemInfo::new_working_dir_from(x)));
+    inode_info_map
+        .parent(ino)
+        .map(|x| result.push(ListItemInfo::new_parent_dir_from(x)));
+
+    inode_info_map
+        .childs(ino)?
+        .iter()
+        .for_each(|x| result.push(ListItemInfo::new_child_from(x)));
+    result.sort_by_key(|x| x.offset);
+
+    Some(result)
 }
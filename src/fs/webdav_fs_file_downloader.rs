@@ -1,26 +1,117 @@
-use std::{collections::HashMap, marker::PhantomData, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{ErrorKind, SeekFrom},
+    mem::size_of,
+    path::Path,
+    sync::Arc,
+    time::{Instant, SystemTime},
+};
 
-use tokio::sync::Mutex;
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::{Mutex, MutexGuard, OwnedRwLockWriteGuard, RwLock},
+};
 
-use super::errors::FSError;
-use crate::{blockfile::BlockFile, webdav::WebDAVClient};
+use super::{
+    cache_health::CacheHealth, cache_policy::CachePolicy, errors::FSError,
+    file_checksum::FileChecksum, file_time, growing_files::GrowingFiles, manifest::ManifestEntry,
+    path_stats::PathStats, slow_ops::OpTimer, versions::is_versions_path,
+};
+use crate::{
+    blockfile::{BlockFile, BlockReader},
+    hash::fnv1a,
+    webdav::{Error as WebDAVError, MemorySink, StatOptions, WebDAVClient, WebDAVList},
+};
 
-const BLOCK_SIZE: u32 = 16 * 1024 * 1024;
+const DEFAULT_BLOCK_SIZE: u32 = 16 * 1024 * 1024;
+/// Bytes compared with the server at the start and the end of a local copy before it is adopted.
+const ADOPT_SAMPLE_SIZE: u64 = 64 * 1024;
+/// Bytes a handle takes besides its struct and strings, roughly its map slot and the shared
+/// locks it points to.
+const HANDLE_OVERHEAD: usize = 160;
+/// Cache files kept open for reading at most; each one takes two file descriptors.
+const MAX_OPEN_READERS: usize = 256;
+/// Written and removed again to tell whether the cache directory works again.
+const CACHE_PROBE_FILE_NAME: &str = ".probe";
 
 #[derive(Clone)]
 pub(super) struct WebDAVFSFileHandle {
     real_path: String,
-    mutex: Arc<Mutex<PhantomData<bool>>>,
+    /// Taken shared by the operations which add data to the cache file, and exclusively by the
+    /// ones which remove or replace it, so those wait for the downloads in flight instead of
+    /// pulling the file away under them.
+    op_lock: Arc<RwLock<()>>,
+    expires_at: Arc<std::sync::Mutex<Option<Instant>>>,
+    /// Modification time of the remote file the cached data belongs to.
+    mtime: SystemTime,
+    etag: Arc<std::sync::Mutex<Option<String>>>,
+    /// Blocks whose checksum was checked since the cache file was opened by this process.
+    verified_blocks: Arc<Mutex<HashSet<u64>>>,
+    /// The reader shared by the reads of the cache file, see `WebDAVFSFileDownloader::reader`.
+    reader: Arc<std::sync::Mutex<Option<BlockReader>>>,
 }
 
 impl WebDAVFSFileHandle {
-    pub fn new(real_path: String) -> Self {
+    pub fn new(real_path: String, mtime: SystemTime) -> Self {
         WebDAVFSFileHandle {
             real_path,
-            mutex: Arc::new(Mutex::new(PhantomData)),
+            op_lock: Arc::new(RwLock::new(())),
+            expires_at: Arc::new(std::sync::Mutex::new(None)),
+            mtime,
+            etag: Arc::new(std::sync::Mutex::new(None)),
+            verified_blocks: Arc::new(Mutex::new(HashSet::new())),
+            reader: Arc::new(std::sync::Mutex::new(None)),
         }
     }
 
+    /// Clears the expiry of the cached data once it passed and returns whether it had, so only
+    /// one caller confirms the data with the server.
+    fn take_expiry(&self) -> bool {
+        let mut expires_at = self.expires_at.lock().unwrap();
+        if !expires_at.map_or(false, |expires_at| expires_at <= Instant::now()) {
+            return false;
+        }
+        *expires_at = None;
+        true
+    }
+
+    /// Closes the shared reader, so the next read opens the cache file again. Called when the
+    /// file is removed or replaced, since the reader would keep the old one.
+    fn close_reader(&self) {
+        *self.reader.lock().unwrap() = None;
+    }
+
+    fn set_etag(&self, etag: Option<&str>) {
+        if etag.is_some() {
+            *self.etag.lock().unwrap() = etag.map(|x| x.to_string());
+        }
+    }
+
+    /// Checks the checksums of the blocks of a range once per process, emptying the blocks
+    /// which do not match. Returns false when a block has to be downloaded again.
+    async fn verify(&self, file: &mut BlockFile, offset: u64, size: u64) -> Result<bool, FSError> {
+        let mut verified_blocks = self.verified_blocks.lock().await;
+        file.verify(offset, size, &mut verified_blocks)
+            .await
+            .map_err(|err| FSError::IO(err))
+    }
+
+    /// Approximate bytes of memory the handle takes in the map of the downloader.
+    async fn memory_size(&self) -> usize {
+        let etag = self
+            .etag
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map_or(0, |x| x.capacity());
+        let verified_blocks = self.verified_blocks.lock().await.capacity();
+        size_of::<WebDAVFSFileHandle>()
+            + HANDLE_OVERHEAD
+            + self.real_path.capacity()
+            + etag
+            + verified_blocks * size_of::<u64>()
+    }
+
     pub async fn get_file(&self) -> Result<BlockFile, FSError> {
         BlockFile::open(&self.real_path, false)
             .await
@@ -34,74 +125,982 @@ impl WebDAVFSFileHandle {
     }
 }
 
+/// The remote version of a file to download.
+pub(super) struct RemoteFile<'a> {
+    /// Decoded path, which the cache is keyed by.
+    pub path: &'a str,
+    /// Path the requests are sent to.
+    pub encoded_path: &'a str,
+    pub size: u64,
+    pub mtime: SystemTime,
+    pub etag: Option<&'a str>,
+}
+
 #[derive(Clone)]
 pub(super) struct WebDAVFSFileDownloader {
     client: WebDAVClient,
     temp_path: String,
+    path_stats: PathStats,
+    cache_policy: CachePolicy,
+    versions_client: Option<WebDAVClient>,
+    block_size: u32,
+    readahead: u32,
+    growing_files: GrowingFiles,
+    cache_health: CacheHealth,
 
     path_to_cache_map: Arc<Mutex<HashMap<String, WebDAVFSFileHandle>>>,
+    /// Handles whose shared reader was opened, oldest first.
+    open_readers: Arc<std::sync::Mutex<VecDeque<WebDAVFSFileHandle>>>,
 }
 
 impl WebDAVFSFileDownloader {
-    pub fn new(client: WebDAVClient, temp_path: String) -> Self {
+    pub fn new(client: WebDAVClient, temp_path: String, path_stats: PathStats) -> Self {
         WebDAVFSFileDownloader {
             client,
             temp_path,
+            path_stats,
+            cache_policy: CachePolicy::default(),
+            versions_client: None,
+            block_size: DEFAULT_BLOCK_SIZE,
+            readahead: 0,
+            growing_files: GrowingFiles::default(),
+            cache_health: CacheHealth::default(),
             path_to_cache_map: Arc::new(Mutex::new(HashMap::new())),
+            open_readers: Arc::new(std::sync::Mutex::new(VecDeque::new())),
+        }
+    }
+
+    pub fn set_cache_policy(&mut self, cache_policy: CachePolicy) {
+        self.cache_policy = cache_policy;
+    }
+
+    /// Sets the size of the blocks of cache files created from now on. Existing cache files keep
+    /// their block size.
+    pub fn set_block_size(&mut self, block_size: u32) {
+        self.block_size = block_size;
+    }
+
+    /// Sets how many bytes past the end of a read are downloaded along with it when the read
+    /// misses the cache.
+    pub fn set_readahead(&mut self, readahead: u32) {
+        self.readahead = readahead;
+    }
+
+    /// Sets the files whose cache is extended instead of recreated when they grew.
+    pub fn set_growing_files(&mut self, growing_files: GrowingFiles) {
+        self.growing_files = growing_files;
+    }
+
+    /// Sets the client revisions below `/.versions` are downloaded with.
+    pub fn set_versions_client(&mut self, versions_client: WebDAVClient) {
+        self.versions_client = Some(versions_client);
+    }
+
+    pub fn client(&self) -> &WebDAVClient {
+        &self.client
+    }
+
+    pub fn temp_path(&self) -> &str {
+        &self.temp_path
+    }
+
+    pub fn cache_health(&self) -> &CacheHealth {
+        &self.cache_health
+    }
+
+    /// Returns whether reads go through the cache. While they do not, the cache directory is
+    /// tried again from time to time by writing a file into it.
+    pub async fn uses_cache(&self) -> bool {
+        if !self.cache_health.is_bypassed() {
+            return true;
+        }
+        if !self.cache_health.probe_due() {
+            return false;
         }
+        let probe_path = Path::new(&self.temp_path).join(CACHE_PROBE_FILE_NAME);
+        let probe = async {
+            tokio::fs::write(&probe_path, b"probe").await?;
+            tokio::fs::remove_file(&probe_path).await
+        };
+        if probe.await.is_err() {
+            return false;
+        }
+        self.cache_health.recovered(&self.temp_path);
+        true
     }
 
+    /// Downloads `offset..offset + size` of `remote_file` without caching it, for reads while
+    /// the cache directory does not work. The bytes end at the end of the file.
+    pub async fn stream(
+        &self,
+        remote_file: &RemoteFile<'_>,
+        offset: u64,
+        size: u32,
+    ) -> Result<Vec<u8>, FSError> {
+        let end = offset.saturating_add(size as u64).min(remote_file.size);
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+        self.path_stats.record_remote_request(remote_file.path);
+        let mut sink = MemorySink::new(offset);
+        self.client_for(remote_file.path)
+            .download(remote_file.encoded_path, &mut sink, offset, end - offset)
+            .await
+            .map_err(|x| FSError::WebDAV(x))?;
+        Ok(sink.into_data())
+    }
+
+    /// Makes sure the blocks covering `offset..offset + size` of `uri_path` are cached. The
+    /// cached data is thrown away when the remote file got another size or mtime since it was
+    /// downloaded, so reads never mix old and new bytes, unless it is a growing file which only
+    /// got longer.
     pub async fn download(
         &self,
-        uri_path: &str,
-        file_size: u64,
+        remote_file: &RemoteFile<'_>,
         offset: u64,
         size: u32,
     ) -> Result<WebDAVFSFileHandle, FSError> {
-        let mut path_to_cache_map = self.path_to_cache_map.lock().await;
+        self.download_timed(remote_file, offset, size, &mut OpTimer::disabled())
+            .await
+    }
 
-        let handle = path_to_cache_map.get(uri_path);
-        let (handle, mut file) = match handle {
-            Some(handle) => {
-                let mut file = handle.get_file_for_write().await?;
-                if file
-                    .is_data_ready(offset, size as u64)
-                    .await
-                    .map_err(|err| FSError::IO(err))?
-                {
-                    return Ok(handle.clone());
+    /// Like `download`, recording the time spent checking the cache and fetching from the
+    /// server as phases of `timer`.
+    pub async fn download_timed(
+        &self,
+        remote_file: &RemoteFile<'_>,
+        offset: u64,
+        size: u32,
+        timer: &mut OpTimer,
+    ) -> Result<WebDAVFSFileHandle, FSError> {
+        let uri_path = remote_file.path;
+        let file_size = remote_file.size;
+        let mtime = remote_file.mtime;
+        let etag = remote_file.etag;
+
+        // Note : the per-file lock is never awaited under the map lock, so a long download does
+        // not hold up the reads of every other file. Whatever the map held before a lock was
+        // awaited is checked again once it is taken, and the lookup starts over when the cache
+        // was replaced or removed meanwhile.
+        let (handle, file, download) = loop {
+            let handle = {
+                let mut path_to_cache_map = self.path_to_cache_map.lock().await;
+                match path_to_cache_map.get(uri_path).cloned() {
+                    Some(handle) => Some(handle),
+                    None => {
+                        self.find_previous_cache(&mut path_to_cache_map, remote_file)
+                            .await
+                    }
+                }
+            };
+            let handle = match handle {
+                Some(handle) if handle.mtime != mtime && self.growing_files.matches(uri_path) => {
+                    Some(self.grow_cache(handle, remote_file).await)
+                }
+                handle => handle,
+            };
+            if let Some(handle) = &handle {
+                if handle.mtime == mtime && self.cache_policy.stale_while_revalidate {
+                    self.revalidate(handle, remote_file);
                 }
-                (handle.clone(), file)
             }
-            None => {
-                let temp_path = self.gen_temp_path();
-                let file = BlockFile::create(&temp_path, file_size, BLOCK_SIZE)
-                    .await
-                    .map_err(|err| FSError::IO(err))?;
+            // Note : expired data is only fetched again when the server reports a change.
+            let changed = match &handle {
+                Some(handle) if handle.mtime == mtime && handle.take_expiry() => {
+                    let confirm =
+                        self.confirm(handle, uri_path, remote_file.encoded_path, file_size);
+                    match confirm.await {
+                        Ok(changed) => changed,
+                        Err(err) => {
+                            // Note : the cached data is served, and confirmed with the next read.
+                            eprintln!("Revalidation Error: {} {:?}", uri_path, err);
+                            *handle.expires_at.lock().unwrap() = Some(Instant::now());
+                            false
+                        }
+                    }
+                }
+                _ => false,
+            };
+            let cached = match handle {
+                Some(handle) if changed => {
+                    eprintln!("Remote file {} was modified, recreating cache", uri_path);
+                    self.recreate_cache(handle, remote_file).await?
+                }
+                Some(handle) if handle.mtime != mtime => {
+                    eprintln!("Remote file {} was modified, recreating cache", uri_path);
+                    self.recreate_cache(handle, remote_file).await?
+                }
+                Some(handle) => match handle.get_file_for_write().await {
+                    Ok(file) if file.file_size() != file_size => {
+                        eprintln!(
+                            "Remote size of {} changed from {} to {}, recreating cache",
+                            uri_path,
+                            file.file_size(),
+                            file_size
+                        );
+                        drop(file);
+                        self.recreate_cache(handle, remote_file).await?
+                    }
+                    Ok(mut file) => {
+                        if file
+                            .is_data_ready(offset, size as u64)
+                            .await
+                            .map_err(|err| FSError::IO(err))?
+                        {
+                            if handle.verify(&mut file, offset, size as u64).await? {
+                                handle.set_etag(etag);
+                                timer.phase("cache check");
+                                return Ok(handle);
+                            }
+                            eprintln!(
+                                "Corrupt block in cache of {}, downloading it again",
+                                uri_path
+                            );
+                        }
+                        Some((handle, file))
+                    }
+                    Err(FSError::IO(err)) if err.kind() == ErrorKind::InvalidData => {
+                        eprintln!("Corrupt cache for {}, recreating: {}", uri_path, err);
+                        self.recreate_cache(handle, remote_file).await?
+                    }
+                    // Note : the cache was removed since the map was looked up, or the file was
+                    // deleted behind our back, which is repaired the same way.
+                    Err(FSError::IO(err)) if err.kind() == ErrorKind::NotFound => {
+                        self.recreate_cache(handle, remote_file).await?
+                    }
+                    Err(err) => return Err(err),
+                },
+                None => {
+                    let mut path_to_cache_map = self.path_to_cache_map.lock().await;
+                    // Note : another read may have created the cache since the map was looked up.
+                    if path_to_cache_map.contains_key(uri_path) {
+                        None
+                    } else {
+                        Some(
+                            self.create_cache(&mut path_to_cache_map, remote_file)
+                                .await?,
+                        )
+                    }
+                }
+            };
+            let Some((handle, file)) = cached else {
+                continue;
+            };
 
-                let file_handle = WebDAVFSFileHandle::new(temp_path.clone());
-                path_to_cache_map.insert(uri_path.to_string(), file_handle.clone());
-                (file_handle, file)
+            // Note : the download is registered before the map is checked, so an eviction
+            // either removed the cache before or waits for the download.
+            let download = handle.op_lock.clone().read_owned().await;
+            if !self.is_current(uri_path, &handle).await {
+                continue;
             }
+            break (handle, file, download);
         };
-        
-        let _ = handle.mutex.lock().await;
-        drop(path_to_cache_map);
+        // Note : a compaction may have replaced the file while the lock was awaited.
+        drop(file);
+        let mut file = handle.get_file_for_write().await?;
+        timer.phase("cache check");
 
-        let (begin, end) = file.calc_block_range_from(offset, size as u64);
-        self.client
-            .download(uri_path, &mut file, begin, end - begin)
+        let fetch_size = (size as u64)
+            .saturating_add(self.readahead as u64)
+            .min(file_size.saturating_sub(offset))
+            .max(size as u64);
+        let (begin, end) = file.calc_block_range_from(offset, fetch_size);
+        self.path_stats.record_remote_request(uri_path);
+        let (cache_control, rest) = self
+            .client_for(uri_path)
+            .download_with_rest(remote_file.encoded_path, &mut file, begin, end - begin)
             .await
             .map_err(|x| FSError::WebDAV(x))?;
+        timer.phase("network fetch");
+        // Note : the read is answered now, the rest of a file sent by a server which ignores
+        // Range is written meanwhile. The download stays registered until it is written.
+        if let Some(mut rest) = rest {
+            let path = uri_path.to_string();
+            tokio::spawn(async move {
+                let _download = download;
+                if let Err(err) = rest.write_to(&mut file).await {
+                    eprintln!("Download Error past the read of {}: {}", path, err);
+                }
+            });
+        }
+        *handle.expires_at.lock().unwrap() = self
+            .cache_policy
+            .ttl(&cache_control)
+            .map(|ttl| Instant::now() + ttl);
+        handle.set_etag(etag);
         Ok(handle)
     }
 
-    fn gen_temp_path(&self) -> String {
-        let uuid = uuid::Uuid::new_v4();
+    /// Returns the reader the reads of the cache file of `handle` share, opening it if no read
+    /// did yet. At most `MAX_OPEN_READERS` readers are kept open, the oldest ones are closed
+    /// beyond that.
+    pub async fn reader(&self, handle: &WebDAVFSFileHandle) -> Result<BlockReader, FSError> {
+        if let Some(reader) = handle.reader.lock().unwrap().clone() {
+            return Ok(reader);
+        }
+        let reader = BlockReader::open(&handle.real_path)
+            .await
+            .map_err(|err| FSError::IO(err))?;
+        {
+            let mut slot = handle.reader.lock().unwrap();
+            // Note : a concurrent read may have opened one meanwhile.
+            if let Some(current) = slot.as_ref() {
+                return Ok(current.clone());
+            }
+            *slot = Some(reader.clone());
+        }
+        let mut open_readers = self.open_readers.lock().unwrap();
+        open_readers.push_back(handle.clone());
+        while open_readers.len() > MAX_OPEN_READERS {
+            if let Some(oldest) = open_readers.pop_front() {
+                oldest.close_reader();
+            }
+        }
+        Ok(reader)
+    }
+
+    /// Confirms the cached data of `remote_file` with the server in the background. The data
+    /// stays fresh while the request is in flight, so it is served meanwhile, and is dropped
+    /// when the file changed on the server.
+    fn revalidate(&self, handle: &WebDAVFSFileHandle, remote_file: &RemoteFile<'_>) {
+        // Note : the expiry is cleared under its lock, so only one revalidation is started.
+        if !handle.take_expiry() {
+            return;
+        }
+        let downloader = self.clone();
+        let handle = handle.clone();
+        let path = remote_file.path.to_string();
+        let encoded_path = remote_file.encoded_path.to_string();
+        let size = remote_file.size;
+        tokio::spawn(async move {
+            match downloader
+                .confirm(&handle, &path, &encoded_path, size)
+                .await
+            {
+                Ok(false) => return,
+                Ok(true) => {}
+                Err(err) => {
+                    // Note : the data expires again, so the next read tries once more.
+                    eprintln!("Revalidation Error: {} {:?}", path, err);
+                    *handle.expires_at.lock().unwrap() = Some(Instant::now());
+                    return;
+                }
+            }
+
+            eprintln!("Remote file {} was modified, dropping its cache", path);
+            let _lock = handle.op_lock.write().await;
+            let mut path_to_cache_map = downloader.path_to_cache_map.lock().await;
+            // Note : a cache which replaced this one meanwhile is left alone.
+            if is_same_cache(path_to_cache_map.get(&path), &handle) {
+                path_to_cache_map.remove(&path);
+                let _ = BlockFile::remove(&handle.real_path).await;
+                handle.close_reader();
+            }
+        });
+    }
+
+    /// Asks the server whether the cached data of `path` is still current, by its etag, or by its
+    /// mtime and size when either side has none, and returns whether it changed. Unchanged data
+    /// stays fresh for as long as the response allows.
+    async fn confirm(
+        &self,
+        handle: &WebDAVFSFileHandle,
+        path: &str,
+        encoded_path: &str,
+        size: u64,
+    ) -> Result<bool, WebDAVError> {
+        let (item, cache_control) = self
+            .client_for(path)
+            .stat(encoded_path, StatOptions::default())
+            .await?;
+        let unchanged = match item {
+            WebDAVList::File(file) => {
+                let etag = handle.etag.lock().unwrap().clone();
+                match (etag, file.etag) {
+                    (Some(etag), Some(remote_etag)) => etag == remote_etag,
+                    _ => {
+                        let mtime = file_time::from_remote(&file.last_modified);
+                        mtime == handle.mtime && file.content_length == size
+                    }
+                }
+            }
+            _ => false,
+        };
+        if unchanged {
+            *handle.expires_at.lock().unwrap() = self
+                .cache_policy
+                .ttl(&cache_control)
+                .map(|ttl| Instant::now() + ttl);
+        }
+        Ok(!unchanged)
+    }
+
+    /// Takes a complete local copy of `remote_file`, e.g. one made with rsync, as its cache, so
+    /// it is not downloaded again. The copy must have the size of the remote file, and match its
+    /// checksum, see `verify_copy`.
+    pub async fn adopt(
+        &self,
+        remote_file: &RemoteFile<'_>,
+        local_path: &str,
+    ) -> Result<(), FSError> {
+        let local_size = tokio::fs::metadata(local_path)
+            .await
+            .map_err(|err| FSError::IO(err))?
+            .len();
+        if local_size != remote_file.size {
+            return Err(FSError::InvalidOperation(format!(
+                "{} has {} bytes, {} has {}",
+                local_path, local_size, remote_file.path, remote_file.size
+            )));
+        }
+        let cache_path = self.gen_temp_path(remote_file.path);
+        self.verify_copy(remote_file, local_path).await?;
+
+        let import_path = format!("{}.import", cache_path);
+        let imported = async {
+            BlockFile::import(&import_path, local_path, self.block_size)
+                .await?
+                .set_origin(&encode_origin(remote_file))
+                .await
+        };
+        if let Err(err) = imported.await {
+            let _ = BlockFile::remove(&import_path).await;
+            return Err(FSError::IO(err));
+        }
+
+        let (mut path_to_cache_map, current) = self.lock_cache(remote_file.path).await;
+        if let Some((handle, _lock)) = current {
+            path_to_cache_map.remove(remote_file.path);
+            let _ = BlockFile::remove(&handle.real_path).await;
+            handle.close_reader();
+        }
+        BlockFile::rename(&import_path, &cache_path)
+            .await
+            .map_err(|err| FSError::IO(err))?;
+        let handle = WebDAVFSFileHandle::new(cache_path, remote_file.mtime);
+        handle.set_etag(remote_file.etag);
+        path_to_cache_map.insert(remote_file.path.to_string(), handle);
+        Ok(())
+    }
+
+    /// Checks that a local copy has the bytes of `remote_file`. When the server has a SHA-1 or
+    /// Adler-32 checksum of the file, the whole copy is hashed and compared with it.
+    ///
+    /// Note : otherwise only the start and the end of the copy are compared with the server, so a
+    /// copy of the same size which differs only in between, e.g. one rewritten in place by a
+    /// database, is taken all the same and its wrong bytes are read until the file changes.
+    async fn verify_copy(
+        &self,
+        remote_file: &RemoteFile<'_>,
+        local_path: &str,
+    ) -> Result<(), FSError> {
+        let checksums = self
+            .client_for(remote_file.path)
+            .checksums(remote_file.encoded_path)
+            .await
+            .map_err(|x| FSError::WebDAV(x))?;
+        let Some(checksum) = checksums.as_deref().and_then(FileChecksum::parse) else {
+            return self.compare_samples(remote_file, local_path).await;
+        };
+        let path = local_path.to_string();
+        let matches = tokio::task::spawn_blocking(move || checksum.matches_file(&path))
+            .await
+            .map_err(|err| FSError::IO(err.into()))?
+            .map_err(|err| FSError::IO(err))?;
+        if !matches {
+            return Err(FSError::InvalidOperation(format!(
+                "{} does not match the checksum of {}",
+                local_path, remote_file.path
+            )));
+        }
+        Ok(())
+    }
+
+    /// Downloads the start and the end of `remote_file` and compares them with the local copy.
+    async fn compare_samples(
+        &self,
+        remote_file: &RemoteFile<'_>,
+        local_path: &str,
+    ) -> Result<(), FSError> {
+        let sample_size = ADOPT_SAMPLE_SIZE.min(remote_file.size);
+        if sample_size == 0 {
+            return Ok(());
+        }
+        let mut local = tokio::fs::File::open(local_path)
+            .await
+            .map_err(|err| FSError::IO(err))?;
+
+        let mut local_buf = vec![0; sample_size as usize];
+        for offset in [0, remote_file.size - sample_size] {
+            let mut remote = MemorySink::new(offset);
+            self.client_for(remote_file.path)
+                .download(remote_file.encoded_path, &mut remote, offset, sample_size)
+                .await
+                .map_err(|x| FSError::WebDAV(x))?;
+            read_sample(&mut local, &mut local_buf, offset)
+                .await
+                .map_err(|err| FSError::IO(err))?;
+            if remote.data() != local_buf {
+                return Err(FSError::InvalidOperation(format!(
+                    "{} differs from {} at offset {}",
+                    local_path, remote_file.path, offset
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Downloads every block of the file which is not cached yet.
+    pub async fn hydrate(&self, remote_file: &RemoteFile<'_>) -> Result<(), FSError> {
+        for offset in (0..remote_file.size).step_by(self.block_size as usize) {
+            self.download(remote_file, offset, self.block_size).await?;
+        }
+        Ok(())
+    }
+
+    /// Lists the files cached by this process with the remote version their data belongs to.
+    ///
+    /// Note : cache files left by a previous process are only known once they are read again.
+    pub async fn manifest(&self) -> Vec<ManifestEntry> {
+        let handles: Vec<(String, WebDAVFSFileHandle)> = {
+            let path_to_cache_map = self.path_to_cache_map.lock().await;
+            path_to_cache_map
+                .iter()
+                .map(|(path, handle)| (path.clone(), handle.clone()))
+                .collect()
+        };
+
+        let mut entries = Vec::new();
+        for (path, handle) in handles {
+            let Ok(file) = handle.get_file().await else {
+                continue;
+            };
+            entries.push(ManifestEntry {
+                path,
+                size: file.file_size(),
+                mtime: handle.mtime,
+                etag: handle.etag.lock().unwrap().clone(),
+            });
+        }
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        entries
+    }
+
+    /// Returns how many bytes of `remote_file` are cached locally. Data cached for another
+    /// version of the file does not count, since a read would download it again.
+    pub async fn cached_bytes(&self, remote_file: &RemoteFile<'_>) -> u64 {
+        let handle = self
+            .path_to_cache_map
+            .lock()
+            .await
+            .get(remote_file.path)
+            .cloned();
+        let temp_path = match &handle {
+            Some(handle) if handle.mtime != remote_file.mtime => return 0,
+            Some(handle) => handle.real_path.clone(),
+            // Note : a cache file left by a previous process is picked up by the next read.
+            None => self.gen_temp_path(remote_file.path),
+        };
+        match BlockFile::open(&temp_path, false).await {
+            Ok(file)
+                if file.file_size() == remote_file.size && is_origin_of(&file, remote_file) =>
+            {
+                file.cached_bytes()
+            }
+            _ => 0,
+        }
+    }
+
+    /// Flushes every cache file to disk.
+    pub async fn flush(&self) -> Result<(), FSError> {
+        let handles = self.cache_handles().await;
+        for handle in handles {
+            let _lock = handle.op_lock.read().await;
+            let mut file = handle.get_file_for_write().await?;
+            file.sync().await.map_err(|err| FSError::IO(err))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of cache files and the bytes they occupy on disk.
+    pub async fn cache_usage(&self) -> (usize, u64) {
+        let handles = self.cache_handles().await;
+        let mut bytes = 0;
+        for handle in handles.iter() {
+            if let Ok(disk_usage) = BlockFile::disk_usage(&handle.real_path).await {
+                bytes += disk_usage;
+            }
+        }
+        (handles.len(), bytes)
+    }
+
+    /// Returns the approximate bytes of memory taken by the handles of the cache files, and the
+    /// response bytes held by downloads in flight.
+    pub async fn memory_usage(&self) -> (usize, usize) {
+        let handles: Vec<(usize, WebDAVFSFileHandle)> = {
+            let path_to_cache_map = self.path_to_cache_map.lock().await;
+            path_to_cache_map
+                .iter()
+                .map(|(path, handle)| (path.capacity(), handle.clone()))
+                .collect()
+        };
+        let mut handle_bytes = 0;
+        for (path_bytes, handle) in handles {
+            handle_bytes += path_bytes + handle.memory_size().await;
+        }
+        (handle_bytes, self.client.inflight_bytes())
+    }
+
+    /// Rewrites the cache files which have blocks not written completely.
+    /// Returns the number of files which were rewritten and the bytes of disk space reclaimed.
+    pub async fn compact(&self) -> Result<(usize, u64), FSError> {
+        let handles = self.cache_handles().await;
+        let mut compacted = 0;
+        let mut reclaimed_bytes = 0;
+        for handle in handles {
+            let _lock = handle.op_lock.write().await;
+            if let Some(bytes) = BlockFile::compact(&handle.real_path)
+                .await
+                .map_err(|err| FSError::IO(err))?
+            {
+                handle.close_reader();
+                compacted += 1;
+                reclaimed_bytes += bytes;
+            }
+        }
+        Ok((compacted, reclaimed_bytes))
+    }
+
+    /// Removes the cache files of `path` and, for directories, of everything below it.
+    pub async fn evict(&self, path: &str) {
+        let dir_prefix = format!("{}/", path.trim_end_matches('/'));
+        let evicted_paths: Vec<String> = {
+            let path_to_cache_map = self.path_to_cache_map.lock().await;
+            path_to_cache_map
+                .keys()
+                .filter(|x| *x == path || x.starts_with(&dir_prefix))
+                .cloned()
+                .collect()
+        };
+
+        for evicted_path in evicted_paths {
+            let (mut path_to_cache_map, current) = self.lock_cache(&evicted_path).await;
+            if let Some((handle, _lock)) = current {
+                path_to_cache_map.remove(&evicted_path);
+                let _ = BlockFile::remove(&handle.real_path).await;
+                handle.close_reader();
+            }
+        }
+    }
+
+    async fn cache_handles(&self) -> Vec<WebDAVFSFileHandle> {
+        let path_to_cache_map = self.path_to_cache_map.lock().await;
+        path_to_cache_map.values().cloned().collect()
+    }
+
+    /// Takes the lock of the cache of `uri_path` exclusively, waiting for the operations on it
+    /// without holding the map lock. Returns the map lock along with the handle and its lock, so
+    /// the cache can be removed or replaced before anyone else looks it up.
+    async fn lock_cache(
+        &self,
+        uri_path: &str,
+    ) -> (
+        MutexGuard<'_, HashMap<String, WebDAVFSFileHandle>>,
+        Option<(WebDAVFSFileHandle, OwnedRwLockWriteGuard<()>)>,
+    ) {
+        loop {
+            let path_to_cache_map = self.path_to_cache_map.lock().await;
+            let Some(handle) = path_to_cache_map.get(uri_path).cloned() else {
+                return (path_to_cache_map, None);
+            };
+            drop(path_to_cache_map);
+            let lock = handle.op_lock.clone().write_owned().await;
+            let path_to_cache_map = self.path_to_cache_map.lock().await;
+            // Note : the cache may have been replaced or removed while the lock was awaited.
+            if is_same_cache(path_to_cache_map.get(uri_path), &handle) {
+                return (path_to_cache_map, Some((handle, lock)));
+            }
+        }
+    }
+
+    /// Returns whether `handle` is still the cache of `uri_path` in the map.
+    async fn is_current(&self, uri_path: &str, handle: &WebDAVFSFileHandle) -> bool {
+        let path_to_cache_map = self.path_to_cache_map.lock().await;
+        is_same_cache(path_to_cache_map.get(uri_path), handle)
+    }
+
+    /// Picks up the cache file a previous process left for `remote_file`, so its completed
+    /// blocks are not downloaded again. Files which fail validation, or hold another file or
+    /// version of it according to their origin, are removed.
+    async fn find_previous_cache(
+        &self,
+        path_to_cache_map: &mut HashMap<String, WebDAVFSFileHandle>,
+        remote_file: &RemoteFile<'_>,
+    ) -> Option<WebDAVFSFileHandle> {
+        let uri_path = remote_file.path;
+        let temp_path = self.gen_temp_path(uri_path);
+        match BlockFile::open(&temp_path, false).await {
+            Ok(file)
+                if file.file_size() == remote_file.size && is_origin_of(&file, remote_file) =>
+            {
+                let file_handle = WebDAVFSFileHandle::new(temp_path, remote_file.mtime);
+                path_to_cache_map.insert(uri_path.to_string(), file_handle.clone());
+                Some(file_handle)
+            }
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                // Note : removes a half of a cache, or a cache file of an older version.
+                let _ = BlockFile::remove(&temp_path).await;
+                None
+            }
+            result => {
+                if let Err(err) = result {
+                    eprintln!("Discarding cache of {}: {}", uri_path, err);
+                }
+                let _ = BlockFile::remove(&temp_path).await;
+                None
+            }
+        }
+    }
+
+    /// Extends the cache of a growing file which got longer on the server, keeping the data
+    /// cached so far, so following an appended log only downloads what was appended. Returns
+    /// the handle as is when the file did not grow or was not only appended to, so its cache is
+    /// recreated.
+    async fn grow_cache(
+        &self,
+        handle: WebDAVFSFileHandle,
+        remote_file: &RemoteFile<'_>,
+    ) -> WebDAVFSFileHandle {
+        let uri_path = remote_file.path;
+        let op_lock = handle.op_lock.clone();
+        let _lock = op_lock.write().await;
+        // Note : a cache which replaced this one meanwhile is looked up again by the caller.
+        if !self.is_current(uri_path, &handle).await {
+            return handle;
+        }
+        let mut file = match handle.get_file_for_write().await {
+            Ok(file) if file.file_size() < remote_file.size => file,
+            _ => return handle,
+        };
+        match self.tail_matches(&mut file, remote_file).await {
+            Ok(true) => {}
+            Ok(false) => {
+                eprintln!("Remote file {} was rewritten, recreating cache", uri_path);
+                return handle;
+            }
+            Err(err) => {
+                eprintln!("Can not check cache of {}, recreating: {:?}", uri_path, err);
+                return handle;
+            }
+        }
+        let grown = async {
+            file.grow(remote_file.size).await?;
+            file.set_origin(&encode_origin(remote_file)).await
+        };
+        if let Err(err) = grown.await {
+            eprintln!("Can not extend cache of {}, recreating: {}", uri_path, err);
+            return handle;
+        }
+        handle.close_reader();
+        let grown = WebDAVFSFileHandle {
+            mtime: remote_file.mtime,
+            ..handle
+        };
+        let mut path_to_cache_map = self.path_to_cache_map.lock().await;
+        path_to_cache_map.insert(uri_path.to_string(), grown.clone());
+        grown
+    }
+
+    /// Downloads the bytes of the last block cached of `remote_file` again and compares them
+    /// with the cache, since a file which got longer may also have been rewritten. Returns false
+    /// when they differ.
+    async fn tail_matches(
+        &self,
+        file: &mut BlockFile,
+        remote_file: &RemoteFile<'_>,
+    ) -> Result<bool, FSError> {
+        let Some((offset, len)) = file.last_written_block() else {
+            return Ok(true);
+        };
+        let mut cached = vec![0; len as usize];
+        file.read(&mut cached, offset)
+            .await
+            .map_err(|err| FSError::IO(err))?;
+        self.path_stats.record_remote_request(remote_file.path);
+        let mut remote = MemorySink::new(offset);
+        self.client_for(remote_file.path)
+            .download(remote_file.encoded_path, &mut remote, offset, len)
+            .await
+            .map_err(|x| FSError::WebDAV(x))?;
+        Ok(remote.data() == cached)
+    }
+
+    /// Replaces the cache of `handle` with an empty one. Returns `None` when the cache was
+    /// replaced or removed by someone else while its lock was awaited, so the caller looks it up
+    /// again.
+    async fn recreate_cache(
+        &self,
+        handle: WebDAVFSFileHandle,
+        remote_file: &RemoteFile<'_>,
+    ) -> Result<Option<(WebDAVFSFileHandle, BlockFile)>, FSError> {
+        let _lock = handle.op_lock.write().await;
+        let mut path_to_cache_map = self.path_to_cache_map.lock().await;
+        if !is_same_cache(path_to_cache_map.get(remote_file.path), &handle) {
+            return Ok(None);
+        }
+        path_to_cache_map.remove(remote_file.path);
+        let _ = BlockFile::remove(&handle.real_path).await;
+        handle.close_reader();
+        self.create_cache(&mut path_to_cache_map, remote_file)
+            .await
+            .map(Some)
+    }
+
+    async fn create_cache(
+        &self,
+        path_to_cache_map: &mut HashMap<String, WebDAVFSFileHandle>,
+        remote_file: &RemoteFile<'_>,
+    ) -> Result<(WebDAVFSFileHandle, BlockFile), FSError> {
+        let temp_path = self.gen_temp_path(remote_file.path);
+        let mut file = BlockFile::create(&temp_path, remote_file.size, self.block_size)
+            .await
+            .map_err(|err| FSError::IO(err))?;
+        file.set_origin(&encode_origin(remote_file))
+            .await
+            .map_err(|err| FSError::IO(err))?;
+
+        let file_handle = WebDAVFSFileHandle::new(temp_path, remote_file.mtime);
+        path_to_cache_map.insert(remote_file.path.to_string(), file_handle.clone());
+        Ok((file_handle, file))
+    }
+
+    fn client_for(&self, uri_path: &str) -> &WebDAVClient {
+        match &self.versions_client {
+            Some(versions_client) if is_versions_path(uri_path) => versions_client,
+            _ => &self.client,
+        }
+    }
+
+    /// The cache file name is derived from the remote path, so a restarted process finds it.
+    fn gen_temp_path(&self, uri_path: &str) -> String {
         std::path::Path::new(&self.temp_path)
-            .join(uuid.to_string())
+            .join(cache_file_name(uri_path))
             .to_str()
             .unwrap()
             .to_string()
     }
 }
+
+/// The name of the cache files of the remote file at `uri_path` in a cache namespace.
+pub(super) fn cache_file_name(uri_path: &str) -> String {
+    format!("{:016x}", fnv1a(uri_path.as_bytes()))
+}
+
+/// The remote version whose data goes into a cache file, stored in its header, so a cache file
+/// is never taken for the one of another path with the same name hash, or of another version of
+/// the same size. The path comes last, since it is the only field which may hold a newline.
+fn encode_origin(remote_file: &RemoteFile<'_>) -> Vec<u8> {
+    format!(
+        "{}\n{}\n{}",
+        file_time::to_nanos(remote_file.mtime),
+        remote_file.etag.unwrap_or(""),
+        remote_file.path
+    )
+    .into_bytes()
+}
+
+/// Returns whether `current`, the handle in the map, is the cache `handle` was taken from.
+fn is_same_cache(current: Option<&WebDAVFSFileHandle>, handle: &WebDAVFSFileHandle) -> bool {
+    current.map_or(false, |current| {
+        Arc::ptr_eq(&current.op_lock, &handle.op_lock)
+    })
+}
+
+/// Returns the remote path the data of a cache file belongs to, `None` when it has no origin.
+pub(super) fn origin_path(file: &BlockFile) -> Option<&str> {
+    let origin = std::str::from_utf8(file.origin()).ok()?;
+    origin.splitn(3, '\n').nth(2)
+}
+
+/// Returns whether `file` holds data of `remote_file`. Etags are compared when both sides have
+/// one, the mtimes otherwise.
+fn is_origin_of(file: &BlockFile, remote_file: &RemoteFile<'_>) -> bool {
+    let Ok(origin) = std::str::from_utf8(file.origin()) else {
+        return false;
+    };
+    let mut fields = origin.splitn(3, '\n');
+    let (Some(mtime), Some(etag), Some(path)) = (fields.next(), fields.next(), fields.next())
+    else {
+        return false;
+    };
+    if path != remote_file.path {
+        return false;
+    }
+    match (etag, remote_file.etag) {
+        ("", _) | (_, None) => mtime.parse() == Ok(file_time::to_nanos(remote_file.mtime)),
+        (etag, Some(remote_etag)) => etag == remote_etag,
+    }
+}
+
+/// Reads the bytes at `offset` of the local copy.
+async fn read_sample(
+    local: &mut tokio::fs::File,
+    local_buf: &mut [u8],
+    offset: u64,
+) -> std::io::Result<()> {
+    local.seek(SeekFrom::Start(offset)).await?;
+    local.read_exact(local_buf).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod webdav_fs_file_downloader_test {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{encode_origin, is_origin_of, origin_path, RemoteFile};
+    use crate::blockfile::BlockFile;
+
+    #[tokio::test]
+    async fn origin_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        let remote_file = RemoteFile {
+            path: "/logs/app\n.log",
+            encoded_path: "/logs/app%0A.log",
+            size: 40,
+            mtime: UNIX_EPOCH + Duration::from_secs(1700000000),
+            etag: Some("\"v1\""),
+        };
+        let mut file = BlockFile::create(path.to_str().unwrap(), 40, 16)
+            .await
+            .unwrap();
+        assert!(!is_origin_of(&file, &remote_file));
+        file.set_origin(&encode_origin(&remote_file)).await.unwrap();
+        assert_eq!(origin_path(&file), Some("/logs/app\n.log"));
+        assert!(is_origin_of(&file, &remote_file));
+
+        // Note : a path with the same name hash, and a new version of the same size.
+        let other_path = RemoteFile {
+            path: "/logs/other.log",
+            ..remote_file
+        };
+        assert!(!is_origin_of(&file, &other_path));
+        let new_version = RemoteFile {
+            etag: Some("\"v2\""),
+            ..remote_file
+        };
+        assert!(!is_origin_of(&file, &new_version));
+
+        // Note : without an etag on the server, the mtime tells the versions apart.
+        let without_etag = RemoteFile {
+            etag: None,
+            ..remote_file
+        };
+        assert!(is_origin_of(&file, &without_etag));
+        let touched = RemoteFile {
+            mtime: remote_file.mtime + Duration::from_secs(1),
+            ..without_etag
+        };
+        assert!(!is_origin_of(&file, &touched));
+    }
+}
@@ -0,0 +1,7 @@
+/// 64-bit FNV-1a, a fast non-cryptographic hash which is stable across builds, for names
+/// derived from paths and identities, e.g. of cache files and sockets.
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,12 @@
+pub mod askpass;
+pub mod bench;
+pub mod blockfile;
+pub mod ctl;
+pub mod fs;
+mod hash;
+pub mod logging;
+pub mod preflight;
+pub mod run_as;
+pub mod runtime;
+pub mod telemetry;
+pub mod webdav;
@@ -0,0 +1,365 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    os::{fd::FromRawFd, unix::net::UnixDatagram},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::mpsc::{self, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+const JOURNALD_SOCKET_PATH: &str = "/run/systemd/journal/socket";
+const SYSLOG_SOCKET_PATH: &str = "/dev/log";
+const IDENTIFIER: &str = "fusedav-rs";
+
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_MAX_FILES: usize = 5;
+
+/// A line written again within this time of its first occurrence is counted instead, and the
+/// count is written once the time is over.
+const REPEAT_WINDOW: Duration = Duration::from_secs(10);
+/// Distinct lines counted at the same time; lines beyond that are always written.
+const MAX_COUNTED_LINES: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogTarget {
+    Stderr,
+    Journald,
+    Syslog,
+    File(PathBuf),
+}
+
+impl FromStr for LogTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "stderr" => Ok(LogTarget::Stderr),
+            "journald" => Ok(LogTarget::Journald),
+            "syslog" => Ok(LogTarget::Syslog),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(LogTarget::File(PathBuf::from(path))),
+                _ => Err(format!(
+                    "invalid log target {:?}, expected stderr, journald, syslog or file:<path>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl Display for LogTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LogTarget::Stderr => write!(f, "stderr"),
+            LogTarget::Journald => write!(f, "journald"),
+            LogTarget::Syslog => write!(f, "syslog"),
+            LogTarget::File(path) => write!(f, "file:{}", path.display()),
+        }
+    }
+}
+
+/// Redirects the stderr of the process to `target`, writing lines repeated within
+/// `REPEAT_WINDOW` once along with how often they were repeated, so e.g. an unreachable server
+/// does not flood the log with an error per operation.
+///
+/// Note : the crate logs with `eprintln!`, so stderr is replaced by a pipe and a thread forwards
+/// every line to the target. Output of child processes like fusermount is forwarded as well.
+pub fn init(target: &LogTarget) -> io::Result<()> {
+    let mut sink = match target {
+        LogTarget::Stderr => {
+            let fd = unsafe { libc::dup(libc::STDERR_FILENO) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Sink::Stderr(unsafe { File::from_raw_fd(fd) })
+        }
+        LogTarget::Journald => Sink::Journald(connect(JOURNALD_SOCKET_PATH)?),
+        LogTarget::Syslog => Sink::Syslog(connect(SYSLOG_SOCKET_PATH)?),
+        LogTarget::File(path) => Sink::File(RotatingFile::open(
+            path,
+            DEFAULT_MAX_FILE_SIZE,
+            DEFAULT_MAX_FILES,
+        )?),
+    };
+
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let reader = unsafe { File::from_raw_fd(fds[0]) };
+    let writer = unsafe { File::from_raw_fd(fds[1]) };
+    if unsafe { libc::dup2(fds[1], libc::STDERR_FILENO) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    drop(writer);
+
+    // Note : lines are read on their own thread, so the counts of repeats are written on time
+    // even when nothing else is logged.
+    let (sender, receiver) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("log-reader".to_string())
+        .spawn(move || {
+            for line in BufReader::new(reader).lines() {
+                let Ok(line) = line else { break };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        })?;
+    std::thread::Builder::new()
+        .name("log".to_string())
+        .spawn(move || {
+            let mut repeats = RepeatFilter::new(REPEAT_WINDOW);
+            loop {
+                let (lines, disconnected) = match receiver.recv_timeout(REPEAT_WINDOW) {
+                    Ok(line) => (repeats.filter(line, Instant::now()), false),
+                    Err(RecvTimeoutError::Timeout) => (repeats.expire(Instant::now()), false),
+                    Err(RecvTimeoutError::Disconnected) => (repeats.expire_all(), true),
+                };
+                for line in lines {
+                    // Note : stderr is the pipe itself, so there is nowhere left to report a
+                    // failure.
+                    let _ = sink.write_line(&line);
+                }
+                if disconnected {
+                    break;
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Counts the lines repeated within a window of their first occurrence instead of passing them.
+struct RepeatFilter {
+    window: Duration,
+    /// The first occurrence of every line in its window, and how often it was repeated since.
+    lines: HashMap<String, (Instant, u64)>,
+}
+
+impl RepeatFilter {
+    fn new(window: Duration) -> RepeatFilter {
+        RepeatFilter {
+            window,
+            lines: HashMap::new(),
+        }
+    }
+
+    /// Returns the lines to write for `line` received at `now`: the counts of the windows which
+    /// are over, then the line itself unless it is a repeat.
+    fn filter(&mut self, line: String, now: Instant) -> Vec<String> {
+        let mut lines = self.expire(now);
+        match self.lines.get_mut(&line) {
+            Some((_, repeated)) => *repeated += 1,
+            None => {
+                if self.lines.len() < MAX_COUNTED_LINES {
+                    self.lines.insert(line.clone(), (now, 0));
+                }
+                lines.push(line);
+            }
+        }
+        lines
+    }
+
+    /// Ends the windows which are over at `now`, returning the counts of their repeats.
+    fn expire(&mut self, now: Instant) -> Vec<String> {
+        let window = self.window;
+        self.drain(|first_at| now.duration_since(first_at) >= window)
+    }
+
+    fn expire_all(&mut self) -> Vec<String> {
+        self.drain(|_| true)
+    }
+
+    fn drain(&mut self, is_over: impl Fn(Instant) -> bool) -> Vec<String> {
+        let mut over: Vec<(Instant, String, u64)> = Vec::new();
+        self.lines.retain(|line, (first_at, repeated)| {
+            if !is_over(*first_at) {
+                return true;
+            }
+            if *repeated > 0 {
+                over.push((*first_at, line.clone(), *repeated));
+            }
+            false
+        });
+        over.sort();
+        over.into_iter()
+            .map(|(_, line, repeated)| {
+                format!("Last message repeated {} times: {}", repeated, line)
+            })
+            .collect()
+    }
+}
+
+fn connect(path: &str) -> io::Result<UnixDatagram> {
+    let socket = UnixDatagram::unbound()?;
+    socket.connect(path)?;
+    Ok(socket)
+}
+
+enum Sink {
+    Stderr(File),
+    Journald(UnixDatagram),
+    Syslog(UnixDatagram),
+    File(RotatingFile),
+}
+
+impl Sink {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        match self {
+            Sink::Stderr(file) => writeln!(file, "{}", line),
+            Sink::Journald(socket) => {
+                let message = format!(
+                    "PRIORITY={}\nSYSLOG_IDENTIFIER={}\nMESSAGE={}\n",
+                    priority(line),
+                    IDENTIFIER,
+                    line
+                );
+                socket.send(message.as_bytes()).map(|_| ())
+            }
+            Sink::Syslog(socket) => {
+                // Note : facility user(1), so the priority value is 1 * 8 + severity.
+                let message = format!(
+                    "<{}>{}[{}]: {}",
+                    8 + priority(line),
+                    IDENTIFIER,
+                    std::process::id(),
+                    line
+                );
+                socket.send(message.as_bytes()).map(|_| ())
+            }
+            Sink::File(file) => file.write_line(line),
+        }
+    }
+}
+
+/// Syslog severity of a log line: err for error messages, info otherwise.
+fn priority(line: &str) -> u8 {
+    if line.contains("Error") || line.contains("error") {
+        3
+    } else {
+        6
+    }
+}
+
+/// A log file which is renamed to `<path>.1` once it grows over `max_size`. Older files are
+/// shifted up to `<path>.<max_files - 1>` and the oldest one is dropped.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    fn open(path: &Path, max_size: u64, max_files: usize) -> io::Result<RotatingFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size,
+            max_files,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 + 1 > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for index in (1..self.max_files).rev() {
+            let from = if index == 1 {
+                self.path.clone()
+            } else {
+                self.rotated_path(index - 1)
+            };
+            match std::fs::rename(&from, self.rotated_path(index)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        if self.max_files <= 1 {
+            std::fs::remove_file(&self.path)?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+}
+
+#[cfg(test)]
+mod rotating_file_test {
+    use super::RotatingFile;
+
+    #[test]
+    fn rotate_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fusedav.log");
+
+        let mut file = RotatingFile::open(&path, 16, 3).unwrap();
+        for line in ["first line", "second line", "third line", "fourth line"] {
+            file.write_line(line).unwrap();
+        }
+
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("fusedav.log"), "fourth line\n");
+        assert_eq!(read("fusedav.log.1"), "third line\n");
+        assert_eq!(read("fusedav.log.2"), "second line\n");
+        assert!(!dir.path().join("fusedav.log.3").exists());
+    }
+}
+
+#[cfg(test)]
+mod repeat_filter_test {
+    use std::time::{Duration, Instant};
+
+    use super::RepeatFilter;
+
+    #[test]
+    fn filter_test() {
+        let mut repeats = RepeatFilter::new(Duration::from_secs(10));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let error = "Get attr error: Timeout".to_string();
+
+        assert_eq!(repeats.filter(error.clone(), at(0)), vec![error.clone()]);
+        assert!(repeats.filter(error.clone(), at(1)).is_empty());
+        assert!(repeats.filter(error.clone(), at(2)).is_empty());
+        assert_eq!(
+            repeats.filter("Mounted".to_string(), at(3)),
+            vec!["Mounted".to_string()]
+        );
+        assert!(repeats.expire(at(9)).is_empty());
+        assert_eq!(
+            repeats.filter(error.clone(), at(11)),
+            vec![
+                "Last message repeated 2 times: Get attr error: Timeout".to_string(),
+                error.clone()
+            ]
+        );
+        assert!(repeats.filter(error.clone(), at(12)).is_empty());
+        assert_eq!(
+            repeats.expire_all(),
+            vec!["Last message repeated 1 times: Get attr error: Timeout".to_string()]
+        );
+    }
+}
@@ -1,45 +1,999 @@
-use clap::Parser;
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use clap::{Parser, Subcommand};
 use fuser::MountOption;
+use tokio::signal::unix::{signal, SignalKind};
+
+use fusedav_rs::{
+    askpass, bench, blockfile, ctl, fs, logging, preflight, run_as, runtime, telemetry, webdav,
+};
 
-mod blockfile;
-mod fs;
-mod webdav;
+/// How often `umount` checks whether the mount process removed its ctl socket.
+const UNMOUNT_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 #[derive(Parser, Debug)]
+#[command(subcommand_negates_reqs = true)]
 struct Args {
-    #[arg(long)]
-    url: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(long, required = true)]
+    url: Option<String>,
     #[arg(long, default_value_t=String::new())]
     user: String,
     #[arg(short, long, default_value_t=String::new())]
     password: String,
+    /// Program asking for the password when --user is given without --password, run with the
+    /// prompt as argument and printing the password, like ssh-askpass; defaults to
+    /// $FUSEDAV_ASKPASS, else the terminal is asked, else $SSH_ASKPASS
+    #[arg(long)]
+    askpass: Option<PathBuf>,
+
+    /// When to send credentials: preemptive (Basic on every request) or challenge (only after
+    /// a 401, with the scheme and for the realm the server asks)
+    #[arg(long, default_value_t = webdav::AuthMode::Preemptive)]
+    auth_mode: webdav::AuthMode,
+
+    #[arg(short, long, required = true)]
+    tmp_path: Option<String>,
+    #[arg(short, long, required = true)]
+    mount_path: Option<String>,
+
+    /// Show files the server does not let the user write, by their privileges, Nextcloud
+    /// permissions or an active lock, as 0444 and such directories as 0555
+    #[arg(long, default_value_t = false)]
+    read_only_perms: bool,
+    /// Bypass the kernel page cache, so every read reaches the mount and sees fresh data; files
+    /// can then not be mapped with mmap. Without it, files opened with O_DIRECT bypass it alone
+    #[arg(long, default_value_t = false)]
+    direct_io: bool,
+    /// Allow other users to access the mount (requires `user_allow_other` for non-root users)
+    #[arg(long, default_value_t = false)]
+    allow_other: bool,
+    /// When started by root, continue as this user and group once mounted, e.g. alice:users or
+    /// 1000:1000; the cache directory is handed to them and files show as theirs
+    #[arg(long)]
+    run_as: Option<run_as::RunAs>,
+    /// Path to the fusermount3/fusermount binary, for systems where it is not in PATH
+    #[arg(long)]
+    fusermount_path: Option<PathBuf>,
+    /// Deviations of the server to work around: auto (detected from the URL or the server),
+    /// none, hetzner (Storage Box), box or yandex
+    #[arg(long, default_value_t = webdav::QuirksMode::Auto)]
+    server_quirks: webdav::QuirksMode,
+    /// Maximum length of request URLs; longer paths fail with ENAMETOOLONG
+    #[arg(long, default_value_t = webdav::DEFAULT_MAX_URL_LENGTH)]
+    max_url_length: usize,
+    /// Seconds requests on a resource locked by another client (423) are retried before failing
+    /// with EBUSY
+    #[arg(long, default_value_t = 0)]
+    locked_wait_secs: u64,
+    /// Seconds between TCP keepalive probes on open connections to the server, 0 to send none
+    #[arg(long, default_value_t = 0)]
+    tcp_keepalive_secs: u64,
+    /// Seconds an unused connection to the server is kept for the next request, 0 to keep it
+    /// until the server closes it; a reused connection skips the TCP and TLS handshakes
+    #[arg(long, default_value_t = webdav::DEFAULT_POOL_IDLE_TIMEOUT.as_secs())]
+    pool_idle_timeout_secs: u64,
+    /// Speak only HTTP/1.1 to the server, for servers whose HTTP/2 is broken; otherwise HTTP/2
+    /// is used where the server offers it
+    #[arg(long)]
+    http1_only: bool,
+    /// Seconds resolved addresses of the server are reused for new connections, 0 to resolve it
+    /// every time; the last addresses are also used while resolving fails
+    #[arg(long, default_value_t = webdav::DEFAULT_DNS_CACHE_TTL.as_secs())]
+    dns_cache_secs: u64,
+    /// Connect to a host at a fixed IP address instead of resolving it, as host:ip; may be
+    /// given several times
+    #[arg(long)]
+    resolve: Vec<webdav::HostOverride>,
+    /// Tune the mount for a workload: media (streamed videos and music), docs (small files
+    /// edited elsewhere) or backup (large archives read once); flags given explicitly win
+    #[arg(long)]
+    profile: Option<fs::Profile>,
+    /// Bytes of the blocks new cache files are split into and downloaded by; defaults to 16 MiB
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=blockfile::MAX_BLOCK_SIZE as i64))]
+    block_size: Option<u32>,
+    /// Bytes downloaded past the end of a read which misses the cache; defaults to 0
+    #[arg(long)]
+    readahead: Option<u32>,
+    /// Bytes of responses all downloads together may hold in memory; defaults to 64 MiB
+    #[arg(long)]
+    max_inflight_bytes: Option<usize>,
+    /// Bytes of a response a single download may hold in memory
+    #[arg(long, default_value_t = webdav::DEFAULT_MAX_DOWNLOAD_BUFFER)]
+    max_download_buffer: usize,
+    /// Downloads running at once at most; below it, their number adapts to how fast the server
+    /// answers
+    #[arg(
+        long,
+        default_value_t = webdav::DEFAULT_MAX_DOWNLOAD_CONCURRENCY as u32,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    max_download_concurrency: u32,
+    /// OTLP gRPC endpoint to export traces to, e.g. http://localhost:4317 (requires the `otel` feature)
+    #[arg(long)]
+    otlp_endpoint: Option<String>,
+    /// Where to write logs: stderr, journald, syslog or file:<path> (rotated at 10 MiB)
+    #[arg(long, default_value_t = logging::LogTarget::Stderr)]
+    log_target: logging::LogTarget,
+    /// Seconds cached listings and file data stay fresh when the server sends no Cache-Control
+    /// or Expires; without it they are kept until invalidated
+    #[arg(long)]
+    cache_ttl: Option<u64>,
+    /// Ignore Cache-Control and Expires from the server and only use --cache-ttl
+    #[arg(long, default_value_t = false)]
+    ignore_cache_control: bool,
+    /// Serve cached file data past --cache-ttl at once and confirm it with the server in the
+    /// background, instead of downloading it again before the read
+    #[arg(long, default_value_t = false)]
+    stale_while_revalidate: bool,
+    /// Seconds after which getattr confirms the attributes of an entry with the server, so sizes
+    /// of files growing on the server stay accurate; without it getattr answers from the listing
+    #[arg(long)]
+    attr_ttl: Option<u64>,
+    /// List directories again on every readdir, so `ls -l` shows files growing on the server
+    #[arg(long, default_value_t = false)]
+    refresh_on_readdir: bool,
+    /// List the sub directories of a directory in the background once it is read, this many at
+    /// a time, so entering them answers at once
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    prefetch_subdirs: Option<u32>,
+    /// Seconds in which every cached directory is polled once for remote changes, the most
+    /// recently used first and spread over the interval
+    #[arg(long)]
+    poll_interval: Option<u64>,
+    /// Command run through `sh -c` once per polling round which detected changes, with all the
+    /// changed remote paths as arguments
+    #[arg(long, requires = "poll_interval")]
+    on_change: Option<String>,
+    /// Remote directory or file kept downloaded in the cache, fetched at mount time and again
+    /// every --mirror-interval-secs with the changes from the server; may be given several
+    /// times
+    #[arg(long)]
+    mirror: Vec<String>,
+    /// Seconds between two mirror rounds
+    #[arg(
+        long,
+        default_value_t = 24 * 60 * 60,
+        requires = "mirror",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    mirror_interval_secs: u64,
+    /// Where file names come from: href (the URL) or displayname (the displayname property,
+    /// with " (2)" added on collisions)
+    #[arg(long, default_value_t = fs::NameSource::Href)]
+    name_source: fs::NameSource,
+    /// When reads update the access time of files: noatime (never), relatime (when it is not
+    /// newer than the mtime, or a day old) or strict (on every read)
+    #[arg(long, default_value_t = fs::AtimeMode::RelAtime)]
+    atime_mode: fs::AtimeMode,
+    /// File of gitignore-like rules of remote paths to hide from the mount and skip when pinning,
+    /// e.g. `/Users/*/` and `!/Users/alice/`
+    #[arg(long)]
+    sync_rules: Option<PathBuf>,
+    /// File of rules by the media type of files, e.g. `image/* prefetch 5M` to download small
+    /// images whole when opened and `video/* evict-on-close` to drop videos from the cache once
+    /// closed
+    #[arg(long)]
+    content_rules: Option<PathBuf>,
+    /// File of gitignore-like patterns of remote files appended to on the server, e.g. `*.log`;
+    /// their size is checked on every open and on reads reaching their end, so `tail -f` follows
+    /// them
+    #[arg(long)]
+    growing_files: Option<PathBuf>,
+    /// File of commands run on events of the mount, e.g. `on_error curl -d @- ntfy.sh/topic`;
+    /// events are on_mount, on_unmount and on_error, and the command gets a JSON object with
+    /// the details on its standard input
+    #[arg(long)]
+    hooks: Option<PathBuf>,
+    /// Versions collection of the user on a Nextcloud server, e.g.
+    /// https://cloud.example.com/remote.php/dav/versions/<user>/versions; previous revisions of
+    /// files are then shown read-only below /.versions
+    #[arg(long)]
+    versions_url: Option<String>,
+    /// Log every operation taking longer than this many milliseconds, with the time spent on
+    /// attributes, cache check, network fetch and disk read
+    #[arg(long)]
+    slow_op_threshold_ms: Option<u64>,
+    /// Directory entries kept in memory, e.g. 1000000; beyond that the listings of the least
+    /// recently used directories move to the cache directory until they are used again
+    #[arg(long)]
+    max_cached_entries: Option<usize>,
+    /// Approximate bytes of memory the directory entries may take, e.g. 67108864; beyond that
+    /// the listings of the least recently used directories move to the cache directory as with
+    /// --max-cached-entries
+    #[arg(long)]
+    max_metadata_bytes: Option<usize>,
+    /// File the health of the mount is written to as JSON every --health-report-interval-secs,
+    /// with the uptime, error counts, cache usage and last contact with the server but no paths
+    /// or names, for monitoring agents such as Telegraf
+    #[arg(long)]
+    health_report: Option<PathBuf>,
+    /// Seconds between two writes of the health report
+    #[arg(
+        long,
+        default_value_t = 60,
+        requires = "health_report",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    health_report_interval_secs: u64,
+    /// Largest remote file in bytes the mount exposes, e.g. 4294967296; larger files fail to open
+    /// with EFBIG or are hidden, see --oversized-files, and are never pinned
+    #[arg(long)]
+    max_file_size: Option<u64>,
+    /// What is done with files above --max-file-size: error (listed, but opening them fails) or
+    /// hide (left out of listings)
+    #[arg(long, default_value_t = fs::OversizedFiles::Error, requires = "max_file_size")]
+    oversized_files: fs::OversizedFiles,
+    /// Number of pinned files downloaded at the same time; defaults to 4
+    #[arg(long)]
+    pin_workers: Option<usize>,
+    /// Threads running async tasks; defaults to the number of CPUs
+    #[arg(long)]
+    worker_threads: Option<usize>,
+    /// Maximum threads for blocking file I/O; defaults to 512
+    #[arg(long)]
+    max_blocking_threads: Option<usize>,
+    /// Restrict all threads of the mount to these CPUs, e.g. 2-3 or 0,2
+    #[arg(long)]
+    cpu_affinity: Option<runtime::CpuList>,
+}
+
+impl Args {
+    /// The settings of `--profile`, overridden by the flags given explicitly. Settings left
+    /// `None` by both keep their defaults.
+    fn tuning(&self) -> fs::Tuning {
+        let profile = self.profile.map(|x| x.tuning()).unwrap_or_default();
+        fs::Tuning {
+            block_size: self.block_size.or(profile.block_size),
+            readahead: self.readahead.or(profile.readahead),
+            cache_ttl: self.cache_ttl.or(profile.cache_ttl),
+            attr_ttl: self.attr_ttl.or(profile.attr_ttl),
+            pin_workers: self.pin_workers.or(profile.pin_workers),
+            max_inflight_bytes: self.max_inflight_bytes.or(profile.max_inflight_bytes),
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Drop the cached listing and data of a remote path in a running mount
+    Invalidate {
+        mount_path: PathBuf,
+        /// Remote path, e.g. /photos/2024
+        path: String,
+    },
+    /// Print the stats of a running mount in the Prometheus text format
+    Stats { mount_path: PathBuf },
+    /// List a remote directory and every directory below it into a running mount, several at a
+    /// time, so browsing the tree needs no requests
+    Prefetch {
+        mount_path: PathBuf,
+        /// Remote path, e.g. /photos/2024
+        path: String,
+    },
+    /// Download a remote file or directory into the cache of a running mount in the background
+    Pin {
+        mount_path: PathBuf,
+        /// Remote path, e.g. /photos/2024
+        path: String,
+    },
+    /// Print the progress of pinned downloads
+    PinStatus { mount_path: PathBuf },
+    /// Pause pinned downloads, also across restarts, until resumed
+    PinPause { mount_path: PathBuf },
+    /// Resume pinned downloads
+    PinResume { mount_path: PathBuf },
+    /// Print the operations a running mount is busy with, with the path each works on and how
+    /// long it has been running
+    Ops { mount_path: PathBuf },
+    /// Move the list of cached files between mounts, e.g. to pre-seed a fresh cache directory
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    /// Measure the read throughput of cache files and of the server, to choose --block-size,
+    /// --readahead and --pin-workers
+    Bench(BenchArgs),
+    /// Write the cache of a running mount to disk, then unmount it and wait for the mount
+    /// process to exit
+    Umount {
+        mount_path: PathBuf,
+        /// Seconds to wait for the mount process to exit
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+        /// Unmount even when the cache can not be written to disk
+        #[arg(long, default_value_t = false)]
+        force: bool,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+struct BenchArgs {
+    /// Directory for the scratch cache files, e.g. the --tmp-path of the mount
+    dir: PathBuf,
+    /// Remote file to read as well, using --url, --user and --password
+    #[arg(long)]
+    remote_path: Option<String>,
+    /// Bytes written to the scratch cache file, and read from the remote file at most
+    #[arg(long, default_value_t = 256 * 1024 * 1024)]
+    size: u64,
+    #[arg(long, default_value_t = 16 * 1024 * 1024, value_parser = clap::value_parser!(u32).range(1..=blockfile::MAX_BLOCK_SIZE as i64))]
+    block_size: u32,
+    /// Bytes of every read, like a read of a program through the mount
+    #[arg(long, default_value_t = 128 * 1024, value_parser = clap::value_parser!(u32).range(1..))]
+    read_size: u32,
+    #[arg(long, default_value_t = 64)]
+    random_reads: usize,
+    /// Downloads at the same time in the parallel remote run
+    #[arg(long, default_value_t = 4)]
+    concurrency: usize,
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Print the cached files of a running mount with their size, mtime and etag
+    ExportManifest {
+        mount_path: PathBuf,
+        /// Write the manifest to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Download the files listed in a manifest into the cache of a running mount in the
+    /// background, see pin-status for the progress
+    ImportManifest {
+        mount_path: PathBuf,
+        manifest: PathBuf,
+    },
+    /// Rewrite the cache files of a running mount without their partially downloaded blocks,
+    /// reclaiming their space
+    Compact { mount_path: PathBuf },
+    /// Take a complete local copy of a remote file, e.g. one made with rsync, as its cache in a
+    /// running mount, so it is not downloaded again
+    Import {
+        mount_path: PathBuf,
+        /// Path of the file on the server, e.g. /Videos/talk.mkv
+        remote_path: String,
+        local_path: PathBuf,
+    },
+    /// Write the cached data of a remote file into a plain file, without a running mount
+    Export(ExportArgs),
+}
 
-    #[arg(short, long)]
-    tmp_path: String,
-    #[arg(short, long)]
-    mount_path: String,
+#[derive(clap::Args, Debug)]
+struct ExportArgs {
+    /// Cache directory of the mount (its --tmp-path), or one of the namespaces in it
+    cache_dir: PathBuf,
+    /// Path of the file on the server, e.g. /Videos/talk.mkv
+    remote_path: String,
+    local_path: PathBuf,
+    /// Download the blocks which are not cached into the exported file, using --url, --user and
+    /// --password; fails while a mount uses the cache
+    #[arg(long, default_value_t = false)]
+    download: bool,
+    /// Leave the blocks which are not cached as zeros instead of failing
+    #[arg(long, default_value_t = false)]
+    allow_missing: bool,
 }
 
-#[tokio::main]
-async fn main() {
+fn main() {
     let args = Args::parse();
+    // Note : preflight may change PATH, which is only sound before the runtime starts threads.
+    if args.command.is_none() {
+        preflight(&args);
+    }
 
-    let client = webdav::WebDAVClient::new(args.url, args.user, args.password).unwrap();
+    let runtime_options = runtime::RuntimeOptions {
+        worker_threads: args.worker_threads,
+        max_blocking_threads: args.max_blocking_threads,
+        cpu_affinity: args.cpu_affinity.clone(),
+    };
+    let runtime = match runtime::build(&runtime_options) {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            eprintln!("Can not start runtime: {}", err);
+            std::process::exit(1);
+        }
+    };
 
-    let user_id = unsafe { libc::getuid() };
-    let group_id = unsafe { libc::getgid() };
+    runtime.block_on(async move {
+        let mut args = args;
+        match args.command.take() {
+            Some(command) => run_command(command, &args).await,
+            None => mount(args).await,
+        }
+    });
+}
+
+async fn run_command(command: Command, args: &Args) {
+    let mut output = None;
+    let (mount_path, request) = match command {
+        Command::Invalidate { mount_path, path } => (mount_path, ctl::Request::Invalidate(path)),
+        Command::Stats { mount_path } => (mount_path, ctl::Request::Stats),
+        Command::Prefetch { mount_path, path } => (mount_path, ctl::Request::Prefetch(path)),
+        Command::Pin { mount_path, path } => (mount_path, ctl::Request::Pin(path)),
+        Command::PinStatus { mount_path } => (mount_path, ctl::Request::PinStatus),
+        Command::PinPause { mount_path } => (mount_path, ctl::Request::PinPause),
+        Command::PinResume { mount_path } => (mount_path, ctl::Request::PinResume),
+        Command::Ops { mount_path } => (mount_path, ctl::Request::Ops),
+        Command::Cache {
+            command:
+                CacheCommand::ExportManifest {
+                    mount_path,
+                    output: path,
+                },
+        } => {
+            output = path;
+            (mount_path, ctl::Request::ExportManifest)
+        }
+        Command::Cache {
+            command:
+                CacheCommand::ImportManifest {
+                    mount_path,
+                    manifest,
+                },
+        } => {
+            // Note : the mount reads the manifest, possibly from another working directory.
+            let manifest = match std::fs::canonicalize(&manifest) {
+                Ok(manifest) => manifest,
+                Err(err) => {
+                    eprintln!("Can not read manifest {:?}: {}", manifest, err);
+                    std::process::exit(1);
+                }
+            };
+            let manifest = manifest.to_string_lossy().to_string();
+            (mount_path, ctl::Request::ImportManifest(manifest))
+        }
+        Command::Cache {
+            command: CacheCommand::Compact { mount_path },
+        } => (mount_path, ctl::Request::CompactCache),
+        Command::Cache {
+            command: CacheCommand::Export(export_args),
+        } => return export_cache(export_args, args).await,
+        Command::Bench(bench_args) => return run_bench(bench_args, args).await,
+        Command::Umount {
+            mount_path,
+            timeout,
+            force,
+        } => return unmount(&mount_path, Duration::from_secs(timeout), force).await,
+        Command::Cache {
+            command:
+                CacheCommand::Import {
+                    mount_path,
+                    remote_path,
+                    local_path,
+                },
+        } => {
+            // Note : the mount reads the local copy, possibly from another working directory.
+            let local_path = match std::fs::canonicalize(&local_path) {
+                Ok(local_path) => local_path,
+                Err(err) => {
+                    eprintln!("Can not read {:?}: {}", local_path, err);
+                    std::process::exit(1);
+                }
+            };
+            let local_path = local_path.to_string_lossy().to_string();
+            (
+                mount_path,
+                ctl::Request::ImportFile(remote_path, local_path),
+            )
+        }
+    };
+    match ctl::send(&mount_path, &request).await {
+        Ok(message) => match output {
+            Some(path) => {
+                if let Err(err) = std::fs::write(&path, format!("{}\n", message)) {
+                    eprintln!("Can not write {:?}: {}", path, err);
+                    std::process::exit(1);
+                }
+            }
+            None => println!("{}", message),
+        },
+        Err(err) => {
+            eprintln!("Request failed: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn unmount(mount_path: &Path, timeout: Duration, force: bool) {
+    // Note : resolve the socket path first, the mount process removes the socket once unmounted.
+    let socket_path = match ctl::socket_path(mount_path) {
+        Ok(socket_path) => socket_path,
+        Err(err) => {
+            eprintln!("Request failed: {}", err);
+            std::process::exit(1);
+        }
+    };
+    match ctl::send(mount_path, &ctl::Request::Unmount { force }).await {
+        Ok(message) => println!("{}", message),
+        Err(err) => {
+            eprintln!("Request failed: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    let started_at = Instant::now();
+    while socket_path.exists() {
+        if started_at.elapsed() >= timeout {
+            eprintln!("Mount process did not exit within {} s", timeout.as_secs());
+            std::process::exit(1);
+        }
+        tokio::time::sleep(UNMOUNT_POLL_INTERVAL).await;
+    }
+    println!("unmounted {:?}", mount_path);
+}
+
+async fn export_cache(export_args: ExportArgs, args: &Args) {
+    let client = match (&args.url, export_args.download) {
+        (Some(url), true) => Some(remote_client(url, args)),
+        (None, true) => {
+            eprintln!("--download needs --url");
+            std::process::exit(1);
+        }
+        (_, false) => None,
+    };
+
+    match fs::export_cached_file(
+        &export_args.cache_dir,
+        &export_args.remote_path,
+        &export_args.local_path,
+        client.as_ref(),
+        export_args.allow_missing,
+    )
+    .await
+    {
+        Ok(export) => println!("{}", export),
+        Err(err) => {
+            eprintln!("Export failed: {:?}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run_bench(bench_args: BenchArgs, args: &Args) {
+    let options = bench::BenchOptions {
+        size: bench_args.size,
+        block_size: bench_args.block_size,
+        read_size: bench_args.read_size,
+        random_reads: bench_args.random_reads,
+        concurrency: bench_args.concurrency,
+    };
+    let client = match (&args.url, &bench_args.remote_path) {
+        (Some(url), Some(_)) => Some(remote_client(url, args)),
+        (None, Some(_)) => {
+            eprintln!("--remote-path needs --url");
+            std::process::exit(1);
+        }
+        (_, None) => None,
+    };
+
+    let mut measurements = bench::bench_cache(&bench_args.dir, &options).await;
+    if let (Some(client), Some(remote_path)) = (&client, &bench_args.remote_path) {
+        if let Ok(measurements) = &mut measurements {
+            match bench::bench_remote(client, remote_path, &bench_args.dir, &options).await {
+                Ok(remote) => measurements.extend(remote),
+                Err(err) => {
+                    eprintln!("Bench failed: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    match measurements {
+        Ok(measurements) => measurements.iter().for_each(|x| println!("{}", x)),
+        Err(err) => {
+            eprintln!("Bench failed: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Builds a client of the server for a subcommand, asking for the password when needed.
+fn remote_client(url: &str, args: &Args) -> webdav::WebDAVClient {
+    let password = password(args);
+    match webdav::WebDAVClient::with_auth_mode(
+        url.to_string(),
+        args.user.clone(),
+        password,
+        args.auth_mode,
+    ) {
+        Ok(mut client) => {
+            client.set_quirks_mode(args.server_quirks);
+            client
+        }
+        Err(err) => {
+            eprintln!("Can not use server URL: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Returns --password, or asks for it when only --user is given.
+fn password(args: &Args) -> webdav::Secret {
+    if !args.password.is_empty() || args.user.is_empty() {
+        return webdav::Secret::new(args.password.clone());
+    }
+    let prompt = format!(
+        "Password for {} at {}: ",
+        args.user,
+        args.url.as_deref().unwrap_or_default()
+    );
+    match askpass::read_password(&prompt, args.askpass.as_deref()) {
+        Ok(Some(password)) => password,
+        // Note : nobody to ask, e.g. under systemd, so the server may still accept no password.
+        Ok(None) => webdav::Secret::new(String::new()),
+        Err(err) => {
+            eprintln!("Can not read the password: {}", err);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Checks that the mount can work and makes --fusermount-path visible to `fuser`.
+fn preflight(args: &Args) {
+    let preflight_options = preflight::PreflightOptions {
+        fusermount_path: args.fusermount_path.clone(),
+        allow_other: args.allow_other,
+    };
+    let result = preflight::check(&preflight_options).and_then(|_| match &args.fusermount_path {
+        Some(path) => preflight::use_fusermount(path),
+        None => Ok(()),
+    });
+    if let Err(err) = result {
+        eprintln!("Can not mount: {}", err);
+        std::process::exit(1);
+    }
+}
+
+async fn mount(args: Args) {
+    if args.run_as.is_some() && unsafe { libc::geteuid() } != 0 {
+        eprintln!("Can not mount: --run-as needs to be started by root");
+        std::process::exit(1);
+    }
+    let password = password(&args);
+    let tuning = args.tuning();
+
+    // Note : clap guarantees these when no subcommand is given.
+    let url = args.url.unwrap();
+    let tmp_path = args.tmp_path.unwrap();
+    let mount_path = PathBuf::from(args.mount_path.unwrap());
+
+    // Note : preflight errors stay on the terminal, everything after goes to the log target.
+    if let Err(err) = logging::init(&args.log_target) {
+        eprintln!("Can not log to {}: {}", args.log_target, err);
+        std::process::exit(1);
+    }
 
-    let webdavfs = fs::WebDAVFS::new(
+    if let Some(endpoint) = &args.otlp_endpoint {
+        if let Err(err) = telemetry::init(endpoint) {
+            eprintln!("Can not export traces: {}", err);
+        }
+    }
+
+    let mut client =
+        match webdav::WebDAVClient::with_auth_mode(url, args.user, password, args.auth_mode) {
+            Ok(client) => client,
+            Err(err) => {
+                eprintln!("Can not use server URL: {}", err);
+                std::process::exit(1);
+            }
+        };
+    client.set_quirks_mode(args.server_quirks);
+    client.set_request_privileges(args.read_only_perms);
+    client.set_max_url_length(args.max_url_length);
+    let max_inflight_bytes = tuning
+        .max_inflight_bytes
+        .unwrap_or(webdav::DEFAULT_MAX_INFLIGHT_BYTES);
+    client.set_download_budget(max_inflight_bytes, args.max_download_buffer);
+    client.set_max_download_concurrency(args.max_download_concurrency as usize);
+    client.set_locked_wait(Duration::from_secs(args.locked_wait_secs));
+    let connection = webdav::ConnectionOptions {
+        tcp_keepalive: Some(args.tcp_keepalive_secs)
+            .filter(|x| *x > 0)
+            .map(Duration::from_secs),
+        pool_idle_timeout: Some(args.pool_idle_timeout_secs)
+            .filter(|x| *x > 0)
+            .map(Duration::from_secs),
+        http1_only: args.http1_only,
+        dns_cache_ttl: Some(args.dns_cache_secs)
+            .filter(|x| *x > 0)
+            .map(Duration::from_secs),
+        resolve: args.resolve.clone(),
+    };
+    if let Err(err) = client.set_connection_options(connection) {
+        eprintln!("Can not set up connections to the server: {}", err);
+        std::process::exit(1);
+    }
+    let versions_client = match &args.versions_url {
+        Some(versions_url) => match client.with_root(versions_url.clone()) {
+            Ok(versions_client) => Some(versions_client),
+            Err(err) => {
+                eprintln!("Can not use versions URL {}: {}", versions_url, err);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // Note : kept until the end of main, it locks the cache directory against other mounts.
+    let cache_namespace = match fs::CacheNamespace::open(Path::new(&tmp_path), &client.identity()) {
+        Ok(cache_namespace) => cache_namespace,
+        Err(err) => {
+            eprintln!("Can not use cache directory {}: {}", tmp_path, err);
+            std::process::exit(1);
+        }
+    };
+
+    let (user_id, group_id) = match &args.run_as {
+        Some(run_as) => (run_as.uid, run_as.gid),
+        None => unsafe { (libc::getuid(), libc::getgid()) },
+    };
+
+    let mut webdavfs = fs::WebDAVFS::new(
         tokio::runtime::Handle::current(),
         client,
-        args.tmp_path,
+        cache_namespace.path().to_string_lossy().to_string(),
         user_id,
         group_id,
     );
-    let options = vec![
+    webdavfs.set_cache_policy(fs::CachePolicy {
+        default_ttl: tuning.cache_ttl.map(Duration::from_secs),
+        ignore_cache_control: args.ignore_cache_control,
+        refresh_on_readdir: args.refresh_on_readdir,
+        attr_ttl: tuning.attr_ttl.map(Duration::from_secs),
+        stale_while_revalidate: args.stale_while_revalidate,
+    });
+    if let Some(block_size) = tuning.block_size {
+        webdavfs.set_block_size(block_size);
+    }
+    if let Some(readahead) = tuning.readahead {
+        webdavfs.set_readahead(readahead);
+    }
+    webdavfs.set_name_source(args.name_source);
+    webdavfs.set_atime_mode(args.atime_mode);
+    if let Some(max_file_size) = args.max_file_size {
+        webdavfs.set_file_size_limit(fs::FileSizeLimit::new(max_file_size, args.oversized_files));
+    }
+    webdavfs.set_direct_io(args.direct_io);
+    webdavfs.set_read_only_perms(args.read_only_perms);
+    if let Some(prefetch_subdirs) = args.prefetch_subdirs {
+        webdavfs.set_subdir_prefetch(prefetch_subdirs as usize);
+    }
+    if let Some(path) = &args.sync_rules {
+        let sync_rules = std::fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| {
+                fs::SyncRules::parse(&text)
+                    .map_err(|line| format!("malformed rule on line {}", line))
+            });
+        match sync_rules {
+            Ok(sync_rules) => webdavfs.set_sync_rules(sync_rules),
+            Err(err) => {
+                eprintln!("Can not read sync rules {:?}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &args.content_rules {
+        let content_rules = std::fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| {
+                fs::ContentRules::parse(&text)
+                    .map_err(|line| format!("malformed rule on line {}", line))
+            });
+        match content_rules {
+            Ok(content_rules) => webdavfs.set_content_rules(content_rules),
+            Err(err) => {
+                eprintln!("Can not read content rules {:?}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &args.growing_files {
+        let growing_files = std::fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| {
+                fs::GrowingFiles::parse(&text)
+                    .map_err(|line| format!("malformed pattern on line {}", line))
+            });
+        match growing_files {
+            Ok(growing_files) => webdavfs.set_growing_files(growing_files),
+            Err(err) => {
+                eprintln!("Can not read growing files {:?}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+    }
+    let hooks = match &args.hooks {
+        Some(path) => {
+            let hooks = std::fs::read_to_string(path)
+                .map_err(|err| err.to_string())
+                .and_then(|text| {
+                    fs::Hooks::parse(&text)
+                        .map_err(|line| format!("malformed hook on line {}", line))
+                });
+            match hooks {
+                Ok(hooks) => hooks,
+                Err(err) => {
+                    eprintln!("Can not read hooks {:?}: {}", path, err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => fs::Hooks::default(),
+    };
+    webdavfs.set_hooks(hooks.clone());
+    if let Some(versions_client) = versions_client {
+        webdavfs.set_versions_client(versions_client);
+    }
+    if let Some(pin_workers) = tuning.pin_workers {
+        webdavfs.set_pin_workers(pin_workers);
+    }
+    if let Some(threshold_ms) = args.slow_op_threshold_ms {
+        webdavfs.set_slow_op_threshold(Duration::from_millis(threshold_ms));
+    }
+    if args.max_cached_entries.is_some() || args.max_metadata_bytes.is_some() {
+        let spill_dir = cache_namespace.path().join("listings");
+        if let Err(err) =
+            webdavfs.set_listing_limits(args.max_cached_entries, args.max_metadata_bytes, spill_dir)
+        {
+            eprintln!("Can not use the listing spill directory: {}", err);
+            std::process::exit(1);
+        }
+    }
+    let mut options = vec![
         MountOption::RO,
         MountOption::Async,
         MountOption::FSName("fusedav-rs".to_string()),
     ];
-    fuser::mount2(webdavfs, args.mount_path, &options).unwrap();
+    if args.allow_other {
+        options.push(MountOption::AllowOther);
+    }
+
+    // Note : resolve the socket path before mounting, while the path is still a plain directory.
+    let socket_path = ctl::socket_path(&mount_path).unwrap();
+
+    // Note : the session runs on its own threads, so the runtime stays free for background tasks.
+    let mut mount_guard = match fs::mount(webdavfs, &mount_path, &options) {
+        Ok(mount_guard) => mount_guard,
+        Err(err) => {
+            eprintln!("Can not mount: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // Note : privileges are dropped once mounted, before the ctl socket is bound, so the user
+    // owns the socket and every file the mount creates from now on.
+    if let Some(run_as) = &args.run_as {
+        let result = run_as::chown_tree(cache_namespace.path(), run_as)
+            .and_then(|_| run_as::drop_privileges(run_as));
+        if let Err(err) = result {
+            eprintln!("Can not run as {}: {}", run_as, err);
+            drop(mount_guard);
+            std::process::exit(1);
+        }
+    }
+
+    match ctl::bind(&socket_path) {
+        Ok(listener) => {
+            tokio::spawn(ctl::serve(listener, mount_guard.handle()));
+        }
+        Err(err) => eprintln!("Can not bind ctl socket {:?}: {}", socket_path, err),
+    }
+
+    if let Some(poll_interval) = args.poll_interval {
+        tokio::spawn(fs::watch(
+            mount_guard.handle(),
+            Duration::from_secs(poll_interval),
+            args.on_change.clone(),
+        ));
+    }
+    if !args.mirror.is_empty() {
+        tokio::spawn(fs::mirror(
+            mount_guard.handle(),
+            args.mirror.clone(),
+            Duration::from_secs(args.mirror_interval_secs),
+        ));
+    }
+
+    if let Some(path) = &args.health_report {
+        tokio::spawn(fs::health_report(
+            mount_guard.handle(),
+            path.clone(),
+            Duration::from_secs(args.health_report_interval_secs),
+        ));
+    }
+
+    let mount_path_field = ("mount_path", mount_path.display().to_string());
+    {
+        let hooks = hooks.clone();
+        let fields = [mount_path_field.clone()];
+        tokio::spawn(async move { hooks.run(fs::HookEvent::Mount, &fields).await });
+    }
+
+    let mount_handle = mount_guard.handle();
+    let unmount_requested = tokio::select! {
+        _ = mount_guard.terminated() => false,
+        _ = wait_shutdown_signal() => true,
+        _ = mount_handle.unmount_requested() => true,
+    };
+    if unmount_requested {
+        if let Err(err) = mount_guard.unmount().await {
+            eprintln!("Unmount error: {}", err);
+        }
+    }
+    let _ = std::fs::remove_file(&socket_path);
+    hooks.run(fs::HookEvent::Unmount, &[mount_path_field]).await;
+    telemetry::shutdown();
+}
+
+async fn wait_shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    let mut sigint = signal(SignalKind::interrupt()).unwrap();
+    tokio::select! {
+        _ = sigterm.recv() => {}
+        _ = sigint.recv() => {}
+    }
+}
+
+#[cfg(test)]
+mod main_test {
+    use clap::Parser;
+
+    use super::Args;
+    use fusedav_rs::webdav;
+
+    fn parse(flags: &[&str]) -> Result<Args, clap::Error> {
+        let required = [
+            "fusedav-rs",
+            "--url",
+            "https://dav.example.com/",
+            "--tmp-path",
+            "/tmp/cache",
+            "--mount-path",
+            "/mnt",
+        ];
+        Args::try_parse_from(required.iter().chain(flags))
+    }
+
+    #[test]
+    fn tuning_test() {
+        let max_inflight_bytes = |flags: &[&str]| {
+            parse(flags)
+                .unwrap()
+                .tuning()
+                .max_inflight_bytes
+                .unwrap_or(webdav::DEFAULT_MAX_INFLIGHT_BYTES)
+        };
+        assert_eq!(max_inflight_bytes(&[]), webdav::DEFAULT_MAX_INFLIGHT_BYTES);
+        assert_eq!(
+            max_inflight_bytes(&["--profile", "backup"]),
+            512 * 1024 * 1024
+        );
+        assert_eq!(
+            max_inflight_bytes(&["--profile", "backup", "--max-inflight-bytes", "1000"]),
+            1000
+        );
+
+        let block_size = |flags: &[&str]| parse(flags).unwrap().tuning().block_size;
+        assert_eq!(block_size(&[]), None);
+        assert_eq!(block_size(&["--profile", "docs"]), Some(1024 * 1024));
+        assert_eq!(
+            block_size(&["--profile", "docs", "--block-size", "4096"]),
+            Some(4096)
+        );
+    }
+
+    #[test]
+    fn block_size_range_test() {
+        let block_size = |value: &str| parse(&["--block-size", value]).map(|x| x.block_size);
+        assert_eq!(block_size("1073741824").unwrap(), Some(1 << 30));
+        assert!(block_size("1073741825").is_err());
+        assert!(block_size("0").is_err());
+    }
 }
@@ -0,0 +1,276 @@
+use std::{
+    ffi::{CString, OsStr},
+    fmt::Display,
+    fs, io,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+const FUSE_DEVICE_PATH: &str = "/dev/fuse";
+const FUSE_CONF_PATH: &str = "/etc/fuse.conf";
+const FUSERMOUNT_BINARIES: [&str; 2] = ["fusermount3", "fusermount"];
+
+#[derive(Debug)]
+pub enum PreflightError {
+    FuseDeviceMissing,
+    FuseDeviceNotAccessible(io::Error),
+    FusermountNotFound,
+    InvalidFusermountPath(PathBuf, String),
+    AllowOtherNotPermitted,
+}
+
+pub struct PreflightOptions {
+    pub fusermount_path: Option<PathBuf>,
+    pub allow_other: bool,
+}
+
+/// Checks that the environment is able to host a FUSE mount before `fuser` tries to, so that
+/// failures inside containers come with a hint instead of a bare `ENOENT`/`EPERM`. It changes
+/// nothing, a custom fusermount is made visible to `fuser` with `use_fusermount`.
+pub fn check(options: &PreflightOptions) -> Result<(), PreflightError> {
+    check_fuse_device(Path::new(FUSE_DEVICE_PATH))?;
+
+    let is_root = unsafe { libc::geteuid() } == 0;
+    match &options.fusermount_path {
+        Some(path) => check_fusermount_path(path)?,
+        None => {
+            let paths = std::env::var_os("PATH").unwrap_or_default();
+            if !is_root && find_fusermount(&paths).is_none() {
+                return Err(PreflightError::FusermountNotFound);
+            }
+        }
+    }
+
+    let allow_other_enabled =
+        fs::read_to_string(FUSE_CONF_PATH).is_ok_and(|conf| is_user_allow_other_enabled(&conf));
+    if options.allow_other && !is_root && !allow_other_enabled {
+        return Err(PreflightError::AllowOtherNotPermitted);
+    }
+    Ok(())
+}
+
+/// `fuser` looks up fusermount through `PATH`, so the checked binary at `path` is made visible by
+/// putting its directory first. Changing the environment races with other threads reading it, so
+/// this is called before the runtime starts any.
+pub fn use_fusermount(path: &Path) -> Result<(), PreflightError> {
+    let dir = path.parent().unwrap_or(Path::new("/"));
+    let mut paths = vec![dir.to_path_buf()];
+    if let Some(current) = std::env::var_os("PATH") {
+        paths.extend(std::env::split_paths(&current));
+    }
+    let joined = std::env::join_paths(paths)
+        .map_err(|e| PreflightError::InvalidFusermountPath(path.to_path_buf(), e.to_string()))?;
+    std::env::set_var("PATH", joined);
+    Ok(())
+}
+
+fn check_fuse_device(device_path: &Path) -> Result<(), PreflightError> {
+    if !device_path.exists() {
+        return Err(PreflightError::FuseDeviceMissing);
+    }
+
+    let path = CString::new(device_path.as_os_str().as_bytes()).unwrap();
+    if unsafe { libc::access(path.as_ptr(), libc::R_OK | libc::W_OK) } != 0 {
+        return Err(PreflightError::FuseDeviceNotAccessible(
+            io::Error::last_os_error(),
+        ));
+    }
+    Ok(())
+}
+
+fn check_fusermount_path(path: &Path) -> Result<(), PreflightError> {
+    let file_name = path.file_name().and_then(|x| x.to_str()).unwrap_or("");
+    if !FUSERMOUNT_BINARIES.contains(&file_name) {
+        return Err(PreflightError::InvalidFusermountPath(
+            path.to_path_buf(),
+            format!("file name must be one of {:?}", FUSERMOUNT_BINARIES),
+        ));
+    }
+    if !is_executable(path) {
+        return Err(PreflightError::InvalidFusermountPath(
+            path.to_path_buf(),
+            "not an executable file".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Looks fusermount up in the directories of `paths`, a `PATH` value.
+fn find_fusermount(paths: &OsStr) -> Option<PathBuf> {
+    std::env::split_paths(paths).find_map(|dir| {
+        FUSERMOUNT_BINARIES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|candidate| is_executable(candidate))
+    })
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::metadata(path)
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Tells whether the content of a fuse.conf lets users mount with allow_other.
+fn is_user_allow_other_enabled(conf: &str) -> bool {
+    conf.lines()
+        .map(|line| line.trim())
+        .any(|line| line == "user_allow_other")
+}
+
+fn is_in_container() -> bool {
+    Path::new("/.dockerenv").exists() || Path::new("/run/.containerenv").exists()
+}
+
+impl PreflightError {
+    fn hint(&self) -> &'static str {
+        match self {
+            PreflightError::FuseDeviceMissing | PreflightError::FuseDeviceNotAccessible(_) => {
+                if is_in_container() {
+                    "start the container with `--device /dev/fuse --cap-add SYS_ADMIN` (podman: `--device /dev/fuse`)"
+                } else {
+                    "load the fuse kernel module (`modprobe fuse`) and check the permissions of /dev/fuse"
+                }
+            }
+            PreflightError::FusermountNotFound => {
+                "install fuse3 (or fuse) or point to the binary with --fusermount-path"
+            }
+            PreflightError::InvalidFusermountPath(_, _) => {
+                "--fusermount-path must point to an executable named fusermount3 or fusermount"
+            }
+            PreflightError::AllowOtherNotPermitted => {
+                "add `user_allow_other` to /etc/fuse.conf or mount without --allow-other"
+            }
+        }
+    }
+}
+
+impl Display for PreflightError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreflightError::FuseDeviceMissing => write!(f, "{} does not exist", FUSE_DEVICE_PATH)?,
+            PreflightError::FuseDeviceNotAccessible(e) => {
+                write!(f, "{} is not accessible: {}", FUSE_DEVICE_PATH, e)?
+            }
+            PreflightError::FusermountNotFound => {
+                write!(f, "fusermount3/fusermount is not found in PATH")?
+            }
+            PreflightError::InvalidFusermountPath(path, reason) => {
+                write!(f, "invalid fusermount path {}: {}", path.display(), reason)?
+            }
+            PreflightError::AllowOtherNotPermitted => {
+                write!(f, "allow_other is not permitted for non-root users")?
+            }
+        }
+        write!(f, " (hint: {})", self.hint())
+    }
+}
+
+impl std::error::Error for PreflightError {}
+
+#[cfg(test)]
+mod preflight_test {
+    use std::{ffi::OsString, fs, os::unix::fs::PermissionsExt, path::Path};
+
+    use super::{
+        check_fuse_device, check_fusermount_path, find_fusermount, is_user_allow_other_enabled,
+        PreflightError,
+    };
+
+    fn create_file(path: &Path, mode: u32) {
+        fs::write(path, b"").unwrap();
+        fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    fn join_paths(dirs: &[&Path]) -> OsString {
+        std::env::join_paths(dirs).unwrap()
+    }
+
+    #[test]
+    fn is_user_allow_other_enabled_test() {
+        assert!(is_user_allow_other_enabled("user_allow_other\n"));
+        assert!(is_user_allow_other_enabled(
+            "# mount_max = 1000\n  user_allow_other  \n"
+        ));
+        assert!(!is_user_allow_other_enabled("#user_allow_other\n"));
+        assert!(!is_user_allow_other_enabled("mount_max = 1000\n"));
+        assert!(!is_user_allow_other_enabled(""));
+    }
+
+    #[test]
+    fn check_fuse_device_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let device = dir.path().join("fuse");
+        assert!(matches!(
+            check_fuse_device(&device),
+            Err(PreflightError::FuseDeviceMissing)
+        ));
+
+        create_file(&device, 0o600);
+        assert!(check_fuse_device(&device).is_ok());
+
+        // Note : permissions do not keep root from opening the device.
+        create_file(&device, 0o000);
+        if unsafe { libc::geteuid() } != 0 {
+            assert!(matches!(
+                check_fuse_device(&device),
+                Err(PreflightError::FuseDeviceNotAccessible(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn find_fusermount_test() {
+        let empty = tempfile::tempdir().unwrap();
+        let bin = tempfile::tempdir().unwrap();
+        assert_eq!(
+            find_fusermount(&join_paths(&[empty.path(), bin.path()])),
+            None
+        );
+
+        create_file(&bin.path().join("fusermount"), 0o644);
+        assert_eq!(
+            find_fusermount(&join_paths(&[empty.path(), bin.path()])),
+            None
+        );
+
+        create_file(&bin.path().join("fusermount"), 0o755);
+        assert_eq!(
+            find_fusermount(&join_paths(&[empty.path(), bin.path()])),
+            Some(bin.path().join("fusermount"))
+        );
+
+        // Note : fusermount3 is preferred within a directory.
+        create_file(&bin.path().join("fusermount3"), 0o755);
+        assert_eq!(
+            find_fusermount(&join_paths(&[bin.path()])),
+            Some(bin.path().join("fusermount3"))
+        );
+    }
+
+    #[test]
+    fn check_fusermount_path_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fusermount3");
+        create_file(&path, 0o755);
+        assert!(check_fusermount_path(&path).is_ok());
+
+        create_file(&path, 0o644);
+        assert!(matches!(
+            check_fusermount_path(&path),
+            Err(PreflightError::InvalidFusermountPath(_, _))
+        ));
+
+        let other = dir.path().join("mount");
+        create_file(&other, 0o755);
+        assert!(matches!(
+            check_fusermount_path(&other),
+            Err(PreflightError::InvalidFusermountPath(_, _))
+        ));
+        assert!(matches!(
+            check_fusermount_path(&dir.path().join("fusermount")),
+            Err(PreflightError::InvalidFusermountPath(_, _))
+        ));
+    }
+}
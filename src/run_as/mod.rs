@@ -0,0 +1,107 @@
+use std::{ffi::CString, fmt::Display, io, os::unix::fs::lchown, path::Path, str::FromStr};
+
+/// The user and group a mount started by root continues as, given as `user:group` or `user`,
+/// by name or id. Without a group the primary group of the user is taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunAs {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+impl FromStr for RunAs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = match s.split_once(':') {
+            Some((user, group)) => (user, Some(group)),
+            None => (s, None),
+        };
+        let (uid, primary_gid) =
+            lookup_user(user).ok_or_else(|| format!("unknown user {:?}", user))?;
+        let gid = match group {
+            Some(group) => {
+                lookup_group(group).ok_or_else(|| format!("unknown group {:?}", group))?
+            }
+            None => primary_gid,
+        };
+        Ok(RunAs { uid, gid })
+    }
+}
+
+impl Display for RunAs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.uid, self.gid)
+    }
+}
+
+/// Returns the uid and primary gid of a user name or id.
+fn lookup_user(user: &str) -> Option<(u32, u32)> {
+    let name = CString::new(user).ok()?;
+    let passwd = unsafe { libc::getpwnam(name.as_ptr()) };
+    if !passwd.is_null() {
+        return Some(unsafe { ((*passwd).pw_uid, (*passwd).pw_gid) });
+    }
+    let uid: u32 = user.parse().ok()?;
+    let passwd = unsafe { libc::getpwuid(uid) };
+    // Note : an id without passwd entry is fine, its primary group is then the same id.
+    match passwd.is_null() {
+        true => Some((uid, uid)),
+        false => Some((uid, unsafe { (*passwd).pw_gid })),
+    }
+}
+
+fn lookup_group(group: &str) -> Option<u32> {
+    let name = CString::new(group).ok()?;
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    match entry.is_null() {
+        true => group.parse().ok(),
+        false => Some(unsafe { (*entry).gr_gid }),
+    }
+}
+
+/// Hands `dir` and everything below it to the user, so the cache stays usable once the
+/// privileges are dropped. Symlinks are changed themselves, not followed.
+pub fn chown_tree(dir: &Path, run_as: &RunAs) -> io::Result<()> {
+    lchown(dir, Some(run_as.uid), Some(run_as.gid))?;
+    if !std::fs::symlink_metadata(dir)?.is_dir() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(dir)? {
+        chown_tree(&entry?.path(), run_as)?;
+    }
+    Ok(())
+}
+
+/// Switches the process, all of its threads, to the user and group for good. The group is set
+/// first, since changing it needs the privileges the user change gives up.
+pub fn drop_privileges(run_as: &RunAs) -> io::Result<()> {
+    unsafe {
+        if libc::setgroups(1, &run_as.gid) != 0
+            || libc::setresgid(run_as.gid, run_as.gid, run_as.gid) != 0
+            || libc::setresuid(run_as.uid, run_as.uid, run_as.uid) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod run_as_test {
+    use super::RunAs;
+
+    #[test]
+    fn parse_test() {
+        assert_eq!("root:root".parse::<RunAs>(), Ok(RunAs { uid: 0, gid: 0 }));
+        assert_eq!("0".parse::<RunAs>(), Ok(RunAs { uid: 0, gid: 0 }));
+        assert_eq!(
+            "123456:654321".parse::<RunAs>(),
+            Ok(RunAs {
+                uid: 123456,
+                gid: 654321
+            })
+        );
+        assert!("no-such-user-here".parse::<RunAs>().is_err());
+        assert!("root:no-such-group-here".parse::<RunAs>().is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use std::{fmt::Display, io, str::FromStr};
+
+use tokio::runtime::{Builder, Runtime};
+
+/// Sizing of the tokio runtime which serves the mount.
+#[derive(Debug, Clone, Default)]
+pub struct RuntimeOptions {
+    /// Threads running async tasks, the number of CPUs when `None`.
+    pub worker_threads: Option<usize>,
+    /// Upper bound of threads for blocking file I/O, tokio's default of 512 when `None`.
+    pub max_blocking_threads: Option<usize>,
+    /// CPUs every thread of the process is restricted to.
+    pub cpu_affinity: Option<CpuList>,
+}
+
+/// A list of CPU indexes in the format of `taskset -c`, e.g. `0-2,6`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuList(Vec<usize>);
+
+impl FromStr for CpuList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid cpu list {:?}, expected e.g. 0-2,6", s);
+        let mut cpus = Vec::new();
+        for part in s.split(',') {
+            let (first, last) = part.split_once('-').unwrap_or((part, part));
+            let first: usize = first.trim().parse().map_err(|_| invalid())?;
+            let last: usize = last.trim().parse().map_err(|_| invalid())?;
+            if last < first || last >= libc::CPU_SETSIZE as usize {
+                return Err(invalid());
+            }
+            cpus.extend(first..=last);
+        }
+        cpus.sort_unstable();
+        cpus.dedup();
+        Ok(CpuList(cpus))
+    }
+}
+
+impl Display for CpuList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cpus: Vec<String> = self.0.iter().map(|cpu| cpu.to_string()).collect();
+        write!(f, "{}", cpus.join(","))
+    }
+}
+
+/// Builds the multi threaded runtime. The CPU affinity is applied to the calling thread first,
+/// so every thread started afterwards, the runtime's as well as the FUSE session's, inherits it.
+pub fn build(options: &RuntimeOptions) -> io::Result<Runtime> {
+    if let Some(cpus) = &options.cpu_affinity {
+        set_cpu_affinity(cpus)?;
+    }
+
+    let mut builder = Builder::new_multi_thread();
+    builder.enable_all();
+    if let Some(worker_threads) = options.worker_threads {
+        builder.worker_threads(worker_threads.max(1));
+    }
+    if let Some(max_blocking_threads) = options.max_blocking_threads {
+        builder.max_blocking_threads(max_blocking_threads.max(1));
+    }
+    builder.build()
+}
+
+fn set_cpu_affinity(cpus: &CpuList) -> io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for cpu in cpus.0.iter() {
+            libc::CPU_SET(*cpu, &mut set);
+        }
+        // Note : pid 0 is the calling thread.
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod cpu_list_test {
+    use super::CpuList;
+
+    #[test]
+    fn from_str_test() {
+        assert_eq!("3".parse(), Ok(CpuList(vec![3])));
+        assert_eq!("0-2,6".parse(), Ok(CpuList(vec![0, 1, 2, 6])));
+        assert_eq!("6,0-1,1".parse(), Ok(CpuList(vec![0, 1, 6])));
+
+        for invalid in ["", "a", "2-1", "0,", "0-99999"] {
+            assert!(invalid.parse::<CpuList>().is_err(), "{}", invalid);
+        }
+    }
+}
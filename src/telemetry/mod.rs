@@ -0,0 +1,86 @@
+use std::{fmt::Display, future::Future};
+
+#[derive(Debug)]
+pub enum TelemetryError {
+    Disabled,
+    Exporter(String),
+}
+
+/// Installs the OTLP trace exporter. Every FUSE operation becomes a root span and the WebDAV
+/// requests it causes become its children.
+#[cfg(feature = "otel")]
+pub fn init(endpoint: &str) -> Result<(), TelemetryError> {
+    use opentelemetry_otlp::WithExportConfig;
+
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
+            opentelemetry_sdk::Resource::new(vec![opentelemetry::KeyValue::new(
+                "service.name",
+                "fusedav-rs",
+            )]),
+        ))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .map_err(|e| TelemetryError::Exporter(e.to_string()))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn init(_endpoint: &str) -> Result<(), TelemetryError> {
+    Err(TelemetryError::Disabled)
+}
+
+/// Flushes pending spans.
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Runs `fut` inside a span which is a child of the span of the calling task, if any.
+#[cfg(feature = "otel")]
+pub async fn in_span<F: Future>(
+    name: &'static str,
+    attributes: Vec<(&'static str, String)>,
+    fut: F,
+) -> F::Output {
+    use opentelemetry::{
+        trace::{FutureExt, TraceContextExt, Tracer},
+        Context, KeyValue,
+    };
+
+    let tracer = opentelemetry::global::tracer("fusedav-rs");
+    let attributes = attributes
+        .into_iter()
+        .map(|(key, value)| KeyValue::new(key, value))
+        .collect::<Vec<KeyValue>>();
+    let span = tracer
+        .span_builder(name)
+        .with_attributes(attributes)
+        .start_with_context(&tracer, &Context::current());
+    fut.with_context(Context::current_with_span(span)).await
+}
+
+#[cfg(not(feature = "otel"))]
+pub async fn in_span<F: Future>(
+    _name: &'static str,
+    _attributes: Vec<(&'static str, String)>,
+    fut: F,
+) -> F::Output {
+    fut.await
+}
+
+impl Display for TelemetryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TelemetryError::Disabled => write!(f, "built without the otel feature"),
+            TelemetryError::Exporter(e) => write!(f, "ExporterError: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TelemetryError {}
@@ -0,0 +1,227 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Notify;
+
+/// Downloads allowed at once before anything is known about the server.
+const INITIAL_LIMIT: f64 = 4.0;
+/// Factor the limit is multiplied by when the server shows it is overloaded.
+const DECREASE_FACTOR: f64 = 0.7;
+/// A response slower than this many times the fastest recent one means requests queue up on
+/// the server or the link.
+const LATENCY_TOLERANCE: u32 = 2;
+/// Added to the tolerated latency, so the jitter of a server answering in a millisecond is not
+/// taken for a queue.
+const LATENCY_SLACK: Duration = Duration::from_millis(50);
+/// The baseline follows latencies above it by this fraction of the difference per response,
+/// so a route which got slower for good stops counting as congested.
+const BASELINE_DECAY: u32 = 64;
+
+/// Bounds the downloads running at once and adjusts the bound to the server, by additive
+/// increase and multiplicative decrease (AIMD).
+///
+/// The limit grows by one download per round of responses answered about as fast as the
+/// fastest recent one, as long as downloads wait for it. It shrinks by `DECREASE_FACTOR` when
+/// responses get slower than `LATENCY_TOLERANCE` times that, or the server fails or throttles
+/// them. A fast NAS on the LAN so ends up with many downloads in parallel, and a throttled
+/// public provider with a few, without tuning either.
+#[derive(Clone)]
+pub(super) struct AdaptiveLimit {
+    state: Arc<Mutex<LimitState>>,
+    released: Arc<Notify>,
+}
+
+struct LimitState {
+    limit: f64,
+    max: usize,
+    inflight: usize,
+    waiting: usize,
+    baseline: Option<Duration>,
+    /// Downloads started before the last decrease report the congestion which caused it, so
+    /// they do not decrease the limit again.
+    decreased_at: Option<Instant>,
+}
+
+impl LimitState {
+    fn current(&self) -> usize {
+        (self.limit as usize).clamp(1, self.max)
+    }
+
+    fn decrease(&mut self, started_at: Instant) {
+        if self.decreased_at.is_some_and(|x| started_at < x) {
+            return;
+        }
+        self.limit = (self.limit * DECREASE_FACTOR).max(1.0);
+        self.decreased_at = Some(Instant::now());
+    }
+}
+
+impl AdaptiveLimit {
+    pub fn new(max: usize) -> AdaptiveLimit {
+        let max = max.max(1);
+        AdaptiveLimit {
+            state: Arc::new(Mutex::new(LimitState {
+                limit: INITIAL_LIMIT.min(max as f64),
+                max,
+                inflight: 0,
+                waiting: 0,
+                baseline: None,
+                decreased_at: None,
+            })),
+            released: Arc::new(Notify::new()),
+        }
+    }
+
+    /// Waits until one more download is allowed. It runs until the permit is dropped.
+    pub async fn acquire(&self) -> AdaptivePermit {
+        let mut waiter = None;
+        loop {
+            // Note : created before the check, so a release between both is not missed.
+            let released = self.released.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.inflight < state.current() {
+                    state.inflight += 1;
+                    drop(state);
+                    drop(waiter);
+                    return AdaptivePermit {
+                        limit: self.clone(),
+                        started_at: Instant::now(),
+                    };
+                }
+                if waiter.is_none() {
+                    state.waiting += 1;
+                    waiter = Some(Waiter(self));
+                }
+            }
+            released.await;
+        }
+    }
+
+    /// Returns the downloads currently allowed at once.
+    pub fn current(&self) -> usize {
+        self.state.lock().unwrap().current()
+    }
+}
+
+/// Counts a download waiting for the limit, until it runs or is given up.
+struct Waiter<'a>(&'a AdaptiveLimit);
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().waiting -= 1;
+    }
+}
+
+/// A running download, which reports how the server answered it.
+pub(super) struct AdaptivePermit {
+    limit: AdaptiveLimit,
+    started_at: Instant,
+}
+
+impl AdaptivePermit {
+    /// Records the time the server took to answer the request.
+    pub fn record_latency(&self, latency: Duration) {
+        let mut state = self.limit.state.lock().unwrap();
+        let Some(baseline) = state.baseline else {
+            state.baseline = Some(latency);
+            return;
+        };
+        state.baseline = Some(if latency < baseline {
+            latency
+        } else {
+            baseline + (latency - baseline) / BASELINE_DECAY
+        });
+
+        if latency > baseline * LATENCY_TOLERANCE + LATENCY_SLACK {
+            state.decrease(self.started_at);
+        } else if state.waiting > 0 && state.current() < state.max {
+            // Note : +1/limit per response is +1 per round of `limit` responses.
+            state.limit = (state.limit + 1.0 / state.limit).min(state.max as f64);
+            drop(state);
+            self.limit.released.notify_waiters();
+        }
+    }
+
+    /// Records that the server failed, throttled or did not answer the request.
+    pub fn record_overload(&self) {
+        self.limit.state.lock().unwrap().decrease(self.started_at);
+    }
+}
+
+impl Drop for AdaptivePermit {
+    fn drop(&mut self) {
+        self.limit.state.lock().unwrap().inflight -= 1;
+        self.limit.released.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod adaptive_limit_test {
+    use std::time::Duration;
+
+    use super::AdaptiveLimit;
+
+    async fn is_blocked(limit: &AdaptiveLimit) -> bool {
+        tokio::time::timeout(Duration::from_millis(20), limit.acquire())
+            .await
+            .is_err()
+    }
+
+    #[tokio::test]
+    async fn acquire_test() {
+        let limit = AdaptiveLimit::new(16);
+        assert_eq!(limit.current(), 4);
+        let mut permits = Vec::new();
+        for _ in 0..4 {
+            permits.push(limit.acquire().await);
+        }
+        assert!(is_blocked(&limit).await);
+        permits.pop();
+        assert!(!is_blocked(&limit).await);
+
+        assert_eq!(AdaptiveLimit::new(2).current(), 2);
+        assert_eq!(AdaptiveLimit::new(0).current(), 1);
+    }
+
+    #[tokio::test]
+    async fn aimd_test() {
+        let limit = AdaptiveLimit::new(6);
+        let fast = Duration::from_millis(100);
+        let permit = limit.acquire().await;
+        permit.record_latency(fast);
+
+        // Note : without downloads waiting, a larger limit would not be used.
+        permit.record_latency(fast);
+        assert_eq!(limit.current(), 4);
+
+        limit.state.lock().unwrap().waiting = 1;
+        for _ in 0..4 {
+            permit.record_latency(fast);
+        }
+        assert_eq!(limit.current(), 4);
+        permit.record_latency(fast);
+        assert_eq!(limit.current(), 5);
+        for _ in 0..20 {
+            permit.record_latency(fast);
+        }
+        assert_eq!(limit.current(), 6);
+
+        // Note : responses of downloads started before the decrease do not decrease it again.
+        permit.record_latency(Duration::from_millis(400));
+        assert_eq!(limit.current(), 4);
+        permit.record_overload();
+        assert_eq!(limit.current(), 4);
+        let later = limit.acquire().await;
+        later.record_overload();
+        assert_eq!(limit.current(), 2);
+
+        drop((permit, later));
+        for _ in 0..5 {
+            limit.acquire().await.record_overload();
+        }
+        assert_eq!(limit.current(), 1);
+    }
+}
@@ -0,0 +1,121 @@
+use std::{fmt::Display, str::FromStr};
+
+use reqwest::header::{HeaderMap, WWW_AUTHENTICATE};
+
+/// When credentials are sent to the server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AuthMode {
+    /// Basic credentials go with every request, which saves the 401 round trip.
+    #[default]
+    Preemptive,
+    /// Requests start anonymous and credentials are only sent once the server asks for them,
+    /// with the scheme it asks for, and only to the realm which asked first.
+    Challenge,
+}
+
+impl FromStr for AuthMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preemptive" => Ok(AuthMode::Preemptive),
+            "challenge" => Ok(AuthMode::Challenge),
+            _ => Err(format!(
+                "invalid auth mode {:?}, expected preemptive or challenge",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for AuthMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuthMode::Preemptive => write!(f, "preemptive"),
+            AuthMode::Challenge => write!(f, "challenge"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum AuthScheme {
+    Basic,
+    Digest,
+}
+
+/// A `WWW-Authenticate` challenge of a scheme we can answer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct AuthChallenge {
+    pub scheme: AuthScheme,
+    pub realm: Option<String>,
+}
+
+impl AuthChallenge {
+    /// Picks the challenge to answer from a 401 response, preferring Digest over Basic.
+    pub fn from_headers(headers: &HeaderMap) -> Option<AuthChallenge> {
+        let mut challenges: Vec<AuthChallenge> = headers
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(AuthChallenge::parse)
+            .collect();
+        challenges.sort_by_key(|challenge| challenge.scheme != AuthScheme::Digest);
+        challenges.into_iter().next()
+    }
+
+    fn parse(value: &str) -> Option<AuthChallenge> {
+        let value = value.trim();
+        let (scheme, params) = value.split_once(' ').unwrap_or((value, ""));
+        let scheme = match scheme.to_ascii_lowercase().as_str() {
+            "basic" => AuthScheme::Basic,
+            "digest" => AuthScheme::Digest,
+            _ => return None,
+        };
+        let realm = params.split(',').find_map(|param| {
+            let (key, value) = param.trim().split_once('=')?;
+            key.trim()
+                .eq_ignore_ascii_case("realm")
+                .then(|| value.trim().trim_matches('"').to_string())
+        });
+        Some(AuthChallenge { scheme, realm })
+    }
+}
+
+#[cfg(test)]
+mod auth_challenge_test {
+    use reqwest::header::{HeaderMap, HeaderValue, WWW_AUTHENTICATE};
+
+    use super::{AuthChallenge, AuthScheme};
+
+    #[test]
+    fn from_headers_test() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static("Basic realm=\"files\", charset=\"UTF-8\""),
+        );
+        assert_eq!(
+            AuthChallenge::from_headers(&headers),
+            Some(AuthChallenge {
+                scheme: AuthScheme::Basic,
+                realm: Some("files".to_string())
+            })
+        );
+
+        headers.append(
+            WWW_AUTHENTICATE,
+            HeaderValue::from_static("Digest realm=\"dav\", qop=\"auth\", nonce=\"abc\""),
+        );
+        assert_eq!(
+            AuthChallenge::from_headers(&headers),
+            Some(AuthChallenge {
+                scheme: AuthScheme::Digest,
+                realm: Some("dav".to_string())
+            })
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.append(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        assert_eq!(AuthChallenge::from_headers(&headers), None);
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Bounds the response bytes held in memory by all downloads together, and by each one.
+///
+/// A download reserves the bytes of every chunk it receives before it polls the response for
+/// the next one, and releases them once the chunk is written, i.e. when the `RangeChunk` holding
+/// them is dropped. While either budget is exhausted the response is not polled, so the server
+/// is slowed down by TCP flow control instead of data piling up here.
+#[derive(Clone)]
+pub(super) struct ByteBudget {
+    semaphore: Arc<Semaphore>,
+    total: usize,
+    per_download: usize,
+}
+
+impl ByteBudget {
+    pub fn new(total: usize, per_download: usize) -> ByteBudget {
+        let total = total.clamp(1, Semaphore::MAX_PERMITS.min(u32::MAX as usize));
+        let per_download = per_download.clamp(1, total);
+        ByteBudget {
+            semaphore: Arc::new(Semaphore::new(total)),
+            total,
+            per_download,
+        }
+    }
+
+    /// Returns the budget of a new download, which also counts against this one.
+    pub fn download(&self) -> DownloadBudget {
+        DownloadBudget {
+            total: self.clone(),
+            semaphore: Arc::new(Semaphore::new(self.per_download)),
+        }
+    }
+
+    /// Returns the bytes currently reserved by downloads.
+    pub fn reserved(&self) -> usize {
+        self.total - self.semaphore.available_permits()
+    }
+}
+
+/// The share of a `ByteBudget` one download holds.
+pub(super) struct DownloadBudget {
+    total: ByteBudget,
+    semaphore: Arc<Semaphore>,
+}
+
+impl DownloadBudget {
+    /// Reserves `len` bytes of a received chunk, waiting until they fit in the budget of the
+    /// download and in the total one. A chunk larger than a budget takes all of it, since more
+    /// could never be acquired.
+    pub async fn reserve(&self, len: usize) -> Reservation {
+        let own = len.min(self.total.per_download) as u32;
+        let total = len.min(self.total.total) as u32;
+        // Note : the semaphores are never closed, so acquiring can not fail. The own share is
+        // taken first, so a download waiting for it does not hold any of the total one.
+        let own = self
+            .semaphore
+            .clone()
+            .acquire_many_owned(own)
+            .await
+            .unwrap();
+        let total = self
+            .total
+            .semaphore
+            .clone()
+            .acquire_many_owned(total)
+            .await
+            .unwrap();
+        Reservation {
+            _own: own,
+            _total: total,
+        }
+    }
+}
+
+/// Bytes reserved in a `DownloadBudget`, released on drop.
+pub(super) struct Reservation {
+    _own: OwnedSemaphorePermit,
+    _total: OwnedSemaphorePermit,
+}
+
+#[cfg(test)]
+mod byte_budget_test {
+    use std::time::Duration;
+
+    use super::ByteBudget;
+
+    #[tokio::test]
+    async fn reserve_test() {
+        let budget = ByteBudget::new(100, 60);
+        let first = budget.download();
+        let second = budget.download();
+        let is_blocked = |reservation| async {
+            tokio::time::timeout(Duration::from_millis(20), reservation)
+                .await
+                .is_err()
+        };
+
+        let small = first.reserve(10).await;
+        assert_eq!(budget.reserved(), 10);
+        let large = second.reserve(80).await;
+        assert_eq!(budget.reserved(), 90);
+        assert!(is_blocked(first.reserve(20)).await);
+
+        // Note : a download can not take more than its own share, even with the total one free.
+        drop(large);
+        let more = first.reserve(50).await;
+        assert_eq!(budget.reserved(), 60);
+        assert!(is_blocked(first.reserve(1)).await);
+        drop((small, more));
+
+        // Note : a chunk larger than the budget takes all of it.
+        let _huge = second.reserve(1000).await;
+        assert_eq!(budget.reserved(), 100);
+    }
+}
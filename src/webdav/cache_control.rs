@@ -0,0 +1,113 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, CACHE_CONTROL, DATE, EXPIRES};
+
+/// Freshness the server announced for a response through `Cache-Control` or `Expires`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    pub max_age: Option<Duration>,
+    /// The response may be kept, but must be confirmed with the server before it is used again.
+    pub no_cache: bool,
+    pub no_store: bool,
+}
+
+impl CacheControl {
+    pub fn from_headers(headers: &HeaderMap) -> CacheControl {
+        let mut cache_control = CacheControl::default();
+        for directive in headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+        {
+            let directive = directive.trim().to_ascii_lowercase();
+            match directive.split_once('=') {
+                Some(("max-age", seconds)) => {
+                    // Note : a malformed max-age makes the response stale, as RFC 9111 asks.
+                    let seconds = seconds.trim_matches('"').parse().unwrap_or(0);
+                    cache_control.max_age = Some(Duration::from_secs(seconds));
+                }
+                None if directive == "no-store" => cache_control.no_store = true,
+                None if directive == "no-cache" => cache_control.no_cache = true,
+                _ => {}
+            }
+        }
+
+        // Note : max-age takes precedence over Expires.
+        if cache_control.max_age.is_none() {
+            cache_control.max_age = CacheControl::max_age_from_expires(headers);
+        }
+        cache_control
+    }
+
+    fn max_age_from_expires(headers: &HeaderMap) -> Option<Duration> {
+        let expires = headers.get(EXPIRES)?.to_str().ok()?;
+        let Ok(expires) = DateTime::parse_from_rfc2822(expires) else {
+            // Note : invalid dates like "0" mean the response is already expired.
+            return Some(Duration::ZERO);
+        };
+        let date = headers
+            .get(DATE)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| DateTime::parse_from_rfc2822(x).ok())
+            .map_or(Utc::now(), |x| x.with_timezone(&Utc));
+        Some(
+            (expires.with_timezone(&Utc) - date)
+                .to_std()
+                .unwrap_or(Duration::ZERO),
+        )
+    }
+}
+
+#[cfg(test)]
+mod cache_control_test {
+    use std::time::Duration;
+
+    use reqwest::header::{HeaderMap, HeaderValue, CACHE_CONTROL, DATE, EXPIRES};
+
+    use super::CacheControl;
+
+    fn headers(values: &[(reqwest::header::HeaderName, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in values {
+            headers.append(name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn from_headers_test() {
+        let cache_control =
+            CacheControl::from_headers(&headers(&[(CACHE_CONTROL, "public, max-age=600")]));
+        assert_eq!(cache_control.max_age, Some(Duration::from_secs(600)));
+        assert!(!cache_control.no_store);
+
+        let cache_control =
+            CacheControl::from_headers(&headers(&[(CACHE_CONTROL, "No-Store, no-cache")]));
+        assert_eq!(cache_control.max_age, None);
+        assert!(cache_control.no_store);
+        assert!(cache_control.no_cache);
+
+        let cache_control =
+            CacheControl::from_headers(&headers(&[(CACHE_CONTROL, "no-cache, max-age=600")]));
+        assert_eq!(cache_control.max_age, Some(Duration::from_secs(600)));
+        assert!(cache_control.no_cache);
+        assert!(!cache_control.no_store);
+
+        let cache_control = CacheControl::from_headers(&headers(&[
+            (DATE, "Wed, 21 Oct 2015 07:28:00 GMT"),
+            (EXPIRES, "Wed, 21 Oct 2015 07:38:00 GMT"),
+        ]));
+        assert_eq!(cache_control.max_age, Some(Duration::from_secs(600)));
+
+        let cache_control =
+            CacheControl::from_headers(&headers(&[(CACHE_CONTROL, "max-age=60"), (EXPIRES, "0")]));
+        assert_eq!(cache_control.max_age, Some(Duration::from_secs(60)));
+
+        assert_eq!(
+            CacheControl::from_headers(&HeaderMap::new()),
+            CacheControl::default()
+        );
+    }
+}
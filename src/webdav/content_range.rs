@@ -0,0 +1,67 @@
+/// A parsed `Content-Range: bytes <begin>-<end>/<total>` header. `end` is inclusive and `total`
+/// is `None` when the server sends `*`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct ContentRange {
+    pub begin: u64,
+    pub end: u64,
+    pub total: Option<u64>,
+}
+
+impl ContentRange {
+    pub fn parse(value: &str) -> Option<ContentRange> {
+        let (unit, range) = value.trim().split_once(' ')?;
+        if !unit.eq_ignore_ascii_case("bytes") {
+            return None;
+        }
+        let (range, total) = range.trim().split_once('/')?;
+        let (begin, end) = range.split_once('-')?;
+        let begin = begin.parse().ok()?;
+        let end = end.parse().ok()?;
+        let total = match total {
+            "*" => None,
+            total => Some(total.parse().ok()?),
+        };
+
+        if end < begin || total.is_some_and(|total| end >= total) {
+            return None;
+        }
+        Some(ContentRange { begin, end, total })
+    }
+}
+
+#[cfg(test)]
+mod content_range_test {
+    use super::ContentRange;
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(
+            ContentRange::parse("bytes 100-199/1000"),
+            Some(ContentRange {
+                begin: 100,
+                end: 199,
+                total: Some(1000)
+            })
+        );
+        assert_eq!(
+            ContentRange::parse("bytes 0-9/*"),
+            Some(ContentRange {
+                begin: 0,
+                end: 9,
+                total: None
+            })
+        );
+
+        for malformed in [
+            "",
+            "bytes",
+            "bytes */1000",
+            "bytes 10-5/1000",
+            "bytes 0-1000/1000",
+            "bytes a-b/c",
+            "items 0-9/10",
+        ] {
+            assert_eq!(ContentRange::parse(malformed), None, "{}", malformed);
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use quick_xml::{events::Event, Reader};
+
+/// Collects the `displayname` property of every response in a multistatus body, keyed by href.
+/// The listing structs of `reqwest_dav` do not carry it, so the body is scanned on its own.
+pub(super) fn parse_display_names(body: &str) -> HashMap<String, String> {
+    let mut reader = Reader::from_str(body);
+    reader.trim_text(true);
+
+    let mut display_names = HashMap::new();
+    let mut element = Vec::new();
+    let mut href = None;
+    let mut display_name = None;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                element = e.local_name().as_ref().to_vec();
+                if element == b"response" {
+                    href = None;
+                    display_name = None;
+                }
+            }
+            Ok(Event::Text(text)) => {
+                let Ok(text) = text.unescape() else {
+                    continue;
+                };
+                match element.as_slice() {
+                    b"href" => href = Some(text.to_string()),
+                    b"displayname" => display_name = Some(text.to_string()),
+                    _ => {}
+                }
+            }
+            Ok(Event::End(e)) => {
+                if e.local_name().as_ref() == b"response" {
+                    if let (Some(href), Some(display_name)) = (href.take(), display_name.take()) {
+                        display_names.insert(href, display_name);
+                    }
+                }
+                element.clear();
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+    display_names
+}
+
+#[cfg(test)]
+mod display_name_test {
+    use super::parse_display_names;
+
+    #[test]
+    fn parse_display_names_test() {
+        let body = r#"<?xml version="1.0" encoding="utf-8"?>
+            <d:multistatus xmlns:d="DAV:">
+              <d:response>
+                <d:href>/sites/team/Shared%20Documents/</d:href>
+                <d:propstat>
+                  <d:prop><d:displayname>Documents</d:displayname></d:prop>
+                  <d:status>HTTP/1.1 200 OK</d:status>
+                </d:propstat>
+              </d:response>
+              <d:response>
+                <d:href>/sites/team/Shared%20Documents/f0a1.docx</d:href>
+                <d:propstat>
+                  <d:prop><d:displayname>Q3 Plan &amp; Budget.docx</d:displayname></d:prop>
+                  <d:status>HTTP/1.1 200 OK</d:status>
+                </d:propstat>
+              </d:response>
+              <d:response>
+                <d:href>/sites/team/Shared%20Documents/notes.txt</d:href>
+                <d:propstat>
+                  <d:prop><d:displayname/></d:prop>
+                  <d:status>HTTP/1.1 404 Not Found</d:status>
+                </d:propstat>
+              </d:response>
+            </d:multistatus>"#;
+
+        let display_names = parse_display_names(body);
+        assert_eq!(display_names.len(), 2);
+        assert_eq!(
+            display_names["/sites/team/Shared%20Documents/"],
+            "Documents"
+        );
+        assert_eq!(
+            display_names["/sites/team/Shared%20Documents/f0a1.docx"],
+            "Q3 Plan & Budget.docx"
+        );
+    }
+}
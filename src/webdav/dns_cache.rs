@@ -0,0 +1,134 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+
+/// Keeps the addresses of resolved hosts for a while, so a slow resolver does not delay every
+/// new connection. When resolving fails the last addresses are used even if they expired.
+pub(super) struct DnsCache {
+    ttl: Duration,
+    entries: Arc<Mutex<HashMap<String, (Instant, Vec<SocketAddr>)>>>,
+}
+
+impl DnsCache {
+    pub fn new(ttl: Duration) -> DnsCache {
+        DnsCache {
+            ttl,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+}
+
+impl Resolve for DnsCache {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        let ttl = self.ttl;
+        let entries = self.entries.clone();
+        Box::pin(async move {
+            let cached = entries.lock().unwrap().get(&host).cloned();
+            if let Some((resolved_at, addrs)) = &cached {
+                if resolved_at.elapsed() < ttl {
+                    return Ok(Box::new(addrs.clone().into_iter()) as Addrs);
+                }
+            }
+            // Note : the port is replaced by the one of the URL.
+            let resolved = tokio::net::lookup_host((host.as_str(), 0))
+                .await
+                .map(|addrs| addrs.collect::<Vec<_>>());
+            match resolved {
+                Ok(addrs) => {
+                    entries
+                        .lock()
+                        .unwrap()
+                        .insert(host, (Instant::now(), addrs.clone()));
+                    Ok(Box::new(addrs.into_iter()) as Addrs)
+                }
+                Err(err) => match cached {
+                    Some((_, addrs)) => {
+                        eprintln!(
+                            "Can not resolve {}, using its last addresses: {}",
+                            host, err
+                        );
+                        Ok(Box::new(addrs.into_iter()) as Addrs)
+                    }
+                    None => Err(err.into()),
+                },
+            }
+        })
+    }
+}
+
+/// A host connected to at a fixed address instead of the resolved ones, given as `host:ip`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostOverride {
+    pub host: String,
+    pub ip: IpAddr,
+}
+
+impl FromStr for HostOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (host, ip) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected host:ip, got {}", s))?;
+        if host.is_empty() {
+            return Err(format!("missing host in {}", s));
+        }
+        // Note : IPv6 addresses may be given in brackets as in URLs.
+        let ip = ip
+            .strip_prefix('[')
+            .and_then(|x| x.strip_suffix(']'))
+            .unwrap_or(ip);
+        let ip = ip
+            .parse()
+            .map_err(|_| format!("invalid IP address {}", ip))?;
+        Ok(HostOverride {
+            host: host.to_ascii_lowercase(),
+            ip,
+        })
+    }
+}
+
+#[cfg(test)]
+mod dns_cache_test {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    use super::HostOverride;
+
+    #[test]
+    fn host_override_test() {
+        assert_eq!(
+            "dav.example.com:192.0.2.1".parse(),
+            Ok(HostOverride {
+                host: "dav.example.com".to_string(),
+                ip: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)),
+            })
+        );
+        assert_eq!(
+            "Dav.Example.com:[::1]".parse(),
+            Ok(HostOverride {
+                host: "dav.example.com".to_string(),
+                ip: IpAddr::V6(Ipv6Addr::LOCALHOST),
+            })
+        );
+        assert_eq!(
+            "dav.example.com:2001:db8::1"
+                .parse::<HostOverride>()
+                .unwrap()
+                .ip,
+            "2001:db8::1".parse::<IpAddr>().unwrap()
+        );
+        assert!("dav.example.com".parse::<HostOverride>().is_err());
+        assert!(":192.0.2.1".parse::<HostOverride>().is_err());
+        assert!("dav.example.com:example.org"
+            .parse::<HostOverride>()
+            .is_err());
+    }
+}
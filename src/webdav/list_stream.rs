@@ -0,0 +1,50 @@
+use std::collections::VecDeque;
+
+use super::{Error, WebDAVClient, WebDAVList};
+
+/// What `WebDAVClient::list_stream` lists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ListOptions {
+    /// Lists the sub collections as well, one Depth-1 PROPFIND each, rather than asking the
+    /// server for the whole tree at once, which many servers refuse.
+    pub recursive: bool,
+}
+
+/// The entries below a collection, listed one collection at a time, so a large tree is never
+/// held in memory at once. The collection itself is not among them.
+pub struct ListStream {
+    client: WebDAVClient,
+    options: ListOptions,
+    /// Encoded paths of the collections still to list.
+    pending: VecDeque<String>,
+    listed: VecDeque<WebDAVList>,
+}
+
+impl ListStream {
+    pub(super) fn new(client: WebDAVClient, path: &str, options: ListOptions) -> ListStream {
+        ListStream {
+            client,
+            options,
+            pending: VecDeque::from([path.to_string()]),
+            listed: VecDeque::new(),
+        }
+    }
+
+    /// Returns the next entry, `None` once every collection is listed. A collection which can
+    /// not be listed is an error, after which the stream goes on with the others.
+    pub async fn next(&mut self) -> Result<Option<WebDAVList>, Error> {
+        loop {
+            if let Some(item) = self.listed.pop_front() {
+                if let (true, WebDAVList::Folder(dir)) = (self.options.recursive, &item) {
+                    self.pending.push_back(dir.encoded_path.clone());
+                }
+                return Ok(Some(item));
+            }
+            let Some(path) = self.pending.pop_front() else {
+                return Ok(None);
+            };
+            let (list, _) = self.client.list_children(&path).await?;
+            self.listed = list.into();
+        }
+    }
+}
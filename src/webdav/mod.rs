@@ -1,13 +1,63 @@
+mod adaptive_limit;
+mod auth;
+mod byte_budget;
+mod cache_control;
+mod content_range;
+mod display_name;
+mod dns_cache;
+mod list_stream;
+mod oc_properties;
+mod privileges;
+mod quirks;
+mod range_sink;
+mod range_stream;
+mod secret;
+mod server_clock;
+mod url_path;
+
 use std::{
+    ffi::OsString,
     fmt::Display,
-    string::FromUtf8Error,
+    future::Future,
+    net::SocketAddr,
+    ops::Deref,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
 };
 
 use chrono::{DateTime, Utc};
-use reqwest_dav::list_cmd::ListEntity;
-use urlencoding::decode;
+use reqwest::{
+    header::{ACCEPT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, LOCATION, RANGE, RETRY_AFTER},
+    Method, Response, StatusCode, Url,
+};
+use reqwest_dav::list_cmd::{ListEntity, ListMultiStatus};
+use zeroize::Zeroize;
 
-use crate::blockfile::BlockFile;
+use crate::telemetry;
+
+use adaptive_limit::AdaptiveLimit;
+use auth::{AuthChallenge, AuthScheme};
+use byte_budget::ByteBudget;
+use display_name::parse_display_names;
+use dns_cache::DnsCache;
+use oc_properties::{parse_property, CHECKSUMS_PROPFIND, FILE_ID_PROPFIND};
+use privileges::{parse_read_only, PRIVILEGES_PROPFIND};
+use quirks::ServerQuirks;
+use url_path::{
+    collection_path, decode_name, decode_path, href_path, parse_root_url, path_below_root,
+};
+
+pub use auth::AuthMode;
+pub use cache_control::CacheControl;
+pub use dns_cache::HostOverride;
+pub use hyper::body::Bytes;
+pub use list_stream::{ListOptions, ListStream};
+pub use quirks::{Provider, Quirks, QuirksMode};
+pub use range_sink::{FileSink, MemorySink, RangeSink};
+pub use range_stream::{RangeChunk, RangeOptions, RangeStream};
+pub use secret::Secret;
+pub use server_clock::ServerClock;
+pub use url_path::encode_path;
 
 #[derive(Debug, Clone)]
 pub enum WebDAVList {
@@ -19,142 +69,1048 @@ pub enum WebDAVList {
 #[derive(Debug, Clone)]
 pub struct WebDAVFile {
     pub href: String,
+    /// Decoded path below the server root, for display and lookup.
     pub path: String,
+    /// `path` as the server encoded it, for requests.
+    pub encoded_path: String,
+    /// The last segment of the href with the bytes the server sent, which need not be UTF-8
+    /// like `path`.
+    pub name: OsString,
+    /// The `displayname` property, which some servers set to a name other than the href's.
+    pub display_name: Option<String>,
     pub last_modified: DateTime<Utc>,
     pub content_length: u64,
     pub content_type: String,
+    pub etag: Option<String>,
+    /// The server does not let the user write the file, see `WebDAVClient::set_request_privileges`.
+    pub read_only: bool,
 }
 
 #[derive(Debug, Clone)]
 pub struct WebDAVDirectory {
     pub href: String,
+    /// Decoded path below the server root, for display and lookup.
     pub path: String,
+    /// `path` as the server encoded it, for requests.
+    pub encoded_path: String,
+    /// The last segment of the href with the bytes the server sent, which need not be UTF-8
+    /// like `path`.
+    pub name: OsString,
+    /// The `displayname` property, which some servers set to a name other than the href's.
+    pub display_name: Option<String>,
     pub last_modified: DateTime<Utc>,
     pub quota_used_bytes: Option<u64>,
     pub quota_available_bytes: Option<u64>,
+    /// Entity tag of the collection, which servers like Nextcloud change whenever an entry below
+    /// it changes.
+    pub etag: Option<String>,
+    /// The server does not let the user create entries in the folder, see
+    /// `WebDAVClient::set_request_privileges`.
+    pub read_only: bool,
 }
 
 #[derive(Debug)]
 pub enum Error {
     ReqwestDAV(reqwest_dav::Error),
     IO(std::io::Error),
-    EncodingError(FromUtf8Error),
+    UriTooLong(String),
+    InvalidResponse(String),
+    InvalidRange(String),
+    NotFound(String),
+    InvalidUrl(String),
+    /// 423, the resource is locked by another client.
+    Locked(String),
+    /// 507, the server has no space left.
+    InsufficientStorage(String),
+    /// Any other unexpected status, with the method and path of the request.
+    Status(String, StatusCode),
+}
+
+/// A GET whose response does not match the requested range is retried this many times.
+const DOWNLOAD_ATTEMPTS: u32 = 3;
+const DOWNLOAD_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// A request answered with 423 is sent again after this delay, unless the server sends a
+/// Retry-After, until the locked wait of the client is over.
+const LOCKED_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// A request throttled by a server known to throttle is sent again this many times, after the
+/// Retry-After of the server or else a delay doubling from `THROTTLE_RETRY_DELAY`.
+const THROTTLE_ATTEMPTS: u32 = 5;
+const THROTTLE_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_THROTTLE_DELAY: Duration = Duration::from_secs(60);
+
+/// Permanent redirects followed for a single PROPFIND.
+const MAX_REDIRECTS: usize = 5;
+
+/// Most servers and proxies reject request lines longer than 8 KiB.
+pub const DEFAULT_MAX_URL_LENGTH: usize = 8 * 1024;
+
+/// Response bytes all downloads together may hold in memory.
+pub const DEFAULT_MAX_INFLIGHT_BYTES: usize = 64 * 1024 * 1024;
+/// Response bytes a single download may hold in memory.
+pub const DEFAULT_MAX_DOWNLOAD_BUFFER: usize = 1024 * 1024;
+/// Downloads the adaptive limit may allow at once.
+pub const DEFAULT_MAX_DOWNLOAD_CONCURRENCY: usize = 16;
+
+/// How long an unused connection stays in the pool, the default of reqwest.
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// How long resolved addresses of the server are reused by default.
+pub const DEFAULT_DNS_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// How connections to the server are opened and kept open between requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConnectionOptions {
+    /// Interval of TCP keepalive probes on open connections, `None` to send none.
+    pub tcp_keepalive: Option<Duration>,
+    /// How long an unused connection is kept for the next request, `None` to keep it until the
+    /// server closes it.
+    pub pool_idle_timeout: Option<Duration>,
+    /// Speak only HTTP/1.1, for servers whose HTTP/2 is broken. Otherwise HTTP/2 is negotiated
+    /// over TLS where the server offers it, and concurrent requests share one connection.
+    pub http1_only: bool,
+    /// How long resolved addresses of the server are reused, `None` to resolve the host for
+    /// every new connection.
+    pub dns_cache_ttl: Option<Duration>,
+    /// Hosts connected to at a fixed address instead of the resolved ones.
+    pub resolve: Vec<HostOverride>,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        ConnectionOptions {
+            tcp_keepalive: None,
+            pool_idle_timeout: Some(DEFAULT_POOL_IDLE_TIMEOUT),
+            http1_only: false,
+            dns_cache_ttl: Some(DEFAULT_DNS_CACHE_TTL),
+            resolve: Vec::new(),
+        }
+    }
+}
+
+/// What `WebDAVClient::stat` asks for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StatOptions {
+    /// The path is known to be a collection, so it is requested with the trailing slash, which
+    /// saves the redirect servers like Apache mod_dav answer without it.
+    pub collection: bool,
 }
 
+/// Owns the only copy of the credentials handed to `reqwest_dav` and scrubs it on drop.
+struct DAVClient(reqwest_dav::Client);
+
+impl Deref for DAVClient {
+    type Target = reqwest_dav::Client;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl Drop for DAVClient {
+    fn drop(&mut self) {
+        match &mut self.0.auth {
+            reqwest_dav::Auth::Basic(_, password) | reqwest_dav::Auth::Digest(_, password) => {
+                password.zeroize()
+            }
+            reqwest_dav::Auth::Anonymous => {}
+        }
+    }
+}
+
+/// Credentials and the challenge they were last sent for.
+struct AuthState {
+    mode: AuthMode,
+    user: String,
+    password: Secret,
+    answered: Mutex<Option<AuthChallenge>>,
+}
+
+/// A client of one WebDAV server, used by the mount and usable on its own.
+///
+/// - `stat` fetches the properties of one file or collection.
+/// - `list` and `list_with_cache_control` list a collection, `list_stream` lists a tree
+///   entry by entry.
+/// - `get_range_stream` streams a range of a file, `download` writes one into a `RangeSink`,
+///   e.g. a cache file, and resumes it when the response breaks off.
+///
+/// Paths taken by the client are percent-encoded below the server root, see
+/// `WebDAVFile::encoded_path` and `encode_path`. Clones share the connection pool, the download
+/// budget and the concurrency limit.
 #[derive(Clone)]
 pub struct WebDAVClient {
-    client: reqwest_dav::Client,
+    // Note : replaced when the server asks for another auth scheme, see `authenticate`.
+    client: Arc<RwLock<Arc<DAVClient>>>,
+    /// The URL the client was built with, which hrefs and redirects are resolved against.
+    root: Url,
+    auth: Arc<AuthState>,
+    max_url_length: usize,
+    download_budget: ByteBudget,
+    download_limit: AdaptiveLimit,
+    locked_wait: Duration,
+    quirks: Arc<ServerQuirks>,
+    request_privileges: bool,
+    /// When the server last answered a request without a server error or an auth failure.
+    last_contact: Arc<Mutex<Option<SystemTime>>>,
+    clock: ServerClock,
+    // Note : shared by the clients rebuilt for other auth schemes, so they keep its pool.
+    agent: reqwest::Client,
 }
 
 impl WebDAVClient {
-    pub fn new(url: String, user: String, password: String) -> Result<WebDAVClient, Error> {
-        let mut url = url;
-        if url.ends_with("/") {
-            url.remove(url.len() - 1);
+    pub fn new(url: String, user: String, password: Secret) -> Result<WebDAVClient, Error> {
+        WebDAVClient::with_auth_mode(url, user, password, AuthMode::default())
+    }
+
+    pub fn with_auth_mode(
+        url: String,
+        user: String,
+        password: Secret,
+        mode: AuthMode,
+    ) -> Result<WebDAVClient, Error> {
+        let root = parse_root_url(&url).map_err(|e| Error::InvalidUrl(e))?;
+        // Note : paths start with a slash, so they are appended to the root without its own.
+        let host = root.as_str().trim_end_matches('/').to_string();
+
+        let auth = match mode {
+            AuthMode::Preemptive => {
+                reqwest_dav::Auth::Basic(user.clone(), password.expose().to_string())
+            }
+            AuthMode::Challenge => reqwest_dav::Auth::Anonymous,
+        };
+        let agent = WebDAVClient::build_agent(&ConnectionOptions::default())?;
+        let client = WebDAVClient::build_client(host, auth, agent.clone())?;
+        Ok(WebDAVClient {
+            client: Arc::new(RwLock::new(Arc::new(client))),
+            auth: Arc::new(AuthState {
+                mode,
+                user,
+                password,
+                answered: Mutex::new(None),
+            }),
+            max_url_length: DEFAULT_MAX_URL_LENGTH,
+            download_budget: ByteBudget::new(
+                DEFAULT_MAX_INFLIGHT_BYTES,
+                DEFAULT_MAX_DOWNLOAD_BUFFER,
+            ),
+            download_limit: AdaptiveLimit::new(DEFAULT_MAX_DOWNLOAD_CONCURRENCY),
+            locked_wait: Duration::ZERO,
+            quirks: Arc::new(ServerQuirks::new(QuirksMode::Auto, &root)),
+            request_privileges: false,
+            last_contact: Arc::new(Mutex::new(None)),
+            clock: ServerClock::default(),
+            agent,
+            root,
+        })
+    }
+
+    /// Returns a client for another collection of the same server, e.g. the versions collection
+    /// of the user, with the same credentials and limits. Downloads of both count against the
+    /// same byte budget and concurrency limit.
+    pub fn with_root(&self, url: String) -> Result<WebDAVClient, Error> {
+        let mut client = WebDAVClient::with_auth_mode(
+            url,
+            self.auth.user.clone(),
+            Secret::new(self.auth.password.expose().to_string()),
+            self.auth.mode,
+        )?;
+        client.max_url_length = self.max_url_length;
+        client.download_budget = self.download_budget.clone();
+        client.download_limit = self.download_limit.clone();
+        client.locked_wait = self.locked_wait;
+        client.quirks = self.quirks.clone();
+        client.request_privileges = self.request_privileges;
+        client.last_contact = self.last_contact.clone();
+        client.clock = self.clock.clone();
+        client.set_agent(self.agent.clone())?;
+        Ok(client)
+    }
+
+    fn build_agent(connection: &ConnectionOptions) -> Result<reqwest::Client, Error> {
+        // Note : reqwest would follow a 301 with a GET, so a moved collection would be listed
+        // from the body of a GET. Redirects are followed by `propfind` instead.
+        let mut agent = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .tcp_keepalive(connection.tcp_keepalive)
+            .pool_idle_timeout(connection.pool_idle_timeout);
+        if connection.http1_only {
+            agent = agent.http1_only();
+        }
+        if let Some(ttl) = connection.dns_cache_ttl {
+            agent = agent.dns_resolver(Arc::new(DnsCache::new(ttl)));
+        }
+        for host_override in &connection.resolve {
+            agent = agent.resolve(&host_override.host, SocketAddr::new(host_override.ip, 0));
         }
+        agent
+            .build()
+            .map_err(|e| Error::ReqwestDAV(reqwest_dav::Error::Reqwest(e)))
+    }
 
+    fn build_client(
+        host: String,
+        auth: reqwest_dav::Auth,
+        agent: reqwest::Client,
+    ) -> Result<DAVClient, Error> {
         let client = reqwest_dav::ClientBuilder::new()
-            .set_auth(reqwest_dav::Auth::Basic(user, password))
-            .set_host(url)
+            .set_agent(agent)
+            .set_auth(auth)
+            .set_host(host)
             .build()
             .map_err(|e| Error::ReqwestDAV(e))?;
-        Ok(WebDAVClient { client })
+        Ok(DAVClient(client))
+    }
+
+    fn client(&self) -> Arc<DAVClient> {
+        self.client.read().unwrap().clone()
+    }
+
+    /// The server URL and user this client acts as, which tells cached data of different
+    /// remotes and accounts apart.
+    pub fn identity(&self) -> String {
+        format!("{} {}", self.client().host, self.auth.user)
+    }
+
+    pub fn set_max_url_length(&mut self, max_url_length: usize) {
+        self.max_url_length = max_url_length;
+    }
+
+    /// Sets how connections are opened and kept open between requests. A connection reused from the pool
+    /// skips the TCP and TLS handshakes, which a mount idle for minutes otherwise pays again on
+    /// its first access. Must be called before the first request.
+    pub fn set_connection_options(&mut self, connection: ConnectionOptions) -> Result<(), Error> {
+        self.set_agent(WebDAVClient::build_agent(&connection)?)
+    }
+
+    fn set_agent(&mut self, agent: reqwest::Client) -> Result<(), Error> {
+        let auth = match self.auth.mode {
+            AuthMode::Preemptive => reqwest_dav::Auth::Basic(
+                self.auth.user.clone(),
+                self.auth.password.expose().to_string(),
+            ),
+            AuthMode::Challenge => reqwest_dav::Auth::Anonymous,
+        };
+        let client = WebDAVClient::build_client(self.client().host.clone(), auth, agent.clone())?;
+        *self.client.write().unwrap() = Arc::new(client);
+        self.agent = agent;
+        Ok(())
+    }
+
+    /// How long requests answered with 423 Locked are retried before failing with
+    /// `Error::Locked`; by default they fail at once.
+    pub fn set_locked_wait(&mut self, locked_wait: Duration) {
+        self.locked_wait = locked_wait;
+    }
+
+    /// Sets how the quirks of the server are chosen; by default they are detected.
+    pub fn set_quirks_mode(&mut self, mode: QuirksMode) {
+        self.quirks = Arc::new(ServerQuirks::new(mode, &self.root));
+    }
+
+    /// Makes listings ask for the privileges of the user on every entry too, which `allprop`
+    /// leaves out, so `read_only` reflects them. Without it only active locks mark entries read
+    /// only.
+    pub fn set_request_privileges(&mut self, request_privileges: bool) {
+        self.request_privileges = request_privileges;
+    }
+
+    /// Limits the response bytes held in memory by all downloads of this client and its clones
+    /// together, and by a single download.
+    pub fn set_download_budget(&mut self, max_inflight_bytes: usize, max_download_buffer: usize) {
+        self.download_budget = ByteBudget::new(max_inflight_bytes, max_download_buffer);
+    }
+
+    /// Caps the downloads of this client and its clones running at once. Below the cap, the
+    /// limit adapts to how fast the server answers, see `AdaptiveLimit`.
+    pub fn set_max_download_concurrency(&mut self, max_concurrency: usize) {
+        self.download_limit = AdaptiveLimit::new(max_concurrency);
+    }
+
+    /// Returns the downloads currently allowed to run at once.
+    pub fn download_concurrency(&self) -> usize {
+        self.download_limit.current()
+    }
+
+    /// Returns the response bytes currently held in memory by downloads, counted by the chunks
+    /// they received and did not write yet.
+    pub fn inflight_bytes(&self) -> usize {
+        self.download_budget.reserved()
+    }
+
+    /// Returns when the server last answered a request of this client or its clones without a
+    /// server error or an auth failure, `None` before it did.
+    pub fn last_contact(&self) -> Option<SystemTime> {
+        *self.last_contact.lock().unwrap()
+    }
+
+    /// The clock of the server, as measured from its responses.
+    pub fn clock(&self) -> &ServerClock {
+        &self.clock
     }
 
     pub async fn list(&self, path: &str) -> Result<Vec<WebDAVList>, Error> {
-        let result = self
-            .client
-            .list(path, reqwest_dav::Depth::Number(1))
+        self.list_with_cache_control(path)
             .await
-            .map_err(|e| Error::ReqwestDAV(e))?;
+            .map(|(list, _)| list)
+    }
 
-        result
+    /// Lists `path` along with the freshness the server announced for the listing.
+    pub async fn list_with_cache_control(
+        &self,
+        path: &str,
+    ) -> Result<(Vec<WebDAVList>, CacheControl), Error> {
+        self.validate_url_length(path)?;
+        let path = collection_path(path);
+        telemetry::in_span(
+            "webdav.PROPFIND",
+            vec![("path", path.clone()), ("depth", "1".to_string())],
+            self.propfind(&path, reqwest_dav::Depth::Number(1)),
+        )
+        .await
+    }
+
+    /// Lists the entries of the collection `path` without the collection itself, which comes
+    /// first in a listing, see `propfind`, along with the freshness the server announced.
+    pub async fn list_children(
+        &self,
+        path: &str,
+    ) -> Result<(Vec<WebDAVList>, CacheControl), Error> {
+        let (list, cache_control) = self.list_with_cache_control(path).await?;
+        let (_, children) = split_collection(list);
+        Ok((children, cache_control))
+    }
+
+    /// Lists the entries below the collection `path`, and below its sub collections with
+    /// `ListOptions::recursive`. Nothing is requested until the first entry is read.
+    pub fn list_stream(&self, path: &str, options: ListOptions) -> ListStream {
+        ListStream::new(self.clone(), path, options)
+    }
+
+    /// Fetches the current properties of `path` alone with a Depth-0 PROPFIND.
+    pub async fn stat(
+        &self,
+        path: &str,
+        options: StatOptions,
+    ) -> Result<(WebDAVList, CacheControl), Error> {
+        self.validate_url_length(path)?;
+        let path = if options.collection {
+            collection_path(path)
+        } else {
+            path.to_string()
+        };
+        let path = path.as_str();
+        // Note : the requested item comes first in a Depth-1 listing as well, see `propfind`.
+        let depth = if self.quirks.get().no_depth_zero {
+            1
+        } else {
+            0
+        };
+        let (list, cache_control) = telemetry::in_span(
+            "webdav.PROPFIND",
+            vec![("path", path.to_string()), ("depth", depth.to_string())],
+            self.propfind(path, reqwest_dav::Depth::Number(depth)),
+        )
+        .await?;
+        let item = list
             .into_iter()
-            .map(|x| WebDAVList::try_from(&self.client.host, x))
-            .collect()
+            .next()
+            .ok_or(Error::InvalidResponse(format!(
+                "PROPFIND {} returned no entry",
+                path
+            )))?;
+        Ok((item, cache_control))
     }
 
-    pub async fn download(
+    /// Fetches the `oc:fileid` property of `path`, which Nextcloud and ownCloud key the versions
+    /// of a file by.
+    pub async fn file_id(&self, path: &str) -> Result<String, Error> {
+        self.oc_property(path, FILE_ID_PROPFIND, b"fileid")
+            .await?
+            .ok_or(Error::InvalidResponse(format!(
+                "PROPFIND {} returned no fileid",
+                path
+            )))
+    }
+
+    /// Fetches the checksums a Nextcloud client reported when it uploaded `path`, e.g.
+    /// `SHA1:2fd4e1c6... MD5:9e107d9d...`, or `None` when there are none.
+    pub async fn checksums(&self, path: &str) -> Result<Option<String>, Error> {
+        self.oc_property(path, CHECKSUMS_PROPFIND, b"checksum")
+            .await
+    }
+
+    /// Sends a Depth-0 PROPFIND with the body `propfind` and returns the text of the property
+    /// named `local_name` in the response.
+    async fn oc_property(
         &self,
         path: &str,
-        file: &mut BlockFile,
-        offset: u64,
-        size: u64,
-    ) -> Result<(), Error> {
-        let mut response = self
-            .client
-            .get_range(path, offset, size)
+        propfind: &'static str,
+        local_name: &[u8],
+    ) -> Result<Option<String>, Error> {
+        self.validate_url_length(path)?;
+        let _connection = self.quirks.connection().await;
+        let attributes = vec![("path", path.to_string()), ("depth", "0".to_string())];
+        let response = telemetry::in_span("webdav.PROPFIND", attributes, async {
+            self.send("PROPFIND", path, |client| async move {
+                client
+                    .start_request(Method::from_bytes(b"PROPFIND").unwrap(), path)
+                    .await?
+                    .header("Depth", "0")
+                    .header(CONTENT_TYPE, "application/xml")
+                    .body(propfind)
+                    .send()
+                    .await
+                    .map_err(reqwest_dav::Error::Reqwest)
+            })
             .await
-            .map_err(|e| Error::ReqwestDAV(e))?;
+        })
+        .await?;
+        match response.status() {
+            StatusCode::MULTI_STATUS => {}
+            StatusCode::NOT_FOUND => return Err(Error::NotFound(path.to_string())),
+            status => return Err(Error::from_status("PROPFIND", path, status)),
+        }
+
+        let body = response
+            .text()
+            .await
+            .map_err(|e| Error::ReqwestDAV(reqwest_dav::Error::Reqwest(e)))?;
+        Ok(parse_property(&body, local_name))
+    }
+
+    // Note : `reqwest_dav::Client::list` drops the response headers, so the multistatus body is
+    // parsed here the same way it does.
+    async fn propfind(
+        &self,
+        path: &str,
+        depth: reqwest_dav::Depth,
+    ) -> Result<(Vec<WebDAVList>, CacheControl), Error> {
+        let _connection = self.quirks.connection().await;
+        let mut path = path.to_string();
+        let mut redirects = 0;
+        let request_privileges = self.request_privileges;
+        let depth_header = match depth {
+            reqwest_dav::Depth::Number(depth) => depth.to_string(),
+            reqwest_dav::Depth::Infinity => "infinity".to_string(),
+        };
+        let response = loop {
+            let request_path = path.as_str();
+            let depth_header = depth_header.as_str();
+            let response = self
+                .send("PROPFIND", request_path, |client| async move {
+                    if !request_privileges {
+                        return client.list_rsp(request_path, depth).await;
+                    }
+                    client
+                        .start_request(Method::from_bytes(b"PROPFIND").unwrap(), request_path)
+                        .await?
+                        .header("Depth", depth_header)
+                        .header(CONTENT_TYPE, "application/xml")
+                        .body(PRIVILEGES_PROPFIND)
+                        .send()
+                        .await
+                        .map_err(reqwest_dav::Error::Reqwest)
+                })
+                .await?;
+            match response.status() {
+                StatusCode::MOVED_PERMANENTLY | StatusCode::PERMANENT_REDIRECT
+                    if redirects < MAX_REDIRECTS =>
+                {
+                    path = self.redirect_path(&path, &response)?;
+                    redirects += 1;
+                }
+                _ => break response,
+            }
+        };
+        let path = path.as_str();
+        match response.status() {
+            StatusCode::MULTI_STATUS => {}
+            StatusCode::NOT_FOUND => return Err(Error::NotFound(path.to_string())),
+            status => return Err(Error::from_status("PROPFIND", path, status)),
+        }
 
-        let file_size: u64 = response.headers().get("Content-Range").map_or(0, |v| {
-            v.to_str()
-                .unwrap()
-                .split("/")
-                .last()
-                .unwrap()
-                .parse()
-                .unwrap()
-        });
+        let cache_control = CacheControl::from_headers(response.headers());
+        let body = response
+            .text()
+            .await
+            .map_err(|e| Error::ReqwestDAV(reqwest_dav::Error::Reqwest(e)))?;
+        let multi_status: ListMultiStatus = serde_xml_rs::from_str(&body)
+            .map_err(|e| Error::InvalidResponse(format!("PROPFIND {}: {}", path, e)))?;
+
+        let display_names = parse_display_names(&body);
+        let read_only = parse_read_only(&body);
 
-        if file_size == 0 {
-            return Ok(());
+        let mut list = multi_status
+            .responses
+            .into_iter()
+            .map(|x| {
+                let display_name = display_names.get(&x.href).cloned();
+                let is_read_only = read_only.contains(&x.href);
+                ListEntity::try_from(x)
+                    .map_err(|e| Error::ReqwestDAV(e))
+                    .and_then(|x| WebDAVList::try_from(&self.root, x))
+                    .map(|x| {
+                        x.with_display_name(display_name)
+                            .with_read_only(is_read_only)
+                    })
+            })
+            .collect::<Result<Vec<WebDAVList>, Error>>()?;
+
+        // Note : callers take the first entry as the requested item, but servers do not always
+        // answer with it first, so it is looked up by its path whatever its trailing slash.
+        let requested = decode_path(path);
+        if let Some(index) = list
+            .iter()
+            .position(|x| x.path().trim_end_matches('/') == requested.trim_end_matches('/'))
+        {
+            list[..=index].rotate_right(1);
         }
+        Ok((list, cache_control))
+    }
 
-        let mut offset = offset;
-        loop {
-            let chunk_result = response.chunk().await;
-            match chunk_result {
-                Ok(chunk) => match chunk {
-                    Some(chunk) => {
-                        let write_result = file.write(&chunk, offset).await;
-                        if let Err(err) = write_result {
-                            return Err(Error::IO(err));
+    /// Writes `size` bytes of `path` from `offset` into `sink`, e.g. a `BlockFile`. When the
+    /// response breaks off, the download is resumed from the first byte the sink is missing.
+    ///
+    /// When the server ignores Range, the rest of the file it sends is written as well, up to
+    /// `RangeSink::file_size`. A failure past the range does not fail the download.
+    pub async fn download<S: RangeSink>(
+        &self,
+        path: &str,
+        sink: &mut S,
+        offset: u64,
+        size: u64,
+    ) -> Result<CacheControl, Error> {
+        let (cache_control, rest) = self.download_with_rest(path, sink, offset, size).await?;
+        if let Some(mut rest) = rest {
+            if let Err(err) = rest.write_to(sink).await {
+                eprintln!("Download Error past the range of {}: {}", path, err);
+            }
+        }
+        Ok(cache_control)
+    }
+
+    /// Like `download`, but returns as soon as the range is written. When the server ignores
+    /// Range, the rest of the file it sends is returned as a stream for the caller to keep, e.g.
+    /// in the background, or to drop.
+    pub async fn download_with_rest<S: RangeSink>(
+        &self,
+        path: &str,
+        sink: &mut S,
+        offset: u64,
+        size: u64,
+    ) -> Result<(CacheControl, Option<RangeStream>), Error> {
+        let attributes = vec![
+            ("path", path.to_string()),
+            ("offset", offset.to_string()),
+            ("size", size.to_string()),
+        ];
+        telemetry::in_span("webdav.GET", attributes, async {
+            let end = offset.saturating_add(size);
+            let mut begin = offset;
+            let mut attempt = 1;
+            let result = loop {
+                match self.download_range(path, sink, begin, end - begin).await {
+                    Err(err @ (Error::InvalidRange(_) | Error::ReqwestDAV(_)))
+                        if attempt < DOWNLOAD_ATTEMPTS =>
+                    {
+                        eprintln!("Download Error (attempt {}): {}", attempt, err);
+                        tokio::time::sleep(DOWNLOAD_RETRY_DELAY * attempt).await;
+                        // Note : the bytes which landed before the response broke off are not
+                        // requested again, and an attempt which got further does not count
+                        // against the limit. At least one byte is requested for the headers.
+                        let resume_at = sink
+                            .first_missing_byte(begin, end)
+                            .await
+                            .map_err(|e| Error::IO(e))?
+                            .min(end.saturating_sub(1))
+                            .max(begin);
+                        if resume_at > begin {
+                            attempt = 1;
+                            begin = resume_at;
+                        } else {
+                            attempt += 1;
                         }
-                        offset += write_result.unwrap() as u64;
-                    }
-                    None => {
-                        break;
                     }
-                },
-                Err(err) => {
-                    return Err(Error::ReqwestDAV(reqwest_dav::Error::Reqwest(err)));
+                    result => break result,
                 }
+            };
+            sink.flush().await.map_err(|e| Error::IO(e))?;
+            result
+        })
+        .await
+    }
+
+    /// Writes `size` bytes of `path` from `offset` into `sink`, and returns what the server
+    /// sends past them, see `RangeStream::into_rest`.
+    async fn download_range<S: RangeSink>(
+        &self,
+        path: &str,
+        sink: &mut S,
+        offset: u64,
+        size: u64,
+    ) -> Result<(CacheControl, Option<RangeStream>), Error> {
+        let options = RangeOptions {
+            offset,
+            size,
+            keep_until: sink.file_size(),
+        };
+        let mut stream = self.get_range_stream(path, options).await?;
+        while let Some(chunk) = stream.chunk().await? {
+            sink.write_at(&chunk, chunk.offset)
+                .await
+                .map_err(|err| Error::IO(err))?;
+        }
+        Ok((stream.cache_control(), stream.into_rest()))
+    }
+
+    /// Requests a range of `path` and returns its body as it arrives. The response is checked
+    /// against the request first, so every chunk comes with the offset it belongs at.
+    ///
+    /// Unlike `download`, a broken response is not resumed. Reading the stream counts against
+    /// the download budget and the concurrency limit of the client, like downloads do.
+    pub async fn get_range_stream(
+        &self,
+        path: &str,
+        options: RangeOptions,
+    ) -> Result<RangeStream, Error> {
+        self.validate_url_length(path)?;
+        let permit = self.download_limit.acquire().await;
+        let connection = self.quirks.connection().await;
+        // Note : with a compressed body, Content-Range and Content-Length count encoded bytes,
+        // which would put the decoded data at the wrong offsets.
+        let range = format!(
+            "bytes={}-{}",
+            options.offset,
+            options.offset.saturating_add(options.size.max(1)) - 1
+        );
+        let sent_at = Instant::now();
+        let response = self
+            .send("GET", path, |client| {
+                let range = range.clone();
+                async move {
+                    client
+                        .start_request(Method::GET, path)
+                        .await?
+                        .header(RANGE, range)
+                        .header(ACCEPT_ENCODING, "identity")
+                        .send()
+                        .await
+                        .map_err(reqwest_dav::Error::Reqwest)
+                }
+            })
+            .await;
+        let response = match response {
+            Err(err @ Error::ReqwestDAV(_)) => {
+                permit.record_overload();
+                return Err(err);
             }
+            response => response?,
+        };
+        match response.status() {
+            StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT => permit.record_overload(),
+            _ => permit.record_latency(sent_at.elapsed()),
+        }
+        RangeStream::new(
+            path,
+            response,
+            options,
+            self.quirks.get().ignores_range,
+            self.download_budget.clone(),
+            permit,
+            connection,
+        )
+    }
+
+    // Note : `Response::content_length` is the size hint of the decoded body, so the header is
+    // read as sent.
+    fn content_length(response: &Response) -> Option<u64> {
+        response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.parse().ok())
+    }
+
+    /// Sends a `method` request, and sends it again while the resource is locked and the locked
+    /// wait is not over, or while a server known to throttle throttles it.
+    async fn send<F, Fut>(&self, method: &str, path: &str, request: F) -> Result<Response, Error>
+    where
+        F: Fn(Arc<DAVClient>) -> Fut,
+        Fut: Future<Output = Result<Response, reqwest_dav::Error>>,
+    {
+        let started_at = Instant::now();
+        let mut throttled = 0;
+        loop {
+            let response = self.send_authenticated(method, path, &request).await?;
+            let waited = started_at.elapsed();
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|x| x.to_str().ok())
+                .and_then(|x| x.trim().parse().ok())
+                .map(Duration::from_secs);
+            let delay = match response.status() {
+                StatusCode::LOCKED if waited < self.locked_wait => retry_after
+                    .unwrap_or(LOCKED_RETRY_DELAY)
+                    .min(self.locked_wait - waited),
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+                    if self.quirks.get().throttles && throttled < THROTTLE_ATTEMPTS =>
+                {
+                    throttled += 1;
+                    retry_after
+                        .unwrap_or(THROTTLE_RETRY_DELAY * 2u32.pow(throttled - 1))
+                        .min(MAX_THROTTLE_DELAY)
+                }
+                _ => return Ok(response),
+            };
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Sends a request and, if the server asks for credentials which can be answered, sends it
+    /// once more with them.
+    async fn send_authenticated<F, Fut>(
+        &self,
+        method: &str,
+        path: &str,
+        request: &F,
+    ) -> Result<Response, Error>
+    where
+        F: Fn(Arc<DAVClient>) -> Fut,
+        Fut: Future<Output = Result<Response, reqwest_dav::Error>>,
+    {
+        let response = request(self.client())
+            .await
+            .map_err(|e| Error::from_reqwest_dav(method, path, e))?;
+        self.quirks.detect(response.headers());
+        self.record_response(&response);
+        if response.status() != StatusCode::UNAUTHORIZED || !self.authenticate(&response)? {
+            return Ok(response);
+        }
+        let response = request(self.client())
+            .await
+            .map_err(|e| Error::from_reqwest_dav(method, path, e))?;
+        self.record_response(&response);
+        Ok(response)
+    }
+
+    /// Records the contact with the server and measures its clock.
+    fn record_response(&self, response: &Response) {
+        self.clock.record(response.headers());
+        let status = response.status();
+        if !status.is_server_error() && status != StatusCode::UNAUTHORIZED {
+            *self.last_contact.lock().unwrap() = Some(SystemTime::now());
+        }
+    }
+
+    /// Switches to the auth scheme a 401 response asks for. Returns false when there is nothing
+    /// to answer, i.e. the credentials were already sent for this challenge or the challenge
+    /// comes from another realm than the one they were first accepted for.
+    fn authenticate(&self, response: &Response) -> Result<bool, Error> {
+        if self.auth.mode == AuthMode::Preemptive {
+            return Ok(false);
+        }
+        let Some(challenge) = AuthChallenge::from_headers(response.headers()) else {
+            return Ok(false);
+        };
+
+        let mut answered = self.auth.answered.lock().unwrap();
+        match answered.as_ref() {
+            Some(answered) if *answered == challenge => return Ok(false),
+            Some(answered) if answered.realm != challenge.realm => {
+                eprintln!(
+                    "Not sending credentials for realm {:?} to realm {:?}",
+                    answered.realm, challenge.realm
+                );
+                return Ok(false);
+            }
+            _ => {}
+        }
+
+        let user = self.auth.user.clone();
+        let password = self.auth.password.expose().to_string();
+        let auth = match challenge.scheme {
+            AuthScheme::Basic => reqwest_dav::Auth::Basic(user, password),
+            AuthScheme::Digest => reqwest_dav::Auth::Digest(user, password),
+        };
+        let client =
+            WebDAVClient::build_client(self.client().host.clone(), auth, self.agent.clone())?;
+        *self.client.write().unwrap() = Arc::new(client);
+        *answered = Some(challenge);
+        Ok(true)
+    }
+
+    /// Follows a permanent redirect, e.g. from a collection URL without trailing slash to the one
+    /// with it. Only targets below the server root can be followed, since requests are built
+    /// from paths.
+    fn redirect_path(&self, path: &str, response: &Response) -> Result<String, Error> {
+        let target = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|location| response.url().join(location).ok());
+        match target {
+            Some(target) => path_below_root(&self.root, &target).ok_or_else(|| {
+                Error::InvalidResponse(format!(
+                    "PROPFIND {} redirected to {} outside of {}",
+                    path, target, self.root
+                ))
+            }),
+            None => Err(Error::InvalidResponse(format!(
+                "PROPFIND {} redirected without a valid Location",
+                path
+            ))),
+        }
+    }
+
+    /// Rejects paths whose request URL would exceed the server limit, so they fail with a clear
+    /// error instead of an opaque 414 or connection reset.
+    fn validate_url_length(&self, path: &str) -> Result<(), Error> {
+        if self.client().host.len() + path.len() > self.max_url_length {
+            Err(Error::UriTooLong(path.to_string()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Error {
+    /// The error of a `method` request on `path` which failed before a response was returned.
+    fn from_reqwest_dav(method: &str, path: &str, err: reqwest_dav::Error) -> Error {
+        match &err {
+            reqwest_dav::Error::Reqwest(e) => match e.status() {
+                Some(
+                    status @ (StatusCode::URI_TOO_LONG
+                    | StatusCode::LOCKED
+                    | StatusCode::INSUFFICIENT_STORAGE),
+                ) => Error::from_status(method, path, status),
+                _ => Error::ReqwestDAV(err),
+            },
+            _ => Error::ReqwestDAV(err),
+        }
+    }
+
+    /// The error of a `method` request on `path` which was answered with an unexpected `status`.
+    fn from_status(method: &str, path: &str, status: StatusCode) -> Error {
+        match status {
+            StatusCode::URI_TOO_LONG => Error::UriTooLong(path.to_string()),
+            StatusCode::LOCKED => Error::Locked(path.to_string()),
+            StatusCode::INSUFFICIENT_STORAGE => Error::InsufficientStorage(path.to_string()),
+            status => Error::Status(format!("{} {}", method, path), status),
+        }
+    }
+
+    /// The HTTP status the server answered with, if the error comes from one.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::ReqwestDAV(reqwest_dav::Error::Reqwest(e)) => e.status(),
+            Error::NotFound(_) => Some(StatusCode::NOT_FOUND),
+            Error::Locked(_) => Some(StatusCode::LOCKED),
+            Error::InsufficientStorage(_) => Some(StatusCode::INSUFFICIENT_STORAGE),
+            Error::Status(_, status) => Some(*status),
+            _ => None,
         }
-        Ok(())
     }
 }
 
 impl WebDAVList {
-    fn try_from(root: &str, value: ListEntity) -> Result<WebDAVList, Error> {
+    /// Decoded path below the server root, empty for `Err`.
+    pub fn path(&self) -> &str {
+        match self {
+            WebDAVList::File(f) => &f.path,
+            WebDAVList::Folder(d) => &d.path,
+            WebDAVList::Err => "",
+        }
+    }
+
+    pub fn etag(&self) -> Option<&str> {
+        match self {
+            WebDAVList::File(f) => f.etag.as_deref(),
+            WebDAVList::Folder(d) => d.etag.as_deref(),
+            WebDAVList::Err => None,
+        }
+    }
+
+    pub fn display_name(&self) -> Option<&str> {
+        match self {
+            WebDAVList::File(f) => f.display_name.as_deref(),
+            WebDAVList::Folder(d) => d.display_name.as_deref(),
+            WebDAVList::Err => None,
+        }
+    }
+
+    fn with_display_name(mut self, display_name: Option<String>) -> WebDAVList {
+        match &mut self {
+            WebDAVList::File(f) => f.display_name = display_name,
+            WebDAVList::Folder(d) => d.display_name = display_name,
+            WebDAVList::Err => {}
+        }
+        self
+    }
+
+    fn with_read_only(mut self, read_only: bool) -> WebDAVList {
+        match &mut self {
+            WebDAVList::File(f) => f.read_only = read_only,
+            WebDAVList::Folder(d) => d.read_only = read_only,
+            WebDAVList::Err => {}
+        }
+        self
+    }
+
+    /// Whether the server does not let the user write the entry.
+    pub fn is_read_only(&self) -> bool {
+        match self {
+            WebDAVList::File(f) => f.read_only,
+            WebDAVList::Folder(d) => d.read_only,
+            WebDAVList::Err => false,
+        }
+    }
+
+    fn try_from(root: &Url, value: ListEntity) -> Result<WebDAVList, Error> {
+        let href_path = |href: &str| {
+            href_path(root, href).ok_or(Error::InvalidResponse(format!(
+                "href {} is outside of {}",
+                href, root
+            )))
+        };
         match value {
             ListEntity::File(f) => {
-                let href = f.href.replace(root, "");
-                let path = decode(&href)
-                    .map_err(|e| Error::EncodingError(e))?
-                    .to_string();
+                let href = href_path(&f.href)?;
+                let path = decode_path(&href);
 
                 Ok(WebDAVList::File(WebDAVFile {
                     href: f.href,
                     path: path,
+                    name: decode_name(&href),
+                    encoded_path: href,
+                    display_name: None,
                     last_modified: f.last_modified,
                     content_length: f.content_length as u64,
                     content_type: f.content_type,
+                    etag: f.tag,
+                    read_only: false,
                 }))
             }
             ListEntity::Folder(f) => {
-                let href = f.href.replace(root, "");
-                let path = decode(&href)
-                    .map_err(|e| Error::EncodingError(e))?
-                    .to_string();
+                // Note : the kind comes from the resourcetype, some servers send collection hrefs
+                // without the trailing slash which other paths of the mount have.
+                let href = collection_path(&href_path(&f.href)?);
+                let path = decode_path(&href);
 
                 Ok(WebDAVList::Folder(WebDAVDirectory {
                     href: f.href,
                     path: path,
+                    name: decode_name(&href),
+                    encoded_path: href,
+                    display_name: None,
                     last_modified: f.last_modified,
                     quota_used_bytes: f.quota_used_bytes.map_or(None, |x| Some(x as u64)),
                     quota_available_bytes: f.quota_available_bytes.map_or(None, |x| Some(x as u64)),
+                    etag: f.tag,
+                    read_only: false,
                 }))
             }
             _ => Ok(WebDAVList::Err),
@@ -162,12 +1118,29 @@ impl WebDAVList {
     }
 }
 
+/// Splits a listing into the collection itself, which comes first, see `propfind`, and its
+/// entries.
+pub fn split_collection(mut list: Vec<WebDAVList>) -> (Option<WebDAVList>, Vec<WebDAVList>) {
+    if list.is_empty() {
+        return (None, list);
+    }
+    let collection = list.remove(0);
+    (Some(collection), list)
+}
+
 impl Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Error::ReqwestDAV(e) => write!(f, "WebDAVLibError: {}", e),
-            Error::EncodingError(e) => write!(f, "EncodingError: {}", e),
             Error::IO(e) => write!(f, "IOError: {}", e),
+            Error::UriTooLong(path) => write!(f, "UriTooLong: {}", path),
+            Error::InvalidResponse(e) => write!(f, "InvalidResponse: {}", e),
+            Error::InvalidRange(e) => write!(f, "InvalidRange: {}", e),
+            Error::NotFound(path) => write!(f, "NotFound: {}", path),
+            Error::InvalidUrl(e) => write!(f, "InvalidUrl: {}", e),
+            Error::Locked(path) => write!(f, "Locked: {}", path),
+            Error::InsufficientStorage(path) => write!(f, "InsufficientStorage: {}", path),
+            Error::Status(request, status) => write!(f, "Status: {} returned {}", request, status),
         }
     }
 }
@@ -0,0 +1,91 @@
+use quick_xml::{events::Event, Reader};
+
+/// The body of a PROPFIND asking for the `oc:fileid` property, the id Nextcloud and ownCloud
+/// key the versions of a file by.
+pub(super) const FILE_ID_PROPFIND: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+  <d:prop><oc:fileid/></d:prop>
+</d:propfind>"#;
+
+/// The body of a PROPFIND asking for the `oc:checksums` property, the checksums Nextcloud
+/// clients report when they upload a file, e.g. `SHA1:2fd4e1c6... MD5:9e107d9d...`.
+pub(super) const CHECKSUMS_PROPFIND: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+  <d:prop><oc:checksums/></d:prop>
+</d:propfind>"#;
+
+/// Returns the text of the first element named `local_name` in a multistatus body, whatever its
+/// namespace.
+pub(super) fn parse_property(body: &str, local_name: &[u8]) -> Option<String> {
+    let mut reader = Reader::from_str(body);
+    reader.trim_text(true);
+
+    let mut in_property = false;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => in_property = e.local_name().as_ref() == local_name,
+            Ok(Event::Text(text)) if in_property => {
+                return text.unescape().ok().map(|x| x.to_string());
+            }
+            Ok(Event::End(_)) => in_property = false,
+            Ok(Event::Eof) | Err(_) => return None,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod oc_properties_test {
+    use super::parse_property;
+
+    #[test]
+    fn parse_file_id_test() {
+        let body = r#"<?xml version="1.0"?>
+            <d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+              <d:response>
+                <d:href>/remote.php/dav/files/alice/Plan.docx</d:href>
+                <d:propstat>
+                  <d:prop><oc:fileid>4711</oc:fileid></d:prop>
+                  <d:status>HTTP/1.1 200 OK</d:status>
+                </d:propstat>
+              </d:response>
+            </d:multistatus>"#;
+        assert_eq!(parse_property(body, b"fileid"), Some("4711".to_string()));
+        assert_eq!(
+            parse_property("<d:multistatus xmlns:d=\"DAV:\"/>", b"fileid"),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_checksums_test() {
+        let body = r#"<?xml version="1.0"?>
+            <d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+              <d:response>
+                <d:href>/remote.php/dav/files/alice/Plan.docx</d:href>
+                <d:propstat>
+                  <d:prop>
+                    <oc:checksums><oc:checksum>SHA1:a9993e36 MD5:90015098</oc:checksum></oc:checksums>
+                  </d:prop>
+                  <d:status>HTTP/1.1 200 OK</d:status>
+                </d:propstat>
+              </d:response>
+            </d:multistatus>"#;
+        assert_eq!(
+            parse_property(body, b"checksum"),
+            Some("SHA1:a9993e36 MD5:90015098".to_string())
+        );
+        // Note : a file uploaded without a checksum has the property in a 404 propstat.
+        let body = r#"<?xml version="1.0"?>
+            <d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+              <d:response>
+                <d:href>/remote.php/dav/files/alice/Plan.docx</d:href>
+                <d:propstat>
+                  <d:prop><oc:checksums/></d:prop>
+                  <d:status>HTTP/1.1 404 Not Found</d:status>
+                </d:propstat>
+              </d:response>
+            </d:multistatus>"#;
+        assert_eq!(parse_property(body, b"checksum"), None);
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashSet;
+
+use quick_xml::{events::Event, Reader};
+
+/// The body of a PROPFIND asking for all properties plus the ones telling whether the user may
+/// write a resource, which `allprop` leaves out.
+pub(super) const PRIVILEGES_PROPFIND: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+  <d:allprop/>
+  <d:include><d:current-user-privilege-set/><oc:permissions/></d:include>
+</d:propfind>"#;
+
+/// Privileges of RFC 3744 which allow changing the content of a resource.
+const WRITE_PRIVILEGES: &[&[u8]] = &[b"all", b"write", b"write-content"];
+/// Letters of `oc:permissions` which allow writing a file, or creating in a folder.
+const WRITE_PERMISSIONS: &[char] = &['W', 'C', 'K'];
+
+/// Collects the hrefs of a multistatus body which the user may not write: resources whose
+/// `current-user-privilege-set` or `oc:permissions` grants no write, and resources with an
+/// active lock, which this client never holds. Resources without these properties are taken
+/// as writable.
+pub(super) fn parse_read_only(body: &str) -> HashSet<String> {
+    let mut reader = Reader::from_str(body);
+    reader.trim_text(true);
+
+    let mut read_only = HashSet::new();
+    let mut element = Vec::new();
+    let mut href = None;
+    // Note : the privilege set of a 404 propstat is an empty element, so it is only counted
+    // when it has children.
+    let mut in_privileges = false;
+    let mut can_write = None;
+    let mut locked = false;
+    loop {
+        match reader.read_event() {
+            Ok(Event::Start(e)) => {
+                element = e.local_name().as_ref().to_vec();
+                match element.as_slice() {
+                    b"response" => {
+                        href = None;
+                        can_write = None;
+                        locked = false;
+                    }
+                    b"current-user-privilege-set" => {
+                        in_privileges = true;
+                        can_write.get_or_insert(false);
+                    }
+                    b"activelock" => locked = true,
+                    name if in_privileges && WRITE_PRIVILEGES.contains(&name) => {
+                        can_write = Some(true)
+                    }
+                    _ => {}
+                }
+            }
+            Ok(Event::Empty(e)) => {
+                if in_privileges && WRITE_PRIVILEGES.contains(&e.local_name().as_ref()) {
+                    can_write = Some(true);
+                }
+            }
+            Ok(Event::Text(text)) => {
+                let Ok(text) = text.unescape() else {
+                    continue;
+                };
+                match element.as_slice() {
+                    b"href" => href = Some(text.to_string()),
+                    b"permissions" => {
+                        let writable = text.contains(WRITE_PERMISSIONS);
+                        can_write = Some(can_write.unwrap_or(true) && writable);
+                    }
+                    _ => {}
+                }
+            }
+            Ok(Event::End(e)) => {
+                match e.local_name().as_ref() {
+                    b"current-user-privilege-set" => in_privileges = false,
+                    b"response" => {
+                        if let Some(href) = href.take() {
+                            if locked || can_write == Some(false) {
+                                read_only.insert(href);
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                element.clear();
+            }
+            Ok(Event::Eof) | Err(_) => break,
+            _ => {}
+        }
+    }
+    read_only
+}
+
+#[cfg(test)]
+mod privileges_test {
+    use super::parse_read_only;
+
+    #[test]
+    fn parse_read_only_test() {
+        let body = r#"<?xml version="1.0" encoding="utf-8"?>
+            <d:multistatus xmlns:d="DAV:" xmlns:oc="http://owncloud.org/ns">
+              <d:response>
+                <d:href>/dav/shared/</d:href>
+                <d:propstat>
+                  <d:prop>
+                    <d:current-user-privilege-set>
+                      <d:privilege><d:read/></d:privilege>
+                    </d:current-user-privilege-set>
+                  </d:prop>
+                  <d:status>HTTP/1.1 200 OK</d:status>
+                </d:propstat>
+              </d:response>
+              <d:response>
+                <d:href>/dav/shared/notes.txt</d:href>
+                <d:propstat>
+                  <d:prop>
+                    <d:current-user-privilege-set>
+                      <d:privilege><d:read/></d:privilege>
+                      <d:privilege><d:write-content/></d:privilege>
+                    </d:current-user-privilege-set>
+                  </d:prop>
+                  <d:status>HTTP/1.1 200 OK</d:status>
+                </d:propstat>
+              </d:response>
+              <d:response>
+                <d:href>/dav/shared/report.pdf</d:href>
+                <d:propstat>
+                  <d:prop><oc:permissions>SRGDNV</oc:permissions></d:prop>
+                  <d:status>HTTP/1.1 200 OK</d:status>
+                </d:propstat>
+              </d:response>
+              <d:response>
+                <d:href>/dav/shared/plan.odt</d:href>
+                <d:propstat>
+                  <d:prop>
+                    <d:lockdiscovery>
+                      <d:activelock><d:lockscope><d:exclusive/></d:lockscope></d:activelock>
+                    </d:lockdiscovery>
+                  </d:prop>
+                  <d:status>HTTP/1.1 200 OK</d:status>
+                </d:propstat>
+              </d:response>
+              <d:response>
+                <d:href>/dav/shared/todo.txt</d:href>
+                <d:propstat>
+                  <d:prop><d:current-user-privilege-set/><oc:permissions/></d:prop>
+                  <d:status>HTTP/1.1 404 Not Found</d:status>
+                </d:propstat>
+              </d:response>
+            </d:multistatus>"#;
+
+        let mut read_only: Vec<String> = parse_read_only(body).into_iter().collect();
+        read_only.sort();
+        assert_eq!(
+            read_only,
+            vec![
+                "/dav/shared/",
+                "/dav/shared/plan.odt",
+                "/dav/shared/report.pdf"
+            ]
+        );
+    }
+}
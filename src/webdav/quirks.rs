@@ -0,0 +1,235 @@
+use std::{
+    fmt::Display,
+    str::FromStr,
+    sync::{Arc, OnceLock},
+};
+
+use reqwest::{
+    header::{HeaderMap, SERVER},
+    Url,
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Deviations of a server from the WebDAV and HTTP RFCs which the client works around.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// GET ignores Range and sends the whole file, so a download keeps the rest of the file
+    /// instead of receiving it again for every block.
+    pub ignores_range: bool,
+    /// PROPFIND with `Depth: 0` is rejected, so single items are fetched with `Depth: 1`.
+    pub no_depth_zero: bool,
+    /// The server throttles with 429 or 503, so those requests are sent again after a delay.
+    pub throttles: bool,
+    /// Connections the server accepts from an account at the same time.
+    pub max_connections: Option<usize>,
+}
+
+/// Providers whose quirks are known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    HetznerStorageBox,
+    Box,
+    Yandex,
+}
+
+/// Hosts of the providers, matched against the host of the server URL or a suffix of it
+/// following a dot.
+const PROVIDER_HOSTS: &[(&str, Provider)] = &[
+    ("your-storagebox.de", Provider::HetznerStorageBox),
+    ("box.com", Provider::Box),
+    ("yandex.ru", Provider::Yandex),
+    ("yandex.com", Provider::Yandex),
+];
+
+/// Words in the Server header of the providers, for servers behind a custom domain.
+const PROVIDER_SERVERS: &[(&str, Provider)] = &[("yandex", Provider::Yandex)];
+
+impl Provider {
+    pub fn quirks(&self) -> Quirks {
+        match self {
+            Provider::HetznerStorageBox => Quirks {
+                max_connections: Some(10),
+                ..Quirks::default()
+            },
+            Provider::Box => Quirks {
+                ignores_range: true,
+                no_depth_zero: true,
+                throttles: true,
+                max_connections: None,
+            },
+            Provider::Yandex => Quirks {
+                throttles: true,
+                ..Quirks::default()
+            },
+        }
+    }
+
+    fn from_host(host: &str) -> Option<Provider> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        PROVIDER_HOSTS
+            .iter()
+            .find(|(suffix, _)| {
+                host == *suffix
+                    || host
+                        .strip_suffix(suffix)
+                        .map_or(false, |x| x.ends_with('.'))
+            })
+            .map(|(_, provider)| *provider)
+    }
+
+    fn from_server_header(server: &str) -> Option<Provider> {
+        let server = server.to_ascii_lowercase();
+        PROVIDER_SERVERS
+            .iter()
+            .find(|(word, _)| server.contains(word))
+            .map(|(_, provider)| *provider)
+    }
+}
+
+/// How the quirks of the server are chosen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QuirksMode {
+    /// Detected from the host of the server URL, else from the Server header of the first
+    /// response.
+    #[default]
+    Auto,
+    /// The server is taken to follow the RFCs.
+    None,
+    Provider(Provider),
+}
+
+impl FromStr for QuirksMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(QuirksMode::Auto),
+            "none" => Ok(QuirksMode::None),
+            "hetzner" => Ok(QuirksMode::Provider(Provider::HetznerStorageBox)),
+            "box" => Ok(QuirksMode::Provider(Provider::Box)),
+            "yandex" => Ok(QuirksMode::Provider(Provider::Yandex)),
+            _ => Err(format!(
+                "invalid server quirks {:?}, expected auto, none, hetzner, box or yandex",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for QuirksMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QuirksMode::Auto => write!(f, "auto"),
+            QuirksMode::None => write!(f, "none"),
+            QuirksMode::Provider(Provider::HetznerStorageBox) => write!(f, "hetzner"),
+            QuirksMode::Provider(Provider::Box) => write!(f, "box"),
+            QuirksMode::Provider(Provider::Yandex) => write!(f, "yandex"),
+        }
+    }
+}
+
+/// The quirks of a server, shared by all clients of it. Until they are known, e.g. before the
+/// first response in auto mode, the server is taken to follow the RFCs.
+pub(super) struct ServerQuirks {
+    mode: QuirksMode,
+    known: OnceLock<(Quirks, Option<Arc<Semaphore>>)>,
+}
+
+impl ServerQuirks {
+    pub fn new(mode: QuirksMode, root: &Url) -> ServerQuirks {
+        let server_quirks = ServerQuirks {
+            mode,
+            known: OnceLock::new(),
+        };
+        match mode {
+            QuirksMode::Auto => {
+                if let Some(provider) = root.host_str().and_then(Provider::from_host) {
+                    server_quirks.set(Some(provider));
+                }
+            }
+            QuirksMode::None => server_quirks.set(None),
+            QuirksMode::Provider(provider) => server_quirks.set(Some(provider)),
+        }
+        server_quirks
+    }
+
+    pub fn get(&self) -> Quirks {
+        self.known
+            .get()
+            .map(|(quirks, _)| *quirks)
+            .unwrap_or_default()
+    }
+
+    /// Detects the provider from the headers of a response, unless the quirks are known.
+    pub fn detect(&self, headers: &HeaderMap) {
+        if self.known.get().is_some() || self.mode != QuirksMode::Auto {
+            return;
+        }
+        let provider = headers
+            .get(SERVER)
+            .and_then(|x| x.to_str().ok())
+            .and_then(Provider::from_server_header);
+        self.set(provider);
+    }
+
+    /// Waits for a free connection when the server limits them. The connection is taken until
+    /// the permit is dropped.
+    pub async fn connection(&self) -> Option<OwnedSemaphorePermit> {
+        let semaphore = self.known.get()?.1.clone()?;
+        // Note : the semaphore is never closed, so acquiring can not fail.
+        Some(semaphore.acquire_owned().await.unwrap())
+    }
+
+    fn set(&self, provider: Option<Provider>) {
+        let quirks = provider.map(|x| x.quirks()).unwrap_or_default();
+        let connections = quirks
+            .max_connections
+            .map(|x| Arc::new(Semaphore::new(x.max(1))));
+        if self.known.set((quirks, connections)).is_ok() {
+            if let Some(provider) = provider {
+                eprintln!("Working around the quirks of {:?}: {:?}", provider, quirks);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod quirks_test {
+    use reqwest::{
+        header::{HeaderMap, HeaderValue, SERVER},
+        Url,
+    };
+
+    use super::{Provider, QuirksMode, ServerQuirks};
+
+    #[test]
+    fn from_host_test() {
+        assert_eq!(
+            Provider::from_host("u123456.your-storagebox.de"),
+            Some(Provider::HetznerStorageBox)
+        );
+        assert_eq!(Provider::from_host("dav.box.com"), Some(Provider::Box));
+        assert_eq!(
+            Provider::from_host("webdav.Yandex.ru"),
+            Some(Provider::Yandex)
+        );
+        assert_eq!(Provider::from_host("notbox.com"), None);
+        assert_eq!(Provider::from_host("cloud.example.com"), None);
+    }
+
+    #[test]
+    fn detect_test() {
+        let root = Url::parse("https://dav.example.com/").unwrap();
+        let server_quirks = ServerQuirks::new(QuirksMode::Auto, &root);
+        assert!(!server_quirks.get().throttles);
+        let mut headers = HeaderMap::new();
+        headers.insert(SERVER, HeaderValue::from_static("Yandex WebDAV"));
+        server_quirks.detect(&headers);
+        assert_eq!(server_quirks.get(), Provider::Yandex.quirks());
+
+        let root = Url::parse("https://dav.box.com/dav").unwrap();
+        let server_quirks = ServerQuirks::new(QuirksMode::None, &root);
+        server_quirks.detect(&headers);
+        assert!(!server_quirks.get().ignores_range);
+    }
+}
@@ -0,0 +1,209 @@
+use std::{fs::File, future::Future, io, os::unix::fs::FileExt, sync::Arc};
+
+use crate::blockfile::BlockFile;
+
+/// Where `WebDAVClient::download` puts the bytes of a range.
+///
+/// The download writes the range in order from its start. When the response breaks off, it is
+/// requested again from the first byte the sink is missing, so a sink only needs to remember
+/// how far it got.
+pub trait RangeSink: Send {
+    /// Size of the file the bytes go into, if it has one. When the server ignores Range and
+    /// sends the whole file, the bytes past the range are kept up to this size.
+    fn file_size(&self) -> Option<u64>;
+
+    /// Writes `buf` at `offset` of the file.
+    fn write_at(&mut self, buf: &[u8], offset: u64) -> impl Future<Output = io::Result<()>> + Send;
+
+    /// Returns the first byte from `begin` up to `end` which was not written yet, `end` when
+    /// every one was.
+    fn first_missing_byte(
+        &mut self,
+        begin: u64,
+        end: u64,
+    ) -> impl Future<Output = io::Result<u64>> + Send;
+
+    /// Persists what was written, once the download is over, whether it failed or not.
+    fn flush(&mut self) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+impl RangeSink for BlockFile {
+    fn file_size(&self) -> Option<u64> {
+        Some(BlockFile::file_size(self))
+    }
+
+    async fn write_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        self.write(buf, offset).await.map(|_| ())
+    }
+
+    async fn first_missing_byte(&mut self, begin: u64, end: u64) -> io::Result<u64> {
+        BlockFile::first_missing_byte(self, begin, end).await
+    }
+
+    // Note : a failed download keeps what it wrote, it is resumed from there.
+    async fn flush(&mut self) -> io::Result<()> {
+        self.flush_block_infos().await
+    }
+}
+
+/// Returns the first byte from `begin` up to `end` which is not in the `written` range.
+fn first_missing_in(written: (u64, u64), begin: u64, end: u64) -> u64 {
+    let (start, written_end) = written;
+    if begin < start || begin > written_end {
+        return begin;
+    }
+    written_end.clamp(begin, end.max(begin))
+}
+
+/// Keeps a range in memory, e.g. to compare it or to hand it out without a cache file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemorySink {
+    offset: u64,
+    data: Vec<u8>,
+}
+
+impl MemorySink {
+    /// A sink for a range starting at `offset`.
+    pub fn new(offset: u64) -> MemorySink {
+        MemorySink {
+            offset,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The bytes written from `offset` on.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl RangeSink for MemorySink {
+    fn file_size(&self) -> Option<u64> {
+        None
+    }
+
+    async fn write_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let end = self.offset + self.data.len() as u64;
+        if offset < self.offset || offset > end {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Can not write at {}, the buffer holds {} to {}",
+                    offset, self.offset, end
+                ),
+            ));
+        }
+        let pos = (offset - self.offset) as usize;
+        let overlap = (self.data.len() - pos).min(buf.len());
+        self.data[pos..pos + overlap].copy_from_slice(&buf[..overlap]);
+        self.data.extend_from_slice(&buf[overlap..]);
+        Ok(())
+    }
+
+    async fn first_missing_byte(&mut self, begin: u64, end: u64) -> io::Result<u64> {
+        let written = (self.offset, self.offset + self.data.len() as u64);
+        Ok(first_missing_in(written, begin, end))
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes ranges into a plain file at their offsets, e.g. to export a file without caching it.
+/// Only the bytes written through the sink count as written, not what the file held before.
+pub struct FileSink {
+    file: Arc<File>,
+    /// The range written last, extended by every write which continues it.
+    written: Option<(u64, u64)>,
+}
+
+impl FileSink {
+    pub fn new(file: File) -> FileSink {
+        FileSink {
+            file: Arc::new(file),
+            written: None,
+        }
+    }
+}
+
+impl RangeSink for FileSink {
+    fn file_size(&self) -> Option<u64> {
+        None
+    }
+
+    async fn write_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let file = self.file.clone();
+        let buf = buf.to_vec();
+        let len = buf.len() as u64;
+        tokio::task::spawn_blocking(move || file.write_all_at(&buf, offset))
+            .await
+            .map_err(io::Error::other)??;
+        self.written = match self.written {
+            Some((start, end)) if start <= offset && offset <= end => {
+                Some((start, end.max(offset + len)))
+            }
+            _ => Some((offset, offset + len)),
+        };
+        Ok(())
+    }
+
+    async fn first_missing_byte(&mut self, begin: u64, end: u64) -> io::Result<u64> {
+        Ok(match self.written {
+            Some(written) => first_missing_in(written, begin, end),
+            None => begin,
+        })
+    }
+
+    async fn flush(&mut self) -> io::Result<()> {
+        let file = self.file.clone();
+        tokio::task::spawn_blocking(move || file.sync_data())
+            .await
+            .map_err(io::Error::other)?
+    }
+}
+
+#[cfg(test)]
+mod range_sink_test {
+    use super::{FileSink, MemorySink, RangeSink};
+
+    #[tokio::test]
+    async fn memory_sink_test() {
+        let mut sink = MemorySink::new(100);
+        assert_eq!(sink.first_missing_byte(100, 200).await.unwrap(), 100);
+        sink.write_at(b"hello", 100).await.unwrap();
+        sink.write_at(b"world", 105).await.unwrap();
+        sink.write_at(b"W", 105).await.unwrap();
+        assert_eq!(sink.data(), b"helloWorld");
+        assert_eq!(sink.first_missing_byte(100, 200).await.unwrap(), 110);
+        assert_eq!(sink.first_missing_byte(100, 105).await.unwrap(), 105);
+        assert_eq!(sink.first_missing_byte(50, 200).await.unwrap(), 50);
+        assert!(sink.write_at(b"gap", 111).await.is_err());
+        assert!(sink.write_at(b"before", 99).await.is_err());
+        assert_eq!(sink.into_data(), b"helloWorld");
+    }
+
+    #[tokio::test]
+    async fn file_sink_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("export.bin");
+        let mut sink = FileSink::new(std::fs::File::create(&path).unwrap());
+        assert_eq!(sink.first_missing_byte(10, 20).await.unwrap(), 10);
+        sink.write_at(b"hello", 10).await.unwrap();
+        sink.write_at(b"world", 15).await.unwrap();
+        sink.flush().await.unwrap();
+        assert_eq!(sink.first_missing_byte(10, 30).await.unwrap(), 20);
+        assert_eq!(sink.first_missing_byte(12, 18).await.unwrap(), 18);
+
+        let content = std::fs::read(&path).unwrap();
+        assert_eq!(&content[10..], b"helloworld");
+    }
+}
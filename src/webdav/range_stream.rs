@@ -0,0 +1,260 @@
+use std::ops::Deref;
+
+use hyper::body::Bytes;
+use reqwest::{
+    header::{CONTENT_ENCODING, CONTENT_RANGE},
+    Response, StatusCode,
+};
+use tokio::sync::OwnedSemaphorePermit;
+
+use super::{
+    adaptive_limit::AdaptivePermit,
+    byte_budget::{ByteBudget, DownloadBudget, Reservation},
+    content_range::ContentRange,
+    CacheControl, Error, RangeSink, WebDAVClient,
+};
+
+/// The range `WebDAVClient::get_range_stream` requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RangeOptions {
+    /// Offset of the first byte.
+    pub offset: u64,
+    /// Bytes requested from `offset`. At least one is requested, so an empty range still gets
+    /// the headers of the file.
+    pub size: u64,
+    /// When the server ignores Range and sends the whole file, the bytes past the range can be
+    /// kept up to this offset instead of being dropped, see `RangeStream::into_rest`, e.g. to
+    /// fill a cache file which would request them next.
+    pub keep_until: Option<u64>,
+}
+
+/// Bytes of a range, and where they go in the file.
+///
+/// The chunk holds its bytes in the download budget of the client until it is dropped, so the
+/// bytes should be written out before the next chunk is pulled.
+pub struct RangeChunk {
+    pub offset: u64,
+    pub data: Bytes,
+    _reservation: Reservation,
+}
+
+impl Deref for RangeChunk {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// The body of a ranged GET, checked against the request, see `WebDAVClient::get_range_stream`.
+///
+/// The download counts against the concurrency limit of the client until the stream is
+/// dropped.
+pub struct RangeStream {
+    path: String,
+    response: Response,
+    cache_control: CacheControl,
+    /// Bytes at the start of the body before the requested offset, sent by servers which
+    /// ignore Range.
+    skip: u64,
+    offset: u64,
+    end: u64,
+    /// Where `into_rest` continues up to, past `end` when the server sends more than the range.
+    keep_end: u64,
+    /// Bytes of the last chunk received which lie past `end`, kept for `into_rest`.
+    pending: Bytes,
+    /// The end comes from the response, so a body which ends before it was cut off.
+    end_known: bool,
+    budget: DownloadBudget,
+    _permit: AdaptivePermit,
+    _connection: Option<OwnedSemaphorePermit>,
+}
+
+impl RangeStream {
+    /// Checks the response against the request, so bytes are never put at the wrong offset.
+    pub(super) fn new(
+        path: &str,
+        response: Response,
+        options: RangeOptions,
+        ignores_range: bool,
+        budget: ByteBudget,
+        permit: AdaptivePermit,
+        connection: Option<OwnedSemaphorePermit>,
+    ) -> Result<RangeStream, Error> {
+        let offset = options.offset;
+        let mut stream = RangeStream {
+            path: path.to_string(),
+            cache_control: CacheControl::from_headers(response.headers()),
+            response,
+            skip: 0,
+            offset,
+            end: offset,
+            keep_end: offset,
+            pending: Bytes::new(),
+            end_known: false,
+            budget: budget.download(),
+            _permit: permit,
+            _connection: connection,
+        };
+        match stream.response.status() {
+            // Note : the offset is past the end of the file, so there is nothing to stream.
+            StatusCode::RANGE_NOT_SATISFIABLE => return Ok(stream),
+            status if !status.is_success() => return Err(Error::from_status("GET", path, status)),
+            _ => {}
+        }
+        let response = &stream.response;
+        if let Some(encoding) = response
+            .headers()
+            .get(CONTENT_ENCODING)
+            .filter(|x| !x.as_bytes().eq_ignore_ascii_case(b"identity"))
+        {
+            return Err(Error::InvalidResponse(format!(
+                "GET {}: unexpected Content-Encoding {:?} on a ranged request",
+                path, encoding
+            )));
+        }
+
+        // Note : servers which ignore Range answer 200 with the whole file, so the bytes before
+        // offset are skipped.
+        let (skip, file_size, range_end) = if response.status() == StatusCode::PARTIAL_CONTENT {
+            let Some(content_range) = response.headers().get(CONTENT_RANGE) else {
+                return Err(Error::InvalidRange(format!(
+                    "{}: missing Content-Range",
+                    path
+                )));
+            };
+            let content_range = content_range
+                .to_str()
+                .ok()
+                .and_then(ContentRange::parse)
+                .ok_or(Error::InvalidRange(format!(
+                    "{}: malformed Content-Range {:?}",
+                    path, content_range
+                )))?;
+            if content_range.begin != offset {
+                return Err(Error::InvalidRange(format!(
+                    "{}: requested offset {}, got {}",
+                    path, offset, content_range.begin
+                )));
+            }
+            let range_len = content_range.end - content_range.begin + 1;
+            if let Some(content_length) = WebDAVClient::content_length(response) {
+                if content_length != range_len {
+                    return Err(Error::InvalidRange(format!(
+                        "{}: Content-Length {} does not match Content-Range length {}",
+                        path, content_length, range_len
+                    )));
+                }
+            }
+            (0, content_range.total, Some(content_range.end + 1))
+        } else {
+            (offset, WebDAVClient::content_length(response), None)
+        };
+
+        // Note : a server which ignores Range sends the rest of the file anyway, so it may be
+        // kept rather than received again for the next blocks.
+        let requested_end = offset.saturating_add(options.size);
+        let keep_end = match options.keep_until {
+            Some(keep_until) if range_end.is_none() && ignores_range => {
+                keep_until.max(requested_end)
+            }
+            _ => requested_end,
+        };
+        let clamp = |end: u64| {
+            [file_size, range_end]
+                .into_iter()
+                .flatten()
+                .fold(end, u64::min)
+        };
+        stream.skip = skip;
+        stream.end = clamp(requested_end);
+        stream.keep_end = clamp(keep_end);
+        stream.end_known = file_size.is_some() || range_end.is_some();
+        Ok(stream)
+    }
+
+    /// The freshness the server announced for the file.
+    pub fn cache_control(&self) -> CacheControl {
+        self.cache_control
+    }
+
+    /// Offset of the next byte of the stream.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Offset the stream ends at, which is before the end of the range when the file is
+    /// shorter.
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Once the range is complete, returns the stream of the bytes the server sends past it up
+    /// to `RangeOptions::keep_until`, if it ignored Range. Dropping the stream instead closes the
+    /// connection.
+    pub fn into_rest(mut self) -> Option<RangeStream> {
+        if self.offset < self.end || self.keep_end <= self.end {
+            return None;
+        }
+        self.end = self.keep_end;
+        Some(self)
+    }
+
+    /// Writes the rest of the stream into `sink`, and flushes it whether that fails or not.
+    pub async fn write_to<S: RangeSink>(&mut self, sink: &mut S) -> Result<(), Error> {
+        let result = async {
+            while let Some(chunk) = self.chunk().await? {
+                sink.write_at(&chunk, chunk.offset)
+                    .await
+                    .map_err(|err| Error::IO(err))?;
+            }
+            Ok(())
+        }
+        .await;
+        sink.flush().await.map_err(|err| Error::IO(err))?;
+        result
+    }
+
+    /// Returns the next bytes of the range, `None` once it is complete. A body which ends before
+    /// the end the server announced is an `InvalidRange` error.
+    pub async fn chunk(&mut self) -> Result<Option<RangeChunk>, Error> {
+        while self.offset < self.end {
+            let mut data = if self.pending.is_empty() {
+                let chunk = self
+                    .response
+                    .chunk()
+                    .await
+                    .map_err(|err| Error::ReqwestDAV(reqwest_dav::Error::Reqwest(err)))?;
+                let Some(mut data) = chunk else { break };
+                let chunk_skip = self.skip.min(data.len() as u64) as usize;
+                self.skip -= chunk_skip as u64;
+                data.split_off(chunk_skip)
+            } else {
+                std::mem::take(&mut self.pending)
+            };
+
+            let len = data.len().min((self.end - self.offset) as usize);
+            self.pending = data.split_off(len);
+            if data.is_empty() {
+                continue;
+            }
+            // Note : the response is not polled again before the bytes fit in the budget.
+            let reservation = self.budget.reserve(data.len()).await;
+            let offset = self.offset;
+            self.offset += data.len() as u64;
+            return Ok(Some(RangeChunk {
+                offset,
+                data,
+                _reservation: reservation,
+            }));
+        }
+
+        if self.end_known && self.offset < self.end {
+            return Err(Error::InvalidRange(format!(
+                "{}: body ended at {}, expected {}",
+                self.path, self.offset, self.end
+            )));
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,103 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicI64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Utc};
+use reqwest::header::{HeaderMap, DATE};
+
+/// Offsets below this are within the one second resolution of `Date` and the time a response
+/// takes to arrive, and are taken as no offset.
+const MIN_OFFSET_MILLIS: i64 = 2_000;
+/// Offsets from this on are logged, once per client.
+const LOGGED_OFFSET_MILLIS: i64 = 60_000;
+
+/// The offset of the clock of the server from the local one, measured from the `Date` header of
+/// its responses. Times the mount compares with times from the server, such as the mtimes of
+/// directories changed by a listing, are taken from this clock, so a server minutes off neither
+/// hides changes behind times from the future nor makes old ones look new.
+#[derive(Debug, Clone, Default)]
+pub struct ServerClock {
+    offset_millis: Arc<AtomicI64>,
+    logged: Arc<AtomicBool>,
+}
+
+impl ServerClock {
+    /// Measures the offset again from the `Date` header of a response, if it has one.
+    pub(super) fn record(&self, headers: &HeaderMap) {
+        let date = headers
+            .get(DATE)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| DateTime::parse_from_rfc2822(x).ok());
+        if let Some(date) = date {
+            self.record_at(date.with_timezone(&Utc), Utc::now());
+        }
+    }
+
+    fn record_at(&self, date: DateTime<Utc>, now: DateTime<Utc>) {
+        let offset = (date - now).num_milliseconds();
+        let offset = if offset.abs() < MIN_OFFSET_MILLIS {
+            0
+        } else {
+            offset
+        };
+        self.offset_millis.store(offset, Ordering::Relaxed);
+        if offset.abs() >= LOGGED_OFFSET_MILLIS && !self.logged.swap(true, Ordering::Relaxed) {
+            eprintln!(
+                "Server clock is {} s {} the local clock, adjusting for it",
+                offset.abs() / 1000,
+                if offset > 0 { "ahead of" } else { "behind" }
+            );
+        }
+    }
+
+    /// Milliseconds the clock of the server is ahead of the local one, negative when it is
+    /// behind.
+    pub fn offset_millis(&self) -> i64 {
+        self.offset_millis.load(Ordering::Relaxed)
+    }
+
+    /// The current time by the clock of the server.
+    pub fn now(&self) -> SystemTime {
+        let now = SystemTime::now();
+        let offset = self.offset_millis();
+        let adjustment = Duration::from_millis(offset.unsigned_abs());
+        if offset >= 0 {
+            now + adjustment
+        } else {
+            now.checked_sub(adjustment).unwrap_or(now)
+        }
+    }
+}
+
+#[cfg(test)]
+mod server_clock_test {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::ServerClock;
+
+    #[test]
+    fn record_at_test() {
+        let clock = ServerClock::default();
+        let now = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        clock.record_at(now + Duration::seconds(300), now);
+        assert_eq!(clock.offset_millis(), 300_000);
+
+        clock.record_at(
+            now - Duration::seconds(90),
+            now + Duration::milliseconds(500),
+        );
+        assert_eq!(clock.offset_millis(), -90_500);
+
+        // Note : within the resolution of `Date`, the clocks agree.
+        clock.record_at(now, now + Duration::milliseconds(1_500));
+        assert_eq!(clock.offset_millis(), 0);
+
+        let clock = ServerClock::default();
+        let before = std::time::SystemTime::now();
+        assert!(clock.now() >= before);
+    }
+}
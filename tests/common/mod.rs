@@ -0,0 +1,132 @@
+use std::{
+    convert::Infallible,
+    net::TcpListener,
+    sync::{Arc, Mutex},
+};
+
+use dav_server::{fakels::FakeLs, localfs::LocalFs, DavHandler};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Method, Response,
+};
+use tempfile::TempDir;
+use tokio::task::JoinHandle;
+
+/// An in-process WebDAV server serving a temporary directory.
+pub struct DavServer {
+    pub url: String,
+    pub root: TempDir,
+    handle: JoinHandle<()>,
+}
+
+impl DavServer {
+    pub fn start() -> DavServer {
+        let root = tempfile::tempdir().unwrap();
+        let dav_handler = DavHandler::builder()
+            .filesystem(LocalFs::new(root.path(), false, false, false))
+            .locksystem(FakeLs::new())
+            .build_handler();
+
+        let make_service = make_service_fn(move |_| {
+            let dav_handler = dav_handler.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    let dav_handler = dav_handler.clone();
+                    async move { Ok::<_, Infallible>(dav_handler.handle(req).await) }
+                }))
+            }
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = hyper::Server::from_tcp(listener)
+            .unwrap()
+            .serve(make_service);
+        let handle = tokio::spawn(async move {
+            let _ = server.await;
+        });
+
+        DavServer { url, root, handle }
+    }
+
+    pub fn create_file(&self, path: &str, content: &[u8]) {
+        let path = self.root.path().join(path.trim_start_matches('/'));
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    pub fn create_dir(&self, path: &str) {
+        std::fs::create_dir_all(self.root.path().join(path.trim_start_matches('/'))).unwrap();
+    }
+}
+
+impl Drop for DavServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// An in-process HTTP server answering every request with `respond`, for behaviors of servers
+/// which `DavServer` does not have. It records the method and path of every request.
+pub struct MockServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<(String, String)>>>,
+    handle: JoinHandle<()>,
+}
+
+impl MockServer {
+    pub fn start<F>(respond: F) -> MockServer
+    where
+        F: Fn(&Method, &str) -> Response<Body> + Send + Sync + 'static,
+    {
+        let respond = Arc::new(respond);
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        let make_service = make_service_fn(move |_| {
+            let respond = respond.clone();
+            let recorded = recorded.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: hyper::Request<Body>| {
+                    let path = req.uri().path().to_string();
+                    recorded
+                        .lock()
+                        .unwrap()
+                        .push((req.method().to_string(), path.clone()));
+                    let response = respond(req.method(), &path);
+                    async move { Ok::<_, Infallible>(response) }
+                }))
+            }
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = hyper::Server::from_tcp(listener)
+            .unwrap()
+            .serve(make_service);
+        let handle = tokio::spawn(async move {
+            let _ = server.await;
+        });
+
+        MockServer {
+            url,
+            requests,
+            handle,
+        }
+    }
+
+    /// The method and path of every request received so far.
+    pub fn requests(&self) -> Vec<(String, String)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Deterministic, non-repeating content so misplaced blocks are detected.
+pub fn gen_content(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i % 251) as u8).collect()
+}
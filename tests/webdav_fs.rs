@@ -0,0 +1,713 @@
+mod common;
+
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    time::Duration,
+};
+
+use common::{gen_content, DavServer, MockServer};
+use fusedav_rs::{
+    blockfile::BlockFile,
+    fs::{self, CacheNamespace, WebDAVFS},
+    preflight,
+    webdav::{
+        ListOptions, MemorySink, Provider, QuirksMode, RangeOptions, Secret, WebDAVClient,
+        WebDAVList,
+    },
+};
+use fuser::MountOption;
+use hyper::{Body, Response};
+
+/// The listing of `/new/` after it was moved from `/old/`.
+const MOVED_LISTING: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:multistatus xmlns:D="DAV:">
+  <D:response>
+    <D:href>/new/</D:href>
+    <D:propstat>
+      <D:prop>
+        <D:getlastmodified>Tue, 14 Nov 2023 22:13:20 GMT</D:getlastmodified>
+        <D:resourcetype><D:collection/></D:resourcetype>
+      </D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+  <D:response>
+    <D:href>/new/a.txt</D:href>
+    <D:propstat>
+      <D:prop>
+        <D:getlastmodified>Tue, 14 Nov 2023 22:13:20 GMT</D:getlastmodified>
+        <D:getcontentlength>5</D:getcontentlength>
+        <D:getcontenttype>text/plain</D:getcontenttype>
+        <D:getetag>"a1"</D:getetag>
+        <D:resourcetype/>
+      </D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+</D:multistatus>"#;
+
+/// The listing of `/`, whose file the server says must be confirmed before every use.
+const NO_CACHE_LISTING: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:multistatus xmlns:D="DAV:">
+  <D:response>
+    <D:href>/</D:href>
+    <D:propstat>
+      <D:prop>
+        <D:getlastmodified>Tue, 14 Nov 2023 22:13:20 GMT</D:getlastmodified>
+        <D:getetag>"root1"</D:getetag>
+        <D:resourcetype><D:collection/></D:resourcetype>
+      </D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+  <D:response>
+    <D:href>/a.txt</D:href>
+    <D:propstat>
+      <D:prop>
+        <D:getlastmodified>Tue, 14 Nov 2023 22:13:20 GMT</D:getlastmodified>
+        <D:getcontentlength>5</D:getcontentlength>
+        <D:getcontenttype>text/plain</D:getcontenttype>
+        <D:getetag>"a1"</D:getetag>
+        <D:resourcetype/>
+      </D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+</D:multistatus>"#;
+
+/// The entry of `/a.txt` in `NO_CACHE_LISTING` alone.
+const NO_CACHE_STAT: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:multistatus xmlns:D="DAV:">
+  <D:response>
+    <D:href>/a.txt</D:href>
+    <D:propstat>
+      <D:prop>
+        <D:getlastmodified>Tue, 14 Nov 2023 22:13:20 GMT</D:getlastmodified>
+        <D:getcontentlength>5</D:getcontentlength>
+        <D:getcontenttype>text/plain</D:getcontenttype>
+        <D:getetag>"a1"</D:getetag>
+        <D:resourcetype/>
+      </D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+</D:multistatus>"#;
+
+/// The listing of `/latin1/`, whose file name is encoded in Latin-1 instead of UTF-8.
+const LATIN1_LISTING: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:multistatus xmlns:D="DAV:">
+  <D:response>
+    <D:href>/latin1/</D:href>
+    <D:propstat>
+      <D:prop>
+        <D:getlastmodified>Tue, 14 Nov 2023 22:13:20 GMT</D:getlastmodified>
+        <D:resourcetype><D:collection/></D:resourcetype>
+      </D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+  <D:response>
+    <D:href>/latin1/caf%E9.txt</D:href>
+    <D:propstat>
+      <D:prop>
+        <D:getlastmodified>Tue, 14 Nov 2023 22:13:20 GMT</D:getlastmodified>
+        <D:getcontentlength>5</D:getcontentlength>
+        <D:getcontenttype>text/plain</D:getcontenttype>
+        <D:resourcetype/>
+      </D:prop>
+      <D:status>HTTP/1.1 200 OK</D:status>
+    </D:propstat>
+  </D:response>
+</D:multistatus>"#;
+
+fn list_names(list: &[WebDAVList]) -> Vec<String> {
+    let mut names: Vec<String> = list
+        .iter()
+        .filter_map(|item| match item {
+            WebDAVList::File(f) => Some(f.path.clone()),
+            WebDAVList::Folder(d) => Some(d.path.clone()),
+            WebDAVList::Err => None,
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn webdav_list_test() {
+    let server = DavServer::start();
+    server.create_file("/a.txt", b"hello");
+    server.create_file("/dir/b.txt", b"world");
+
+    let client = WebDAVClient::new(
+        server.url.clone(),
+        String::new(),
+        Secret::new(String::new()),
+    )
+    .unwrap();
+    let list = client.list("/").await.unwrap();
+    assert_eq!(list_names(&list), vec!["/", "/a.txt", "/dir/"]);
+
+    let list = client.list("/dir/").await.unwrap();
+    assert_eq!(list_names(&list), vec!["/dir/", "/dir/b.txt"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn webdav_list_redirect_test() {
+    let server = MockServer::start(|method, path| {
+        let response = Response::builder();
+        match (method.as_str(), path) {
+            ("PROPFIND", "/old/") => response.status(301).header("Location", "/new/"),
+            ("PROPFIND", "/new/") => {
+                return response
+                    .status(207)
+                    .header("Content-Type", "application/xml; charset=utf-8")
+                    .body(Body::from(MOVED_LISTING))
+                    .unwrap()
+            }
+            _ => response.status(405),
+        }
+        .body(Body::empty())
+        .unwrap()
+    });
+
+    let client = WebDAVClient::new(
+        server.url.clone(),
+        String::new(),
+        Secret::new(String::new()),
+    )
+    .unwrap();
+    let list = client.list("/old/").await.unwrap();
+    assert_eq!(list_names(&list), vec!["/new/", "/new/a.txt"]);
+    // Note : the redirect is followed with a PROPFIND, not with the GET of reqwest.
+    assert_eq!(
+        server.requests(),
+        vec![
+            ("PROPFIND".to_string(), "/old/".to_string()),
+            ("PROPFIND".to_string(), "/new/".to_string()),
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn webdav_list_raw_name_test() {
+    let server = MockServer::start(|method, path| {
+        let response = Response::builder();
+        match (method.as_str(), path) {
+            ("PROPFIND", "/latin1/") => response
+                .status(207)
+                .header("Content-Type", "application/xml; charset=utf-8")
+                .body(Body::from(LATIN1_LISTING)),
+            ("GET", "/latin1/caf%E9.txt") => response.status(200).body(Body::from("hello")),
+            _ => response.status(404).body(Body::empty()),
+        }
+        .unwrap()
+    });
+
+    let client = WebDAVClient::new(
+        server.url.clone(),
+        String::new(),
+        Secret::new(String::new()),
+    )
+    .unwrap();
+    let list = client.list("/latin1/").await.unwrap();
+    let file = list
+        .iter()
+        .find_map(|item| match item {
+            WebDAVList::File(f) => Some(f.clone()),
+            _ => None,
+        })
+        .unwrap();
+    assert_eq!(file.name, OsStr::from_bytes(b"caf\xe9.txt"));
+    assert_eq!(file.encoded_path, "/latin1/caf%E9.txt");
+
+    // Note : the request goes to the bytes the server sent, not to a name made valid UTF-8.
+    let mut sink = MemorySink::new(0);
+    client
+        .download(&file.encoded_path, &mut sink, 0, 5)
+        .await
+        .unwrap();
+    assert_eq!(sink.into_data(), b"hello");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn webdav_download_test() {
+    let server = DavServer::start();
+    let content = gen_content(1000);
+    server.create_file("/data.bin", &content);
+
+    let client = WebDAVClient::new(
+        server.url.clone(),
+        String::new(),
+        Secret::new(String::new()),
+    )
+    .unwrap();
+    let cache_dir = tempfile::tempdir().unwrap();
+    let cache_path = cache_dir.path().join("data.bin");
+    let cache_path = cache_path.to_str().unwrap();
+
+    let mut file = BlockFile::create(cache_path, content.len() as u64, 64)
+        .await
+        .unwrap();
+    let (begin, end) = file.calc_block_range_from(100, 300);
+    client
+        .download("/data.bin", &mut file, begin, end - begin)
+        .await
+        .unwrap();
+    assert!(file.is_data_ready(100, 300).await.unwrap());
+    assert!(!file.is_data_ready(900, 100).await.unwrap());
+
+    let mut buf = vec![0; 300];
+    file.read(&mut buf, 100).await.unwrap();
+    assert_eq!(buf, &content[100..400]);
+
+    let mut sink = MemorySink::new(100);
+    client
+        .download("/data.bin", &mut sink, 100, 300)
+        .await
+        .unwrap();
+    assert_eq!(sink.data(), &content[100..400]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn webdav_download_ignored_range_test() {
+    let content = gen_content(1000);
+    let body = content.clone();
+    let server = MockServer::start(move |_, _| Response::new(Body::from(body.clone())));
+    let mut client = WebDAVClient::new(
+        server.url.clone(),
+        String::new(),
+        Secret::new(String::new()),
+    )
+    .unwrap();
+    client.set_quirks_mode(QuirksMode::Provider(Provider::Box));
+    let cache_dir = tempfile::tempdir().unwrap();
+    let cache_path = cache_dir.path().join("data.bin");
+    let mut file = BlockFile::create(cache_path.to_str().unwrap(), 1000, 64)
+        .await
+        .unwrap();
+
+    // Note : the range is written before the call returns, the rest of the file after it.
+    let (_, rest) = client
+        .download_with_rest("/data.bin", &mut file, 128, 256)
+        .await
+        .unwrap();
+    assert!(file.is_data_ready(128, 256).await.unwrap());
+    assert!(!file.is_data_ready(384, 616).await.unwrap());
+    rest.unwrap().write_to(&mut file).await.unwrap();
+    assert!(file.is_data_ready(384, 616).await.unwrap());
+    let mut buf = vec![0; 616];
+    file.read(&mut buf, 384).await.unwrap();
+    assert_eq!(buf, &content[384..]);
+
+    // Note : a sink without a file size keeps nothing past the range.
+    let mut sink = MemorySink::new(100);
+    let (_, rest) = client
+        .download_with_rest("/data.bin", &mut sink, 100, 300)
+        .await
+        .unwrap();
+    assert!(rest.is_none());
+    assert_eq!(sink.data(), &content[100..400]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn webdav_range_stream_test() {
+    let server = DavServer::start();
+    let content = gen_content(100_000);
+    server.create_file("/data.bin", &content);
+
+    let client = WebDAVClient::new(
+        server.url.clone(),
+        String::new(),
+        Secret::new(String::new()),
+    )
+    .unwrap();
+    let options = RangeOptions {
+        offset: 1000,
+        size: 50_000,
+        keep_until: None,
+    };
+    let mut stream = client.get_range_stream("/data.bin", options).await.unwrap();
+    assert_eq!(stream.end(), 51_000);
+    let mut received = Vec::new();
+    while let Some(chunk) = stream.chunk().await.unwrap() {
+        assert_eq!(chunk.offset, 1000 + received.len() as u64);
+        received.extend_from_slice(&chunk);
+    }
+    assert_eq!(received, &content[1000..51_000]);
+
+    // Note : the range is cut at the end of the file.
+    let options = RangeOptions {
+        offset: 99_990,
+        size: 100,
+        keep_until: None,
+    };
+    let mut stream = client.get_range_stream("/data.bin", options).await.unwrap();
+    let chunk = stream.chunk().await.unwrap().unwrap();
+    assert_eq!((chunk.offset, &chunk[..]), (99_990, &content[99_990..]));
+    drop(chunk);
+    assert!(stream.chunk().await.unwrap().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn webdav_list_stream_test() {
+    let server = DavServer::start();
+    server.create_file("/a.txt", b"hello");
+    server.create_file("/dir/b.txt", b"world");
+    server.create_file("/dir/sub/c.txt", b"!");
+
+    let client = WebDAVClient::new(
+        server.url.clone(),
+        String::new(),
+        Secret::new(String::new()),
+    )
+    .unwrap();
+    let collect = |options| {
+        let mut stream = client.list_stream("/", options);
+        async move {
+            let mut list = Vec::new();
+            while let Some(item) = stream.next().await.unwrap() {
+                list.push(item);
+            }
+            list_names(&list)
+        }
+    };
+    assert_eq!(
+        collect(ListOptions::default()).await,
+        vec!["/a.txt", "/dir/"]
+    );
+    assert_eq!(
+        collect(ListOptions { recursive: true }).await,
+        vec![
+            "/a.txt",
+            "/dir/",
+            "/dir/b.txt",
+            "/dir/sub/",
+            "/dir/sub/c.txt"
+        ]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mount_test() {
+    let preflight_options = preflight::PreflightOptions {
+        fusermount_path: None,
+        allow_other: false,
+    };
+    if let Err(err) = preflight::check(&preflight_options) {
+        eprintln!("Skip mount test: {}", err);
+        return;
+    }
+
+    let server = DavServer::start();
+    let content = gen_content(100_000);
+    server.create_file("/a.txt", b"hello");
+    server.create_file("/dir/data.bin", &content);
+    server.create_dir("/empty");
+
+    let client = WebDAVClient::new(
+        server.url.clone(),
+        String::new(),
+        Secret::new(String::new()),
+    )
+    .unwrap();
+    let cache_dir = tempfile::tempdir().unwrap();
+    let mount_dir = tempfile::tempdir().unwrap();
+    let webdavfs = WebDAVFS::new(
+        tokio::runtime::Handle::current(),
+        client,
+        cache_dir.path().to_str().unwrap().to_string(),
+        unsafe { libc::getuid() },
+        unsafe { libc::getgid() },
+    );
+    let options = vec![
+        MountOption::RO,
+        MountOption::FSName("fusedav-rs".to_string()),
+    ];
+    let session = match fuser::spawn_mount2(webdavfs, mount_dir.path(), &options) {
+        Ok(session) => session,
+        Err(err) => {
+            eprintln!("Skip mount test: {}", err);
+            return;
+        }
+    };
+
+    let mount_path = mount_dir.path().to_path_buf();
+    let expected = content.clone();
+    tokio::task::spawn_blocking(move || {
+        // Note : give the kernel a moment to finish the mount handshake.
+        std::thread::sleep(Duration::from_millis(200));
+
+        let mut names: Vec<OsString> = std::fs::read_dir(&mount_path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["a.txt", "dir", "empty"]);
+
+        let metadata = std::fs::metadata(mount_path.join("dir/data.bin")).unwrap();
+        assert_eq!(metadata.len(), expected.len() as u64);
+        assert!(std::fs::metadata(mount_path.join("empty"))
+            .unwrap()
+            .is_dir());
+        assert!(std::fs::metadata(mount_path.join("missing")).is_err());
+
+        assert_eq!(std::fs::read(mount_path.join("a.txt")).unwrap(), b"hello");
+        assert_eq!(
+            std::fs::read(mount_path.join("dir/data.bin")).unwrap(),
+            expected
+        );
+    })
+    .await
+    .unwrap();
+
+    drop(session);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn no_cache_test() {
+    let preflight_options = preflight::PreflightOptions {
+        fusermount_path: None,
+        allow_other: false,
+    };
+    if let Err(err) = preflight::check(&preflight_options) {
+        eprintln!("Skip no-cache test: {}", err);
+        return;
+    }
+
+    let server = MockServer::start(|method, path| {
+        let response = Response::builder().header("Cache-Control", "no-cache");
+        let body = match (method.as_str(), path) {
+            ("PROPFIND", "/") => NO_CACHE_LISTING,
+            ("PROPFIND", "/a.txt") => NO_CACHE_STAT,
+            ("GET", "/a.txt") => {
+                return response
+                    .status(200)
+                    .header("ETag", "\"a1\"")
+                    .body(Body::from("hello"))
+                    .unwrap()
+            }
+            _ => return response.status(404).body(Body::empty()).unwrap(),
+        };
+        response
+            .status(207)
+            .header("Content-Type", "application/xml; charset=utf-8")
+            .body(Body::from(body))
+            .unwrap()
+    });
+
+    let client = WebDAVClient::new(
+        server.url.clone(),
+        String::new(),
+        Secret::new(String::new()),
+    )
+    .unwrap();
+    let cache_dir = tempfile::tempdir().unwrap();
+    let mount_dir = tempfile::tempdir().unwrap();
+    let webdavfs = WebDAVFS::new(
+        tokio::runtime::Handle::current(),
+        client,
+        cache_dir.path().to_str().unwrap().to_string(),
+        unsafe { libc::getuid() },
+        unsafe { libc::getgid() },
+    );
+    let options = vec![
+        MountOption::RO,
+        MountOption::FSName("fusedav-rs".to_string()),
+    ];
+    let guard = match fs::mount(webdavfs, mount_dir.path(), &options) {
+        Ok(guard) => guard,
+        Err(err) => {
+            eprintln!("Skip no-cache test: {}", err);
+            return;
+        }
+    };
+
+    let mount_path = mount_dir.path().to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        // Note : give the kernel a moment to finish the mount handshake.
+        std::thread::sleep(Duration::from_millis(200));
+
+        for _ in 0..2 {
+            assert_eq!(std::fs::read(mount_path.join("a.txt")).unwrap(), b"hello");
+        }
+    })
+    .await
+    .unwrap();
+
+    // Note : the second read confirms the data with a PROPFIND instead of fetching it again.
+    let gets = server
+        .requests()
+        .into_iter()
+        .filter(|(method, _)| method == "GET")
+        .count();
+    assert_eq!(gets, 1);
+    guard.unmount().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn concurrent_mounts_test() {
+    let preflight_options = preflight::PreflightOptions {
+        fusermount_path: None,
+        allow_other: false,
+    };
+    if let Err(err) = preflight::check(&preflight_options) {
+        eprintln!("Skip concurrent mounts test: {}", err);
+        return;
+    }
+
+    let servers = [DavServer::start(), DavServer::start()];
+    servers[0].create_file("/a.txt", b"first");
+    servers[1].create_file("/a.txt", b"second server");
+    servers[1].create_file("/b.txt", b"only on the second");
+
+    // Note : both mounts share one cache directory, each in the namespace of its server.
+    let cache_dir = tempfile::tempdir().unwrap();
+    let options = vec![
+        MountOption::RO,
+        MountOption::FSName("fusedav-rs".to_string()),
+    ];
+    let mut mounts = Vec::new();
+    for server in servers.iter() {
+        let client = WebDAVClient::new(
+            server.url.clone(),
+            String::new(),
+            Secret::new(String::new()),
+        )
+        .unwrap();
+        let namespace = CacheNamespace::open(cache_dir.path(), &client.identity()).unwrap();
+        let mount_dir = tempfile::tempdir().unwrap();
+        let webdavfs = WebDAVFS::new(
+            tokio::runtime::Handle::current(),
+            client,
+            namespace.path().to_str().unwrap().to_string(),
+            unsafe { libc::getuid() },
+            unsafe { libc::getgid() },
+        );
+        let guard = match fs::mount(webdavfs, mount_dir.path(), &options) {
+            Ok(guard) => guard,
+            Err(err) => {
+                eprintln!("Skip concurrent mounts test: {}", err);
+                return;
+            }
+        };
+        mounts.push((namespace, mount_dir, guard));
+    }
+
+    let paths: Vec<_> = mounts
+        .iter()
+        .map(|(_, mount_dir, _)| mount_dir.path().to_path_buf())
+        .collect();
+    tokio::task::spawn_blocking(move || {
+        // Note : give the kernel a moment to finish the mount handshakes.
+        std::thread::sleep(Duration::from_millis(200));
+
+        assert_eq!(std::fs::read(paths[0].join("a.txt")).unwrap(), b"first");
+        assert_eq!(
+            std::fs::read(paths[1].join("a.txt")).unwrap(),
+            b"second server"
+        );
+        assert!(std::fs::metadata(paths[0].join("b.txt")).is_err());
+        assert_eq!(
+            std::fs::read(paths[1].join("b.txt")).unwrap(),
+            b"only on the second"
+        );
+    })
+    .await
+    .unwrap();
+
+    let first = mounts[0].2.stats().await;
+    let second = mounts[1].2.stats().await;
+    assert_eq!(first.cached_files, 1);
+    assert_eq!(second.cached_files, 2);
+    let read_bytes = |top_paths: &[(String, fs::PathStat)]| {
+        top_paths
+            .iter()
+            .map(|(_, stat)| stat.read_bytes)
+            .sum::<u64>()
+    };
+    assert_eq!(read_bytes(&first.top_paths), 5);
+    assert_eq!(read_bytes(&second.top_paths), 31);
+
+    for (_, _, guard) in mounts {
+        guard.unmount().await.unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_only_cache_test() {
+    let preflight_options = preflight::PreflightOptions {
+        fusermount_path: None,
+        allow_other: false,
+    };
+    if let Err(err) = preflight::check(&preflight_options) {
+        eprintln!("Skip read-only cache test: {}", err);
+        return;
+    }
+
+    let server = DavServer::start();
+    let content = gen_content(100_000);
+    server.create_file("/data.bin", &content);
+
+    let client = WebDAVClient::new(
+        server.url.clone(),
+        String::new(),
+        Secret::new(String::new()),
+    )
+    .unwrap();
+    let cache_dir = tempfile::tempdir().unwrap();
+    let mount_dir = tempfile::tempdir().unwrap();
+    let webdavfs = WebDAVFS::new(
+        tokio::runtime::Handle::current(),
+        client,
+        cache_dir.path().to_str().unwrap().to_string(),
+        unsafe { libc::getuid() },
+        unsafe { libc::getgid() },
+    );
+    let options = vec![
+        MountOption::RO,
+        MountOption::FSName("fusedav-rs".to_string()),
+    ];
+    let guard = match fs::mount(webdavfs, mount_dir.path(), &options) {
+        Ok(guard) => guard,
+        Err(err) => {
+            eprintln!("Skip read-only cache test: {}", err);
+            return;
+        }
+    };
+
+    let set_mode = |mode| {
+        std::fs::set_permissions(cache_dir.path(), std::fs::Permissions::from_mode(mode)).unwrap()
+    };
+    set_mode(0o555);
+    // Note : permissions do not keep root from writing.
+    if std::fs::write(cache_dir.path().join("probe"), b"probe").is_ok() {
+        eprintln!("Skip read-only cache test: the cache directory is still writable");
+        set_mode(0o755);
+        guard.unmount().await.unwrap();
+        return;
+    }
+
+    let mount_path = mount_dir.path().to_path_buf();
+    let expected = content.clone();
+    tokio::task::spawn_blocking(move || {
+        // Note : give the kernel a moment to finish the mount handshake.
+        std::thread::sleep(Duration::from_millis(200));
+
+        // Note : every open reads the file again, past the failures after which the cache is
+        // bypassed.
+        for _ in 0..4 {
+            assert_eq!(
+                std::fs::read(mount_path.join("data.bin")).unwrap(),
+                expected
+            );
+        }
+    })
+    .await
+    .unwrap();
+
+    assert!(guard.handle().health().await.cache_bypassed);
+    set_mode(0o755);
+    guard.unmount().await.unwrap();
+}